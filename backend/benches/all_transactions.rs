@@ -0,0 +1,139 @@
+//! Bytes allocated answering GET /transactions/all, serializing the history
+//! straight from the state as the store does now, against cloning the
+//! matching transactions first as it used to
+
+mod common;
+
+use backend::types::{HistoricalTransaction, Page, TransactionFilter, TransactionSort};
+use common::{ACCOUNT, store};
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::runtime::Runtime;
+
+/// Counts every byte allocated, for `Allocated` to measure
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Bytes allocated by each iteration, in place of the time it took
+struct Allocated;
+
+impl Measurement for Allocated {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> usize {
+        ALLOCATED.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: usize) -> usize {
+        ALLOCATED.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, a: &usize, b: &usize) -> usize {
+        a + b
+    }
+
+    fn zero(&self) -> usize {
+        0
+    }
+
+    fn to_f64(&self, value: &usize) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &Bytes
+    }
+}
+
+struct Bytes;
+
+impl ValueFormatter for Bytes {
+    fn scale_values(&self, typical: f64, values: &mut [f64]) -> &'static str {
+        let (divisor, unit) = match typical {
+            t if t >= 1024.0 * 1024.0 => (1024.0 * 1024.0, "MiB"),
+            t if t >= 1024.0 => (1024.0, "KiB"),
+            _ => (1.0, "B"),
+        };
+        for value in values {
+            *value /= divisor;
+        }
+        unit
+    }
+
+    fn scale_throughputs(&self, _typical: f64, throughput: &Throughput, values: &mut [f64]) -> &'static str {
+        if let Throughput::Elements(elements) = throughput {
+            for value in values {
+                *value /= *elements as f64;
+            }
+        }
+        "B/transaction"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "B"
+    }
+}
+
+fn all_transactions(c: &mut Criterion<Allocated>) {
+    let runtime = Runtime::new().unwrap();
+    let (store, ids) = store(&runtime);
+    let filter = TransactionFilter {
+        account_id: Some(ACCOUNT.to_string()),
+        ..TransactionFilter::default()
+    };
+    let sort = TransactionSort::default();
+    let page = || Page { offset: 0, limit: None };
+    let mut group = c.benchmark_group("all_transactions");
+    group.throughput(Throughput::Elements(ids.len() as u64));
+
+    // Before: the matching transactions cloned out of the state, then serialized
+    let snapshot = store.snapshot();
+    group.bench_function("cloned", |b| {
+        b.iter(|| {
+            let mut transactions: Vec<HistoricalTransaction> = snapshot.all[ACCOUNT]
+                .iter()
+                .filter(|t| filter.matches(&t.id) && filter.matches_metadata(&t.metadata))
+                .cloned()
+                .collect();
+            transactions.sort_by(|a, b| sort.compare(&a.id, &b.id));
+            serde_json::to_vec(page().slice(&transactions)).unwrap()
+        })
+    });
+
+    // After: serialized by reference, straight from the state
+    group.bench_function("by_reference", |b| {
+        b.iter(|| store.all_transactions_json(&filter, sort, page(), None).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    // Every iteration allocates the same, which the plots can't draw
+    config = Criterion::default().with_measurement(Allocated).without_plots();
+    targets = all_transactions
+}
+criterion_main!(benches);
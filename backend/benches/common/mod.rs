@@ -0,0 +1,39 @@
+//! A store for the benches to read, filled as an account would be over years
+
+use backend::storage::MemoryStorage;
+use backend::store::TransactionStore;
+use backend::types::{CreateAccountRequest, CreateTransactionRequest, TransactionId};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+/// Transactions in the account the benches read
+const TRANSACTIONS: usize = 5_000;
+pub const ACCOUNT: &str = "checking";
+
+/// A store holding `TRANSACTIONS` transactions, and their ids
+pub fn store(runtime: &Runtime) -> (TransactionStore, Vec<TransactionId>) {
+    let store = TransactionStore::new(Arc::new(MemoryStorage::new()), HashMap::new(), HashMap::new(), false);
+    let account: CreateAccountRequest =
+        serde_json::from_value(serde_json::json!({ "account_id": ACCOUNT, "display_name": "Checking" })).unwrap();
+    store.create_account(account).unwrap();
+
+    let start: DateTime<Utc> = "2024-01-01T00:00:00Z".parse().unwrap();
+    let ids = (0..TRANSACTIONS)
+        .map(|i| {
+            let request = CreateTransactionRequest {
+                account_id: ACCOUNT.to_string(),
+                timestamp: start + Duration::minutes(i as i64),
+                payee: format!("Payee {}", i % 200),
+                amount: -((i % 5_000) as f64 + 1.0) / 100.0,
+                currency: "USD".to_string(),
+                allow_duplicate: false,
+                foreign_currency: false,
+                metadata: Default::default(),
+            };
+            runtime.block_on(store.create_transaction(request, false)).unwrap().id
+        })
+        .collect();
+    (store, ids)
+}
@@ -0,0 +1,137 @@
+//! GET /transactions/current while an import changes the store. Taking the
+//! state is measured as the store used to, behind a mutex its writers held
+//! while applying changes, and as it does now, lock-free with arc-swap; the
+//! whole read path is measured idle and while importing.
+
+mod common;
+
+use arc_swap::ArcSwap;
+use backend::storage::{Mutation, Snapshot};
+use backend::types::{Page, TransactionFilter, TransactionId, TransactionSort};
+use common::{ACCOUNT, store};
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::runtime::Runtime;
+
+/// How often the import changes the store, as rows arrive
+const WRITE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+
+/// The change an import makes over and over: the memo of one of `ids`
+fn memo(ids: &[TransactionId], n: usize) -> Mutation {
+    Mutation::MemoUpdated {
+        account_id: ACCOUNT.to_string(),
+        id: ids[n % ids.len()].clone(),
+        memo: Some(format!("memo {}", n)),
+    }
+}
+
+/// Run `write` every `WRITE_INTERVAL` on another thread for as long as `read` runs
+fn while_writing(mut write: impl FnMut(usize) + Send + 'static, read: impl FnOnce()) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let mut n = 0;
+            while !stop.load(Ordering::Relaxed) {
+                write(n);
+                n += 1;
+                thread::sleep(WRITE_INTERVAL);
+            }
+        })
+    };
+    read();
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+}
+
+fn take_state_while_importing(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let (store, ids) = store(&runtime);
+    let snapshot = store.snapshot();
+    let mut group = c.benchmark_group("take_state_while_importing");
+
+    // Before: changes applied in place with the mutex held, copying the
+    // whole state first whenever a reader still had it
+    let slot = Arc::new(Mutex::new(Arc::new(snapshot.clone())));
+    let writer = {
+        let (slot, ids) = (slot.clone(), ids.clone());
+        move |n| {
+            let mutation = memo(&ids, n);
+            let mut slot = slot.lock().unwrap();
+            Arc::make_mut(&mut slot).apply(&mutation);
+        }
+    };
+    while_writing(writer, || {
+        group.bench_function("mutex", |b| {
+            b.iter(|| {
+                let state = slot.lock().unwrap().clone();
+                serde_json::to_vec(&state.all[ACCOUNT]).unwrap()
+            })
+        });
+    });
+
+    // After: changes applied to a spare one change behind and swapped in
+    let slot = Arc::new(ArcSwap::from_pointee(snapshot));
+    let writer = {
+        let (slot, ids) = (slot.clone(), ids.clone());
+        let mut spare: Option<(Arc<Snapshot>, Mutation)> = None;
+        move |n| {
+            let mutation = memo(&ids, n);
+            let caught_up = spare.take().and_then(|(mut spare, behind)| {
+                Arc::get_mut(&mut spare)?.apply(&behind);
+                Some(spare)
+            });
+            let mut next = caught_up.unwrap_or_else(|| Arc::new((*slot.load_full()).clone()));
+            Arc::make_mut(&mut next).apply(&mutation);
+            spare = Some((slot.swap(next), mutation));
+        }
+    };
+    while_writing(writer, || {
+        group.bench_function("arc_swap", |b| {
+            b.iter(|| {
+                let state = slot.load_full();
+                serde_json::to_vec(&state.all[ACCOUNT]).unwrap()
+            })
+        });
+    });
+    group.finish();
+}
+
+fn current_transactions(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let (store, ids) = store(&runtime);
+    let filter = TransactionFilter {
+        account_id: Some(ACCOUNT.to_string()),
+        ..TransactionFilter::default()
+    };
+    let page = || Page { offset: 0, limit: None };
+    let mut group = c.benchmark_group("current_transactions");
+
+    group.bench_function("idle", |b| {
+        b.iter(|| store.current_transactions_json(&filter, TransactionSort::default(), page(), None).unwrap())
+    });
+
+    let writer = {
+        let store = store.clone();
+        move |n| {
+            let Mutation::MemoUpdated { account_id, id, memo } = memo(&ids, n) else {
+                unreachable!()
+            };
+            runtime.block_on(store.update_transaction_memo(account_id, id, memo, false, None)).unwrap();
+            if n % 1_000 == 0 {
+                runtime.block_on(store.flush());
+            }
+        }
+    };
+    while_writing(writer, || {
+        group.bench_function("while_importing", |b| {
+            b.iter(|| store.current_transactions_json(&filter, TransactionSort::default(), page(), None).unwrap())
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, take_state_while_importing, current_transactions);
+criterion_main!(benches);
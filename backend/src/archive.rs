@@ -0,0 +1,251 @@
+use crate::config::Month;
+use crate::storage::Snapshot;
+use crate::types::{
+    AccountDetails, ArchiveImportMode, BalanceAssertion, CurrentTransaction, HistoricalTransaction,
+    ImportArchiveResponse, ImportRecord, Permission, TransactionId,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::hash_map::Entry;
+
+/// Bumped whenever the archive layout changes incompatibly
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Everything in the store, one entry per account, in an order that depends
+/// only on the data so unchanged data exports byte for byte the same
+#[derive(Serialize)]
+struct ArchiveRef<'a> {
+    format_version: u32,
+    accounts: Vec<AccountRef<'a>>,
+}
+
+#[derive(Serialize)]
+struct AccountRef<'a> {
+    account_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<&'a AccountDetails>,
+    current_transactions: Vec<&'a TransactionId>,
+    /// In the order they were recorded, memos included
+    historical_transactions: &'a [HistoricalTransaction],
+    balance_assertions: Vec<&'a BalanceAssertion>,
+    imports: Vec<&'a ImportRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    closed_month: Option<&'a Month>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<&'a String>,
+    /// Sorted by user, as a map's order would change from one export to the next
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    grants: BTreeMap<&'a String, &'a Permission>,
+}
+
+/// Serialize `snapshot` as a pretty-printed archive, straight from references
+pub fn to_json(snapshot: &Snapshot) -> Result<Vec<u8>, serde_json::Error> {
+    // Imports are recorded even when they fail for an account that doesn't exist
+    let mut account_ids: Vec<_> = snapshot
+        .current
+        .keys()
+        .chain(snapshot.accounts.keys())
+        .chain(snapshot.all.keys())
+        .chain(snapshot.imports.iter().map(|record| &record.account_id))
+        .collect();
+    account_ids.sort();
+    account_ids.dedup();
+
+    let accounts = account_ids
+        .into_iter()
+        .map(|account_id| {
+            let mut current_transactions: Vec<_> = snapshot
+                .current
+                .get(account_id)
+                .map(|transactions| transactions.keys().collect())
+                .unwrap_or_default();
+            current_transactions.sort_by_key(|id| (id.timestamp, &id.payee, id.amount_cents, &id.currency, id.occurrence));
+
+            let mut balance_assertions: Vec<_> = snapshot
+                .balance_assertions
+                .iter()
+                .filter(|assertion| assertion.account_id == *account_id)
+                .collect();
+            balance_assertions.sort_by_key(|assertion| (assertion.date, &assertion.currency));
+
+            AccountRef {
+                account_id,
+                details: snapshot.accounts.get(account_id),
+                current_transactions,
+                historical_transactions: snapshot.all.get(account_id).map(Vec::as_slice).unwrap_or_default(),
+                balance_assertions,
+                imports: snapshot
+                    .imports
+                    .iter()
+                    .filter(|record| record.account_id == *account_id)
+                    .collect(),
+                closed_month: snapshot.closed_months.get(account_id),
+                owner: snapshot.account_owners.get(account_id),
+                grants: snapshot.account_grants.get(account_id).into_iter().flatten().collect(),
+            }
+        })
+        .collect();
+
+    serde_json::to_vec_pretty(&ArchiveRef {
+        format_version: FORMAT_VERSION,
+        accounts,
+    })
+}
+
+/// An archive as uploaded, the owned counterpart of `ArchiveRef`
+#[derive(Deserialize)]
+pub struct Archive {
+    format_version: u32,
+    accounts: Vec<AccountArchive>,
+}
+
+#[derive(Deserialize)]
+struct AccountArchive {
+    account_id: String,
+    #[serde(default)]
+    details: Option<AccountDetails>,
+    current_transactions: Vec<TransactionId>,
+    historical_transactions: Vec<HistoricalTransaction>,
+    #[serde(default)]
+    balance_assertions: Vec<BalanceAssertion>,
+    #[serde(default)]
+    imports: Vec<ImportRecord>,
+    #[serde(default)]
+    closed_month: Option<Month>,
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default)]
+    grants: HashMap<String, Permission>,
+}
+
+impl Archive {
+    /// Check the archive is one we can load and everything in it is filed
+    /// under the account it belongs to
+    pub fn validate(&self) -> Result<(), String> {
+        if self.format_version != FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported archive format version {}, expected {}",
+                self.format_version, FORMAT_VERSION
+            ));
+        }
+
+        let mut account_ids = HashSet::new();
+        for account in &self.accounts {
+            if !account_ids.insert(&account.account_id) {
+                return Err(format!("Account {} appears more than once", account.account_id));
+            }
+            let misfiled = account
+                .historical_transactions
+                .iter()
+                .map(|t| &t.account_id)
+                .chain(account.balance_assertions.iter().map(|a| &a.account_id))
+                .chain(account.imports.iter().map(|r| &r.account_id))
+                .find(|account_id| **account_id != account.account_id);
+            if let Some(account_id) = misfiled {
+                return Err(format!(
+                    "Data for account {} is filed under account {}",
+                    account_id, account.account_id
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the archive into `base`: `Replace` discards what `base` holds, `Merge`
+    /// only adds what it doesn't have yet. Returns the result and a count of
+    /// what was loaded from the archive.
+    pub fn load_into(self, base: &Snapshot, mode: ArchiveImportMode) -> (Snapshot, ImportArchiveResponse) {
+        let mut snapshot = match mode {
+            ArchiveImportMode::Replace => Snapshot::default(),
+            ArchiveImportMode::Merge => base.clone(),
+        };
+        let mut summary = ImportArchiveResponse {
+            mode,
+            accounts: self.accounts.len(),
+            current_transactions: 0,
+            historical_transactions: 0,
+            balance_assertions: 0,
+            imports: 0,
+        };
+
+        for account in self.accounts {
+            // An account already in the store keeps its details
+            if let Some(details) = account.details {
+                snapshot.accounts.entry(account.account_id.clone()).or_insert(details);
+                snapshot.current.entry(account.account_id.clone()).or_default();
+            }
+
+            // An entry with nothing but imports is for an account that was never created
+            if !account.current_transactions.is_empty() || !account.historical_transactions.is_empty() {
+                let all = snapshot.all.entry(account.account_id.clone()).or_default();
+                // Where a transaction is already recorded, the existing record and its memo win
+                let known: HashSet<_> = all.iter().map(|t| t.id.clone()).collect();
+                for transaction in account.historical_transactions {
+                    if !known.contains(&transaction.id) {
+                        all.push(transaction);
+                        summary.historical_transactions += 1;
+                    }
+                }
+
+                // Every account has a current map, even one whose transactions are all replaced
+                let current = snapshot.current.entry(account.account_id.clone()).or_default();
+                for id in account.current_transactions {
+                    if let Entry::Vacant(entry) = current.entry(id) {
+                        // The store gives it the uuid of its record in the history
+                        let transaction = CurrentTransaction {
+                            account_id: account.account_id.clone(),
+                            uuid: String::new(),
+                            id: entry.key().clone(),
+                        };
+                        entry.insert(transaction);
+                        summary.current_transactions += 1;
+                    }
+                }
+            }
+
+            for assertion in account.balance_assertions {
+                let exists = snapshot.balance_assertions.iter().any(|existing| {
+                    existing.account_id == assertion.account_id
+                        && existing.currency == assertion.currency
+                        && existing.date == assertion.date
+                });
+                if !exists {
+                    snapshot.balance_assertions.push(assertion);
+                    summary.balance_assertions += 1;
+                }
+            }
+
+            for record in account.imports {
+                let exists = snapshot.imports.iter().any(|existing| {
+                    existing.account_id == record.account_id
+                        && existing.source == record.source
+                        && existing.imported_at == record.imported_at
+                });
+                if !exists || mode == ArchiveImportMode::Replace {
+                    snapshot.imports.push(record);
+                    summary.imports += 1;
+                }
+            }
+
+            // An account already in the store keeps its owner
+            if let Some(owner) = account.owner {
+                snapshot.account_owners.entry(account.account_id.clone()).or_insert(owner);
+            }
+            // As do the grants of users it was shared with already
+            if !account.grants.is_empty() {
+                let grants = snapshot.account_grants.entry(account.account_id.clone()).or_default();
+                for (user, permission) in account.grants {
+                    grants.entry(user).or_insert(permission);
+                }
+            }
+
+            // A month closed in either stays closed
+            if let Some(month) = account.closed_month {
+                let closed = snapshot.closed_months.entry(account.account_id).or_insert(month);
+                *closed = (*closed).max(month);
+            }
+        }
+
+        (snapshot, summary)
+    }
+}
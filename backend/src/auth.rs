@@ -0,0 +1,652 @@
+use crate::config::{AuthConfig, Config, ScopedToken};
+use crate::error::{ApiError, AuthError};
+use crate::ingest::secrets_match;
+use crate::oidc::Oidc;
+use crate::storage::StorageError;
+use crate::types::Scope;
+use crate::users::Users;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use md5::{Digest, Md5};
+use ring::digest::{SHA1_FOR_LEGACY_USE_ONLY, digest};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::sync::RwLock;
+use warp::Filter;
+use warp::http::{HeaderMap, Method, StatusCode};
+
+/// Sent with a 401 so browsers ask for a username and password
+const BASIC_CHALLENGE: &str = r#"Basic realm="wdmmg", charset="UTF-8""#;
+pub const BEARER_CHALLENGE: &str = r#"Bearer realm="wdmmg""#;
+
+/// Who sent a request, and what their credentials let them do
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub user: String,
+    pub scope: Scope,
+}
+
+impl Identity {
+    /// `user`, signed in with credentials that let them do everything
+    pub fn full(user: String) -> Self {
+        Self { user, scope: Scope::Full }
+    }
+}
+
+/// Who sent the request being served, put in its extensions by the server as
+/// it comes in. The provider is asked the first time a filter wants to know,
+/// and the filters after are told what it said, so each request is verified
+/// once and nothing verified is kept for the next.
+#[derive(Clone)]
+pub struct RequestAuth {
+    /// Where the request came from, when over TCP without TLS
+    remote: Option<SocketAddr>,
+    identity: Arc<tokio::sync::OnceCell<Result<Identity, AuthError>>>,
+}
+
+impl RequestAuth {
+    pub fn new(remote: Option<SocketAddr>) -> Self {
+        Self {
+            remote,
+            identity: Arc::new(tokio::sync::OnceCell::new()),
+        }
+    }
+}
+
+/// A way of telling who sent a request, chosen by the `auth` config
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// Who sent a request with `headers`, from `remote` when it came over TCP
+    /// without TLS
+    async fn authenticate(&self, headers: &HeaderMap, remote: Option<SocketAddr>) -> Result<Identity, AuthError>;
+}
+
+/// Who may do what: the configured provider, none letting every request in,
+/// and which of its users are admins
+#[derive(Clone)]
+pub struct Access {
+    provider: Option<Arc<dyn AuthProvider>>,
+    admins: Arc<Vec<String>>,
+}
+
+impl Access {
+    /// The access `config` sets up. `users` are those loaded for
+    /// `auth.provider = "users"`.
+    pub async fn from_config(config: &Config, users: Option<Users>) -> Result<Self, StorageError> {
+        let provider: Option<Arc<dyn AuthProvider>> = match &config.auth {
+            None => None,
+            Some(AuthConfig::StaticTokens {
+                token_envs,
+                scoped_tokens,
+            }) => Some(Arc::new(StaticTokens::new(token_envs, scoped_tokens)?)),
+            Some(AuthConfig::Htpasswd { file }) => Some(Arc::new(Htpasswd::new(file).await?)),
+            Some(AuthConfig::Users { .. }) => Some(Arc::new(users.ok_or("The users weren't loaded")?)),
+            Some(AuthConfig::Oidc {
+                issuer,
+                audience,
+                username_claim,
+                user_map,
+                allowed_groups,
+            }) => Some(Arc::new(Oidc::new(issuer, audience, username_claim, user_map, allowed_groups)?)),
+            Some(AuthConfig::ProxyHeader { header, trusted_proxies }) => Some(Arc::new(ProxyHeader::new(
+                header,
+                trusted_proxies,
+                config.server.unix_socket.is_some(),
+            )?)),
+        };
+        Ok(Self {
+            provider,
+            admins: Arc::new(config.admins.clone()),
+        })
+    }
+
+    /// Who sent the request, or None when there's no provider to tell;
+    /// requests it can't tell the sender of are refused. The provider is
+    /// asked once per request: `authenticated()` asks it, and the filters
+    /// after it, such as `scoped()` and `owner()`, are given what it said
+    /// through the request's `RequestAuth`.
+    fn identity(&self) -> impl Filter<Extract = (Option<Identity>,), Error = warp::Rejection> + Clone + use<> {
+        let provider = self.provider.clone();
+        warp::header::headers_cloned()
+            .and(warp::addr::remote())
+            .and(warp::ext::optional::<RequestAuth>())
+            .and_then(move |headers: HeaderMap, remote: Option<SocketAddr>, request: Option<RequestAuth>| {
+                let provider = provider.clone();
+                async move {
+                    let Some(provider) = provider else {
+                        return Ok::<_, warp::Rejection>(None);
+                    };
+                    // Requests not served by the server, as in tests, are verified by each filter
+                    let request = request.unwrap_or_else(|| RequestAuth::new(remote));
+                    let remote = request.remote.or(remote);
+                    let identity = request.identity.get_or_init(|| provider.authenticate(&headers, remote)).await;
+                    identity.clone().map(Some).map_err(warp::reject::custom)
+                }
+            })
+    }
+
+    /// The user who sent the request, or None when there's no provider to
+    /// tell; requests it can't tell the sender of are refused
+    pub fn user(&self) -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone + use<> {
+        self.identity().map(|identity: Option<Identity>| identity.map(|identity| identity.user))
+    }
+
+    /// Refuse requests the provider can't tell the sender of, and reads with
+    /// credentials that don't allow reading, as import-only tokens don't
+    pub fn authenticated(&self) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone + use<> {
+        self.identity()
+            .and(warp::method())
+            .and_then(|identity: Option<Identity>, method: Method| async move {
+                match identity {
+                    Some(identity)
+                        if (method == Method::GET || method == Method::HEAD) && !identity.scope.allows(Scope::Read) =>
+                    {
+                        Err(warp::reject::custom(insufficient_scope(identity.scope, Scope::Read)))
+                    }
+                    _ => Ok(()),
+                }
+            })
+            .untuple_one()
+    }
+
+    /// Refuse requests whose credentials don't allow `scope`, such as a
+    /// read-only token's changes. Everyone may do everything without a
+    /// provider.
+    pub fn scoped(&self, scope: Scope) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone + use<> {
+        self.identity()
+            .and_then(move |identity: Option<Identity>| async move {
+                match identity {
+                    Some(identity) if !identity.scope.allows(scope) => {
+                        Err(warp::reject::custom(insufficient_scope(identity.scope, scope)))
+                    }
+                    _ => Ok(()),
+                }
+            })
+            .untuple_one()
+    }
+
+    /// The user whose data alone the request may see and change, or None for
+    /// all of it, as admins and everyone without a provider may
+    pub fn owner(&self) -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone + use<> {
+        let admins = self.admins.clone();
+        self.user().map(move |user: Option<String>| user.filter(|user| !admins.contains(user)))
+    }
+
+    /// Refuse requests from anyone but admins, for what concerns every user's
+    /// data at once
+    pub fn admin(&self) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone + use<> {
+        self.owner()
+            .and_then(|owner: Option<String>| async move {
+                match owner {
+                    Some(_) => Err(warp::reject::custom(ApiError {
+                        message: "Only an admin may do this; admins are listed in the config's admins".to_string(),
+                        status: StatusCode::FORBIDDEN,
+                    })),
+                    None => Ok(()),
+                }
+            })
+            .untuple_one()
+    }
+}
+
+fn insufficient_scope(given: Scope, needed: Scope) -> ApiError {
+    ApiError {
+        message: format!("These credentials are {} only; this needs {} access", scope_name(given), scope_name(needed)),
+        status: StatusCode::FORBIDDEN,
+    }
+}
+
+fn scope_name(scope: Scope) -> &'static str {
+    match scope {
+        Scope::Read => "read",
+        Scope::Import => "import",
+        Scope::Full => "full",
+    }
+}
+
+/// What follows `scheme` in the `Authorization` header
+pub fn credentials<'a>(headers: &'a HeaderMap, scheme: &str) -> Option<&'a str> {
+    let value = headers.get("authorization")?.to_str().ok()?;
+    let (given, credentials) = value.split_once(' ')?;
+    given.eq_ignore_ascii_case(scheme).then(|| credentials.trim())
+}
+
+/// Bearer tokens handed out to each user by whoever runs the server
+struct StaticTokens {
+    tokens: Vec<(Identity, String)>,
+}
+
+impl StaticTokens {
+    fn new(token_envs: &HashMap<String, String>, scoped_tokens: &[ScopedToken]) -> Result<Self, StorageError> {
+        let full = token_envs.iter().map(|(user, name)| (user, name, Scope::Full));
+        let scoped = scoped_tokens.iter().map(|scoped| (&scoped.user, &scoped.token_env, scoped.scope));
+        let mut tokens = Vec::new();
+        for (user, name, scope) in full.chain(scoped) {
+            let token = std::env::var(name)
+                .ok()
+                .filter(|token| !token.is_empty())
+                .ok_or_else(|| format!("Environment variable {} with the token of {} is not set", name, user))?;
+            let identity = Identity {
+                user: user.clone(),
+                scope,
+            };
+            tokens.push((identity, token));
+        }
+        Ok(Self { tokens })
+    }
+}
+
+#[async_trait]
+impl AuthProvider for StaticTokens {
+    async fn authenticate(&self, headers: &HeaderMap, _remote: Option<SocketAddr>) -> Result<Identity, AuthError> {
+        let provided = credentials(headers, "bearer")
+            .ok_or_else(|| AuthError::challenge("A bearer token is required", BEARER_CHALLENGE))?;
+        self.tokens
+            .iter()
+            .find(|(_, token)| secrets_match(token, provided))
+            .map(|(identity, _)| identity.clone())
+            .ok_or_else(|| AuthError::challenge("Invalid token", BEARER_CHALLENGE))
+    }
+}
+
+/// Users of an Apache htpasswd file, read again whenever it changes so
+/// `htpasswd` can add and remove them while the server runs
+struct Htpasswd {
+    path: String,
+    users: RwLock<(Option<SystemTime>, HashMap<String, PasswordHash>)>,
+}
+
+enum PasswordHash {
+    /// `$apr1$` from `htpasswd -m`, or crypt's `$1$`
+    Md5Crypt { magic: &'static str, salt: String, hash: String },
+    /// `{SHA}` from `htpasswd -s`, in base64
+    Sha1(String),
+}
+
+impl Htpasswd {
+    async fn new(path: &str) -> Result<Self, StorageError> {
+        let htpasswd = Self {
+            path: path.to_string(),
+            users: RwLock::new((None, HashMap::new())),
+        };
+        htpasswd.reload().await?;
+        Ok(htpasswd)
+    }
+
+    /// Read the file again if it has changed since it was last read
+    async fn reload(&self) -> Result<(), StorageError> {
+        let modified = fs::metadata(&self.path).await?.modified()?;
+        if self.users.read().await.0 == Some(modified) {
+            return Ok(());
+        }
+        let mut users = self.users.write().await;
+        if users.0 == Some(modified) {
+            return Ok(());
+        }
+        *users = (Some(modified), parse_htpasswd(&fs::read_to_string(&self.path).await?));
+        Ok(())
+    }
+}
+
+fn parse_htpasswd(content: &str) -> HashMap<String, PasswordHash> {
+    let mut users = HashMap::new();
+    for line in content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let Some((user, hash)) = line.split_once(':') else {
+            tracing::warn!("Skipping an htpasswd line without a user");
+            continue;
+        };
+        let parsed = if let Some(digest) = hash.strip_prefix("{SHA}") {
+            Some(PasswordHash::Sha1(digest.to_string()))
+        } else {
+            ["$apr1$", "$1$"].into_iter().find_map(|magic| {
+                let (salt, hash) = hash.strip_prefix(magic)?.split_once('$')?;
+                Some(PasswordHash::Md5Crypt {
+                    magic,
+                    salt: salt.to_string(),
+                    hash: hash.to_string(),
+                })
+            })
+        };
+        match parsed {
+            Some(parsed) => {
+                users.insert(user.to_string(), parsed);
+            }
+            None => tracing::warn!(
+                "Skipping htpasswd user {}, whose password is hashed in a way other than htpasswd -m or -s",
+                user
+            ),
+        }
+    }
+    users
+}
+
+impl PasswordHash {
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            Self::Md5Crypt { magic, salt, hash } => secrets_match(hash, &md5_crypt(password, salt, magic)),
+            Self::Sha1(expected) => secrets_match(expected, &STANDARD.encode(digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes()))),
+        }
+    }
+}
+
+/// The hash part of Poul-Henning Kamp's MD5-based crypt, which Apache's
+/// `$apr1$` only gives a different `magic` prefix
+fn md5_crypt(password: &str, salt: &str, magic: &str) -> String {
+    const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let (password, salt) = (password.as_bytes(), &salt.as_bytes()[..salt.len().min(8)]);
+
+    let alternate = Md5::new().chain_update(password).chain_update(salt).chain_update(password).finalize();
+    let mut digest = Md5::new().chain_update(password).chain_update(magic).chain_update(salt);
+    for chunk in password.chunks(16) {
+        digest.update(&alternate[..chunk.len()]);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        digest.update(if length & 1 == 1 { &[0u8][..] } else { &password[..1] });
+        length >>= 1;
+    }
+    let mut hash = digest.finalize();
+
+    // Deliberately slow to guess at
+    for round in 0..1000 {
+        let mut digest = Md5::new();
+        if round & 1 == 1 {
+            digest.update(password);
+        } else {
+            digest.update(hash);
+        }
+        if round % 3 != 0 {
+            digest.update(salt);
+        }
+        if round % 7 != 0 {
+            digest.update(password);
+        }
+        if round & 1 == 1 {
+            digest.update(hash);
+        } else {
+            digest.update(password);
+        }
+        hash = digest.finalize();
+    }
+
+    let mut encoded = String::new();
+    let mut push = |value: u32, chars: usize| {
+        for i in 0..chars {
+            encoded.push(ITOA64[(value >> (6 * i)) as usize & 0x3f] as char);
+        }
+    };
+    for [a, b, c] in [[0, 6, 12], [1, 7, 13], [2, 8, 14], [3, 9, 15], [4, 10, 5]] {
+        push(u32::from(hash[a]) << 16 | u32::from(hash[b]) << 8 | u32::from(hash[c]), 4);
+    }
+    push(u32::from(hash[11]), 2);
+    encoded
+}
+
+#[async_trait]
+impl AuthProvider for Htpasswd {
+    async fn authenticate(&self, headers: &HeaderMap, _remote: Option<SocketAddr>) -> Result<Identity, AuthError> {
+        let invalid = || AuthError::challenge("Invalid username or password", BASIC_CHALLENGE);
+        let encoded = credentials(headers, "basic")
+            .ok_or_else(|| AuthError::challenge("A username and password are required", BASIC_CHALLENGE))?;
+        let decoded = STANDARD.decode(encoded).ok().and_then(|decoded| String::from_utf8(decoded).ok());
+        let (user, password) = decoded.as_deref().and_then(|decoded| decoded.split_once(':')).ok_or_else(invalid)?;
+
+        // Carry on with the users last read, rather than locking everyone out
+        // over a file that's briefly missing while it's replaced
+        if let Err(e) = self.reload().await
+            && e.downcast_ref::<std::io::Error>().is_none_or(|e| e.kind() != ErrorKind::NotFound)
+        {
+            tracing::warn!("Failed to read {}: {}", self.path, e);
+        }
+        let users = self.users.read().await;
+        match users.1.get(user) {
+            Some(hash) if hash.verify(password) => Ok(Identity::full(user.to_string())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The user named in a header by a reverse proxy that authenticated them
+/// itself, such as Authentik's outpost or Caddy's forward_auth. Requests
+/// over a Unix socket come from a proxy only filesystem permissions let in;
+/// those over TCP must come from one of the trusted addresses. The address
+/// of a client isn't known when the server serves HTTPS itself, so no proxy
+/// in front of it can be trusted.
+struct ProxyHeader {
+    header: String,
+    trusted: Vec<(IpAddr, u8)>,
+    over_unix_socket: bool,
+}
+
+impl ProxyHeader {
+    fn new(header: &str, trusted_proxies: &[String], over_unix_socket: bool) -> Result<Self, StorageError> {
+        let trusted = trusted_proxies
+            .iter()
+            .map(|proxy| parse_network(proxy).ok_or_else(|| format!("Invalid trusted proxy {}, expected an address or CIDR range", proxy)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            header: header.to_ascii_lowercase(),
+            trusted,
+            over_unix_socket,
+        })
+    }
+
+    fn trusts(&self, remote: Option<SocketAddr>) -> bool {
+        match remote {
+            Some(remote) => self.trusted.iter().any(|&(network, prefix)| in_network(remote.ip(), network, prefix)),
+            None => self.over_unix_socket,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for ProxyHeader {
+    async fn authenticate(&self, headers: &HeaderMap, remote: Option<SocketAddr>) -> Result<Identity, AuthError> {
+        if !self.trusts(remote) {
+            return Err(AuthError::new("Requests must come through the authenticating proxy"));
+        }
+        headers
+            .get(&self.header)
+            .and_then(|user| user.to_str().ok())
+            .map(str::trim)
+            .filter(|user| !user.is_empty())
+            .map(|user| Identity::full(user.to_string()))
+            .ok_or_else(|| AuthError::new(format!("The proxy didn't name a user in {}", self.header)))
+    }
+}
+
+/// An address, as a network of just itself, or a CIDR range such as `172.16.0.0/12`
+fn parse_network(network: &str) -> Option<(IpAddr, u8)> {
+    let (address, prefix) = match network.split_once('/') {
+        Some((address, prefix)) => (address.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+        None => (network.parse::<IpAddr>().ok()?, None),
+    };
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(bits);
+    (prefix <= bits).then_some((address, prefix))
+}
+
+fn in_network(address: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    // Peers on a dual-stack socket show up as IPv4-mapped IPv6 addresses
+    let address = match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        IpAddr::V4(_) => address,
+    };
+    let (address, network, bits) = match (address, network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => (u32::from(address).into(), u32::from(network).into(), 32),
+        (IpAddr::V6(address), IpAddr::V6(network)) => (u128::from(address), u128::from(network), 128),
+        _ => return false,
+    };
+    let mask = u128::MAX.checked_shl(u32::from(bits - prefix)).unwrap_or(0) & (u128::MAX >> (128 - bits));
+    address & mask == network & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::handle_rejection;
+    use crate::testing::temp_path;
+    use crate::types::{CreateTokenRequest, Credentials};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Lets in `alice` with a full token or an import-only one, counting
+    /// how often it is asked
+    struct Counting(AtomicUsize);
+
+    #[async_trait]
+    impl AuthProvider for Counting {
+        async fn authenticate(&self, headers: &HeaderMap, _remote: Option<SocketAddr>) -> Result<Identity, AuthError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match credentials(headers, "Bearer") {
+                Some("alice-token") => Ok(Identity::full("alice".to_string())),
+                Some("fetcher-token") => Ok(Identity {
+                    user: "alice".to_string(),
+                    scope: Scope::Import,
+                }),
+                _ => Err(AuthError::new("Unknown token")),
+            }
+        }
+    }
+
+    fn access(provider: Arc<Counting>) -> Access {
+        Access {
+            provider: Some(provider),
+            admins: Arc::new(Vec::new()),
+        }
+    }
+
+    #[tokio::test]
+    async fn asks_the_provider_once_per_request() {
+        let provider = Arc::new(Counting(AtomicUsize::new(0)));
+        let access = access(provider.clone());
+        let route = access
+            .authenticated()
+            .and(access.scoped(Scope::Full))
+            .and(access.owner())
+            .and(access.user());
+
+        let (owner, user) = warp::test::request()
+            .header("authorization", "Bearer alice-token")
+            .extension(RequestAuth::new(None))
+            .filter(&route)
+            .await
+            .unwrap();
+        assert_eq!(owner.as_deref(), Some("alice"));
+        assert_eq!(user.as_deref(), Some("alice"));
+        assert_eq!(provider.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_what_the_provider_refuses_every_time() {
+        let provider = Arc::new(Counting(AtomicUsize::new(0)));
+        let access = access(provider.clone());
+        for _ in 0..2 {
+            let result = warp::test::request()
+                .header("authorization", "Bearer mallory-token")
+                .filter(&access.authenticated())
+                .await;
+            assert!(result.is_err());
+        }
+        assert_eq!(provider.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn lets_import_only_credentials_import_but_not_read() {
+        let access = access(Arc::new(Counting(AtomicUsize::new(0))));
+        let import = access.authenticated().and(access.scoped(Scope::Import));
+        let read = access.authenticated();
+
+        let imported = warp::test::request()
+            .method("POST")
+            .header("authorization", "Bearer fetcher-token")
+            .filter(&import)
+            .await;
+        assert!(imported.is_ok());
+        let read_with_fetcher = warp::test::request()
+            .header("authorization", "Bearer fetcher-token")
+            .filter(&read)
+            .await;
+        assert!(read_with_fetcher.is_err());
+        let read_with_full = warp::test::request()
+            .header("authorization", "Bearer alice-token")
+            .filter(&read)
+            .await;
+        assert!(read_with_full.is_ok());
+    }
+
+    #[tokio::test]
+    async fn refuses_sessions_and_tokens_as_soon_as_they_end() {
+        let file = temp_path("users.json");
+        let config: Config = serde_json::from_value(json!({ "auth": { "provider": "users", "file": file } })).unwrap();
+        let users = Users::from_config(&config).await.unwrap().unwrap();
+        let access = Access::from_config(&config, Some(users.clone())).await.unwrap();
+        let credentials = || Credentials {
+            username: "alice".to_string(),
+            password: "correct horse".to_string(),
+        };
+        let user = users.register(credentials(), false).await.unwrap();
+        let (session, _, _) = users.login(credentials()).await.unwrap();
+        let request = CreateTokenRequest {
+            name: "fetcher".to_string(),
+            scope: Scope::Full,
+        };
+        let created = users.create_token(&user, request).await.unwrap();
+
+        let status = |token: &str| {
+            let request = warp::test::request()
+                .header("authorization", format!("Bearer {}", token))
+                .extension(RequestAuth::new(None));
+            let route = access.authenticated();
+            async move {
+                match request.filter(&route).await {
+                    Ok(()) => StatusCode::OK,
+                    Err(rejection) => handle_rejection(rejection).await.unwrap().status(),
+                }
+            }
+        };
+        assert_eq!(status(&session).await, StatusCode::OK);
+        users.logout(&session).await.unwrap();
+        assert_eq!(status(&session).await, StatusCode::UNAUTHORIZED);
+
+        assert_eq!(status(&created.token).await, StatusCode::OK);
+        users.revoke_token(&user, &created.api_token.id).await.unwrap();
+        assert_eq!(status(&created.token).await, StatusCode::UNAUTHORIZED);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn scopes_allow_only_what_they_are_named_for() {
+        assert!(Scope::Full.allows(Scope::Read) && Scope::Full.allows(Scope::Import));
+        assert!(Scope::Read.allows(Scope::Read) && !Scope::Read.allows(Scope::Import));
+        assert!(Scope::Import.allows(Scope::Import) && !Scope::Import.allows(Scope::Read));
+        assert!(!Scope::Read.allows(Scope::Full) && !Scope::Import.allows(Scope::Full));
+    }
+
+    #[test]
+    fn hashes_passwords_as_openssl_passwd_does() {
+        assert_eq!(md5_crypt("password", "saltsalt", "$1$"), "qjXMvbEw8oaL.CzflDtaK/");
+        assert_eq!(md5_crypt("correct horse", "4AbC/xyz", "$apr1$"), "TGx9nLOWVUSXQqLSSvm0W.");
+    }
+
+    #[test]
+    fn checks_passwords_against_each_kind_of_htpasswd_line() {
+        let users = parse_htpasswd(
+            "# written by htpasswd\n\
+             alice:$apr1$4AbC/xyz$TGx9nLOWVUSXQqLSSvm0W.\n\
+             bob:{SHA}87u9ZqY9S/F0eUBXjsPQEDUw4h0=\n\
+             carol:$1$saltsalt$qjXMvbEw8oaL.CzflDtaK/\n\
+             dave:$2y$05$abcdefghijklmnopqrstuv\n",
+        );
+        assert!(users["alice"].verify("correct horse") && !users["alice"].verify("correct horse "));
+        assert!(users["bob"].verify("hunter2") && !users["bob"].verify("hunter3"));
+        assert!(users["carol"].verify("password") && !users["carol"].verify("Password"));
+        // bcrypt isn't supported, so dave can't sign in rather than anyone can
+        assert!(!users.contains_key("dave"));
+    }
+}
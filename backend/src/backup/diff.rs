@@ -0,0 +1,66 @@
+use crate::storage::Snapshot;
+use crate::types::{HistoricalTransaction, Metadata, ModifiedTransaction};
+use std::collections::HashMap;
+
+/// What changed from snapshot `a` to snapshot `b`: the current transactions
+/// only `b` has, those only `a` has, and those both have that differ in id,
+/// memo or metadata. Transactions are matched by uuid, so an edit is one
+/// modification rather than a removal and an addition.
+pub fn diff(
+    a: &Snapshot,
+    b: &Snapshot,
+) -> (Vec<HistoricalTransaction>, Vec<HistoricalTransaction>, Vec<ModifiedTransaction>) {
+    let mut before = current_by_key(a);
+    let mut added = Vec::new();
+    let mut modified = Vec::new();
+    for (key, after) in current_by_key(b) {
+        match before.remove(&key) {
+            None => added.push(after),
+            Some(before) if before.id != after.id || before.memo != after.memo || before.metadata != after.metadata => {
+                modified.push(ModifiedTransaction { before, after })
+            }
+            Some(_) => {}
+        }
+    }
+    let mut removed: Vec<_> = before.into_values().collect();
+
+    let order = |t: &HistoricalTransaction| (t.account_id.clone(), t.id.timestamp, t.uuid.clone());
+    added.sort_by_key(order);
+    removed.sort_by_key(order);
+    modified.sort_by_key(|m| order(&m.after));
+    (added, removed, modified)
+}
+
+/// Every current transaction with its memo and metadata, by uuid, or by
+/// account and id for one persisted before uuids were given
+fn current_by_key(snapshot: &Snapshot) -> HashMap<String, HistoricalTransaction> {
+    let mut transactions = HashMap::new();
+    for (account_id, current) in &snapshot.current {
+        let history: HashMap<_, _> = snapshot
+            .all
+            .get(account_id)
+            .into_iter()
+            .flatten()
+            .map(|t| (&t.id, t))
+            .collect();
+        for (id, transaction) in current {
+            let transaction = match history.get(id) {
+                Some(&historical) => historical.clone(),
+                None => HistoricalTransaction {
+                    account_id: account_id.clone(),
+                    uuid: transaction.uuid.clone(),
+                    id: id.clone(),
+                    memo: None,
+                    metadata: Metadata::new(),
+                },
+            };
+            let key = if transaction.uuid.is_empty() {
+                format!("{}/{:?}", account_id, id)
+            } else {
+                transaction.uuid.clone()
+            };
+            transactions.insert(key, transaction);
+        }
+    }
+    transactions
+}
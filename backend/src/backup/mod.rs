@@ -0,0 +1,273 @@
+mod diff;
+mod remote;
+
+use crate::config::BackupConfig;
+use crate::error::ApiError;
+use crate::storage::{Cipher, Snapshot, StorageError, cipher};
+use crate::store::TransactionStore;
+use crate::types::{BackupResponse, RemoteBackupStatus, RestoreResponse, SnapshotDiffResponse};
+use chrono::{Datelike, NaiveDateTime, Utc};
+use remote::Remote;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::Mutex;
+use warp::http::StatusCode;
+
+// Millisecond precision keeps a safety backup taken by a restore from
+// overwriting a backup taken moments before
+const FILE_NAME_FORMAT: &str = "wdmmg-%Y%m%dT%H%M%S%.3fZ.json";
+
+/// What `diff` takes to name the store as it is now rather than a backup
+pub const CURRENT: &str = "current";
+
+/// Point-in-time copies of the whole store, written as a single JSON
+/// snapshot whichever storage backend is in use, pruned to keep the newest
+/// backup of each recent day and week, and optionally copied to a remote
+#[derive(Clone)]
+pub struct Backups {
+    dir: PathBuf,
+    keep_daily: usize,
+    keep_weekly: usize,
+    cipher: Option<Arc<Cipher>>,
+    store: TransactionStore,
+    remote: Option<Remote>,
+    // Held while a backup is written and pruned so concurrent ones don't race
+    running: Arc<Mutex<()>>,
+}
+
+impl Backups {
+    pub fn new(
+        config: &BackupConfig,
+        cipher: Option<Arc<Cipher>>,
+        store: TransactionStore,
+    ) -> Result<Self, StorageError> {
+        let dir = PathBuf::from(&config.dir);
+        let remote = match &config.remote {
+            Some(remote) => Some(Remote::new(
+                remote,
+                dir.clone(),
+                config.keep_daily,
+                config.keep_weekly,
+                cipher.is_some(),
+            )?),
+            None => None,
+        };
+
+        Ok(Self {
+            dir,
+            keep_daily: config.keep_daily,
+            keep_weekly: config.keep_weekly,
+            cipher,
+            store,
+            remote,
+            running: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Take a backup every `interval`, starting one interval from now
+    pub fn spawn_schedule(&self, interval: Duration) {
+        let backups = self.clone();
+        tokio::spawn(async move {
+            let start = tokio::time::Instant::now() + interval;
+            let mut ticker = tokio::time::interval_at(start, interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = backups.create().await {
+                    tracing::warn!("Failed to create scheduled backup: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Write a backup of the current state, then apply the retention policy
+    pub async fn create(&self) -> Result<BackupResponse, StorageError> {
+        let _running = self.running.lock().await;
+        let file = self.write().await?;
+        let pruned = self.prune().await?;
+        self.spawn_push();
+        Ok(BackupResponse { file, pruned })
+    }
+
+    /// Copy backups to the remote in the background, if one is configured
+    pub fn spawn_push(&self) {
+        let Some(remote) = self.remote.clone() else {
+            return;
+        };
+        tokio::spawn(async move {
+            if let Err(e) = remote.push().await {
+                tracing::warn!("Failed to push backups to the remote: {}", e);
+            }
+        });
+    }
+
+    /// How pushing to the remote is going, or `None` without a remote
+    pub async fn remote_status(&self) -> Option<Result<RemoteBackupStatus, StorageError>> {
+        match &self.remote {
+            Some(remote) => Some(remote.status().await),
+            None => None,
+        }
+    }
+
+    /// Replace the whole store with the backup named `name`, first backing up
+    /// the state being replaced
+    pub async fn restore(&self, name: &str) -> Result<RestoreResponse, ApiError> {
+        let _running = self.running.lock().await;
+        let snapshot = self.open(name).await?;
+
+        let internal = |action: &str, e: StorageError| ApiError {
+            message: format!("Failed to {}: {}", action, e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let safety_backup = self
+            .write()
+            .await
+            .map_err(|e| internal("back up the current state", e))?;
+        self.store.replace_with(|_| Ok((snapshot, ()))).await?;
+
+        Ok(RestoreResponse {
+            restored: name.to_string(),
+            safety_backup,
+        })
+    }
+
+    /// The transactions added, removed and modified from backup `a` to backup
+    /// `b`, either of which can be `CURRENT` for the store as it is now
+    pub async fn diff(&self, a: &str, b: &str) -> Result<SnapshotDiffResponse, ApiError> {
+        let (before, after) = (self.open_or_current(a).await?, self.open_or_current(b).await?);
+        let (added, removed, modified) = diff::diff(&before, &after);
+        Ok(SnapshotDiffResponse {
+            a: a.to_string(),
+            b: b.to_string(),
+            added,
+            removed,
+            modified,
+        })
+    }
+
+    async fn open_or_current(&self, name: &str) -> Result<Snapshot, ApiError> {
+        match name {
+            CURRENT => Ok(self.store.snapshot()),
+            name => self.open(name).await,
+        }
+    }
+
+    /// Read and check the backup named `name`
+    async fn open(&self, name: &str) -> Result<Snapshot, ApiError> {
+        // Only names we generate are accepted, which also keeps paths inside the directory
+        if taken_at(name).is_none() {
+            return Err(ApiError {
+                message: format!("'{}' is not a backup file name", name),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+        let path = self.dir.join(name);
+        if !path.exists() {
+            return Err(ApiError {
+                message: format!("Backup {} not found", name),
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+
+        read_snapshot(self.cipher.as_deref(), &path).await.map_err(|e| ApiError {
+            message: format!("Backup is not valid: {}", e),
+            status: StatusCode::BAD_REQUEST,
+        })
+    }
+
+    /// Write a backup of the current state, returning its file name
+    async fn write(&self) -> Result<String, StorageError> {
+        let file = Utc::now().format(FILE_NAME_FORMAT).to_string();
+        let content = cipher::seal(self.cipher.as_deref(), self.store.snapshot_json()?)?;
+
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(&file);
+        let tmp_path = self.dir.join(format!("{}.tmp", file));
+        fs::write(&tmp_path, content).await?;
+        fs::rename(&tmp_path, &path).await?;
+        Ok(file)
+    }
+
+    /// When the newest backup was taken, if there is one
+    pub async fn latest(&self) -> Result<Option<NaiveDateTime>, StorageError> {
+        if !self.dir.exists() {
+            return Ok(None);
+        }
+        let mut latest = None;
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let taken_at = entry.file_name().to_str().and_then(taken_at);
+            latest = latest.max(taken_at);
+        }
+        Ok(latest)
+    }
+
+    /// Delete every backup the retention policy doesn't keep, returning the
+    /// names of those removed
+    async fn prune(&self) -> Result<Vec<String>, StorageError> {
+        let mut names = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Ok(name) = entry.file_name().into_string() {
+                names.push(name);
+            }
+        }
+
+        let pruned = expired(names, self.keep_daily, self.keep_weekly);
+        for name in &pruned {
+            fs::remove_file(self.dir.join(name)).await?;
+        }
+        Ok(pruned)
+    }
+}
+
+/// When the backup named `name` was taken, if it is a backup's name at all
+fn taken_at(name: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(name, FILE_NAME_FORMAT).ok()
+}
+
+/// The backups among `names` that aren't the newest of one of the last
+/// `keep_daily` days or `keep_weekly` weeks. Names that don't look like
+/// backups are left out.
+fn expired(names: Vec<String>, keep_daily: usize, keep_weekly: usize) -> Vec<String> {
+    let mut backups: Vec<_> = names
+        .into_iter()
+        .filter_map(|name| Some((taken_at(&name)?, name)))
+        .collect();
+    backups.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    backups
+        .into_iter()
+        .filter(|(taken_at, _)| {
+            let daily = days.len() < keep_daily && days.insert(taken_at.date());
+            let weekly = weeks.len() < keep_weekly && weeks.insert(taken_at.iso_week());
+            !daily && !weekly
+        })
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// Read and check a backup. Every transaction must be filed under its own account.
+async fn read_snapshot(cipher: Option<&Cipher>, path: &Path) -> Result<Snapshot, StorageError> {
+    let content = cipher::open(cipher, fs::read(path).await?)?;
+    let snapshot: Snapshot = serde_json::from_slice(&content)?;
+
+    let misfiled = snapshot.current.iter().find_map(|(account_id, transactions)| {
+        transactions.values().find(|t| t.account_id != *account_id)
+    });
+    if let Some(transaction) = misfiled {
+        return Err(format!("Transaction for {} is filed under another account", transaction.account_id).into());
+    }
+    let misfiled = snapshot.all.iter().find_map(|(account_id, transactions)| {
+        transactions.iter().find(|t| t.account_id != *account_id)
+    });
+    if let Some(transaction) = misfiled {
+        return Err(format!("Transaction for {} is filed under another account", transaction.account_id).into());
+    }
+
+    Ok(snapshot)
+}
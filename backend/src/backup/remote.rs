@@ -0,0 +1,321 @@
+use crate::config::{RemoteBackupConfig, RemoteTarget};
+use crate::storage::StorageError;
+use crate::types::RemoteBackupStatus;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::fs;
+
+/// Names of the backups on the remote, kept next to the local backups so
+/// retention never depends on listing the remote
+const PUSHED_FILE: &str = "remote.json";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Copies local backups to a WebDAV collection or an S3 bucket and applies
+/// its own retention policy there
+#[derive(Clone)]
+pub struct Remote {
+    target: Target,
+    client: Client,
+    dir: PathBuf,
+    keep_daily: usize,
+    keep_weekly: usize,
+    status: Arc<Mutex<Status>>,
+    // Held while pushing so a slow push and the next backup's don't overlap
+    pushing: Arc<tokio::sync::Mutex<()>>,
+}
+
+/// `RemoteTarget` with its credentials read from the environment
+#[derive(Clone)]
+enum Target {
+    Webdav {
+        url: String,
+        credentials: Option<(String, Option<String>)>,
+    },
+    S3 {
+        endpoint: String,
+        bucket: String,
+        region: String,
+        prefix: String,
+        access_key_id: String,
+        secret_access_key: String,
+    },
+}
+
+#[derive(Default)]
+struct Status {
+    last_attempt_at: Option<DateTime<Utc>>,
+    last_success_at: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+impl Remote {
+    /// Backups are only pushed encrypted, so `encrypted` must be true
+    pub fn new(
+        config: &RemoteBackupConfig,
+        dir: PathBuf,
+        keep_daily: usize,
+        keep_weekly: usize,
+        encrypted: bool,
+    ) -> Result<Self, StorageError> {
+        if !encrypted {
+            return Err(
+                "Remote backups are only pushed encrypted; set storage.encryption_passphrase_env".into(),
+            );
+        }
+
+        let target = match &config.target {
+            RemoteTarget::Webdav {
+                url,
+                username,
+                password_env,
+            } => Target::Webdav {
+                url: url.clone(),
+                credentials: match username {
+                    Some(username) => Some((username.clone(), password_env.as_deref().map(env).transpose()?)),
+                    None => None,
+                },
+            },
+            RemoteTarget::S3 {
+                endpoint,
+                bucket,
+                region,
+                prefix,
+                access_key_id_env,
+                secret_access_key_env,
+            } => Target::S3 {
+                endpoint: endpoint.trim_end_matches('/').to_string(),
+                bucket: bucket.clone(),
+                region: region.clone(),
+                prefix: prefix.clone(),
+                access_key_id: env(access_key_id_env)?,
+                secret_access_key: env(secret_access_key_env)?,
+            },
+        };
+
+        Ok(Self {
+            target,
+            client: Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            dir,
+            keep_daily: config.keep_daily.unwrap_or(keep_daily),
+            keep_weekly: config.keep_weekly.unwrap_or(keep_weekly),
+            status: Arc::new(Mutex::new(Status::default())),
+            pushing: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    /// Upload every local backup the remote doesn't have yet, oldest first, then
+    /// delete remote backups the retention policy no longer keeps. Backups that
+    /// failed to upload are picked up again by the next push.
+    pub async fn push(&self) -> Result<(), StorageError> {
+        let _pushing = self.pushing.lock().await;
+        self.status.lock().unwrap().last_attempt_at = Some(Utc::now());
+
+        let result = self.sync().await;
+        let mut status = self.status.lock().unwrap();
+        match &result {
+            Ok(()) => {
+                status.last_success_at = status.last_attempt_at;
+                status.last_error = None;
+            }
+            Err(e) => status.last_error = Some(e.to_string()),
+        }
+        result
+    }
+
+    pub async fn status(&self) -> Result<RemoteBackupStatus, StorageError> {
+        let backups = self.read_pushed().await?;
+        let status = self.status.lock().unwrap();
+        Ok(RemoteBackupStatus {
+            target: self.describe(),
+            last_attempt_at: status.last_attempt_at,
+            last_success_at: status.last_success_at,
+            last_error: status.last_error.clone(),
+            backups,
+        })
+    }
+
+    async fn sync(&self) -> Result<(), StorageError> {
+        // Nothing to push before the first backup is taken
+        if !self.dir.exists() {
+            return Ok(());
+        }
+        let mut pushed = self.read_pushed().await?;
+
+        let mut unpushed = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if let Some(taken_at) = super::taken_at(&name)
+                && !pushed.contains(&name)
+            {
+                unpushed.push((taken_at, name));
+            }
+        }
+        unpushed.sort_unstable();
+
+        for (_, name) in unpushed {
+            let content = fs::read(self.dir.join(&name)).await?;
+            let response = self.request(Method::PUT, &name, content)?.send().await?;
+            check(response, &name)?;
+            pushed.push(name);
+            self.write_pushed(&pushed).await?;
+        }
+
+        for name in super::expired(pushed.clone(), self.keep_daily, self.keep_weekly) {
+            let response = self.request(Method::DELETE, &name, Vec::new())?.send().await?;
+            // Already gone is as good as deleted
+            if response.status() != StatusCode::NOT_FOUND {
+                check(response, &name)?;
+            }
+            pushed.retain(|pushed| *pushed != name);
+            self.write_pushed(&pushed).await?;
+        }
+        Ok(())
+    }
+
+    fn request(&self, method: Method, name: &str, body: Vec<u8>) -> Result<RequestBuilder, StorageError> {
+        Ok(match &self.target {
+            Target::Webdav { url, credentials } => {
+                let url = format!("{}/{}", url.trim_end_matches('/'), name);
+                let request = self.client.request(method, url).body(body);
+                match credentials {
+                    Some((username, password)) => request.basic_auth(username, password.as_ref()),
+                    None => request,
+                }
+            }
+            Target::S3 {
+                endpoint,
+                bucket,
+                region,
+                prefix,
+                access_key_id,
+                secret_access_key,
+            } => {
+                let url = Url::parse(&format!(
+                    "{}/{}/{}",
+                    endpoint,
+                    bucket,
+                    uri_encode(&format!("{}{}", prefix, name))
+                ))?;
+                let host = match (url.host_str(), url.port()) {
+                    (Some(host), Some(port)) => format!("{}:{}", host, port),
+                    (Some(host), None) => host.to_string(),
+                    (None, _) => return Err(format!("{} has no host", endpoint).into()),
+                };
+
+                // AWS Signature Version 4, signing the headers we send and the payload
+                let now = Utc::now();
+                let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+                let scope = format!("{}/{}/s3/aws4_request", now.format("%Y%m%d"), region);
+                let payload_hash = hex(&Sha256::digest(&body));
+                let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+                let canonical_request = format!(
+                    "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+                    method,
+                    url.path(),
+                    host,
+                    payload_hash,
+                    amz_date,
+                    signed_headers,
+                    payload_hash
+                );
+                let string_to_sign = format!(
+                    "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                    amz_date,
+                    scope,
+                    hex(&Sha256::digest(canonical_request.as_bytes()))
+                );
+                let mut key = format!("AWS4{}", secret_access_key).into_bytes();
+                for part in [now.format("%Y%m%d").to_string().as_str(), region, "s3", "aws4_request"] {
+                    key = hmac(&key, part.as_bytes());
+                }
+                let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+                self.client
+                    .request(method, url)
+                    .header("x-amz-date", amz_date)
+                    .header("x-amz-content-sha256", payload_hash)
+                    .header(
+                        reqwest::header::AUTHORIZATION,
+                        format!(
+                            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                            access_key_id, scope, signed_headers, signature
+                        ),
+                    )
+                    .body(body)
+            }
+        })
+    }
+
+    /// Where backups go, without credentials
+    fn describe(&self) -> String {
+        match &self.target {
+            Target::Webdav { url, .. } => format!("webdav {}", url),
+            Target::S3 {
+                endpoint,
+                bucket,
+                prefix,
+                ..
+            } => format!("s3 {}/{}/{}", endpoint, bucket, prefix),
+        }
+    }
+
+    async fn read_pushed(&self) -> Result<Vec<String>, StorageError> {
+        let path = self.dir.join(PUSHED_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(serde_json::from_slice(&fs::read(path).await?)?)
+    }
+
+    async fn write_pushed(&self, pushed: &[String]) -> Result<(), StorageError> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(PUSHED_FILE);
+        let tmp_path = self.dir.join(format!("{}.tmp", PUSHED_FILE));
+        fs::write(&tmp_path, serde_json::to_vec_pretty(pushed)?).await?;
+        fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+}
+
+fn env(var: &str) -> Result<String, StorageError> {
+    Ok(std::env::var(var).map_err(|_| format!("Environment variable {} is not set", var))?)
+}
+
+fn check(response: Response, name: &str) -> Result<(), StorageError> {
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("Remote answered {} for {}", response.status(), name).into())
+    }
+}
+
+/// Percent-encode an S3 object key as SigV4 expects, keeping `/` as the separator
+fn uri_encode(key: &str) -> String {
+    key.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
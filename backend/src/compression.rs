@@ -0,0 +1,205 @@
+use crate::config::ResponseCompressionConfig;
+use std::convert::Infallible;
+use std::io::Write;
+use warp::http::header::{ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HeaderMap, HeaderValue, VARY};
+use warp::http::StatusCode;
+use warp::hyper::Body;
+use warp::reply::{Reply, Response};
+
+/// Content types worth compressing; images, PDFs and archives already are
+fn compressible(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence,
+            "application/json" | "application/x-ndjson" | "application/javascript" | "application/xml"
+        )
+}
+
+/// The content codings responses can be compressed with, in the order they
+/// are preferred when a client accepts several as much
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    const ALL: [Encoding; 3] = [Encoding::Zstd, Encoding::Brotli, Encoding::Gzip];
+
+    /// Its name in `Accept-Encoding` and `Content-Encoding`
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(self, bytes: &[u8], config: &ResponseCompressionConfig) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Zstd => zstd::bulk::compress(bytes, config.level),
+            Encoding::Brotli => {
+                // 22 is the window size brotli uses by default
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, config.brotli_quality, 22);
+                writer.write_all(bytes)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(config.gzip_level));
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// The coding an `Accept-Encoding` header gives the highest q-value of
+/// those responses can be compressed with, naming it or through `*`. Ties
+/// go to the coding preferred; a q-value of 0 refuses a coding.
+fn negotiate(headers: &HeaderMap) -> Option<Encoding> {
+    let mut named = [None; Encoding::ALL.len()];
+    let mut wildcard = None;
+    for coding in headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        // A q-value that doesn't parse leaves the coding out rather than guessing
+        let Some(q) = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+        else {
+            continue;
+        };
+        if name == "*" {
+            wildcard = Some(q);
+        } else if let Some(index) = Encoding::ALL.iter().position(|encoding| name.eq_ignore_ascii_case(encoding.name())) {
+            named[index] = Some(q);
+        }
+    }
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for (encoding, q) in Encoding::ALL.into_iter().zip(named) {
+        let q = q.or(wildcard).unwrap_or(0.0);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Compress `reply` with the coding the request's `headers` prefer, when
+/// they accept one and it is text of at least `min_bytes`
+pub async fn compress(
+    reply: impl Reply,
+    headers: HeaderMap,
+    config: &ResponseCompressionConfig,
+) -> Result<Response, Infallible> {
+    let response = reply.into_response();
+    let content_type = response.headers().get(CONTENT_TYPE).and_then(|value| value.to_str().ok());
+    if !config.enabled
+        || response.status() == StatusCode::NO_CONTENT
+        || response.status() == StatusCode::NOT_MODIFIED
+        || response.headers().contains_key(CONTENT_ENCODING)
+        // Ranges are of the bytes as they are, so resumable downloads stay as they are
+        || response.headers().contains_key(ACCEPT_RANGES)
+        || !content_type.is_some_and(compressible)
+    {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    // Caches must keep the encodings apart, even for responses sent as they are
+    parts.headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    let bytes = match warp::hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!("Failed to read a response to compress: {}", e);
+            return Ok(Response::from_parts(parts, Body::empty()));
+        }
+    };
+    let encoding = match negotiate(&headers) {
+        Some(encoding) if bytes.len() >= config.min_bytes => encoding,
+        _ => return Ok(Response::from_parts(parts, Body::from(bytes))),
+    };
+
+    let config = config.clone();
+    let compressed = tokio::task::spawn_blocking(move || encoding.compress(&bytes, &config).map_err(|e| (e, bytes)))
+        .await
+        .expect("compression doesn't panic");
+    match compressed {
+        Ok(compressed) => {
+            parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+            parts.headers.remove(CONTENT_LENGTH);
+            Ok(Response::from_parts(parts, Body::from(compressed)))
+        }
+        Err((e, bytes)) => {
+            tracing::warn!("Failed to compress a response: {}", e);
+            Ok(Response::from_parts(parts, Body::from(bytes)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn accepting(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn picks_the_coding_with_the_highest_q_value() {
+        assert_eq!(negotiate(&accepting("gzip, deflate, br, zstd")), Some(Encoding::Zstd));
+        assert_eq!(negotiate(&accepting("gzip, deflate, br")), Some(Encoding::Brotli));
+        assert_eq!(negotiate(&accepting("gzip;q=1.0, br;q=0.8, zstd;q=0.5")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&accepting("zstd;q=0, br;q=0.1")), Some(Encoding::Brotli));
+        assert_eq!(negotiate(&accepting("deflate, identity")), None);
+        assert_eq!(negotiate(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn lets_a_wildcard_stand_for_the_codings_not_named() {
+        assert_eq!(negotiate(&accepting("*")), Some(Encoding::Zstd));
+        assert_eq!(negotiate(&accepting("zstd;q=0, *;q=0.5")), Some(Encoding::Brotli));
+        assert_eq!(negotiate(&accepting("gzip;q=0.9, *;q=0.2")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&accepting("gzip;q=0.1, *;q=0")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(&accepting("*;q=0")), None);
+    }
+
+    #[test]
+    fn leaves_out_codings_with_q_values_that_dont_parse() {
+        assert_eq!(negotiate(&accepting("zstd;q=high, gzip")), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn compresses_what_each_coding_decompresses_again() {
+        let config = ResponseCompressionConfig::default();
+        let text = "{\"payee\":\"Cafe\",\"amount\":-4.5}".repeat(100);
+        for encoding in Encoding::ALL {
+            let compressed = encoding.compress(text.as_bytes(), &config).unwrap();
+            assert!(compressed.len() < text.len());
+            let mut decompressed = String::new();
+            match encoding {
+                Encoding::Zstd => decompressed = String::from_utf8(zstd::decode_all(&compressed[..]).unwrap()).unwrap(),
+                Encoding::Brotli => {
+                    brotli::Decompressor::new(&compressed[..], 4096).read_to_string(&mut decompressed).unwrap();
+                }
+                Encoding::Gzip => {
+                    flate2::read::GzDecoder::new(&compressed[..]).read_to_string(&mut decompressed).unwrap();
+                }
+            }
+            assert_eq!(decompressed, text);
+        }
+    }
+}
@@ -0,0 +1,798 @@
+use crate::import::ImportProfile;
+use crate::types::Scope;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use tokio::fs;
+
+const CONFIG_FILE: &str = "config.json";
+
+/// Server configuration, read from `config.json` in the working directory.
+/// Every field has a default so a missing file or a partial file is fine.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    /// Who may use the API, other than webhooks, which have their own
+    /// secrets; unset lets in everyone who can reach the server
+    pub auth: Option<AuthConfig>,
+    /// Users, as `auth` names them, who see and change everyone's data and
+    /// may back up, restore and compact it, and give accounts nobody owns to
+    /// a user; everyone else only sees the accounts they own and those shared
+    /// with them
+    pub admins: Vec<String>,
+    pub logging: LoggingConfig,
+    pub storage: StorageConfig,
+    /// What to do with amounts that have more decimal places than their currency allows
+    pub amount_precision: AmountPrecision,
+    /// Named CSV layouts that `POST /transactions/bulk/:account_id?profile=` can select
+    pub import_profiles: HashMap<String, ImportProfile>,
+    /// Tidies the payees of imported statement files. Only later imports are
+    /// affected, so a file imported again after changing it isn't recognised
+    /// as a duplicate of the earlier import.
+    pub payee_normalization: PayeeNormalizationConfig,
+    /// How `POST /transactions/parse-text` reads lines such as `lunch 14.20
+    /// at Pho King yesterday`
+    pub text_entry: TextEntryConfig,
+    /// Push sources allowed to post to `/ingest/webhook/:source`, keyed by source name
+    pub webhooks: HashMap<String, WebhookSource>,
+    /// Minimum balance per account and currency; dipping below it logs a
+    /// warning and is reported by `GET /maintenance/check`
+    pub low_balance_thresholds: HashMap<String, HashMap<String, f64>>,
+    /// Last reconciled month per account, as `YYYY-MM`. Transactions dated in
+    /// or before it can only be changed with `override_lock=true`.
+    pub period_locks: HashMap<String, Month>,
+    /// Interest rates of savings and loan accounts, each from the day it takes
+    /// effect, for `GET /accounts/:account_id/projected-interest`
+    pub interest_rates: HashMap<String, Vec<InterestRate>>,
+    /// Refuse edits of existing transactions sent without an `If-Match`
+    /// header, so no client can overwrite a change it hasn't seen
+    pub require_if_match: bool,
+    pub backups: BackupConfig,
+    pub statements: StatementConfig,
+    /// A directory watched for statement files, which are imported as they
+    /// arrive; unset disables it
+    pub inbox: Option<InboxConfig>,
+    /// Remote locations statement files are fetched from and imported,
+    /// keyed by source name
+    pub statement_sources: HashMap<String, StatementSource>,
+    /// How many expensive requests of each kind are served at once
+    pub concurrency: ConcurrencyConfig,
+    pub idempotency: IdempotencyConfig,
+    pub response_compression: ResponseCompressionConfig,
+    /// URLs registered with `POST /subscriptions` to be told of changes
+    pub subscriptions: SubscriptionConfig,
+    pub dashboard: DashboardConfig,
+    /// Hashing the event log to detect changes made to it outside the server
+    pub integrity: IntegrityConfig,
+    /// The built frontend, served for every GET no API route matches; unset
+    /// serves none
+    pub frontend: Option<FrontendConfig>,
+}
+
+/// An annual rate in percent, in effect until the account's next rate takes
+/// over. Negative balances, as on a loan, are charged it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct InterestRate {
+    pub effective_from: NaiveDate,
+    pub annual_percent: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Address and port to listen on; 0.0.0.0 serves every network the machine is on
+    pub address: String,
+    /// Serve HTTPS rather than plain HTTP; unset serves HTTP
+    pub tls: Option<TlsConfig>,
+    /// Listen on a Unix domain socket instead of `address`, for a reverse
+    /// proxy on the same machine to forward to
+    pub unix_socket: Option<UnixSocketConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:3030".to_string(),
+            tls: None,
+            unix_socket: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UnixSocketConfig {
+    pub path: String,
+    /// Permissions of the socket in octal, such as `660` to let the proxy's
+    /// group connect
+    pub mode: String,
+}
+
+impl Default for UnixSocketConfig {
+    fn default() -> Self {
+        Self {
+            path: "wdmmg.sock".to_string(),
+            mode: "660".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// The least severe level logged. At `debug` and `trace` the libraries
+    /// the server is built on are logged too.
+    pub level: LogLevel,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// A line for people to read
+    #[default]
+    Text,
+    /// An object per line, for collectors such as Loki
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum AuthConfig {
+    /// Bearer tokens, keyed by user, each read from the named environment
+    /// variable rather than kept in the config file
+    StaticTokens {
+        token_envs: HashMap<String, String>,
+        /// More tokens, allowing less than everything, such as one for a
+        /// dashboard that only reads or a bank fetcher that only imports,
+        /// which can't read what it imported
+        #[serde(default)]
+        scoped_tokens: Vec<ScopedToken>,
+    },
+    /// The users of an Apache htpasswd file, sent with HTTP Basic auth.
+    /// Passwords hashed with `htpasswd -m` or `-s` are understood, not bcrypt.
+    Htpasswd { file: String },
+    /// Accounts registered with `POST /auth/register`, which sign in with
+    /// `POST /auth/login` for a session token
+    Users {
+        /// File the users and their sessions are kept in
+        #[serde(default = "default_users_file")]
+        file: String,
+        /// Let anyone register. Otherwise only the first user registers
+        /// themselves, and the rest are registered by a signed-in user.
+        #[serde(default)]
+        open_registration: bool,
+        /// How long a session lasts from signing in
+        #[serde(default = "default_session_days")]
+        session_days: u32,
+    },
+    /// Users signed in with an OpenID Connect provider such as Authelia,
+    /// sending the ID token or JWT access token it issued them as a bearer
+    /// token
+    Oidc {
+        /// The provider's issuer URL, which its endpoints and keys are
+        /// discovered from
+        issuer: String,
+        /// The client ID wdmmg is registered with, which tokens must be meant for
+        audience: String,
+        /// The claim naming the user
+        #[serde(default = "default_username_claim")]
+        username_claim: String,
+        /// Users as the provider names them -> as wdmmg does, for those
+        /// whose data was kept under another name before; the rest keep
+        /// the provider's name
+        #[serde(default)]
+        user_map: HashMap<String, String>,
+        /// Only let in users in one of these groups, by the token's `groups`
+        /// claim; empty lets in everyone the provider signs in
+        #[serde(default)]
+        allowed_groups: Vec<String>,
+    },
+    /// The user a reverse proxy names in a header once it has authenticated
+    /// them, as Authentik and Caddy's forward_auth do
+    ProxyHeader {
+        #[serde(default = "default_user_header")]
+        header: String,
+        /// Addresses or CIDR ranges of the proxies, as only requests from
+        /// them can be taken at their word. Requests over a Unix socket are
+        /// trusted, and none are when the server serves HTTPS itself.
+        #[serde(default = "default_trusted_proxies")]
+        trusted_proxies: Vec<String>,
+    },
+}
+
+/// A static token of `user` read from the environment variable `token_env`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScopedToken {
+    pub user: String,
+    pub token_env: String,
+    pub scope: Scope,
+}
+
+fn default_username_claim() -> String {
+    "preferred_username".to_string()
+}
+
+fn default_users_file() -> String {
+    "users.json".to_string()
+}
+
+fn default_session_days() -> u32 {
+    30
+}
+
+fn default_user_header() -> String {
+    "remote-user".to_string()
+}
+
+fn default_trusted_proxies() -> Vec<String> {
+    vec!["127.0.0.1".to_string(), "::1".to_string()]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM file with the certificate, followed by any intermediates
+    pub cert_path: String,
+    /// PEM file with the certificate's private key
+    pub key_path: String,
+    /// Generate a self-signed certificate and key into `cert_path` and
+    /// `key_path` when they don't exist yet, for serving a home network
+    /// without a certificate authority. Browsers warn about it until it is
+    /// trusted.
+    pub self_signed: bool,
+    /// Host names and IP addresses the self-signed certificate is for
+    pub hostnames: Vec<String>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert_path: "tls/cert.pem".to_string(),
+            key_path: "tls/key.pem".to_string(),
+            self_signed: false,
+            hostnames: vec!["localhost".to_string(), "127.0.0.1".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Directory the json backend keeps its per-account files and journal in
+    pub data_dir: String,
+    /// Environment variable holding the passphrase the json backend's files and
+    /// backups are encrypted with; unset leaves them as plain JSON
+    pub encryption_passphrase_env: Option<String>,
+    /// How the json backend compresses its account files
+    pub compression: Compression,
+    /// Database file used by the sqlite backend
+    pub sqlite_path: String,
+    /// Connection string used by the postgres backend. Its `sslmode` is
+    /// `disable`, `prefer` (the default) or `require`.
+    pub postgres_url: String,
+    /// PEM file of the CA certificates the postgres server's certificate is
+    /// checked against; unset trusts the public web roots
+    pub postgres_ca_file: Option<String>,
+    /// Maximum number of pooled connections for the postgres backend
+    pub postgres_pool_size: usize,
+    /// Minimum time between writes to the backend; mutations in between are batched
+    pub flush_interval_ms: u64,
+    /// How often the json backend folds its journal back into the account files
+    pub compaction_interval_secs: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::default(),
+            data_dir: "data".to_string(),
+            encryption_passphrase_env: None,
+            compression: Compression::default(),
+            sqlite_path: "wdmmg.db".to_string(),
+            postgres_url: "postgres://localhost/wdmmg".to_string(),
+            postgres_ca_file: None,
+            postgres_pool_size: 8,
+            flush_interval_ms: 1000,
+            compaction_interval_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    /// Directory backups are written to
+    pub dir: String,
+    /// How often to take a backup automatically; unset disables scheduled backups
+    pub interval_secs: Option<u64>,
+    /// Number of recent days whose newest backup is kept
+    pub keep_daily: usize,
+    /// Number of recent weeks whose newest backup is kept
+    pub keep_weekly: usize,
+    /// Where every backup is copied after it is taken, so backups leave the
+    /// machine; unset keeps them local only
+    pub remote: Option<RemoteBackupConfig>,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: "backups".to_string(),
+            interval_secs: None,
+            keep_daily: 7,
+            keep_weekly: 4,
+            remote: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatementConfig {
+    /// Directory the statement files attached to imports are kept in,
+    /// encrypted like the json backend's files when a passphrase is set
+    pub dir: String,
+}
+
+impl Default for StatementConfig {
+    fn default() -> Self {
+        Self {
+            dir: "statements".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FrontendConfig {
+    /// Directory the frontend's build output is in, `index.html` at its root
+    pub dir: String,
+}
+
+impl Default for FrontendConfig {
+    fn default() -> Self {
+        Self {
+            dir: "frontend".to_string(),
+        }
+    }
+}
+
+/// Turns the payees banks print, such as `KARTENZAHLUNG REWE Markt GmbH
+/// Berlin`, into the merchant's name, so one merchant gets one payee
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PayeeNormalizationConfig {
+    /// Built-in tables to apply: the payment-method prefixes banks writing in
+    /// that language put before the merchant, and the names of common
+    /// merchants in countries speaking it
+    pub locales: Vec<PayeeLocale>,
+    /// Own rules, tried before the built-in merchants. The first rule whose
+    /// pattern matches the payee, once its prefixes are stripped, applies.
+    pub rules: Vec<PayeeRule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TextEntryConfig {
+    /// The account drafts are for when the request names none
+    pub account_id: Option<String>,
+    /// The currency of amounts written without one when the request names none
+    pub currency: Option<String>,
+    /// Words that put a transaction in a category, by category, e.g.
+    /// `"dining": ["lunch", "dinner"]`. The category goes in the draft's
+    /// `category` metadata.
+    pub categories: HashMap<String, Vec<String>>,
+}
+
+impl Default for TextEntryConfig {
+    fn default() -> Self {
+        let categories: &[(&str, &[&str])] = &[
+            ("dining", &["breakfast", "brunch", "coffee", "dinner", "lunch", "restaurant", "takeaway"]),
+            ("groceries", &["groceries", "grocery", "supermarket"]),
+            ("transport", &["bus", "fuel", "gas", "parking", "petrol", "taxi", "train", "uber"]),
+            ("entertainment", &["cinema", "concert", "movie", "movies", "tickets"]),
+            ("health", &["doctor", "gym", "pharmacy"]),
+        ];
+        Self {
+            account_id: None,
+            currency: None,
+            categories: categories
+                .iter()
+                .map(|(category, keywords)| {
+                    (category.to_string(), keywords.iter().map(|keyword| keyword.to_string()).collect())
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayeeLocale {
+    De,
+    Es,
+    Fr,
+    It,
+    Nl,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PayeeRule {
+    /// Payee pattern, ignoring case, where `*` matches any run of characters
+    /// and `?` any one, e.g. `netto marken-discount*`
+    pub pattern: String,
+    /// What matching payees become
+    pub payee: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InboxConfig {
+    /// Directory files are dropped into. Imported files are moved to its
+    /// `processed` folder and files that couldn't be imported to `failed`.
+    pub dir: String,
+    /// How often the directory is checked for new files
+    pub poll_interval_secs: u64,
+    /// Which account and profile a file is imported with, by file name. The
+    /// first rule whose pattern matches is used.
+    pub rules: Vec<InboxRule>,
+}
+
+impl Default for InboxConfig {
+    fn default() -> Self {
+        Self {
+            dir: "inbox".to_string(),
+            poll_interval_secs: 60,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InboxRule {
+    /// File name pattern, ignoring case, where `*` matches any run of
+    /// characters and `?` any one, e.g. `chase-*.csv`
+    pub pattern: String,
+    pub account_id: String,
+    /// Import profile to read matching files with; unset uses the default layout
+    pub profile: Option<String>,
+    /// User the account belongs to, given it when the first file is imported
+    /// into it. Files may then only be imported into that user's accounts.
+    /// Unset leaves a new account to admins.
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatementSource {
+    #[serde(flatten)]
+    pub location: StatementLocation,
+    /// How often the location is checked for new files
+    #[serde(default = "default_fetch_interval")]
+    pub interval_secs: u64,
+    /// Which account and profile each file is imported with, by file name,
+    /// as for the inbox
+    pub rules: Vec<InboxRule>,
+}
+
+fn default_fetch_interval() -> u64 {
+    60 * 60
+}
+
+/// Credentials are read from the named environment variables rather than
+/// kept in the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum StatementLocation {
+    /// A WebDAV collection, e.g. `https://files.example-bank.com/exports/`.
+    /// Only the files directly in it are fetched.
+    Webdav {
+        url: String,
+        username: Option<String>,
+        password_env: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteBackupConfig {
+    #[serde(flatten)]
+    pub target: RemoteTarget,
+    /// Retention on the remote side; unset uses the local `keep_daily`
+    pub keep_daily: Option<usize>,
+    /// Retention on the remote side; unset uses the local `keep_weekly`
+    pub keep_weekly: Option<usize>,
+}
+
+/// Credentials are read from the named environment variables rather than
+/// kept in the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum RemoteTarget {
+    /// A WebDAV collection, e.g. `https://cloud.example.com/remote.php/dav/files/me/wdmmg/`
+    Webdav {
+        url: String,
+        username: Option<String>,
+        password_env: Option<String>,
+    },
+    /// A bucket on an S3-compatible service, addressed path-style
+    S3 {
+        /// e.g. `https://s3.eu-west-1.amazonaws.com`
+        endpoint: String,
+        bucket: String,
+        region: String,
+        /// Prepended to every object key, e.g. `wdmmg/`
+        #[serde(default)]
+        prefix: String,
+        access_key_id_env: String,
+        secret_access_key_env: String,
+    },
+}
+
+/// Slots for the endpoints that read or write everything at once, so a few
+/// heavy requests can't starve quick transaction reads. Requests beyond the
+/// slots wait for one to free up, then are turned away with 429.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConcurrencyConfig {
+    /// Full history, balance checks and integrity checks
+    pub reports: usize,
+    /// Exports, the event log, backups and restores
+    pub exports: usize,
+    /// CSV, webhook and archive imports
+    pub imports: usize,
+    /// How long a request may wait for a slot
+    pub queue_timeout_ms: u64,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            reports: 2,
+            exports: 2,
+            imports: 4,
+            queue_timeout_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IdempotencyConfig {
+    /// How long the response to a request sent with an `Idempotency-Key`
+    /// header is kept to answer retries with
+    pub retention_secs: u64,
+    /// Where the kept responses are saved, so retries are answered across
+    /// restarts
+    pub file: String,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            retention_secs: 24 * 60 * 60,
+            file: "idempotency.json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ResponseCompressionConfig {
+    /// Compress JSON, CSV and other text responses with zstd, brotli or
+    /// gzip, whichever the client's `Accept-Encoding` prefers
+    pub enabled: bool,
+    /// Responses smaller than this are sent as they are
+    pub min_bytes: usize,
+    /// zstd level, from 1 (fastest) to 19 (smallest)
+    pub level: i32,
+    /// brotli quality, from 0 (fastest) to 11 (smallest)
+    pub brotli_quality: u32,
+    /// gzip level, from 1 (fastest) to 9 (smallest)
+    pub gzip_level: u32,
+}
+
+impl Default for ResponseCompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_bytes: 1024,
+            level: 3,
+            brotli_quality: 4,
+            gzip_level: 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IntegrityConfig {
+    /// File the hashes of the event log are recorded in, to check it against
+    pub file: String,
+    /// How often to check the event log, from startup
+    pub interval_secs: u64,
+}
+
+impl Default for IntegrityConfig {
+    fn default() -> Self {
+        Self {
+            file: "integrity.json".to_string(),
+            interval_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DashboardConfig {
+    /// File the dashboard layouts saved with `PUT /dashboard/layout` are kept in, one per user
+    pub file: String,
+}
+
+impl Default for DashboardConfig {
+    fn default() -> Self {
+        Self {
+            file: "dashboard.json".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SubscriptionConfig {
+    /// File the registered URLs, and how far each has been sent the event
+    /// log, are kept in
+    pub file: String,
+    /// How often the event log is checked for changes to send
+    pub poll_interval_ms: u64,
+    /// How long a subscriber may take to answer before the delivery counts
+    /// as failed
+    pub timeout_secs: u64,
+    /// How long after a failed delivery it is first retried; the wait
+    /// doubles with each further failure
+    pub retry_initial_secs: u64,
+    /// Longest wait between retries
+    pub retry_max_secs: u64,
+    /// Delivery attempts kept per subscription for the delivery log
+    pub delivery_log_size: usize,
+    /// Hosts subscriptions may send to although they are at a loopback,
+    /// private or link-local address, such as a receiver on the local network
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        Self {
+            file: "subscriptions.json".to_string(),
+            poll_interval_ms: 2000,
+            timeout_secs: 10,
+            retry_initial_secs: 5,
+            retry_max_secs: 3600,
+            delivery_log_size: 100,
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Json,
+    Memory,
+    Sqlite,
+    Postgres,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Pretty-printed JSON, readable by hand
+    #[default]
+    None,
+    /// Compact JSON compressed with zstd
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmountPrecision {
+    /// Refuse the transaction and report the offending amount
+    #[default]
+    Reject,
+    /// Round to the nearest representable amount, as older versions did
+    Round,
+}
+
+/// A calendar month, written `YYYY-MM`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Month {
+    first_day: NaiveDate,
+}
+
+impl Month {
+    /// The first instant after the month, in UTC
+    pub fn end(&self) -> DateTime<Utc> {
+        let next = if self.first_day.month() == 12 {
+            NaiveDate::from_ymd_opt(self.first_day.year() + 1, 1, 1)
+        } else {
+            NaiveDate::from_ymd_opt(self.first_day.year(), self.first_day.month() + 1, 1)
+        };
+        next.and_then(|day| day.and_hms_opt(0, 0, 0))
+            .map(|midnight| midnight.and_utc())
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+impl std::fmt::Display for Month {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.first_day.format("%Y-%m"))
+    }
+}
+
+impl std::str::FromStr for Month {
+    type Err = String;
+
+    fn from_str(month: &str) -> Result<Self, Self::Err> {
+        NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+            .map(|first_day| Self { first_day })
+            .map_err(|_| format!("invalid month '{}', expected YYYY-MM", month))
+    }
+}
+
+impl Serialize for Month {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Month {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookSource {
+    /// Shared secret the sender must present in the `X-Webhook-Secret` header
+    pub secret: String,
+    /// Account that pushed transactions are recorded against
+    pub account_id: String,
+    /// Name of the payload mapper used to decode this source's requests
+    #[serde(default = "default_mapper")]
+    pub mapper: String,
+    /// User the account belongs to, as for inbox rules
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+fn default_mapper() -> String {
+    "generic".to_string()
+}
+
+impl Config {
+    /// The config in `config.json`, or the defaults when there is no such
+    /// file. A file that can't be read or parsed is an error rather than a
+    /// reason to fall back to the defaults, which require no sign-in.
+    pub async fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let content = match fs::read_to_string(CONFIG_FILE).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("Failed to read {}: {}", CONFIG_FILE, e).into()),
+        };
+        serde_json::from_str(&content).map_err(|e| format!("Invalid {}: {}", CONFIG_FILE, e).into())
+    }
+}
@@ -0,0 +1,96 @@
+/// Digits after the decimal point for ISO 4217 currencies that don't use two
+const MINOR_UNITS: &[(&str, u32)] = &[
+    ("BIF", 0),
+    ("CLP", 0),
+    ("DJF", 0),
+    ("GNF", 0),
+    ("ISK", 0),
+    ("JPY", 0),
+    ("KMF", 0),
+    ("KRW", 0),
+    ("PYG", 0),
+    ("RWF", 0),
+    ("UGX", 0),
+    ("UYI", 0),
+    ("VND", 0),
+    ("VUV", 0),
+    ("XAF", 0),
+    ("XOF", 0),
+    ("XPF", 0),
+    ("BHD", 3),
+    ("IQD", 3),
+    ("JOD", 3),
+    ("KWD", 3),
+    ("LYD", 3),
+    ("OMR", 3),
+    ("TND", 3),
+    ("CLF", 4),
+    ("UYW", 4),
+];
+
+pub fn minor_units(currency: &str) -> u32 {
+    MINOR_UNITS
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(currency))
+        .map_or(2, |(_, units)| *units)
+}
+
+/// Decimal places amounts are stored with, as they are kept in hundredths
+pub const STORED_DECIMALS: u32 = 2;
+
+/// Decimal places an amount in `currency` may carry, which is its minor unit
+pub fn allowed_decimals(currency: &str) -> u32 {
+    minor_units(currency)
+}
+
+/// Decimal places in the shortest representation of `amount`, which is how it was written
+pub fn decimal_places(amount: f64) -> u32 {
+    let text = amount.to_string();
+    text.split_once('.').map_or(0, |(_, fraction)| fraction.len() as u32)
+}
+
+/// Round `cents` to the nearest multiple of `step_cents`, halves to the even
+/// multiple, as banks and spreadsheets round, so repeated rounding doesn't drift
+pub fn round_half_even(cents: i64, step_cents: i64) -> i64 {
+    let quotient = cents.div_euclid(step_cents);
+    let remainder = cents.rem_euclid(step_cents);
+    // Compared with what's left to the next multiple, as twice the remainder could overflow
+    let rounded = match remainder.cmp(&(step_cents - remainder)) {
+        std::cmp::Ordering::Less => quotient,
+        std::cmp::Ordering::Greater => quotient + 1,
+        std::cmp::Ordering::Equal => quotient + quotient.rem_euclid(2),
+    };
+    rounded * step_cents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_halves_to_the_even_multiple() {
+        assert_eq!(round_half_even(150, 100), 200);
+        assert_eq!(round_half_even(250, 100), 200);
+        assert_eq!(round_half_even(-150, 100), -200);
+        assert_eq!(round_half_even(-250, 100), -200);
+        assert_eq!(round_half_even(1_249, 500), 1_000);
+        assert_eq!(round_half_even(1_251, 500), 1_500);
+    }
+
+    #[test]
+    fn rounds_with_steps_too_large_to_double() {
+        let step_cents = i64::MAX / 2 + 1;
+        assert_eq!(round_half_even(12_345, step_cents), 0);
+        assert_eq!(round_half_even(-12_345, step_cents), 0);
+    }
+
+    #[test]
+    fn allows_the_decimals_of_each_currencys_minor_unit() {
+        assert_eq!(allowed_decimals("JPY"), 0);
+        assert_eq!(allowed_decimals("usd"), 2);
+        assert_eq!(allowed_decimals("KWD"), 3);
+        assert_eq!(allowed_decimals("CLF"), 4);
+        assert_eq!(decimal_places(12.5), 1);
+        assert_eq!(decimal_places(100.0), 0);
+    }
+}
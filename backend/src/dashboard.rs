@@ -0,0 +1,141 @@
+use crate::config::DashboardConfig;
+use crate::error::ApiError;
+use crate::storage::StorageError;
+use crate::types::DashboardLayout;
+use crate::utils::etag_matches;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::Mutex;
+use warp::http::StatusCode;
+
+/// Every user's dashboard layout, as kept in the file
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Layouts {
+    /// The layout of requests with no user, as there are without an auth provider
+    shared: DashboardLayout,
+    /// Each user's own layout, keyed by user
+    users: HashMap<String, DashboardLayout>,
+}
+
+/// The file as it is now, or as it was when it held the one layout everyone shared
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Layouts(Layouts),
+    Shared(DashboardLayout),
+}
+
+/// The dashboard layouts, kept in a file of their own as they aren't
+/// financial data and so have no place in the store, its backups or exports
+#[derive(Clone)]
+pub struct Dashboard {
+    path: PathBuf,
+    layouts: Arc<Mutex<Layouts>>,
+    // The user whose layout this handle reads and saves, or None for the
+    // shared one
+    user: Option<Arc<str>>,
+}
+
+impl Dashboard {
+    pub async fn new(config: &DashboardConfig) -> Result<Self, StorageError> {
+        let path = PathBuf::from(&config.file);
+        let layouts = if path.exists() {
+            match serde_json::from_slice(&fs::read(&path).await?)? {
+                Stored::Layouts(layouts) => layouts,
+                // Users start with a layout of their own rather than the one that was shared
+                Stored::Shared(shared) => Layouts {
+                    shared,
+                    users: HashMap::new(),
+                },
+            }
+        } else {
+            Layouts::default()
+        };
+        Ok(Self {
+            path,
+            layouts: Arc::new(Mutex::new(layouts)),
+            user: None,
+        })
+    }
+
+    /// The dashboard of `user`, each user having a layout of their own, or
+    /// the shared one for None
+    pub fn for_user(&self, user: Option<String>) -> Self {
+        Self {
+            user: user.map(Arc::from),
+            ..self.clone()
+        }
+    }
+
+    /// The saved layout, or an empty one before any is, and its entity tag
+    pub async fn get(&self) -> (DashboardLayout, String) {
+        let layout = self.layout_in(&*self.layouts.lock().await);
+        let etag = etag(&layout);
+        (layout, etag)
+    }
+
+    /// Save `layout` in place of the one before, unless `if_match` names a
+    /// different one, which another device must have saved meanwhile
+    pub async fn put(
+        &self,
+        mut layout: DashboardLayout,
+        if_match: Option<&str>,
+    ) -> Result<(DashboardLayout, String), ApiError> {
+        let mut layouts = self.layouts.lock().await;
+        if let Some(if_match) = if_match
+            && !etag_matches(if_match, &etag(&self.layout_in(&layouts)))
+        {
+            return Err(ApiError {
+                message: "The dashboard layout has changed since it was read; fetch it again and retry".to_string(),
+                status: StatusCode::CONFLICT,
+            });
+        }
+
+        layout.updated_at = Some(Utc::now());
+        let mut updated = layouts.clone();
+        match &self.user {
+            Some(user) => {
+                updated.users.insert(user.to_string(), layout.clone());
+            }
+            None => updated.shared = layout.clone(),
+        }
+        self.save(&updated).await.map_err(|e| ApiError {
+            message: format!("Failed to save the dashboard layout: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+        *layouts = updated;
+        let etag = etag(&layout);
+        Ok((layout, etag))
+    }
+
+    /// This handle's layout in `layouts`, an empty one for a user yet to save theirs
+    fn layout_in(&self, layouts: &Layouts) -> DashboardLayout {
+        match &self.user {
+            Some(user) => layouts.users.get(&**user).cloned().unwrap_or_default(),
+            None => layouts.shared.clone(),
+        }
+    }
+
+    async fn save(&self, layouts: &Layouts) -> Result<(), StorageError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(layouts)?).await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+/// Each save is tagged with when it was made
+fn etag(layout: &DashboardLayout) -> String {
+    match layout.updated_at {
+        Some(updated_at) => format!("\"{}\"", updated_at.timestamp_micros()),
+        None => "\"unsaved\"".to_string(),
+    }
+}
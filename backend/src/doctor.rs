@@ -0,0 +1,62 @@
+use crate::config::{Config, StorageBackend};
+use crate::storage::{self, JsonFileStorage};
+
+const USAGE: &str = "Usage: backend doctor [--repair]";
+
+/// `doctor [--repair]`: check the json backend's data directory for damage and
+/// report it, repairing what can be repaired safely. Run it with the server stopped.
+/// Returns the process exit code, which is non-zero while problems remain.
+pub async fn run(config: &Config, args: &[String]) -> i32 {
+    let repair = match args {
+        [] => false,
+        [flag] if flag == "--repair" => true,
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    if !matches!(config.storage.backend, StorageBackend::Json) {
+        eprintln!("Error: doctor only checks the data directory of the json storage backend");
+        return 2;
+    }
+
+    let cipher = match storage::cipher_from_config(&config.storage).await {
+        Ok(cipher) => cipher,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    let storage =
+        JsonFileStorage::new(&config.storage.data_dir, cipher, config.storage.compression);
+
+    let findings = match storage.doctor(repair).await {
+        Ok(findings) => findings,
+        Err(e) => {
+            eprintln!("Error: Failed to check {}: {}", config.storage.data_dir, e);
+            return 1;
+        }
+    };
+
+    for finding in &findings {
+        let status = if finding.repaired { " (repaired)" } else { "" };
+        println!("{}: {}{}", finding.path.display(), finding.problem, status);
+    }
+
+    let remaining = findings.iter().filter(|finding| !finding.repaired).count();
+    if findings.is_empty() {
+        println!("No problems found in {}", config.storage.data_dir);
+    } else {
+        println!(
+            "{} problems found, {} repaired",
+            findings.len(),
+            findings.len() - remaining
+        );
+        if remaining > 0 && !repair {
+            println!("Run with --repair to fix what can be fixed safely");
+        }
+    }
+
+    if remaining == 0 { 0 } else { 1 }
+}
@@ -1,5 +1,5 @@
-use serde_json;
-use warp;
+use serde::Serialize;
+use warp::Reply;
 
 #[derive(Debug)]
 pub struct ApiError {
@@ -9,27 +9,114 @@ pub struct ApiError {
 
 impl warp::reject::Reject for ApiError {}
 
-pub async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, std::convert::Infallible> {
+/// A request refused with a 401 because it couldn't be told who sent it
+#[derive(Debug, Clone)]
+pub struct AuthError {
+    pub message: String,
+    /// The `WWW-Authenticate` header, telling clients how to authenticate
+    pub challenge: Option<&'static str>,
+}
+
+impl AuthError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            challenge: None,
+        }
+    }
+
+    pub fn challenge(message: impl Into<String>, challenge: &'static str) -> Self {
+        Self {
+            message: message.into(),
+            challenge: Some(challenge),
+        }
+    }
+}
+
+impl warp::reject::Reject for AuthError {}
+
+/// One rejected input value, echoed back exactly as it was interpreted
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub value: String,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct ValidationError {
+    pub errors: Vec<FieldError>,
+}
+
+impl warp::reject::Reject for ValidationError {}
+
+pub async fn handle_rejection(err: warp::Rejection) -> Result<warp::reply::Response, std::convert::Infallible> {
     if let Some(api_error) = err.find::<ApiError>() {
+        if api_error.status.is_server_error() {
+            tracing::error!("{}", api_error.message);
+        }
         Ok(warp::reply::with_status(
             warp::reply::json(&serde_json::json!({
                 "error": api_error.message
             })),
             api_error.status,
-        ))
+        )
+        .into_response())
+    } else if let Some(auth_error) = err.find::<AuthError>() {
+        let mut response = warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": auth_error.message
+            })),
+            warp::http::StatusCode::UNAUTHORIZED,
+        )
+        .into_response();
+        if let Some(challenge) = auth_error.challenge {
+            response
+                .headers_mut()
+                .insert("www-authenticate", warp::http::HeaderValue::from_static(challenge));
+        }
+        Ok(response)
+    } else if let Some(validation_error) = err.find::<ValidationError>() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": "Validation failed",
+                "fields": validation_error.errors
+            })),
+            warp::http::StatusCode::BAD_REQUEST,
+        )
+        .into_response())
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": "Request body is too large"
+            })),
+            warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+        )
+        .into_response())
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": "A Content-Length header is required"
+            })),
+            warp::http::StatusCode::LENGTH_REQUIRED,
+        )
+        .into_response())
     } else if err.is_not_found() {
         Ok(warp::reply::with_status(
             warp::reply::json(&serde_json::json!({
                 "error": "Not found"
             })),
             warp::http::StatusCode::NOT_FOUND,
-        ))
+        )
+        .into_response())
     } else {
+        tracing::error!("Unhandled rejection: {:?}", err);
         Ok(warp::reply::with_status(
             warp::reply::json(&serde_json::json!({
                 "error": "Internal server error"
             })),
             warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+        )
+        .into_response())
     }
 }
\ No newline at end of file
@@ -0,0 +1,234 @@
+use crate::config::{Config, InboxRule, StatementLocation, StatementSource};
+use crate::inbox;
+use crate::quarantine::Quarantine;
+use crate::statements::Statements;
+use crate::storage::StorageError;
+use crate::store::TransactionStore;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Asks only for what a listing needs
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/></d:prop></d:propfind>"#;
+
+/// Fetches new statement files from a remote location, such as the WebDAV
+/// share some banks and brokers export to, and imports each with the first
+/// of the source's rules matching its name. Files are fetched once; the names
+/// already fetched are kept next to the statements.
+#[derive(Clone)]
+pub struct StatementFetcher {
+    name: String,
+    url: String,
+    credentials: Option<(String, Option<String>)>,
+    client: Client,
+    rules: Vec<InboxRule>,
+    fetched_path: PathBuf,
+    config: Arc<Config>,
+    store: TransactionStore,
+    statements: Statements,
+    quarantine: Quarantine,
+}
+
+impl StatementFetcher {
+    pub fn new(
+        name: &str,
+        source: &StatementSource,
+        config: Arc<Config>,
+        store: TransactionStore,
+        statements: Statements,
+        quarantine: Quarantine,
+    ) -> Result<Self, StorageError> {
+        let StatementLocation::Webdav {
+            url,
+            username,
+            password_env,
+        } = &source.location;
+        let credentials = match username {
+            Some(username) => Some((username.clone(), password_env.as_deref().map(env).transpose()?)),
+            None => None,
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            url: format!("{}/", url.trim_end_matches('/')),
+            credentials,
+            client: Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            rules: source.rules.clone(),
+            fetched_path: PathBuf::from(&config.statements.dir)
+                .join("fetched")
+                .join(format!("{}.json", name)),
+            config,
+            store,
+            statements,
+            quarantine,
+        })
+    }
+
+    /// Check the location every `interval`, starting now
+    pub fn spawn_schedule(&self, interval: Duration) {
+        let fetcher = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = fetcher.fetch().await {
+                    tracing::warn!("Failed to fetch statements from {}: {}", fetcher.name, e);
+                }
+            }
+        });
+    }
+
+    /// Import every file that hasn't been fetched before. A file that fails
+    /// to download is tried again next time; one that downloads but can't be
+    /// imported is not, as it would fail the same way again.
+    async fn fetch(&self) -> Result<(), StorageError> {
+        let mut fetched = self.read_fetched().await?;
+        let mut names = self.list().await?;
+        names.retain(|name| !fetched.contains(name));
+        names.sort();
+
+        for name in names {
+            let response = self.request(Method::GET, &name).send().await?;
+            if !response.status().is_success() {
+                return Err(format!("Remote answered {} for {}", response.status(), name).into());
+            }
+            let data = response.bytes().await?.to_vec();
+
+            let imported = inbox::import_file(
+                &self.store,
+                &self.config,
+                &self.statements,
+                &self.quarantine,
+                &self.rules,
+                &name,
+                data,
+            );
+            match imported.await {
+                Ok(summary) => tracing::info!("Imported {} from {}: {}", name, self.name, summary),
+                Err(message) => tracing::warn!("Failed to import {} from {}: {}", name, self.name, message),
+            }
+            fetched.push(name);
+            self.write_fetched(&fetched).await?;
+        }
+        Ok(())
+    }
+
+    /// Names of the files directly in the collection
+    async fn list(&self) -> Result<Vec<String>, StorageError> {
+        let response = self
+            .request(Method::from_bytes(b"PROPFIND")?, "")
+            .header("depth", "1")
+            .header("content-type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .await?;
+        if response.status() != StatusCode::MULTI_STATUS {
+            return Err(format!("Remote answered {} when listing {}", response.status(), self.url).into());
+        }
+        let body = response.text().await?;
+
+        // Collections, the listed one included, are the hrefs ending in a slash
+        Ok(hrefs(&body)
+            .into_iter()
+            .filter(|href| !href.ends_with('/'))
+            .filter_map(|href| href.rsplit('/').next().map(percent_decode))
+            .filter(|name| !name.is_empty())
+            .collect())
+    }
+
+    fn request(&self, method: Method, name: &str) -> RequestBuilder {
+        let url = format!("{}{}", self.url, percent_encode(name));
+        let request = self.client.request(method, url);
+        match &self.credentials {
+            Some((username, password)) => request.basic_auth(username, password.as_ref()),
+            None => request,
+        }
+    }
+
+    async fn read_fetched(&self) -> Result<Vec<String>, StorageError> {
+        if !self.fetched_path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(serde_json::from_slice(&fs::read(&self.fetched_path).await?)?)
+    }
+
+    async fn write_fetched(&self, fetched: &[String]) -> Result<(), StorageError> {
+        if let Some(dir) = self.fetched_path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut tmp_path = self.fetched_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(fetched)?).await?;
+        fs::rename(&tmp_path, &self.fetched_path).await?;
+        Ok(())
+    }
+}
+
+fn env(var: &str) -> Result<String, StorageError> {
+    Ok(std::env::var(var).map_err(|_| format!("Environment variable {} is not set", var))?)
+}
+
+/// The text of every `href` element in a PROPFIND response, whatever prefix
+/// the server gives the DAV namespace
+fn hrefs(xml: &str) -> Vec<String> {
+    let mut hrefs = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let name = tag.split_whitespace().next().unwrap_or_default();
+        let local_name = name.rsplit(':').next().unwrap_or_default();
+        let opening = !name.starts_with('/') && !tag.ends_with('/');
+        if opening && local_name.eq_ignore_ascii_case("href") {
+            let text = &rest[..rest.find('<').unwrap_or(rest.len())];
+            hrefs.push(
+                text.trim()
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&"),
+            );
+        }
+    }
+    hrefs
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn percent_encode(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,172 @@
+use crate::config::FrontendConfig;
+use crate::utils::etag_matches;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use warp::http::{HeaderValue, StatusCode, header};
+use warp::reply::Response;
+
+const INDEX_FILE: &str = "index.html";
+
+/// Cached for a year and never revalidated; a changed file gets a new name
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Revalidated on every use, so a new deployment is picked up at once
+const NO_CACHE: &str = "no-cache";
+
+/// Compressed copies a build can place next to a file, best first
+const ENCODINGS: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+/// The built frontend, served from a directory. Files whose names carry a
+/// content hash are cached for good; everything else, `index.html` above
+/// all, is revalidated against its ETag. Precompressed `.br` and `.gz`
+/// copies are served in place of a file to clients that accept them.
+#[derive(Clone)]
+pub struct Frontend {
+    dir: PathBuf,
+}
+
+/// What a request for a frontend file asks for
+pub struct AssetRequest<'a> {
+    pub path: &'a str,
+    pub accept: Option<&'a str>,
+    pub accept_encoding: Option<&'a str>,
+    pub if_none_match: Option<&'a str>,
+}
+
+impl Frontend {
+    pub fn new(config: &FrontendConfig) -> Self {
+        Self {
+            dir: PathBuf::from(&config.dir),
+        }
+    }
+
+    /// The file `request.path` names or, for a page the frontend routes
+    /// itself, `index.html`; `None` when there is neither
+    pub async fn serve(&self, request: AssetRequest<'_>) -> std::io::Result<Option<Response>> {
+        // Nothing outside the directory, no hidden files, and nothing the API owns
+        let api = request.path.split('/').next() == Some("api");
+        if api
+            || request
+                .path
+                .split('/')
+                .any(|segment| segment.starts_with('.') || segment.contains('\\'))
+        {
+            return Ok(None);
+        }
+
+        let mut name = match request.path.trim_end_matches('/') {
+            "" => INDEX_FILE,
+            path => path,
+        };
+        if !self.dir.join(name).is_file() {
+            // Only a browser navigating gets the page in place of a missing
+            // file, so API clients still see their errors
+            if !request.accept.is_some_and(|accept| accept.contains("text/html")) {
+                return Ok(None);
+            }
+            name = INDEX_FILE;
+        }
+        let path = self.dir.join(name);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let mut chosen = (path.clone(), None);
+        for (encoding, extension) in ENCODINGS {
+            let mut compressed = path.clone().into_os_string();
+            compressed.push(format!(".{}", extension));
+            let compressed = PathBuf::from(compressed);
+            if accepts(request.accept_encoding, encoding) && compressed.is_file() {
+                chosen = (compressed, Some(*encoding));
+                break;
+            }
+        }
+        let (file, encoding) = chosen;
+
+        let metadata = fs::metadata(&file).await?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_nanos())
+            .unwrap_or_default();
+        let suffix = encoding.map(|encoding| format!("-{}", encoding)).unwrap_or_default();
+        let etag = format!("\"{:x}-{:x}{}\"", metadata.len(), modified, suffix);
+
+        let mut response = if request.if_none_match.is_some_and(|tags| etag_matches(tags, &etag)) {
+            let mut response = Response::new(Vec::new().into());
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            response
+        } else {
+            Response::new(fs::read(&file).await?.into())
+        };
+
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type(&path)));
+        let cache_control = if name != INDEX_FILE && is_hashed(&path) { IMMUTABLE } else { NO_CACHE };
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        if let Ok(etag) = HeaderValue::from_str(&etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(encoding) = encoding {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        Ok(Some(response))
+    }
+}
+
+/// Whether an `Accept-Encoding` header allows `encoding`, i.e. lists it or
+/// `*` without `q=0`
+fn accepts(accept_encoding: Option<&str>, encoding: &str) -> bool {
+    accept_encoding.is_some_and(|header| {
+        header.split(',').any(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f64>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case(encoding) || name == "*") && !refused
+        })
+    })
+}
+
+/// Whether the file name carries a content hash, as bundlers add to the
+/// assets they build: a last `-` or `.` separated part of its stem at least
+/// eight letters, digits or underscores long, digits included
+fn is_hashed(path: &Path) -> bool {
+    let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+        return false;
+    };
+    let Some(hash) = stem.rsplit(['-', '.']).next().filter(|hash| *hash != stem) else {
+        return false;
+    };
+    hash.len() >= 8
+        && hash.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && hash.chars().any(|c| c.is_ascii_digit())
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "html" => "text/html; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,40 @@
+use crate::store::TransactionStore;
+use crate::types::{GrantRequest, OwnerRequest};
+
+pub async fn account_grants_handler(
+    account_id: String,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let grants = store.account_grants(&account_id).map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&grants))
+}
+
+pub async fn grant_access_handler(
+    account_id: String,
+    user: String,
+    request: GrantRequest,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let grants = store
+        .grant_access(&account_id, &user, request.permission)
+        .map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&grants))
+}
+
+pub async fn revoke_access_handler(
+    account_id: String,
+    user: String,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    store.revoke_access(&account_id, &user).map_err(warp::reject::custom)?;
+    Ok(warp::http::StatusCode::NO_CONTENT)
+}
+
+pub async fn give_account_handler(
+    account_id: String,
+    request: OwnerRequest,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let grants = store.give_account(&account_id, &request.owner).map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&grants))
+}
@@ -0,0 +1,81 @@
+use crate::error::ApiError;
+use crate::limits::Slot;
+use crate::store::TransactionStore;
+use crate::types::{AccountDetails, AccountGrouping, CreateAccountRequest, MergeAccountsRequest};
+use crate::utils::{override_lock, past_from_query, rounding_from_query, selector_from_query};
+use std::collections::HashMap;
+
+pub async fn list_accounts_handler(
+    query_params: HashMap<String, String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let selector = selector_from_query(&query_params).map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&store.accounts(&selector)))
+}
+
+pub async fn account_groups_handler(
+    query_params: HashMap<String, String>,
+    store: TransactionStore,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let grouping = match query_params.get("by").map(String::as_str) {
+        None | Some("group") => AccountGrouping::Group,
+        Some("institution") => AccountGrouping::Institution,
+        Some(_) => {
+            return Err(warp::reject::custom(ApiError {
+                message: "Invalid by parameter, expected group or institution".to_string(),
+                status: warp::http::StatusCode::BAD_REQUEST,
+            }));
+        }
+    };
+    let selector = selector_from_query(&query_params).map_err(warp::reject::custom)?;
+    let rounding = rounding_from_query(&query_params).map_err(warp::reject::custom)?;
+    let past = past_from_query(&store, &query_params).await.map_err(warp::reject::custom)?;
+    let mut groups = store.account_groups(grouping, past.as_ref(), &selector);
+
+    if let Some(step_cents) = rounding {
+        for total in groups.iter_mut().flat_map(|group| group.totals.iter_mut()) {
+            total.round(step_cents);
+        }
+    }
+    Ok(warp::reply::json(&groups))
+}
+
+pub async fn create_account_handler(
+    request: CreateAccountRequest,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account = store.create_account(request).map_err(warp::reject::custom)?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&account),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+pub async fn get_account_handler(
+    account_id: String,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account = store.account(&account_id).map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&account))
+}
+
+pub async fn update_account_handler(
+    account_id: String,
+    details: AccountDetails,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account = store.update_account(&account_id, details).map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&account))
+}
+
+pub async fn merge_accounts_handler(
+    query_params: HashMap<String, String>,
+    request: MergeAccountsRequest,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let merged = store
+        .merge_accounts(request, override_lock(&query_params))
+        .map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&merged))
+}
@@ -1,9 +1,56 @@
-use crate::store::TransactionStore;
-use warp;
+use crate::error::ApiError;
+use crate::limits::Slot;
+use crate::store::{Past, TransactionStore};
+use crate::utils::{etag_matches, filter_from_query, page_from_query, past_from_query, sort_from_query};
+use std::collections::HashMap;
+use warp::http::StatusCode;
+use warp::Reply;
 
+/// Narrow the listing down with the filter parameters, order it with `sort`,
+/// and pass `limit` and `offset` to get one page; the `X-Total-Count` header
+/// says how many match in all. The `ETag` header names the state of the
+/// store, and a request whose `If-None-Match` has it is answered with 304.
+/// `as_of_revision` or `as_of_date` lists the transactions as they were then,
+/// the `X-As-Of-Revision` header naming the last change included.
 pub async fn get_all_transactions_handler(
+    query_params: HashMap<String, String>,
+    if_none_match: Option<String>,
     store: TransactionStore,
+    _slot: Slot,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let transactions = store.get_all_transactions();
-    Ok(warp::reply::json(&transactions))
+    let filter = filter_from_query(&query_params).map_err(warp::reject::custom)?;
+    let sort = sort_from_query(&query_params).map_err(warp::reject::custom)?;
+    let page = page_from_query(&query_params).map_err(warp::reject::custom)?;
+    let past = past_from_query(&store, &query_params).await.map_err(warp::reject::custom)?;
+    let etag = past.as_ref().map_or_else(|| store.etag(), Past::etag);
+    if if_none_match.is_some_and(|tags| etag_matches(&tags, &etag)) {
+        let reply = warp::reply::with_status(warp::reply(), StatusCode::NOT_MODIFIED);
+        return Ok(warp::reply::with_header(reply, "etag", etag).into_response());
+    }
+
+    let (body, total) = store.all_transactions_json(&filter, sort, page, past.as_ref()).map_err(|e| {
+        warp::reject::custom(ApiError {
+            message: format!("Failed to serialize transactions: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    })?;
+    let reply = warp::reply::with_header(body, "content-type", "application/json");
+    let reply = warp::reply::with_header(reply, "etag", etag);
+    let reply = warp::reply::with_header(reply, "x-total-count", total.to_string());
+    match past {
+        Some(past) => Ok(warp::reply::with_header(reply, "x-as-of-revision", past.revision.to_string()).into_response()),
+        None => Ok(reply.into_response()),
+    }
+}
+
+/// The same listing for one account, whatever `account_id` parameter is passed
+pub async fn get_account_all_transactions_handler(
+    account_id: String,
+    mut query_params: HashMap<String, String>,
+    if_none_match: Option<String>,
+    store: TransactionStore,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    query_params.insert("account_id".to_string(), account_id);
+    get_all_transactions_handler(query_params, if_none_match, store, _slot).await
 }
\ No newline at end of file
@@ -0,0 +1,10 @@
+use crate::openapi;
+
+pub async fn openapi_handler() -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&openapi::document()))
+}
+
+pub async fn swagger_ui_handler() -> Result<impl warp::Reply, warp::Rejection> {
+    // Relative, so the page finds the document under whichever prefix it was served
+    Ok(warp::reply::html(openapi::swagger_ui("openapi.json")))
+}
\ No newline at end of file
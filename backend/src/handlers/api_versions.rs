@@ -0,0 +1,8 @@
+use crate::utils::{API_VERSIONS, LATEST_API_VERSION};
+
+pub async fn api_versions_handler() -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&serde_json::json!({
+        "versions": API_VERSIONS,
+        "latest": LATEST_API_VERSION,
+    })))
+}
\ No newline at end of file
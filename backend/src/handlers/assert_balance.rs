@@ -0,0 +1,15 @@
+use crate::store::TransactionStore;
+use crate::types::AssertBalanceRequest;
+
+pub async fn assert_balance_handler(
+    account_id: String,
+    request: AssertBalanceRequest,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let result = store.assert_balance(account_id, request).await.map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&result),
+        warp::http::StatusCode::CREATED,
+    ))
+}
@@ -0,0 +1,19 @@
+use crate::statements::Statements;
+use std::collections::HashMap;
+
+pub async fn attach_statement_handler(
+    import_id: String,
+    content_type: Option<String>,
+    data: bytes::Bytes,
+    query_params: HashMap<String, String>,
+    statements: Statements,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let file_name = query_params.get("file_name").cloned().unwrap_or_else(|| "statement".to_string());
+    let content_type = content_type.unwrap_or_else(|| "application/octet-stream".to_string());
+    let statement = statements
+        .attach(&import_id, file_name, content_type, data.to_vec())
+        .await
+        .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::json(&statement))
+}
\ No newline at end of file
@@ -0,0 +1,152 @@
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::idempotency::{Claim, fingerprint};
+use crate::import::{ImportOptions, import_csv};
+use crate::limits::Slot;
+use crate::quarantine::Quarantine;
+use crate::store::TransactionStore;
+use crate::types::{BatchImportEntry, BatchImportFileResult, BatchImportResponse};
+use bytes::Buf;
+use futures_util::TryStreamExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::multipart::FormData;
+
+/// Accepts a `manifest` part, a JSON array of `{file, account_id, profile}`,
+/// and one `file` part per entry. Each file is imported as its own batch, so
+/// one bad file doesn't stop the others.
+pub async fn batch_import_handler(
+    form: FormData,
+    query_params: HashMap<String, String>,
+    config: Arc<Config>,
+    store: TransactionStore,
+    quarantine: Quarantine,
+    idempotency: Claim,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (manifest, files) = read_form(form).await.map_err(warp::reject::custom)?;
+    // The files in name order, so the order they were sent in doesn't matter
+    let mut names: Vec<_> = files.keys().collect();
+    names.sort();
+    let mut body: Vec<&[u8]> = vec![manifest.as_deref().unwrap_or_default()];
+    body.extend(names.into_iter().flat_map(|name| [name.as_bytes(), files[name].as_slice()]));
+    let fingerprint = fingerprint(&body, &query_params);
+    idempotency
+        .run(fingerprint, batch_import(manifest, files, query_params, config, store, quarantine))
+        .await
+}
+
+async fn batch_import(
+    manifest: Option<Vec<u8>>,
+    mut files: HashMap<String, Vec<u8>>,
+    query_params: HashMap<String, String>,
+    config: Arc<Config>,
+    store: TransactionStore,
+    quarantine: Quarantine,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let options = ImportOptions::from_query(&query_params);
+
+    // Check the request as a whole before importing anything
+    let bad_request = |message: String| {
+        warp::reject::custom(ApiError {
+            message,
+            status: StatusCode::BAD_REQUEST,
+        })
+    };
+    let manifest = manifest.ok_or_else(|| bad_request("Missing manifest part".to_string()))?;
+    let manifest: Vec<BatchImportEntry> = serde_json::from_slice(&manifest)
+        .map_err(|e| bad_request(format!("Invalid manifest - {}", e)))?;
+    for entry in &manifest {
+        if !files.contains_key(&entry.file) {
+            return Err(bad_request(format!("File '{}' was not uploaded", entry.file)));
+        }
+    }
+    if let Some(file) = files.keys().find(|file| !manifest.iter().any(|entry| entry.file == **file)) {
+        return Err(bad_request(format!("File '{}' is not in the manifest", file)));
+    }
+
+    let mut results = Vec::with_capacity(manifest.len());
+    for entry in manifest {
+        let data = files.remove(&entry.file).unwrap_or_default();
+        let outcome = import_csv(
+            &store,
+            &quarantine,
+            &config,
+            entry.account_id.clone(),
+            entry.profile.as_deref(),
+            &data,
+            ImportOptions {
+                foreign_currency: options.foreign_currency || entry.foreign_currency,
+                ..options
+            },
+        )
+        .await
+        .map_err(|e| e.message);
+
+        let (result, error) = match outcome {
+            Ok(response) => (Some(response), None),
+            Err(message) => (None, Some(message)),
+        };
+        results.push(BatchImportFileResult {
+            file: entry.file,
+            account_id: entry.account_id,
+            result,
+            error,
+        });
+    }
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&BatchImportResponse { files: results }),
+        StatusCode::OK,
+    ))
+}
+
+/// Split the form into the manifest and the uploaded files, keyed by file name
+async fn read_form(form: FormData) -> Result<(Option<Vec<u8>>, HashMap<String, Vec<u8>>), ApiError> {
+    let invalid = |e: warp::Error| ApiError {
+        message: format!("Invalid multipart body - {}", e),
+        status: StatusCode::BAD_REQUEST,
+    };
+
+    let mut manifest = None;
+    let mut files = HashMap::new();
+    let mut parts = form;
+    while let Some(part) = parts.try_next().await.map_err(invalid)? {
+        let name = part.name().to_string();
+        let filename = part.filename().map(str::to_string);
+        let data = part
+            .stream()
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(chunk.chunk());
+                Ok(data)
+            })
+            .await
+            .map_err(invalid)?;
+
+        match (name.as_str(), filename) {
+            ("manifest", _) => manifest = Some(data),
+            ("file", Some(filename)) => {
+                if files.insert(filename.clone(), data).is_some() {
+                    return Err(ApiError {
+                        message: format!("File '{}' was uploaded more than once", filename),
+                        status: StatusCode::BAD_REQUEST,
+                    });
+                }
+            }
+            ("file", None) => {
+                return Err(ApiError {
+                    message: "Every file part needs a file name".to_string(),
+                    status: StatusCode::BAD_REQUEST,
+                });
+            }
+            _ => {
+                return Err(ApiError {
+                    message: format!("Unexpected form field '{}'", name),
+                    status: StatusCode::BAD_REQUEST,
+                });
+            }
+        }
+    }
+    Ok((manifest, files))
+}
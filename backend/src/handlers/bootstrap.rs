@@ -0,0 +1,57 @@
+use crate::config::Config;
+use crate::currency::{STORED_DECIMALS, allowed_decimals, minor_units, round_half_even};
+use crate::error::ApiError;
+use crate::store::TransactionStore;
+use crate::types::{AccountSelector, BootstrapResponse, BootstrapSettings, CurrencyInfo};
+use crate::utils::rounding_from_query;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use warp::http::StatusCode;
+
+pub async fn bootstrap_handler(
+    query_params: HashMap<String, String>,
+    config: Arc<Config>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let rounding = rounding_from_query(&query_params).map_err(warp::reject::custom)?;
+    let revision = store.revision().await.map_err(|e| {
+        warp::reject::custom(ApiError {
+            message: format!("Failed to read data revision: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    })?;
+    let mut accounts = store.accounts(&AccountSelector::default());
+    if let Some(step_cents) = rounding {
+        for balance in accounts.iter_mut().flat_map(|account| account.balances.iter_mut()) {
+            balance.balance_cents = round_half_even(balance.balance_cents, step_cents);
+        }
+    }
+
+    let codes: BTreeSet<_> = accounts
+        .iter()
+        .flat_map(|account| account.balances.iter().map(|balance| balance.currency.as_str()))
+        .chain(config.low_balance_thresholds.values().flat_map(|thresholds| thresholds.keys().map(String::as_str)))
+        .collect();
+    let currencies = codes
+        .into_iter()
+        .map(|code| CurrencyInfo {
+            code: code.to_string(),
+            exponent: minor_units(code),
+            allowed_decimals: allowed_decimals(code).min(STORED_DECIMALS),
+        })
+        .collect();
+
+    let mut import_profiles: Vec<_> = config.import_profiles.keys().cloned().collect();
+    import_profiles.sort();
+
+    Ok(warp::reply::json(&BootstrapResponse {
+        revision,
+        currencies,
+        accounts,
+        settings: BootstrapSettings {
+            amount_precision: config.amount_precision,
+            import_profiles,
+            low_balance_thresholds: config.low_balance_thresholds.clone(),
+        },
+    }))
+}
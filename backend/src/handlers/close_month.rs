@@ -0,0 +1,24 @@
+use crate::config::Month;
+use crate::error::ApiError;
+use crate::store::TransactionStore;
+use warp::http::StatusCode;
+
+pub async fn month_checklist_handler(month: String, store: TransactionStore) -> Result<impl warp::Reply, warp::Rejection> {
+    let month = parse_month(&month)?;
+    Ok(warp::reply::json(&store.month_checklist(month)))
+}
+
+pub async fn close_month_handler(month: String, store: TransactionStore) -> Result<impl warp::Reply, warp::Rejection> {
+    let month = parse_month(&month)?;
+    let response = store.close_month(month).map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&response))
+}
+
+fn parse_month(month: &str) -> Result<Month, warp::Rejection> {
+    month.parse().map_err(|message| {
+        warp::reject::custom(ApiError {
+            message,
+            status: StatusCode::BAD_REQUEST,
+        })
+    })
+}
\ No newline at end of file
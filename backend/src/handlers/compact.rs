@@ -0,0 +1,14 @@
+use crate::error::ApiError;
+use crate::limits::Slot;
+use crate::store::TransactionStore;
+use warp::http::StatusCode;
+
+pub async fn compact_handler(store: TransactionStore, _slot: Slot) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = store.compact().await.map_err(|e| {
+        warp::reject::custom(ApiError {
+            message: format!("Failed to compact storage: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    })?;
+    Ok(warp::reply::json(&response))
+}
\ No newline at end of file
@@ -0,0 +1,18 @@
+use crate::backup::Backups;
+use crate::error::ApiError;
+use crate::limits::Slot;
+use warp::http::StatusCode;
+
+pub async fn create_backup_handler(backups: Backups, _slot: Slot) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = backups.create().await.map_err(|e| {
+        warp::reject::custom(ApiError {
+            message: format!("Failed to create backup: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    })?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        StatusCode::CREATED,
+    ))
+}
@@ -1,13 +1,37 @@
+use crate::config::Config;
+use crate::error::ValidationError;
+use crate::idempotency::{Claim, fingerprint};
 use crate::store::TransactionStore;
 use crate::types::CreateTransactionRequest;
+use crate::utils::{override_lock, validate_amount};
+use std::collections::HashMap;
+use std::sync::Arc;
 use warp;
 
 pub async fn create_transaction_handler(
     request: CreateTransactionRequest,
+    query_params: HashMap<String, String>,
+    config: Arc<Config>,
     store: TransactionStore,
+    idempotency: Claim,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let current_transaction = store.create_transaction(request).await.map_err(warp::reject::custom)?;
+    let body = serde_json::to_vec(&request).unwrap_or_default();
+    let fingerprint = fingerprint(&[&body], &query_params);
+    idempotency
+        .run(fingerprint, create_transaction(request, query_params, config, store))
+        .await
+}
 
+async fn create_transaction(
+    mut request: CreateTransactionRequest,
+    query_params: HashMap<String, String>,
+    config: Arc<Config>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    request.amount = validate_amount(request.amount, &request.currency, config.amount_precision)
+        .map_err(|e| warp::reject::custom(ValidationError { errors: vec![e] }))?;
+
+    let current_transaction = store.create_transaction(request, override_lock(&query_params)).await.map_err(warp::reject::custom)?;
     Ok(warp::reply::with_status(
         warp::reply::json(&current_transaction),
         warp::http::StatusCode::CREATED,
@@ -0,0 +1,18 @@
+use crate::dashboard::Dashboard;
+use crate::types::DashboardLayout;
+
+pub async fn get_dashboard_layout_handler(dashboard: Dashboard) -> Result<impl warp::Reply, warp::Rejection> {
+    let (layout, etag) = dashboard.get().await;
+    Ok(warp::reply::with_header(warp::reply::json(&layout), "etag", etag))
+}
+
+/// Honours `If-Match`, so a device can't overwrite a layout saved by another
+/// since it last read it
+pub async fn put_dashboard_layout_handler(
+    if_match: Option<String>,
+    layout: DashboardLayout,
+    dashboard: Dashboard,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (layout, etag) = dashboard.put(layout, if_match.as_deref()).await.map_err(warp::reject::custom)?;
+    Ok(warp::reply::with_header(warp::reply::json(&layout), "etag", etag))
+}
\ No newline at end of file
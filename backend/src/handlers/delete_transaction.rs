@@ -0,0 +1,40 @@
+use crate::store::TransactionStore;
+use crate::utils::{override_lock, transaction_from_uuid, transaction_id_from_query};
+use std::collections::HashMap;
+use warp;
+
+pub async fn delete_transaction_handler(
+    account_id: String,
+    query_params: HashMap<String, String>,
+    if_match: Option<String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let transaction_id = transaction_id_from_query(&query_params).map_err(warp::reject::custom)?;
+
+    store
+        .delete_transaction(account_id, transaction_id, override_lock(&query_params), if_match.as_deref())
+        .await.map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({"message": "Transaction deleted successfully"})),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+
+/// The same deletion, for a transaction named by its uuid
+pub async fn delete_transaction_by_uuid_handler(
+    uuid: String,
+    query_params: HashMap<String, String>,
+    if_match: Option<String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (account_id, transaction_id) = transaction_from_uuid(&store, &uuid).map_err(warp::reject::custom)?;
+
+    store
+        .delete_transaction(account_id, transaction_id, override_lock(&query_params), if_match.as_deref())
+        .await
+        .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::json(&serde_json::json!({"message": "Transaction deleted successfully"})))
+}
\ No newline at end of file
@@ -0,0 +1,58 @@
+use crate::config::Config;
+use crate::error::ValidationError;
+use crate::store::TransactionStore;
+use crate::types::EditTransactionRequest;
+use crate::utils::{override_lock, transaction_from_uuid, transaction_id_from_query, validate_amount};
+use std::collections::HashMap;
+use std::sync::Arc;
+use warp;
+
+pub async fn edit_transaction_handler(
+    account_id: String,
+    mut request: EditTransactionRequest,
+    query_params: HashMap<String, String>,
+    if_match: Option<String>,
+    config: Arc<Config>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let transaction_id = transaction_id_from_query(&query_params).map_err(warp::reject::custom)?;
+    if let Some(amount) = request.amount {
+        let amount = validate_amount(amount, &transaction_id.currency, config.amount_precision)
+            .map_err(|e| warp::reject::custom(ValidationError { errors: vec![e] }))?;
+        request.amount = Some(amount);
+    }
+
+    let (current_transaction, etag) = store
+        .edit_transaction(account_id, transaction_id, request, override_lock(&query_params), if_match.as_deref())
+        .await
+        .map_err(warp::reject::custom)?;
+    Ok(warp::reply::with_header(
+        warp::reply::json(&current_transaction),
+        "etag",
+        etag,
+    ))
+}
+
+
+/// The same correction, for a transaction named by its uuid
+pub async fn edit_transaction_by_uuid_handler(
+    uuid: String,
+    mut request: EditTransactionRequest,
+    query_params: HashMap<String, String>,
+    if_match: Option<String>,
+    config: Arc<Config>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (account_id, transaction_id) = transaction_from_uuid(&store, &uuid).map_err(warp::reject::custom)?;
+    if let Some(amount) = request.amount {
+        let amount = validate_amount(amount, &transaction_id.currency, config.amount_precision)
+            .map_err(|e| warp::reject::custom(ValidationError { errors: vec![e] }))?;
+        request.amount = Some(amount);
+    }
+
+    let (current_transaction, etag) = store
+        .edit_transaction(account_id, transaction_id, request, override_lock(&query_params), if_match.as_deref())
+        .await
+        .map_err(warp::reject::custom)?;
+    Ok(warp::reply::with_header(warp::reply::json(&current_transaction), "etag", etag))
+}
\ No newline at end of file
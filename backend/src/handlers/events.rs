@@ -0,0 +1,30 @@
+use crate::error::ApiError;
+use crate::limits::Slot;
+use crate::store::TransactionStore;
+use crate::utils::get_usize_param;
+use std::collections::HashMap;
+use warp::http::StatusCode;
+
+const DEFAULT_LIMIT: usize = 1000;
+const MAX_LIMIT: usize = 10_000;
+
+/// Page through the event log: pass the `seq` of the last event seen as
+/// `after` to get only what happened since
+pub async fn events_handler(
+    query_params: HashMap<String, String>,
+    store: TransactionStore,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let after = get_usize_param(&query_params, "after", 0).map_err(warp::reject::custom)? as u64;
+    let limit = get_usize_param(&query_params, "limit", DEFAULT_LIMIT)
+        .map_err(warp::reject::custom)?
+        .min(MAX_LIMIT);
+
+    let events = store.events(after, limit).await.map_err(|e| {
+        warp::reject::custom(ApiError {
+            message: format!("Failed to read events: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    })?;
+    Ok(warp::reply::json(&events))
+}
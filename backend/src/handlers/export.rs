@@ -0,0 +1,31 @@
+use crate::error::ApiError;
+use crate::limits::Slot;
+use crate::ranges::{self, Download};
+use crate::store::TransactionStore;
+use chrono::Utc;
+use warp::http::StatusCode;
+
+/// Honours `Range` requests, and `If-Range` with the `ETag` it was sent, so
+/// an interrupted download can be resumed
+pub async fn export_handler(
+    range: Option<String>,
+    if_range: Option<String>,
+    store: TransactionStore,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let etag = store.etag();
+    let body = store.export_json().map_err(|e| {
+        warp::reject::custom(ApiError {
+            message: format!("Failed to serialize export: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    })?;
+
+    let download = Download {
+        body,
+        content_type: "application/json".to_string(),
+        file_name: format!("wdmmg-export-{}.json", Utc::now().format("%Y-%m-%d")),
+        etag,
+    };
+    ranges::reply(download, range, if_range).map_err(warp::reject::custom)
+}
@@ -0,0 +1,33 @@
+use crate::error::ApiError;
+use crate::frontend::{AssetRequest, Frontend};
+use warp::http::StatusCode;
+use warp::path::Tail;
+
+pub async fn frontend_handler(
+    tail: Tail,
+    accept: Option<String>,
+    accept_encoding: Option<String>,
+    if_none_match: Option<String>,
+    frontend: Option<Frontend>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let Some(frontend) = frontend else {
+        return Err(warp::reject::not_found());
+    };
+
+    let request = AssetRequest {
+        path: tail.as_str(),
+        accept: accept.as_deref(),
+        accept_encoding: accept_encoding.as_deref(),
+        if_none_match: if_none_match.as_deref(),
+    };
+    frontend
+        .serve(request)
+        .await
+        .map_err(|e| {
+            warp::reject::custom(ApiError {
+                message: format!("Failed to read frontend file: {}", e),
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+            })
+        })?
+        .ok_or_else(warp::reject::not_found)
+}
\ No newline at end of file
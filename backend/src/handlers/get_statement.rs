@@ -0,0 +1,30 @@
+use crate::limits::Slot;
+use crate::ranges::{self, Download};
+use crate::statements::Statements;
+
+/// Honours `Range` requests, and `If-Range` with the `ETag` it was sent, so
+/// an interrupted download can be resumed
+pub async fn get_statement_handler(
+    import_id: String,
+    range: Option<String>,
+    if_range: Option<String>,
+    statements: Statements,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (statement, data) = statements.read(&import_id).await.map_err(warp::reject::custom)?;
+
+    // The name is the uploader's, so keep it to what a header can carry
+    let file_name: String = statement
+        .file_name
+        .chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' })
+        .collect();
+    // Attaching a file again replaces it, so the tag is of when it was attached
+    let download = Download {
+        body: data,
+        content_type: statement.content_type,
+        file_name,
+        etag: format!("\"{}-{}\"", import_id, statement.attached_at.timestamp_micros()),
+    };
+    ranges::reply(download, range, if_range).map_err(warp::reject::custom)
+}
\ No newline at end of file
@@ -0,0 +1,37 @@
+use crate::error::ApiError;
+use crate::store::TransactionStore;
+use crate::utils::{transaction_from_uuid, transaction_id_from_query};
+use std::collections::HashMap;
+use warp;
+
+pub async fn get_transaction_handler(
+    account_id: String,
+    query_params: HashMap<String, String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let transaction_id = transaction_id_from_query(&query_params).map_err(warp::reject::custom)?;
+
+    let (transaction, etag) = store.transaction(&account_id, &transaction_id).ok_or_else(|| {
+        warp::reject::custom(ApiError {
+            message: "Transaction not found".to_string(),
+            status: warp::http::StatusCode::NOT_FOUND,
+        })
+    })?;
+    Ok(warp::reply::with_header(warp::reply::json(&transaction), "etag", etag))
+}
+
+
+/// The same transaction, named by its uuid
+pub async fn get_transaction_by_uuid_handler(
+    uuid: String,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (account_id, transaction_id) = transaction_from_uuid(&store, &uuid).map_err(warp::reject::custom)?;
+    let (transaction, etag) = store.transaction(&account_id, &transaction_id).ok_or_else(|| {
+        warp::reject::custom(ApiError {
+            message: "Transaction not found".to_string(),
+            status: warp::http::StatusCode::NOT_FOUND,
+        })
+    })?;
+    Ok(warp::reply::with_header(warp::reply::json(&transaction), "etag", etag))
+}
\ No newline at end of file
@@ -0,0 +1,13 @@
+use crate::hash_chain::HashChain;
+
+/// Answers 409 Conflict once a check has found the log changed, like
+/// `GET /admin/verify` does for discrepancies
+pub async fn integrity_handler(hash_chain: HashChain) -> Result<impl warp::Reply, warp::Rejection> {
+    let status = hash_chain.status().await;
+    let code = if status.last_run.as_ref().is_some_and(|run| run.mismatch.is_some()) {
+        warp::http::StatusCode::CONFLICT
+    } else {
+        warp::http::StatusCode::OK
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&status), code))
+}
\ No newline at end of file
@@ -0,0 +1,39 @@
+use crate::archive::Archive;
+use crate::error::ApiError;
+use crate::limits::Slot;
+use crate::store::TransactionStore;
+use crate::types::ArchiveImportMode;
+use std::collections::HashMap;
+use warp::http::StatusCode;
+
+pub async fn import_archive_handler(
+    query_params: HashMap<String, String>,
+    archive: Archive,
+    store: TransactionStore,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let bad_request = |message: String| {
+        warp::reject::custom(ApiError {
+            message,
+            status: StatusCode::BAD_REQUEST,
+        })
+    };
+
+    let mode = match query_params.get("mode").map(String::as_str) {
+        None | Some("replace") => ArchiveImportMode::Replace,
+        Some("merge") => ArchiveImportMode::Merge,
+        Some(other) => {
+            return Err(bad_request(format!(
+                "Unknown mode '{}', expected 'replace' or 'merge'",
+                other
+            )));
+        }
+    };
+    archive.validate().map_err(|e| bad_request(format!("Invalid archive - {}", e)))?;
+
+    let summary = store
+        .replace_with(|state| Ok(archive.load_into(state, mode)))
+        .await
+        .map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&summary))
+}
@@ -0,0 +1,44 @@
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::import;
+use crate::limits::Slot;
+use crate::quarantine::Quarantine;
+use crate::store::TransactionStore;
+use crate::types::{ImportFailuresResponse, ReprocessImportRequest};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub async fn import_failures_handler(
+    import_id: String,
+    store: TransactionStore,
+    quarantine: Quarantine,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let record = store.import_record(&import_id).ok_or_else(|| warp::reject::custom(ApiError {
+        message: "Import not found".to_string(),
+        status: warp::http::StatusCode::NOT_FOUND,
+    }))?;
+    let rows = quarantine.rows(&import_id).await.map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::json(&ImportFailuresResponse {
+        import_id,
+        account_id: record.account_id,
+        rows,
+    }))
+}
+
+/// Corrected rows are imported as part of the same import, so its metrics
+/// count them as imported rather than as errors
+pub async fn reprocess_import_handler(
+    import_id: String,
+    query_params: HashMap<String, String>,
+    request: ReprocessImportRequest,
+    config: Arc<Config>,
+    store: TransactionStore,
+    quarantine: Quarantine,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = import::reprocess(&store, &quarantine, &config, &import_id, request, import::ImportOptions::from_query(&query_params))
+        .await
+        .map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&response))
+}
\ No newline at end of file
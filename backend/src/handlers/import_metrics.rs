@@ -0,0 +1,10 @@
+use crate::store::TransactionStore;
+use std::collections::HashMap;
+
+pub async fn import_metrics_handler(
+    query_params: HashMap<String, String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let account_id = query_params.get("account_id").map(String::as_str);
+    Ok(warp::reply::json(&store.import_metrics(account_id)))
+}
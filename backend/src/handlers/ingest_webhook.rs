@@ -0,0 +1,87 @@
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::ingest::{mapper_for, secrets_match};
+use crate::limits::Slot;
+use crate::store::TransactionStore;
+use crate::types::{BulkImportResponse, ImportRecord};
+use crate::utils::validate_amount;
+use chrono::Utc;
+use std::sync::Arc;
+
+pub async fn ingest_webhook_handler(
+    source_name: String,
+    secret: Option<String>,
+    payload: serde_json::Value,
+    config: Arc<Config>,
+    store: TransactionStore,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let source = config.webhooks.get(&source_name).ok_or(ApiError {
+        message: "Unknown webhook source".to_string(),
+        status: warp::http::StatusCode::NOT_FOUND,
+    }).map_err(warp::reject::custom)?;
+
+    if !secret.is_some_and(|secret| secrets_match(&source.secret, &secret)) {
+        return Err(warp::reject::custom(ApiError {
+            message: "Invalid webhook secret".to_string(),
+            status: warp::http::StatusCode::UNAUTHORIZED,
+        }));
+    }
+
+    let mapper = mapper_for(&source.mapper).ok_or(ApiError {
+        message: format!("Unknown payload mapper '{}'", source.mapper),
+        status: warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+    }).map_err(warp::reject::custom)?;
+
+    let requests = mapper.map(payload, source).map_err(|message| warp::reject::custom(ApiError {
+        message,
+        status: warp::http::StatusCode::BAD_REQUEST,
+    }))?;
+
+    // Recorded as the source's owner, so a new account is theirs rather than nobody's
+    let store = store.for_owner(source.owner.clone());
+
+    // Senders retry on failure, so a transaction we already have is counted rather than rejected
+    let rows = requests.len();
+    let account_id = source.account_id.clone();
+    let mut response = BulkImportResponse {
+        import_id: None,
+        imported: 0,
+        duplicates: 0,
+        errors: vec![],
+    };
+    for mut request in requests {
+        match validate_amount(request.amount, &request.currency, config.amount_precision) {
+            Ok(amount) => request.amount = amount,
+            Err(e) => {
+                response.errors.push(format!("{}: {} ({})", request.payee, e.message, e.value));
+                continue;
+            }
+        }
+        match store.create_transaction(request, false).await {
+            Ok(_) => response.imported += 1,
+            Err(e) if e.status == warp::http::StatusCode::CONFLICT => response.duplicates += 1,
+            Err(e) => response.errors.push(e.message),
+        }
+    }
+
+    let import_id = store
+        .record_import(ImportRecord {
+            id: None,
+            account_id,
+            source: format!("webhook:{}", source_name),
+            imported_at: Utc::now(),
+            rows,
+            imported: response.imported,
+            duplicates: response.duplicates,
+            errors: response.errors.len(),
+            statement: None,
+        })
+        .map_err(warp::reject::custom)?;
+    response.import_id = Some(import_id);
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        warp::http::StatusCode::OK,
+    ))
+}
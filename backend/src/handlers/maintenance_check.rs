@@ -0,0 +1,49 @@
+use crate::currency::round_half_even;
+use crate::limits::Slot;
+use crate::store::TransactionStore;
+use crate::types::MaintenanceCheckResponse;
+use crate::utils::{past_from_query, rounding_from_query, selector_from_query};
+use std::collections::HashMap;
+
+pub async fn maintenance_check_handler(
+    query_params: HashMap<String, String>,
+    store: TransactionStore,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let rounding = rounding_from_query(&query_params).map_err(warp::reject::custom)?;
+    let selector = selector_from_query(&query_params).map_err(warp::reject::custom)?;
+    // With as_of_revision or as_of_date, the balances as they were then
+    let past = past_from_query(&store, &query_params).await.map_err(warp::reject::custom)?;
+    let mut failed_assertions = store.failed_balance_assertions(past.as_ref(), &selector);
+    let mut low_balances = store.low_balance_accounts(past.as_ref(), &selector);
+
+    // Rounded only for display; whether an assertion holds is decided on exact amounts
+    if let Some(step_cents) = rounding {
+        for result in &mut failed_assertions {
+            result.assertion.balance_cents = round_half_even(result.assertion.balance_cents, step_cents);
+            result.actual_cents = round_half_even(result.actual_cents, step_cents);
+        }
+        for low in &mut low_balances {
+            low.balance_cents = round_half_even(low.balance_cents, step_cents);
+            low.threshold_cents = round_half_even(low.threshold_cents, step_cents);
+        }
+    }
+    // Low balances are worth knowing about but don't make the books wrong
+    let ok = failed_assertions.is_empty();
+
+    // Broken assertions mean the books no longer match the bank, so fail the check outright
+    let status = if ok {
+        warp::http::StatusCode::OK
+    } else {
+        warp::http::StatusCode::CONFLICT
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&MaintenanceCheckResponse {
+            ok,
+            failed_assertions,
+            low_balances,
+        }),
+        status,
+    ))
+}
@@ -0,0 +1,6 @@
+use crate::limits::Slot;
+use crate::store::TransactionStore;
+
+pub async fn memory_handler(store: TransactionStore, _slot: Slot) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&store.memory()))
+}
\ No newline at end of file
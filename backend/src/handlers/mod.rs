@@ -1,11 +1,93 @@
+pub mod account_grants;
+pub mod accounts;
 pub mod all_transactions;
+pub mod api_docs;
+pub mod api_versions;
+pub mod assert_balance;
+pub mod attach_statement;
+pub mod batch_import;
+pub mod bootstrap;
 pub mod bulk_import;
+pub mod close_month;
+pub mod compact;
+pub mod create_backup;
 pub mod create_transaction;
 pub mod current_transactions;
+pub mod dashboard;
+pub mod delete_transaction;
+pub mod edit_transaction;
+pub mod events;
+pub mod export;
+pub mod frontend;
+pub mod get_statement;
+pub mod get_transaction;
+pub mod hash_chain;
+pub mod import_archive;
+pub mod import_failures;
+pub mod import_metrics;
+pub mod ingest_webhook;
+pub mod maintenance_check;
+pub mod memory;
+pub mod net_worth;
+pub mod parse_text;
+pub mod payees;
+pub mod preview_mapping;
+pub mod projected_interest;
+pub mod query_transactions;
+pub mod remote_backup_status;
+pub mod restore_backup;
+pub mod snapshot_diff;
+pub mod status;
+pub mod subscriptions;
 pub mod update_memo;
+pub mod update_memos;
+pub mod update_metadata;
+pub mod users;
+pub mod verify;
 
+pub use account_grants::*;
+pub use accounts::*;
 pub use all_transactions::*;
+pub use api_docs::*;
+pub use api_versions::*;
+pub use assert_balance::*;
+pub use attach_statement::*;
+pub use batch_import::*;
+pub use bootstrap::*;
 pub use bulk_import::*;
+pub use close_month::*;
+pub use compact::*;
+pub use create_backup::*;
 pub use create_transaction::*;
 pub use current_transactions::*;
-pub use update_memo::*;
\ No newline at end of file
+pub use dashboard::*;
+pub use delete_transaction::*;
+pub use edit_transaction::*;
+pub use events::*;
+pub use export::*;
+pub use frontend::*;
+pub use get_statement::*;
+pub use get_transaction::*;
+pub use hash_chain::*;
+pub use import_archive::*;
+pub use import_failures::*;
+pub use import_metrics::*;
+pub use ingest_webhook::*;
+pub use maintenance_check::*;
+pub use memory::*;
+pub use net_worth::*;
+pub use parse_text::*;
+pub use payees::*;
+pub use preview_mapping::*;
+pub use projected_interest::*;
+pub use query_transactions::*;
+pub use remote_backup_status::*;
+pub use restore_backup::*;
+pub use snapshot_diff::*;
+pub use status::*;
+pub use subscriptions::*;
+pub use update_memo::*;
+pub use update_memos::*;
+pub use update_metadata::*;
+pub use users::*;
+pub use verify::*;
\ No newline at end of file
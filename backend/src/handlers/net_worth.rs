@@ -0,0 +1,24 @@
+use crate::limits::Slot;
+use crate::store::TransactionStore;
+use crate::types::NetWorthResponse;
+use crate::utils::{past_from_query, rounding_from_query, selector_from_query};
+use std::collections::HashMap;
+
+pub async fn net_worth_handler(
+    query_params: HashMap<String, String>,
+    store: TransactionStore,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let rounding = rounding_from_query(&query_params).map_err(warp::reject::custom)?;
+    let selector = selector_from_query(&query_params).map_err(warp::reject::custom)?;
+    // With as_of_revision or as_of_date, net worth as it was then
+    let past = past_from_query(&store, &query_params).await.map_err(warp::reject::custom)?;
+    let mut currencies = store.net_worth(past.as_ref(), &selector);
+
+    if let Some(step_cents) = rounding {
+        for total in &mut currencies {
+            total.round(step_cents);
+        }
+    }
+    Ok(warp::reply::json(&NetWorthResponse { currencies }))
+}
@@ -0,0 +1,64 @@
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::store::TransactionStore;
+use crate::text_entry;
+use crate::types::{CreateTransactionRequest, Metadata, ParseTextRequest, ParseTextResponse};
+use chrono::{NaiveTime, Utc};
+use std::sync::Arc;
+use warp::http::StatusCode;
+
+pub async fn parse_text_handler(
+    request: ParseTextRequest,
+    config: Arc<Config>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let text_entry = &config.text_entry;
+    let today = request.today.unwrap_or_else(|| Utc::now().date_naive());
+    let parsed = text_entry::parse(&request.text, today, text_entry).map_err(bad_request)?;
+
+    let account_id = request
+        .account_id
+        .or_else(|| text_entry.account_id.clone())
+        .ok_or_else(|| bad_request("No account_id given, and text_entry.account_id isn't configured".to_string()))?;
+    let currency = parsed
+        .currency
+        .or(request.currency)
+        .or_else(|| text_entry.currency.clone())
+        .ok_or_else(|| bad_request("No currency in the text or given, and text_entry.currency isn't configured".to_string()))?;
+    // Dates alone are taken as midnight UTC, as imported files' are
+    let timestamp = match parsed.date {
+        Some(date) => date.and_time(NaiveTime::MIN).and_utc(),
+        None => Utc::now(),
+    };
+    // Spelt as it already is, so the payee isn't split in two by its case
+    let payee = store
+        .payees(Some(&parsed.payee), usize::MAX)
+        .into_iter()
+        .find(|known| known.payee.eq_ignore_ascii_case(&parsed.payee))
+        .map_or(parsed.payee, |known| known.payee);
+    let mut metadata = Metadata::new();
+    if let Some(category) = parsed.category {
+        metadata.insert("category".to_string(), category.into());
+    }
+
+    Ok(warp::reply::json(&ParseTextResponse {
+        draft: CreateTransactionRequest {
+            account_id,
+            timestamp,
+            payee,
+            amount: parsed.amount,
+            currency,
+            allow_duplicate: false,
+            foreign_currency: false,
+            metadata,
+        },
+        ignored: parsed.ignored,
+    }))
+}
+
+fn bad_request(message: String) -> warp::Rejection {
+    warp::reject::custom(ApiError {
+        message,
+        status: StatusCode::BAD_REQUEST,
+    })
+}
\ No newline at end of file
@@ -0,0 +1,21 @@
+use crate::currency::round_half_even;
+use crate::store::TransactionStore;
+use crate::utils::{get_usize_param, rounding_from_query};
+use std::collections::HashMap;
+
+pub async fn payees_handler(
+    query_params: HashMap<String, String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let limit = get_usize_param(&query_params, "limit", 10).map_err(warp::reject::custom)?;
+    let prefix = query_params.get("prefix").map(String::as_str);
+    let rounding = rounding_from_query(&query_params).map_err(warp::reject::custom)?;
+
+    let mut payees = store.payees(prefix, limit);
+    if let Some(step_cents) = rounding {
+        for total in payees.iter_mut().flat_map(|payee| payee.totals.iter_mut()) {
+            total.total_cents = round_half_even(total.total_cents, step_cents);
+        }
+    }
+    Ok(warp::reply::json(&payees))
+}
\ No newline at end of file
@@ -0,0 +1,64 @@
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::import::{AmountNormalizer, CsvParser, PayeeNormalizer, Pipeline, Utf8Decoder, describe};
+use crate::limits::Slot;
+use crate::types::{ColumnMapping, PreviewMappingRequest, PreviewMappingResponse};
+use std::sync::Arc;
+
+pub async fn preview_mapping_handler(
+    request: PreviewMappingRequest,
+    config: Arc<Config>,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let profile = &request.profile;
+    profile.validate().map_err(|message| warp::reject::custom(ApiError {
+        message,
+        status: warp::http::StatusCode::BAD_REQUEST,
+    }))?;
+
+    let headers = profile
+        .reader(&request.sample)
+        .headers()
+        .map_err(|e| warp::reject::custom(ApiError {
+            message: format!("CSV parsing error - {}", e),
+            status: warp::http::StatusCode::BAD_REQUEST,
+        }))?
+        .clone();
+
+    let columns = headers
+        .iter()
+        .enumerate()
+        .map(|(index, header)| ColumnMapping {
+            index,
+            header: header.to_string(),
+            field: profile.field_for(header),
+        })
+        .collect();
+
+    // An incomplete mapping is an expected wizard state, so report it rather than fail
+    let missing_columns = profile.columns(&headers).err().unwrap_or_default();
+    let (transactions, errors) = if missing_columns.is_empty() {
+        let pipeline = Pipeline {
+            decoder: &Utf8Decoder,
+            parser: &CsvParser { profile },
+            normalizer: &AmountNormalizer {
+                precision: config.amount_precision,
+                payees: PayeeNormalizer::new(&config.payee_normalization),
+            },
+        };
+        let (parsed, errors) = pipeline
+            .read(request.sample.as_bytes(), "preview", Some(request.limit))
+            .unwrap_or_default();
+        let errors = errors.iter().map(describe).collect();
+        (parsed.into_iter().map(|(id, _, _)| id).collect(), errors)
+    } else {
+        (vec![], vec![])
+    };
+
+    Ok(warp::reply::json(&PreviewMappingResponse {
+        columns,
+        missing_columns,
+        transactions,
+        errors,
+    }))
+}
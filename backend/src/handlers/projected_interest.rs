@@ -0,0 +1,41 @@
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::interest;
+use crate::store::TransactionStore;
+use crate::types::ProjectedInterestResponse;
+use crate::utils::get_usize_param;
+use chrono::{TimeDelta, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const DEFAULT_DAYS: usize = 365;
+const MAX_DAYS: usize = 10 * 365;
+
+/// Projects the current balances from today, as nothing is known of the
+/// transactions still to come
+pub async fn projected_interest_handler(
+    account_id: String,
+    query_params: HashMap<String, String>,
+    config: Arc<Config>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let days = get_usize_param(&query_params, "days", DEFAULT_DAYS)
+        .map_err(warp::reject::custom)?
+        .min(MAX_DAYS);
+    let rates = config.interest_rates.get(&account_id).ok_or_else(|| {
+        warp::reject::custom(ApiError {
+            message: "No interest rates are configured for this account".to_string(),
+            status: warp::http::StatusCode::NOT_FOUND,
+        })
+    })?;
+
+    let from = Utc::now().date_naive();
+    let until = from + TimeDelta::days(days as i64);
+    let balances = interest::project(rates, &store.account_balances(&account_id), from, until);
+    Ok(warp::reply::json(&ProjectedInterestResponse {
+        account_id,
+        from,
+        until,
+        balances,
+    }))
+}
\ No newline at end of file
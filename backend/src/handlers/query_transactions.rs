@@ -0,0 +1,43 @@
+use crate::error::ApiError;
+use crate::query;
+use crate::store::TransactionStore;
+use crate::utils::{page_from_query, past_from_query, sort_from_query};
+use warp::Reply;
+use std::collections::HashMap;
+use warp::http::StatusCode;
+
+/// Like the current transactions listing, but narrowed down with the query
+/// language in `q` rather than the filter parameters, and likewise as of an
+/// earlier moment
+pub async fn query_transactions_handler(
+    query_params: HashMap<String, String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let text = query_params.get("q").ok_or_else(|| {
+        warp::reject::custom(ApiError {
+            message: "Missing q parameter".to_string(),
+            status: StatusCode::BAD_REQUEST,
+        })
+    })?;
+    let query = query::parse(text).map_err(|e| {
+        warp::reject::custom(ApiError {
+            message: format!("Invalid query - {}", e),
+            status: StatusCode::BAD_REQUEST,
+        })
+    })?;
+    let sort = sort_from_query(&query_params).map_err(warp::reject::custom)?;
+    let page = page_from_query(&query_params).map_err(warp::reject::custom)?;
+    let past = past_from_query(&store, &query_params).await.map_err(warp::reject::custom)?;
+    let (body, total) = store.query_transactions_json(&query, sort, page, past.as_ref()).map_err(|e| {
+        warp::reject::custom(ApiError {
+            message: format!("Failed to serialize transactions: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    })?;
+    let reply = warp::reply::with_header(body, "content-type", "application/json");
+    let reply = warp::reply::with_header(reply, "x-total-count", total.to_string());
+    match past {
+        Some(past) => Ok(warp::reply::with_header(reply, "x-as-of-revision", past.revision.to_string()).into_response()),
+        None => Ok(reply.into_response()),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,23 @@
+use crate::backup::Backups;
+use crate::error::ApiError;
+use warp::http::StatusCode;
+
+pub async fn remote_backup_status_handler(backups: Backups) -> Result<impl warp::Reply, warp::Rejection> {
+    let status = backups
+        .remote_status()
+        .await
+        .ok_or_else(|| {
+            warp::reject::custom(ApiError {
+                message: "No remote is configured for backups".to_string(),
+                status: StatusCode::NOT_FOUND,
+            })
+        })?
+        .map_err(|e| {
+            warp::reject::custom(ApiError {
+                message: format!("Failed to read remote backup status: {}", e),
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+            })
+        })?;
+
+    Ok(warp::reply::json(&status))
+}
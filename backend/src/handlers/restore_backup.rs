@@ -0,0 +1,12 @@
+use crate::backup::Backups;
+use crate::limits::Slot;
+use crate::types::RestoreRequest;
+
+pub async fn restore_backup_handler(
+    request: RestoreRequest,
+    backups: Backups,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = backups.restore(&request.backup).await.map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&response))
+}
@@ -0,0 +1,15 @@
+use crate::backup::{Backups, CURRENT};
+use crate::limits::Slot;
+use crate::utils::get_required_param;
+use std::collections::HashMap;
+
+pub async fn snapshot_diff_handler(
+    query_params: HashMap<String, String>,
+    backups: Backups,
+    _slot: Slot,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let a = get_required_param(&query_params, "a").map_err(warp::reject::custom)?;
+    let b = query_params.get("b").map_or(CURRENT, String::as_str);
+    let response = backups.diff(&a, b).await.map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&response))
+}
\ No newline at end of file
@@ -0,0 +1,20 @@
+use crate::store::TransactionStore;
+use crate::types::StatusResponse;
+use std::sync::Arc;
+
+pub async fn status_handler(
+    status: Arc<StatusResponse>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut status = (*status).clone();
+    if let Some(failure) = store.write_failure() {
+        status.ok = false;
+        status.write_failure = Some(failure);
+    }
+    let code = if status.ok {
+        warp::http::StatusCode::OK
+    } else {
+        warp::http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&status), code))
+}
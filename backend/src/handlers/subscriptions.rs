@@ -0,0 +1,39 @@
+use crate::subscriptions::Subscriptions;
+use crate::types::CreateSubscriptionRequest;
+
+pub async fn list_subscriptions_handler(
+    owner: Option<String>,
+    subscriptions: Subscriptions,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&subscriptions.list(owner.as_deref()).await))
+}
+
+pub async fn create_subscription_handler(
+    request: CreateSubscriptionRequest,
+    owner: Option<String>,
+    subscriptions: Subscriptions,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let subscription = subscriptions.create(request, owner).await.map_err(warp::reject::custom)?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&subscription),
+        warp::http::StatusCode::CREATED,
+    ))
+}
+
+pub async fn delete_subscription_handler(
+    id: String,
+    owner: Option<String>,
+    subscriptions: Subscriptions,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    subscriptions.delete(&id, owner.as_deref()).await.map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&serde_json::json!({"message": "Subscription deleted successfully"})))
+}
+
+pub async fn subscription_deliveries_handler(
+    id: String,
+    owner: Option<String>,
+    subscriptions: Subscriptions,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let deliveries = subscriptions.deliveries(&id, owner.as_deref()).await.map_err(warp::reject::custom)?;
+    Ok(warp::reply::json(&deliveries))
+}
\ No newline at end of file
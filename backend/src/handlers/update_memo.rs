@@ -1,6 +1,6 @@
 use crate::store::TransactionStore;
-use crate::types::{TransactionId, UpdateMemoRequest};
-use crate::utils::{get_required_param, parse_timestamp, parse_amount};
+use crate::types::UpdateMemoRequest;
+use crate::utils::{override_lock, transaction_from_uuid, transaction_id_from_query};
 use std::collections::HashMap;
 use warp;
 
@@ -8,27 +8,41 @@ pub async fn update_memo_handler(
     account_id: String,
     memo_request: UpdateMemoRequest,
     query_params: HashMap<String, String>,
+    if_match: Option<String>,
     store: TransactionStore,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let timestamp_str = get_required_param(&query_params, "timestamp").map_err(warp::reject::custom)?;
-    let amount_str = get_required_param(&query_params, "amount").map_err(warp::reject::custom)?;
-    let currency = get_required_param(&query_params, "currency").map_err(warp::reject::custom)?;
-    let payee = get_required_param(&query_params, "payee").map_err(warp::reject::custom)?;
+    let transaction_id = transaction_id_from_query(&query_params).map_err(warp::reject::custom)?;
 
-    let timestamp = parse_timestamp(&timestamp_str).map_err(warp::reject::custom)?;
-    let amount = parse_amount(&amount_str).map_err(warp::reject::custom)?;
+    let etag = store
+        .update_transaction_memo(account_id, transaction_id, memo_request.memo, override_lock(&query_params), if_match.as_deref())
+        .await.map_err(warp::reject::custom)?;
 
-    let transaction_id = TransactionId {
-        timestamp,
-        amount_cents: (amount * 100.0).round() as i64,
-        currency,
-        payee,
-    };
+    Ok(warp::reply::with_header(
+        warp::reply::json(&serde_json::json!({"message": "Memo updated successfully"})),
+        "etag",
+        etag,
+    ))
+}
+
+
+/// The same update, for a transaction named by its uuid
+pub async fn update_memo_by_uuid_handler(
+    uuid: String,
+    memo_request: UpdateMemoRequest,
+    query_params: HashMap<String, String>,
+    if_match: Option<String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (account_id, transaction_id) = transaction_from_uuid(&store, &uuid).map_err(warp::reject::custom)?;
 
-    store.update_transaction_memo(account_id, transaction_id, memo_request.memo).await.map_err(warp::reject::custom)?;
+    let etag = store
+        .update_transaction_memo(account_id, transaction_id, memo_request.memo, override_lock(&query_params), if_match.as_deref())
+        .await
+        .map_err(warp::reject::custom)?;
 
-    Ok(warp::reply::with_status(
+    Ok(warp::reply::with_header(
         warp::reply::json(&serde_json::json!({"message": "Memo updated successfully"})),
-        warp::http::StatusCode::OK,
+        "etag",
+        etag,
     ))
 }
\ No newline at end of file
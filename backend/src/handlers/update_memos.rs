@@ -0,0 +1,22 @@
+use crate::store::TransactionStore;
+use crate::types::{UpdateMemosRequest, UpdateMemosResponse};
+use crate::utils::override_lock;
+use std::collections::HashMap;
+
+pub async fn update_memos_handler(
+    memos_request: UpdateMemosRequest,
+    query_params: HashMap<String, String>,
+    if_match: Option<String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (updated, etag) = store
+        .update_transaction_memos(memos_request.memos, override_lock(&query_params), if_match.as_deref())
+        .await
+        .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_header(
+        warp::reply::json(&UpdateMemosResponse { updated }),
+        "etag",
+        etag,
+    ))
+}
\ No newline at end of file
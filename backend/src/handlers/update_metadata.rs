@@ -0,0 +1,60 @@
+use crate::store::TransactionStore;
+use crate::types::UpdateMetadataRequest;
+use crate::utils::{override_lock, transaction_from_uuid, transaction_id_from_query};
+use std::collections::HashMap;
+
+pub async fn update_metadata_handler(
+    account_id: String,
+    metadata_request: UpdateMetadataRequest,
+    query_params: HashMap<String, String>,
+    if_match: Option<String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let transaction_id = transaction_id_from_query(&query_params).map_err(warp::reject::custom)?;
+
+    let (metadata, etag) = store
+        .update_transaction_metadata(
+            account_id,
+            transaction_id,
+            metadata_request.metadata,
+            override_lock(&query_params),
+            if_match.as_deref(),
+        )
+        .await
+        .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_header(
+        warp::reply::json(&serde_json::json!({ "metadata": metadata })),
+        "etag",
+        etag,
+    ))
+}
+
+
+/// The same update, for a transaction named by its uuid
+pub async fn update_metadata_by_uuid_handler(
+    uuid: String,
+    metadata_request: UpdateMetadataRequest,
+    query_params: HashMap<String, String>,
+    if_match: Option<String>,
+    store: TransactionStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (account_id, transaction_id) = transaction_from_uuid(&store, &uuid).map_err(warp::reject::custom)?;
+
+    let (metadata, etag) = store
+        .update_transaction_metadata(
+            account_id,
+            transaction_id,
+            metadata_request.metadata,
+            override_lock(&query_params),
+            if_match.as_deref(),
+        )
+        .await
+        .map_err(warp::reject::custom)?;
+
+    Ok(warp::reply::with_header(
+        warp::reply::json(&serde_json::json!({ "metadata": metadata })),
+        "etag",
+        etag,
+    ))
+}
\ No newline at end of file
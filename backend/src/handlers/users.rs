@@ -0,0 +1,78 @@
+use crate::error::AuthError;
+use crate::types::{CreateTokenRequest, Credentials, Scope, SessionResponse, User};
+use crate::users::{Users, session_token};
+use warp::http::{HeaderMap, StatusCode};
+
+/// Anyone may register while there are no users, so the first can sign up;
+/// after that it takes a signed-in user unless registration is open
+pub async fn register_handler(
+    headers: HeaderMap,
+    credentials: Credentials,
+    users: Users,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Registering is a change, which read and import tokens can't make
+    let signed_in = match session_token(&headers) {
+        Some(token) => users.token_user(token).await.is_some_and(|(_, scope)| scope == Scope::Full),
+        None => false,
+    };
+    let user = users.register(credentials, signed_in).await.map_err(warp::reject::custom)?;
+    Ok(warp::reply::with_status(warp::reply::json(&user), StatusCode::CREATED))
+}
+
+pub async fn login_handler(credentials: Credentials, users: Users) -> Result<impl warp::Reply, warp::Rejection> {
+    let (token, expires_at, user) = users.login(credentials).await.map_err(warp::reject::custom)?;
+    let cookie = users.cookie(&token, expires_at);
+    let response = SessionResponse {
+        token,
+        expires_at,
+        user,
+    };
+    Ok(warp::reply::with_header(warp::reply::json(&response), "set-cookie", cookie))
+}
+
+pub async fn logout_handler(headers: HeaderMap, users: Users) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Some(token) = session_token(&headers) {
+        users.logout(token).await.map_err(warp::reject::custom)?;
+    }
+    Ok(warp::reply::with_header(
+        StatusCode::NO_CONTENT,
+        "set-cookie",
+        users.cleared_cookie(),
+    ))
+}
+
+pub async fn current_user_handler(headers: HeaderMap, users: Users) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&signed_in_user(&headers, &users).await?))
+}
+
+pub async fn create_token_handler(
+    headers: HeaderMap,
+    request: CreateTokenRequest,
+    users: Users,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = signed_in_user(&headers, &users).await?;
+    let created = users.create_token(&user, request).await.map_err(warp::reject::custom)?;
+    Ok(warp::reply::with_status(warp::reply::json(&created), StatusCode::CREATED))
+}
+
+pub async fn list_tokens_handler(headers: HeaderMap, users: Users) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = signed_in_user(&headers, &users).await?;
+    Ok(warp::reply::json(&users.tokens(&user).await))
+}
+
+pub async fn revoke_token_handler(id: String, headers: HeaderMap, users: Users) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = signed_in_user(&headers, &users).await?;
+    users.revoke_token(&user, &id).await.map_err(warp::reject::custom)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The local user whose session or API token the request was sent with
+async fn signed_in_user(headers: &HeaderMap, users: &Users) -> Result<User, warp::Rejection> {
+    let token = session_token(headers)
+        .ok_or_else(|| warp::reject::custom(AuthError::new("Sign in with POST /auth/login first")))?;
+    users
+        .token_user(token)
+        .await
+        .map(|(user, _)| user)
+        .ok_or_else(|| warp::reject::custom(AuthError::new("The session has expired or was signed out")))
+}
\ No newline at end of file
@@ -0,0 +1,19 @@
+use crate::limits::Slot;
+use crate::store::TransactionStore;
+use crate::types::VerifyResponse;
+
+pub async fn verify_handler(store: TransactionStore, _slot: Slot) -> Result<impl warp::Reply, warp::Rejection> {
+    let discrepancies = store.verify();
+    let ok = discrepancies.is_empty();
+
+    let status = if ok {
+        warp::http::StatusCode::OK
+    } else {
+        warp::http::StatusCode::CONFLICT
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&VerifyResponse { ok, discrepancies }),
+        status,
+    ))
+}
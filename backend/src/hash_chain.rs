@@ -0,0 +1,190 @@
+use crate::config::IntegrityConfig;
+use crate::storage::StorageError;
+use crate::store::TransactionStore;
+use crate::types::{ChainCheckpoint, ChainMismatch, IntegrityChain, IntegrityResponse, IntegrityRun};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::Mutex;
+
+/// Events read from the log at a time
+const BATCH_SIZE: usize = 1000;
+
+/// Events between the checkpoints kept of the chain
+const CHECKPOINT_EVERY: u64 = 1000;
+
+/// A hash chained over the event log, each event's hash covering the one
+/// before it, so changing, removing or reordering any event changes every
+/// hash after it. Each check hashes the whole log again and compares it with
+/// the checkpoints recorded by the last, so changes made to the storage
+/// backend's files while the server wasn't looking are found.
+#[derive(Clone)]
+pub struct HashChain {
+    path: PathBuf,
+    store: TransactionStore,
+    // As kept in the file, which is rewritten after every check
+    chain: Arc<Mutex<IntegrityChain>>,
+}
+
+impl HashChain {
+    pub async fn new(config: &IntegrityConfig, store: TransactionStore) -> Result<Self, StorageError> {
+        let path = PathBuf::from(&config.file);
+        let chain = if path.exists() {
+            serde_json::from_slice(&fs::read(&path).await?)?
+        } else {
+            IntegrityChain::default()
+        };
+        Ok(Self {
+            path,
+            store,
+            chain: Arc::new(Mutex::new(chain)),
+        })
+    }
+
+    /// Check the log every `interval`, starting now
+    pub fn spawn_schedule(&self, interval: Duration) {
+        let hash_chain = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = hash_chain.check().await {
+                    tracing::warn!("Failed to record the event log's hashes: {}", e);
+                }
+            }
+        });
+    }
+
+    pub async fn status(&self) -> IntegrityResponse {
+        let chain = self.chain.lock().await;
+        IntegrityResponse {
+            ok: chain.last_run.as_ref().is_none_or(|run| run.mismatch.is_none() && run.error.is_none()),
+            head: chain.checkpoints.last().cloned(),
+            last_run: chain.last_run.clone(),
+        }
+    }
+
+    /// Hash the whole log and compare it with the checkpoints recorded by the
+    /// last check. The checkpoints move on only when it matches, so a mismatch
+    /// keeps being reported until the file is removed.
+    async fn check(&self) -> Result<(), StorageError> {
+        let started_at = Utc::now();
+        let recorded = self.chain.lock().await.checkpoints.clone();
+        let (run, checkpoints) = match self.hash(&recorded).await {
+            Ok((events, checkpoints, mismatch)) => {
+                if let Some(mismatch) = &mismatch {
+                    tracing::warn!(
+                        "The event log has changed since it was last checked, at or before event {}",
+                        mismatch.seq
+                    );
+                }
+                let run = IntegrityRun {
+                    started_at,
+                    finished_at: Utc::now(),
+                    events,
+                    error: None,
+                    mismatch: mismatch.clone(),
+                };
+                (run, if mismatch.is_none() { checkpoints } else { recorded })
+            }
+            Err(e) => {
+                let run = IntegrityRun {
+                    started_at,
+                    finished_at: Utc::now(),
+                    events: 0,
+                    mismatch: None,
+                    error: Some(e.to_string()),
+                };
+                (run, recorded)
+            }
+        };
+
+        let mut chain = self.chain.lock().await;
+        chain.checkpoints = checkpoints;
+        chain.last_run = Some(run);
+        self.save(&chain).await
+    }
+
+    /// How many events the log has, its checkpoints, and the first of
+    /// `recorded` it doesn't match
+    async fn hash(
+        &self,
+        recorded: &[ChainCheckpoint],
+    ) -> Result<(u64, Vec<ChainCheckpoint>, Option<ChainMismatch>), StorageError> {
+        let mut recorded = recorded.iter().peekable();
+        let mut mismatch = None;
+        let mut checkpoints = Vec::new();
+        let mut head = None;
+        let mut hash = [0u8; 32];
+        let (mut after, mut count) = (0, 0);
+        loop {
+            let events = self.store.events(after, BATCH_SIZE).await?;
+            for logged in &events {
+                let mut hasher = Sha256::new();
+                hasher.update(hash);
+                hasher.update(serde_json::to_vec(logged)?);
+                hash = hasher.finalize().into();
+                let checkpoint = ChainCheckpoint {
+                    seq: logged.seq,
+                    hash: hex(&hash),
+                };
+
+                // A checkpoint skipped over is of an event that was removed
+                while let Some(expected) = recorded.next_if(|expected| expected.seq <= logged.seq) {
+                    if mismatch.is_none() && (expected.seq < logged.seq || expected.hash != checkpoint.hash) {
+                        mismatch = Some(ChainMismatch {
+                            seq: expected.seq,
+                            recorded: expected.hash.clone(),
+                            found: (expected.seq == logged.seq).then(|| checkpoint.hash.clone()),
+                        });
+                    }
+                }
+                after = logged.seq;
+                count += 1;
+                if logged.seq % CHECKPOINT_EVERY == 0 {
+                    checkpoints.push(checkpoint.clone());
+                }
+                head = Some(checkpoint);
+            }
+            if events.len() < BATCH_SIZE {
+                break;
+            }
+        }
+
+        if let Some(head) = head
+            && head.seq % CHECKPOINT_EVERY != 0
+        {
+            checkpoints.push(head);
+        }
+
+        // The log ends before events it had
+        if let Some(expected) = recorded.next()
+            && mismatch.is_none()
+        {
+            mismatch = Some(ChainMismatch {
+                seq: expected.seq,
+                recorded: expected.hash.clone(),
+                found: None,
+            });
+        }
+        Ok((count, checkpoints, mismatch))
+    }
+
+    async fn save(&self, chain: &IntegrityChain) -> Result<(), StorageError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(chain)?).await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,363 @@
+use crate::config::IdempotencyConfig;
+use crate::error::ApiError;
+use crate::storage::StorageError;
+use bytes::Bytes;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::fs;
+use warp::http::header::HeaderName;
+use warp::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use warp::reply::Response;
+use warp::Reply;
+
+/// Longest `Idempotency-Key` accepted
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Responses to requests sent with an `Idempotency-Key` header, so a client
+/// retrying one after losing the response gets the same answer back instead
+/// of the request being carried out twice. Only successful responses are
+/// kept; a request that failed changed nothing and is simply run again.
+/// They are kept in a file of their own, so retries are answered across
+/// restarts too.
+#[derive(Clone)]
+pub struct Idempotency {
+    // method, path and key -> what became of the request
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    retention: TimeDelta,
+    path: PathBuf,
+    // Held while saving, so the file is written one save at a time
+    saving: Arc<tokio::sync::Mutex<()>>,
+}
+
+enum Entry {
+    Running,
+    Done(Done),
+}
+
+/// A request that was answered, as kept in the file
+#[derive(Clone, Serialize, Deserialize)]
+struct Done {
+    at: DateTime<Utc>,
+    /// What the request asked for, from `fingerprint`
+    fingerprint: String,
+    response: StoredResponse,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    #[serde(with = "base64_body")]
+    body: Bytes,
+}
+
+impl StoredResponse {
+    fn new(status: StatusCode, headers: &HeaderMap, body: Bytes) -> Self {
+        Self {
+            status: status.as_u16(),
+            headers: headers
+                .iter()
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+                .collect(),
+            body,
+        }
+    }
+
+    fn to_response(&self) -> Response {
+        let mut response = Response::new(self.body.clone().into());
+        *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                response.headers_mut().append(name, value);
+            }
+        }
+        response
+    }
+}
+
+/// Response bodies in the file, as base64 since they needn't be text
+mod base64_body {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map(Bytes::from).map_err(serde::de::Error::custom)
+    }
+}
+
+/// What a request asks for, from its `query` and the parts of its `body`,
+/// to tell a retry from a different request reusing its key
+pub fn fingerprint(body: &[&[u8]], query: &HashMap<String, String>) -> String {
+    let mut query: Vec<_> = query.iter().collect();
+    query.sort();
+    let mut hasher = Sha256::new();
+    // Each part is prefixed with its length, so parts can't run into each other
+    let parts = query
+        .into_iter()
+        .flat_map(|(name, value)| [name.as_bytes(), value.as_bytes()])
+        .chain(body.iter().copied());
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// What a handler should do about the request's `Idempotency-Key`
+pub enum Claim {
+    /// The request has no key
+    Unkeyed,
+    /// The key was seen before; answer with the response given then, as long
+    /// as the request is the same as the one given it
+    Replay { fingerprint: String, response: Response },
+    /// The key is new and held for this request until it finishes
+    Fresh(Reservation),
+}
+
+/// A key held by a request in progress. Dropping it without a response, as
+/// when the handler fails or the client goes away, frees the key again.
+pub struct Reservation {
+    idempotency: Idempotency,
+    scope: String,
+    finished: bool,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.idempotency.entries.lock().unwrap().remove(&self.scope);
+        }
+    }
+}
+
+impl Idempotency {
+    pub async fn new(config: &IdempotencyConfig) -> Result<Self, StorageError> {
+        let path = PathBuf::from(&config.file);
+        let retention = TimeDelta::seconds(config.retention_secs.try_into().unwrap_or(i64::MAX));
+        let saved: HashMap<String, Done> = if path.exists() {
+            serde_json::from_slice(&fs::read(&path).await?)?
+        } else {
+            HashMap::new()
+        };
+        let entries = saved
+            .into_iter()
+            .filter(|(_, done)| Utc::now() - done.at < retention)
+            .map(|(scope, done)| (scope, Entry::Done(done)))
+            .collect();
+        Ok(Self {
+            entries: Arc::new(Mutex::new(entries)),
+            retention,
+            path,
+            saving: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    /// Look up `key` for a request from `user` with this method and path. A
+    /// key reused while its first request is still running is refused with 409.
+    pub fn claim(&self, user: Option<&str>, key: Option<String>, method: &Method, path: &str) -> Result<Claim, ApiError> {
+        let Some(key) = key else {
+            return Ok(Claim::Unkeyed);
+        };
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+            return Err(ApiError {
+                message: format!("Idempotency-Key must be 1 to {} characters", MAX_KEY_LENGTH),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+
+        // Users choosing the same key get their own responses back
+        let scope = format!("{} {} {} {}", user.unwrap_or_default(), method, path, key);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| match entry {
+            Entry::Running => true,
+            Entry::Done(done) => Utc::now() - done.at < self.retention,
+        });
+        match entries.get(&scope) {
+            Some(Entry::Running) => Err(ApiError {
+                message: "A request with this Idempotency-Key is still being processed".to_string(),
+                status: StatusCode::CONFLICT,
+            }),
+            Some(Entry::Done(done)) => {
+                let mut response = done.response.to_response();
+                response
+                    .headers_mut()
+                    .insert("idempotent-replayed", HeaderValue::from_static("true"));
+                Ok(Claim::Replay {
+                    fingerprint: done.fingerprint.clone(),
+                    response,
+                })
+            }
+            None => {
+                entries.insert(scope.clone(), Entry::Running);
+                Ok(Claim::Fresh(Reservation {
+                    idempotency: self.clone(),
+                    scope,
+                    finished: false,
+                }))
+            }
+        }
+    }
+
+    /// Write every response still kept to the file
+    async fn save(&self) -> Result<(), StorageError> {
+        // Taken while saving, so the file never goes back to an older set
+        let _saving = self.saving.lock().await;
+        let done: HashMap<String, Done> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(scope, entry)| match entry {
+                Entry::Done(done) => Some((scope.clone(), done.clone())),
+                Entry::Running => None,
+            })
+            .collect();
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&done)?).await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+impl Claim {
+    /// Run `handler` unless the request is a replay, keeping its response
+    /// when the request has a key. A key reused for a request whose
+    /// `fingerprint` differs from the one it was first used for is refused
+    /// with 422.
+    pub async fn run<F, R>(self, fingerprint: String, handler: F) -> Result<Response, warp::Rejection>
+    where
+        F: Future<Output = Result<R, warp::Rejection>>,
+        R: Reply,
+    {
+        let mut reservation = match self {
+            Claim::Unkeyed => return handler.await.map(Reply::into_response),
+            Claim::Replay { fingerprint: first, response } if first == fingerprint => return Ok(response),
+            Claim::Replay { .. } => {
+                return Err(warp::reject::custom(ApiError {
+                    message: "This Idempotency-Key was used for a different request".to_string(),
+                    status: StatusCode::UNPROCESSABLE_ENTITY,
+                }));
+            }
+            Claim::Fresh(reservation) => reservation,
+        };
+
+        let (parts, body) = handler.await?.into_response().into_parts();
+        let body = warp::hyper::body::to_bytes(body).await.map_err(|e| {
+            warp::reject::custom(ApiError {
+                message: format!("Failed to read response: {}", e),
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+            })
+        })?;
+        let response = StoredResponse::new(parts.status, &parts.headers, body);
+
+        let idempotency = reservation.idempotency.clone();
+        idempotency.entries.lock().unwrap().insert(
+            reservation.scope.clone(),
+            Entry::Done(Done {
+                at: Utc::now(),
+                fingerprint,
+                response: response.clone(),
+            }),
+        );
+        reservation.finished = true;
+        // The request is done either way; only retries after a restart miss out
+        if let Err(e) = idempotency.save().await {
+            tracing::warn!("Failed to save the response for its Idempotency-Key: {}", e);
+        }
+        Ok(response.to_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn config(name: &str) -> IdempotencyConfig {
+        let file = temp_path(&format!("idempotency-{}.json", name));
+        IdempotencyConfig {
+            file: file.to_string_lossy().into_owned(),
+            ..IdempotencyConfig::default()
+        }
+    }
+
+    /// Send `body` with `key` to a handler counting how often it runs
+    async fn send(idempotency: &Idempotency, body: &str, runs: &AtomicUsize) -> Result<Response, warp::Rejection> {
+        let claim = idempotency.claim(Some("alice"), Some("key".to_string()), &Method::POST, "/transactions")?;
+        let fingerprint = fingerprint(&[body.as_bytes()], &HashMap::new());
+        claim
+            .run(fingerprint, async {
+                let n = runs.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, warp::Rejection>(warp::reply::with_status(format!("run {}", n), StatusCode::CREATED))
+            })
+            .await
+    }
+
+    async fn body(response: Response) -> Bytes {
+        warp::hyper::body::to_bytes(response.into_body()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_a_retry_with_the_first_response() {
+        let idempotency = Idempotency::new(&config("retry")).await.unwrap();
+        let runs = AtomicUsize::new(0);
+
+        let first = send(&idempotency, "coffee", &runs).await.unwrap();
+        let retry = send(&idempotency, "coffee", &runs).await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+        assert_eq!(body(first).await, body(retry).await);
+    }
+
+    #[tokio::test]
+    async fn refuses_a_key_reused_for_a_different_body() {
+        let idempotency = Idempotency::new(&config("different")).await.unwrap();
+        let runs = AtomicUsize::new(0);
+
+        send(&idempotency, "coffee", &runs).await.unwrap();
+        let rejection = send(&idempotency, "tea", &runs).await.unwrap_err();
+        let error = rejection.find::<ApiError>().unwrap();
+        assert_eq!(error.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_responses_across_restarts() {
+        let config = config("restart");
+        let runs = AtomicUsize::new(0);
+        let first = send(&Idempotency::new(&config).await.unwrap(), "coffee", &runs).await.unwrap();
+
+        let restarted = Idempotency::new(&config).await.unwrap();
+        let retry = send(&restarted, "coffee", &runs).await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(body(first).await, body(retry).await);
+        std::fs::remove_file(&config.file).unwrap();
+    }
+
+    #[test]
+    fn tells_parts_apart_wherever_they_split() {
+        let query = HashMap::new();
+        assert_ne!(fingerprint(&[b"ab", b"c"], &query), fingerprint(&[b"a", b"bc"], &query));
+        let dry_run = HashMap::from([("dry_run".to_string(), "true".to_string())]);
+        assert_ne!(fingerprint(&[b"abc"], &query), fingerprint(&[b"abc"], &dry_run));
+    }
+}
@@ -0,0 +1,195 @@
+use super::{ParsedRow, Parser};
+use crate::types::RawTransaction;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use csv::{ReaderBuilder, StringRecord};
+use serde::{Deserialize, Serialize};
+
+/// How the columns of a bank's CSV export map onto transaction fields.
+/// The default matches the `timestamp,payee,amount,currency` layout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportProfile {
+    pub delimiter: char,
+    pub timestamp_column: String,
+    /// chrono format of the timestamp column, RFC 3339 when absent.
+    /// Date-only formats are read as midnight UTC.
+    pub timestamp_format: Option<String>,
+    pub payee_column: String,
+    pub amount_column: String,
+    /// Flip the sign of every amount, for banks that export spending as positive
+    pub negate_amounts: bool,
+    pub currency_column: Option<String>,
+    /// Currency of every row, for exports without a currency column
+    pub currency: Option<String>,
+}
+
+impl Default for ImportProfile {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            timestamp_column: "timestamp".to_string(),
+            timestamp_format: None,
+            payee_column: "payee".to_string(),
+            amount_column: "amount".to_string(),
+            negate_amounts: false,
+            currency_column: Some("currency".to_string()),
+            currency: None,
+        }
+    }
+}
+
+/// Positions of the mapped columns within a file's header row
+pub struct Columns {
+    timestamp: usize,
+    payee: usize,
+    amount: usize,
+    currency: Option<usize>,
+}
+
+impl ImportProfile {
+    pub fn reader<'a>(&self, csv: &'a str) -> csv::Reader<&'a [u8]> {
+        ReaderBuilder::new()
+            .delimiter(self.delimiter as u8)
+            .flexible(true)
+            .from_reader(csv.as_bytes())
+    }
+
+    /// The transaction field a header is mapped to, if any
+    pub fn field_for(&self, header: &str) -> Option<&'static str> {
+        if header == self.timestamp_column {
+            Some("timestamp")
+        } else if header == self.payee_column {
+            Some("payee")
+        } else if header == self.amount_column {
+            Some("amount")
+        } else if self.currency_column.as_deref() == Some(header) {
+            Some("currency")
+        } else {
+            None
+        }
+    }
+
+    /// Locate the mapped columns, or list the ones the header row lacks
+    pub fn columns(&self, headers: &StringRecord) -> Result<Columns, Vec<String>> {
+        let mut missing = Vec::new();
+        let mut find = |name: &str| {
+            let index = headers.iter().position(|header| header == name);
+            if index.is_none() {
+                missing.push(name.to_string());
+            }
+            index
+        };
+
+        let timestamp = find(&self.timestamp_column);
+        let payee = find(&self.payee_column);
+        let amount = find(&self.amount_column);
+        let currency = self.currency_column.as_deref().map(&mut find);
+
+        match (timestamp, payee, amount, currency) {
+            (Some(timestamp), Some(payee), Some(amount), currency) if missing.is_empty() => {
+                Ok(Columns {
+                    timestamp,
+                    payee,
+                    amount,
+                    currency: currency.flatten(),
+                })
+            }
+            _ => Err(missing),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.delimiter.is_ascii() {
+            return Err("Delimiter must be a single ASCII character".to_string());
+        }
+        match (&self.currency_column, &self.currency) {
+            (Some(_), Some(_)) => Err("Set either currency_column or currency, not both".to_string()),
+            (None, None) => Err("Either currency_column or currency is required".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn extract(&self, columns: &Columns, record: &StringRecord) -> Result<RawTransaction, String> {
+        let field = |index: usize, name: &str| {
+            record
+                .get(index)
+                .map(str::trim)
+                .ok_or_else(|| format!("Missing {} value", name))
+        };
+
+        let timestamp = self.parse_timestamp(field(columns.timestamp, "timestamp")?)?;
+        let amount: f64 = field(columns.amount, "amount")?
+            .parse()
+            .map_err(|e| format!("Invalid amount format - {}", e))?;
+        let currency = match (columns.currency, &self.currency) {
+            (Some(index), _) => field(index, "currency")?.to_string(),
+            (None, Some(currency)) => currency.clone(),
+            (None, None) => return Err("Missing currency value".to_string()),
+        };
+
+        Ok(RawTransaction {
+            timestamp,
+            payee: field(columns.payee, "payee")?.to_string(),
+            amount: if self.negate_amounts { -amount } else { amount },
+            currency,
+        })
+    }
+
+    fn parse_timestamp(&self, value: &str) -> Result<DateTime<Utc>, String> {
+        let parsed = match &self.timestamp_format {
+            None => value.parse::<DateTime<Utc>>().map_err(|e| e.to_string()),
+            Some(format) => NaiveDateTime::parse_from_str(value, format)
+                .or_else(|_| {
+                    NaiveDate::parse_from_str(value, format)
+                        .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+                })
+                .map(|timestamp| timestamp.and_utc())
+                .map_err(|e| e.to_string()),
+        };
+        parsed.map_err(|e| format!("Invalid timestamp format - {}", e))
+    }
+}
+
+/// Reads CSV files laid out as `profile` describes
+pub struct CsvParser<'a> {
+    pub profile: &'a ImportProfile,
+}
+
+impl Parser for CsvParser<'_> {
+    /// Fails outright only when the header row doesn't fit the profile
+    fn parse(&self, text: &str, limit: Option<usize>) -> Result<Vec<ParsedRow>, String> {
+        self.profile.validate()?;
+
+        let mut reader = self.profile.reader(text);
+        let headers = reader
+            .headers()
+            .map_err(|e| format!("CSV parsing error - {}", e))?
+            .clone();
+        let columns = self
+            .profile
+            .columns(&headers)
+            .map_err(|missing| format!("Missing columns: {}", missing.join(", ")))?;
+
+        Ok(reader
+            .records()
+            .take(limit.unwrap_or(usize::MAX))
+            .enumerate()
+            .map(|(row_idx, result)| match result {
+                Ok(record) => ParsedRow {
+                    position: format!("Row {}", row_idx + 2),
+                    transaction: self.profile.extract(&columns, &record),
+                    fields: headers
+                        .iter()
+                        .zip(&record)
+                        .map(|(header, value)| (header.to_string(), value.to_string()))
+                        .collect(),
+                },
+                Err(e) => ParsedRow {
+                    position: format!("Row {}", row_idx + 2),
+                    transaction: Err(format!("CSV parsing error - {}", e)),
+                    fields: Default::default(),
+                },
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,337 @@
+mod csv;
+mod payee_names;
+
+pub use self::csv::{CsvParser, ImportProfile};
+pub use self::payee_names::PayeeNormalizer;
+
+use crate::config::{AmountPrecision, Config};
+use crate::error::ApiError;
+use crate::quarantine::Quarantine;
+use crate::store::TransactionStore;
+use crate::types::{
+    BulkImportResponse, CurrentTransaction, HistoricalTransaction, ImportRecord, Metadata, QuarantinedRow,
+    RawTransaction, ReprocessImportRequest, TransactionId,
+};
+use crate::utils::{override_lock, validate_amount};
+use chrono::Utc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+pub type ParsedTransaction = (TransactionId, CurrentTransaction, HistoricalTransaction);
+
+/// What an import is let do that it would be refused otherwise
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportOptions {
+    /// Change transactions in a locked period
+    pub override_lock: bool,
+    /// Bring in transactions in another currency than the account's
+    pub foreign_currency: bool,
+}
+
+impl ImportOptions {
+    /// From the `override_lock=true` and `foreign_currency=true` query parameters
+    pub fn from_query(params: &HashMap<String, String>) -> Self {
+        Self {
+            override_lock: override_lock(params),
+            foreign_currency: params.get("foreign_currency").is_some_and(|value| value == "true"),
+        }
+    }
+}
+
+/// Turns the bytes of an uploaded file into text
+pub trait Decoder: Send + Sync {
+    fn decode(&self, data: &[u8]) -> Result<String, String>;
+}
+
+/// Reads transactions out of a decoded file. Each file format implements
+/// one and shares every stage after it.
+pub trait Parser: Send + Sync {
+    /// Read up to `limit` transactions. Problems with single rows are reported
+    /// per row; an error means the file as a whole can't be read.
+    fn parse(&self, text: &str, limit: Option<usize>) -> Result<Vec<ParsedRow>, String>;
+}
+
+/// Checks a transaction as read from a file and turns it into what the store keeps
+pub trait Normalizer: Send + Sync {
+    fn normalize(&self, transaction: RawTransaction, account_id: &str) -> Result<ParsedTransaction, String>;
+}
+
+/// One transaction read by a parser, or why it couldn't be read
+pub struct ParsedRow {
+    /// Where the transaction is in the file, prefixed to its errors
+    pub position: String,
+    pub transaction: Result<RawTransaction, String>,
+    /// The row's values by column header, kept in quarantine if it can't be used
+    pub fields: BTreeMap<String, String>,
+}
+
+pub struct Utf8Decoder;
+
+impl Decoder for Utf8Decoder {
+    fn decode(&self, data: &[u8]) -> Result<String, String> {
+        String::from_utf8(data.to_vec()).map_err(|_| "Invalid UTF-8 in file".to_string())
+    }
+}
+
+/// Validates amounts for their currency, tidies payees and keys transactions by id
+pub struct AmountNormalizer {
+    pub precision: AmountPrecision,
+    pub payees: PayeeNormalizer,
+}
+
+impl Normalizer for AmountNormalizer {
+    fn normalize(&self, transaction: RawTransaction, account_id: &str) -> Result<ParsedTransaction, String> {
+        let amount = validate_amount(transaction.amount, &transaction.currency, self.precision)
+            .map_err(|e| format!("{} ({})", e.message, e.value))?;
+
+        let id = TransactionId {
+            timestamp: transaction.timestamp,
+            amount_cents: (amount * 100.0).round() as i64,
+            currency: transaction.currency,
+            payee: self.payees.normalize(&transaction.payee),
+            occurrence: 0,
+        };
+        // The store gives transactions their uuid as they are imported
+        Ok((
+            id.clone(),
+            CurrentTransaction {
+                account_id: account_id.to_string(),
+                uuid: String::new(),
+                id: id.clone(),
+            },
+            HistoricalTransaction {
+                account_id: account_id.to_string(),
+                uuid: String::new(),
+                id,
+                memo: None,
+                metadata: Metadata::new(),
+            },
+        ))
+    }
+}
+
+/// Decode, parse and normalize a file, then hand its transactions to the
+/// store, which drops the ones it already has and saves the rest
+pub struct Pipeline<'a> {
+    pub decoder: &'a dyn Decoder,
+    pub parser: &'a dyn Parser,
+    pub normalizer: &'a dyn Normalizer,
+}
+
+impl Pipeline<'_> {
+    /// Read up to `limit` transactions of `data` for `account_id`, collecting
+    /// the rows that can't be used alongside them
+    pub fn read(
+        &self,
+        data: &[u8],
+        account_id: &str,
+        limit: Option<usize>,
+    ) -> Result<(Vec<ParsedTransaction>, Vec<QuarantinedRow>), String> {
+        let text = self.decoder.decode(data)?;
+        let (successes, failures): (Vec<_>, Vec<_>) = self
+            .parser
+            .parse(&text, limit)?
+            .into_iter()
+            .map(|row| {
+                row.transaction
+                    .and_then(|transaction| self.normalizer.normalize(transaction, account_id))
+                    .map_err(|error| QuarantinedRow {
+                        position: row.position,
+                        error,
+                        fields: row.fields,
+                    })
+            })
+            .partition(Result::is_ok);
+
+        Ok((
+            successes.into_iter().map(Result::unwrap).collect(),
+            failures.into_iter().map(Result::unwrap_err).collect(),
+        ))
+    }
+
+    /// Import `data` into `account_id` as one batch and record the outcome,
+    /// under `source`, for the import metrics. The rows that can't be used
+    /// are quarantined with the batch, to be corrected later.
+    pub async fn import(
+        &self,
+        store: &TransactionStore,
+        quarantine: &Quarantine,
+        account_id: String,
+        source: String,
+        data: &[u8],
+        options: ImportOptions,
+    ) -> Result<BulkImportResponse, ApiError> {
+        let (new_transactions, failures) = self.read(data, &account_id, None).map_err(|message| ApiError {
+            message,
+            status: warp::http::StatusCode::BAD_REQUEST,
+        })?;
+        let errors: Vec<_> = failures.iter().map(describe).collect();
+        // Rather than recording a failed import for an account that doesn't exist
+        store.require_account(&account_id)?;
+        // Most likely a statement of another account, caught before anything is recorded
+        if !options.foreign_currency {
+            store.require_currency(&account_id, new_transactions.iter().map(|(id, _, _)| id.currency.as_str()))?;
+        }
+
+        let mut record = ImportRecord {
+            id: None,
+            account_id: account_id.clone(),
+            source,
+            imported_at: Utc::now(),
+            rows: new_transactions.len() + errors.len(),
+            imported: 0,
+            duplicates: 0,
+            errors: errors.len(),
+            statement: None,
+        };
+        if new_transactions.is_empty() && !errors.is_empty() {
+            let import_id = store.record_import(record)?;
+            keep(quarantine, &import_id, failures).await;
+            return Err(ApiError {
+                message: format!(
+                    "Parsing failed with {} errors; see GET /imports/{}/failures",
+                    errors.len(),
+                    import_id
+                ),
+                status: warp::http::StatusCode::BAD_REQUEST,
+            });
+        }
+
+        let mut response = store
+            .bulk_import_transactions(account_id, new_transactions, options.override_lock)
+            .await?;
+        response.errors = errors; // Add any parsing errors to the response
+
+        record.imported = response.imported;
+        record.duplicates = response.duplicates;
+        let import_id = store.record_import(record)?;
+        if !failures.is_empty() {
+            keep(quarantine, &import_id, failures).await;
+        }
+        response.import_id = Some(import_id);
+        Ok(response)
+    }
+}
+
+/// A row that can't be used, as it is listed in an import's errors
+pub fn describe(row: &QuarantinedRow) -> String {
+    format!("{}: {}", row.position, row.error)
+}
+
+/// The transactions are in, and the errors are in the response, so failing
+/// to quarantine the rows isn't worth failing the import over
+async fn keep(quarantine: &Quarantine, import_id: &str, rows: Vec<QuarantinedRow>) {
+    if let Err(e) = quarantine.keep(import_id, rows).await {
+        tracing::warn!("Failed to quarantine the failed rows of import {}: {}", import_id, e);
+    }
+}
+
+/// Import a CSV file into `account_id` as one batch, reading it with the
+/// configured profile named `profile_name` or the default layout
+pub async fn import_csv(
+    store: &TransactionStore,
+    quarantine: &Quarantine,
+    config: &Config,
+    account_id: String,
+    profile_name: Option<&str>,
+    data: &[u8],
+    options: ImportOptions,
+) -> Result<BulkImportResponse, ApiError> {
+    let default_profile = ImportProfile::default();
+    let profile = match profile_name {
+        Some(name) => config.import_profiles.get(name).ok_or(ApiError {
+            message: format!("Unknown import profile '{}'", name),
+            status: warp::http::StatusCode::BAD_REQUEST,
+        })?,
+        None => &default_profile,
+    };
+
+    let pipeline = Pipeline {
+        decoder: &Utf8Decoder,
+        parser: &CsvParser { profile },
+        normalizer: &AmountNormalizer {
+            precision: config.amount_precision,
+            payees: PayeeNormalizer::new(&config.payee_normalization),
+        },
+    };
+    let source = format!("csv:{}", profile_name.unwrap_or("default"));
+    pipeline.import(store, quarantine, account_id, source, data, options).await
+}
+
+/// Import corrected versions of quarantined rows of the import `import_id`
+/// as part of it. Rows that still can't be used stay quarantined with their
+/// new error.
+pub async fn reprocess(
+    store: &TransactionStore,
+    quarantine: &Quarantine,
+    config: &Config,
+    import_id: &str,
+    request: ReprocessImportRequest,
+    options: ImportOptions,
+) -> Result<BulkImportResponse, ApiError> {
+    let record = store.import_record(import_id).ok_or(ApiError {
+        message: "Import not found".to_string(),
+        status: warp::http::StatusCode::NOT_FOUND,
+    })?;
+    let _changing = quarantine.lock().await;
+    let mut rows = quarantine.rows(import_id).await?;
+
+    let mut positions = HashSet::new();
+    for corrected in &request.rows {
+        if !rows.iter().any(|row| row.position == corrected.position) {
+            return Err(ApiError {
+                message: format!("{} of this import is not quarantined", corrected.position),
+                status: warp::http::StatusCode::BAD_REQUEST,
+            });
+        }
+        if !positions.insert(corrected.position.as_str()) {
+            return Err(ApiError {
+                message: format!("{} is corrected more than once", corrected.position),
+                status: warp::http::StatusCode::BAD_REQUEST,
+            });
+        }
+    }
+
+    let normalizer = AmountNormalizer {
+        precision: config.amount_precision,
+        payees: PayeeNormalizer::new(&config.payee_normalization),
+    };
+    let mut transactions = Vec::new();
+    let mut resolved = HashSet::new();
+    let mut errors = Vec::new();
+    for corrected in request.rows {
+        let transaction = RawTransaction {
+            timestamp: corrected.timestamp,
+            payee: corrected.payee,
+            amount: corrected.amount,
+            currency: corrected.currency,
+        };
+        match normalizer.normalize(transaction, &record.account_id) {
+            Ok(parsed) => {
+                transactions.push(parsed);
+                resolved.insert(corrected.position);
+            }
+            Err(error) => {
+                let row = rows.iter_mut().find(|row| row.position == corrected.position).unwrap();
+                row.error = error;
+                errors.push(describe(row));
+            }
+        }
+    }
+
+    let (imported, duplicates) = if transactions.is_empty() {
+        (0, 0)
+    } else {
+        if !options.foreign_currency {
+            store.require_currency(&record.account_id, transactions.iter().map(|(id, _, _)| id.currency.as_str()))?;
+        }
+        store.import_corrections(import_id, transactions, options.override_lock)?
+    };
+    rows.retain(|row| !resolved.contains(&row.position));
+    quarantine.replace(import_id, &rows).await?;
+    Ok(BulkImportResponse {
+        import_id: Some(import_id.to_string()),
+        imported,
+        duplicates,
+        errors,
+    })
+}
@@ -0,0 +1,188 @@
+use crate::config::{PayeeLocale, PayeeNormalizationConfig, PayeeRule};
+use crate::utils::matches_pattern;
+
+/// What banks writing in one language print around the merchant's name
+struct Table {
+    /// Payment methods put before the merchant, longest first where one
+    /// starts another
+    prefixes: &'static [&'static str],
+    /// Merchant patterns, as for `PayeeRule`, and the payee they become
+    merchants: &'static [(&'static str, &'static str)],
+}
+
+const DE: Table = Table {
+    prefixes: &[
+        "SEPA-LASTSCHRIFT",
+        "SEPA LASTSCHRIFT",
+        "LASTSCHRIFT",
+        "EC-KARTENZAHLUNG",
+        "KARTENZAHLUNG",
+        "GIROCARD",
+        "SEPA-ÜBERWEISUNG",
+        "ÜBERWEISUNG",
+        "DAUERAUFTRAG",
+        "GUTSCHRIFT",
+    ],
+    merchants: &[
+        ("rewe*", "REWE"),
+        ("edeka*", "EDEKA"),
+        ("lidl*", "Lidl"),
+        ("aldi*", "ALDI"),
+        ("kaufland*", "Kaufland"),
+        ("netto marken-discount*", "Netto"),
+        ("dm-drogerie*", "dm-drogerie markt"),
+        ("dm drogerie*", "dm-drogerie markt"),
+        ("rossmann*", "Rossmann"),
+        ("db vertrieb*", "Deutsche Bahn"),
+        ("deutsche bahn*", "Deutsche Bahn"),
+    ],
+};
+
+const ES: Table = Table {
+    prefixes: &[
+        "COMPRA TARJETA",
+        "COMPRA TARJ.",
+        "COMPRA EN",
+        "PAGO CON TARJETA",
+        "ADEUDO RECIBO",
+        "RECIBO",
+        "TRANSFERENCIA",
+    ],
+    merchants: &[
+        ("mercadona*", "Mercadona"),
+        ("carrefour*", "Carrefour"),
+        ("eroski*", "Eroski"),
+        ("el corte ingles*", "El Corte Inglés"),
+        ("renfe*", "Renfe"),
+    ],
+};
+
+const FR: Table = Table {
+    prefixes: &[
+        "PAIEMENT PAR CARTE",
+        "PAIEMENT CB",
+        "CB",
+        "PRLV SEPA",
+        "PRELEVEMENT",
+        "VIR SEPA",
+        "VIREMENT",
+        "RETRAIT DAB",
+    ],
+    merchants: &[
+        ("carrefour*", "Carrefour"),
+        ("auchan*", "Auchan"),
+        ("e.leclerc*", "E.Leclerc"),
+        ("leclerc*", "E.Leclerc"),
+        ("intermarche*", "Intermarché"),
+        ("monoprix*", "Monoprix"),
+        ("franprix*", "Franprix"),
+        ("sncf*", "SNCF"),
+    ],
+};
+
+const IT: Table = Table {
+    prefixes: &[
+        "PAGAMENTO POS",
+        "PAGAMENTO CARTA",
+        "PAG. POS",
+        "ADDEBITO SDD",
+        "ADDEBITO",
+        "BONIFICO",
+    ],
+    merchants: &[
+        ("esselunga*", "Esselunga"),
+        ("conad*", "Conad"),
+        ("coop", "Coop"),
+        ("coop *", "Coop"),
+        ("carrefour*", "Carrefour"),
+        ("trenitalia*", "Trenitalia"),
+    ],
+};
+
+const NL: Table = Table {
+    prefixes: &[
+        "BETAALAUTOMAAT",
+        "PINBETALING",
+        "SEPA INCASSO",
+        "INCASSO",
+        "OVERBOEKING",
+        "IDEAL",
+        "BEA",
+        "GEA",
+        "APPLE PAY",
+        "GOOGLE PAY",
+    ],
+    merchants: &[
+        ("albert heijn*", "Albert Heijn"),
+        ("ah to go*", "Albert Heijn"),
+        ("jumbo*", "Jumbo"),
+        ("hema*", "HEMA"),
+        ("bol.com*", "bol.com"),
+        ("ns groep*", "NS"),
+        ("ns reizigers*", "NS"),
+    ],
+};
+
+fn table(locale: PayeeLocale) -> &'static Table {
+    match locale {
+        PayeeLocale::De => &DE,
+        PayeeLocale::Es => &ES,
+        PayeeLocale::Fr => &FR,
+        PayeeLocale::It => &IT,
+        PayeeLocale::Nl => &NL,
+    }
+}
+
+/// What may follow a payment-method prefix before the merchant
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ',' | ':' | '/' | '-' | '.')
+}
+
+/// Applies the configured payee tables and rules to payees read from a file
+pub struct PayeeNormalizer {
+    tables: Vec<&'static Table>,
+    rules: Vec<PayeeRule>,
+}
+
+impl PayeeNormalizer {
+    pub fn new(config: &PayeeNormalizationConfig) -> Self {
+        Self {
+            tables: config.locales.iter().map(|&locale| table(locale)).collect(),
+            rules: config.rules.clone(),
+        }
+    }
+
+    /// `payee` with its payment-method prefixes stripped and, when a rule or
+    /// known merchant matches what is left, that merchant's name. Left as it
+    /// is when nothing is configured.
+    pub fn normalize(&self, payee: &str) -> String {
+        if self.tables.is_empty() && self.rules.is_empty() {
+            return payee.to_string();
+        }
+
+        let mut rest = payee.split_whitespace().collect::<Vec<_>>().join(" ");
+        while let Some(stripped) = self.strip_prefix(&rest) {
+            rest = stripped.to_string();
+        }
+        if rest.is_empty() {
+            // Nothing but a payment method; better kept than lost
+            return payee.trim().to_string();
+        }
+
+        let own = self.rules.iter().map(|rule| (rule.pattern.as_str(), rule.payee.as_str()));
+        let built_in = self.tables.iter().flat_map(|table| table.merchants.iter().copied());
+        own.chain(built_in)
+            .find(|(pattern, _)| matches_pattern(pattern, &rest))
+            .map_or(rest.clone(), |(_, merchant)| merchant.to_string())
+    }
+
+    /// What follows the first table prefix `payee` starts with, as a whole word
+    fn strip_prefix<'a>(&self, payee: &'a str) -> Option<&'a str> {
+        self.tables.iter().flat_map(|table| table.prefixes.iter()).find_map(|prefix| {
+            let head = payee.get(..prefix.len())?;
+            let tail = &payee[prefix.len()..];
+            let whole_word = tail.is_empty() || tail.starts_with(is_separator) || prefix.ends_with('.');
+            (head.eq_ignore_ascii_case(prefix) && whole_word).then(|| tail.trim_start_matches(is_separator))
+        })
+    }
+}
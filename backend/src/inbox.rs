@@ -0,0 +1,159 @@
+use crate::config::{Config, InboxConfig, InboxRule};
+use crate::import;
+use crate::quarantine::Quarantine;
+use crate::statements::Statements;
+use crate::store::TransactionStore;
+use crate::utils::matches_pattern;
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+
+/// Files changed more recently than this are left for the next poll, as
+/// whatever is downloading them may not be done yet
+const SETTLE_TIME: Duration = Duration::from_secs(5);
+
+/// A directory that statement files are dropped into, e.g. by scheduled bank
+/// exports. Each file is imported with the first rule matching its name,
+/// kept as the import's statement, and moved out of the way.
+#[derive(Clone)]
+pub struct Inbox {
+    dir: PathBuf,
+    rules: Vec<InboxRule>,
+    config: Arc<Config>,
+    store: TransactionStore,
+    statements: Statements,
+    quarantine: Quarantine,
+}
+
+impl Inbox {
+    pub fn new(
+        inbox: &InboxConfig,
+        config: Arc<Config>,
+        store: TransactionStore,
+        statements: Statements,
+        quarantine: Quarantine,
+    ) -> Self {
+        Self {
+            dir: PathBuf::from(&inbox.dir),
+            rules: inbox.rules.clone(),
+            config,
+            store,
+            statements,
+            quarantine,
+        }
+    }
+
+    /// Check the directory every `interval`, starting now
+    pub fn spawn_watch(&self, interval: Duration) {
+        let inbox = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = inbox.poll().await {
+                    tracing::warn!("Failed to check the inbox {}: {}", inbox.dir.display(), e);
+                }
+            }
+        });
+    }
+
+    /// Import every settled file in the directory
+    async fn poll(&self) -> std::io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let mut files = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let settled = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age >= SETTLE_TIME);
+            if metadata.is_file() && !name.starts_with('.') && settled {
+                files.push(name);
+            }
+        }
+        files.sort();
+
+        for name in files {
+            let path = self.dir.join(&name);
+            match self.import(&name, &path).await {
+                Ok(summary) => {
+                    tracing::info!("Imported {} from the inbox: {}", name, summary);
+                    self.move_to(&path, "processed", &name, None).await?;
+                }
+                Err(message) => {
+                    tracing::warn!("Failed to import {} from the inbox: {}", name, message);
+                    self.move_to(&path, "failed", &name, Some(&message)).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn import(&self, name: &str, path: &Path) -> Result<String, String> {
+        let data = fs::read(path).await.map_err(|e| format!("Failed to read file: {}", e))?;
+        import_file(&self.store, &self.config, &self.statements, &self.quarantine, &self.rules, name, data).await
+    }
+
+    /// Move `path` into the `folder` subdirectory under a timestamped name, so
+    /// a file dropped again with the same name doesn't replace the earlier
+    /// one, with `error` written next to it when there is one
+    async fn move_to(&self, path: &Path, folder: &str, name: &str, error: Option<&str>) -> std::io::Result<()> {
+        let folder = self.dir.join(folder);
+        fs::create_dir_all(&folder).await?;
+        let target = folder.join(format!("{}-{}", Utc::now().format("%Y%m%dT%H%M%S%.3fZ"), name));
+        fs::rename(path, &target).await?;
+        if let Some(error) = error {
+            let mut error_path = target.into_os_string();
+            error_path.push(".error.txt");
+            fs::write(error_path, format!("{}\n", error)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Import a statement file with the first of `rules` matching its name and
+/// keep it as the import's statement, returning a summary of the import
+pub async fn import_file(
+    store: &TransactionStore,
+    config: &Config,
+    statements: &Statements,
+    quarantine: &Quarantine,
+    rules: &[InboxRule],
+    name: &str,
+    data: Vec<u8>,
+) -> Result<String, String> {
+    let rule = rules
+        .iter()
+        .find(|rule| matches_pattern(&rule.pattern, name))
+        .ok_or_else(|| "No rule matches the file name".to_string())?;
+
+    // Imported as the rule's owner, so a new account is theirs rather than nobody's
+    let store = &store.for_owner(rule.owner.clone());
+    let response =
+        import::import_csv(store, quarantine, config, rule.account_id.clone(), rule.profile.as_deref(), &data, import::ImportOptions::default())
+            .await
+            .map_err(|e| e.message)?;
+
+    let mut summary = format!(
+        "{} imported, {} duplicates, {} errors into {}",
+        response.imported,
+        response.duplicates,
+        response.errors.len(),
+        rule.account_id
+    );
+    if let Some(import_id) = &response.import_id {
+        // The transactions are in; losing the original file isn't worth failing over
+        if let Err(e) = statements
+            .attach(import_id, name.to_string(), "text/csv".to_string(), data)
+            .await
+        {
+            summary.push_str(&format!(" (statement not kept: {})", e.message));
+        }
+    }
+    Ok(summary)
+}
@@ -0,0 +1,133 @@
+use crate::config::WebhookSource;
+use crate::types::{CreateTransactionRequest, Metadata};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Turns the body of a pushed webhook into transactions for the source's account.
+pub trait PayloadMapper: Send + Sync {
+    fn map(
+        &self,
+        payload: Value,
+        source: &WebhookSource,
+    ) -> Result<Vec<CreateTransactionRequest>, String>;
+}
+
+/// Look up a mapper by the name used in the webhook source config
+pub fn mapper_for(name: &str) -> Option<Box<dyn PayloadMapper>> {
+    match name {
+        "generic" => Some(Box::new(GenericMapper)),
+        "plaid" => Some(Box::new(PlaidMapper)),
+        _ => None,
+    }
+}
+
+/// Compare secrets without short-circuiting on the first differing byte
+pub fn secrets_match(expected: &str, provided: &str) -> bool {
+    let (expected, provided) = (expected.as_bytes(), provided.as_bytes());
+    if expected.len() != provided.len() {
+        return false;
+    }
+    expected
+        .iter()
+        .zip(provided)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+#[derive(Debug, Deserialize)]
+struct GenericTransaction {
+    timestamp: DateTime<Utc>,
+    payee: String,
+    amount: f64,
+    currency: String,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+/// Accepts a single transaction object or an array of them, using the same
+/// field names as `POST /transactions` minus `account_id`.
+/// Suitable for phone automations and hand-rolled scripts.
+pub struct GenericMapper;
+
+impl PayloadMapper for GenericMapper {
+    fn map(
+        &self,
+        payload: Value,
+        source: &WebhookSource,
+    ) -> Result<Vec<CreateTransactionRequest>, String> {
+        let transactions: Vec<GenericTransaction> = if payload.is_array() {
+            serde_json::from_value(payload)
+        } else {
+            serde_json::from_value(payload).map(|tx| vec![tx])
+        }
+        .map_err(|e| format!("Invalid payload - {}", e))?;
+
+        Ok(transactions
+            .into_iter()
+            .map(|tx| CreateTransactionRequest {
+                account_id: source.account_id.clone(),
+                timestamp: tx.timestamp,
+                payee: tx.payee,
+                amount: tx.amount,
+                currency: tx.currency,
+                allow_duplicate: false,
+                foreign_currency: false,
+                metadata: tx.metadata,
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaidPayload {
+    #[serde(default)]
+    added: Vec<PlaidTransaction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaidTransaction {
+    date: NaiveDate,
+    name: String,
+    merchant_name: Option<String>,
+    amount: f64,
+    iso_currency_code: Option<String>,
+    unofficial_currency_code: Option<String>,
+}
+
+/// Accepts the `added` list of a Plaid `/transactions/sync` response, as
+/// forwarded by a relay after a `SYNC_UPDATES_AVAILABLE` webhook.
+/// Plaid reports outflows as positive amounts, so the sign is flipped.
+pub struct PlaidMapper;
+
+impl PayloadMapper for PlaidMapper {
+    fn map(
+        &self,
+        payload: Value,
+        source: &WebhookSource,
+    ) -> Result<Vec<CreateTransactionRequest>, String> {
+        let payload: PlaidPayload =
+            serde_json::from_value(payload).map_err(|e| format!("Invalid payload - {}", e))?;
+
+        payload
+            .added
+            .into_iter()
+            .map(|tx| {
+                let currency = tx
+                    .iso_currency_code
+                    .or(tx.unofficial_currency_code)
+                    .ok_or_else(|| format!("Transaction '{}' has no currency", tx.name))?;
+                Ok(CreateTransactionRequest {
+                    account_id: source.account_id.clone(),
+                    timestamp: tx.date.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                    payee: tx.merchant_name.unwrap_or(tx.name),
+                    amount: -tx.amount,
+                    currency,
+                    allow_duplicate: false,
+                    foreign_currency: false,
+                    metadata: Metadata::new(),
+                })
+            })
+            .collect()
+    }
+}
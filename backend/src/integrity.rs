@@ -0,0 +1,109 @@
+use crate::storage::Snapshot;
+use crate::types::{Discrepancy, DiscrepancyKind, TransactionId};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashMap;
+
+/// Cross-check the current and historical maps of `snapshot`, returning every
+/// inconsistency found, account by account
+pub fn check(snapshot: &Snapshot, now: DateTime<Utc>) -> Vec<Discrepancy> {
+    // Nothing in anyone's books predates this
+    let earliest = Utc.with_ymd_and_hms(1900, 1, 1, 0, 0, 0).unwrap();
+
+    let mut account_ids: Vec<_> = snapshot.current.keys().chain(snapshot.all.keys()).collect();
+    account_ids.sort();
+    account_ids.dedup();
+
+    let mut discrepancies = Vec::new();
+    for account_id in account_ids {
+        let mut found = |kind, id: Option<&TransactionId>, detail: String| {
+            discrepancies.push(Discrepancy {
+                kind,
+                account_id: account_id.clone(),
+                id: id.cloned(),
+                detail,
+            })
+        };
+        let history = snapshot.all.get(account_id).map(Vec::as_slice).unwrap_or_default();
+
+        // Positions in the history of every record of each transaction
+        let mut positions: HashMap<&TransactionId, Vec<usize>> = HashMap::new();
+        for (index, transaction) in history.iter().enumerate() {
+            positions.entry(&transaction.id).or_default().push(index);
+
+            if transaction.account_id != *account_id {
+                found(
+                    DiscrepancyKind::MisfiledHistorical,
+                    Some(&transaction.id),
+                    format!(
+                        "Historical record {} belongs to account {}",
+                        index, transaction.account_id
+                    ),
+                );
+            }
+            if transaction.id.timestamp > now || transaction.id.timestamp < earliest {
+                found(
+                    DiscrepancyKind::ImpossibleTimestamp,
+                    Some(&transaction.id),
+                    format!(
+                        "Historical record {} is dated {}, which is {}",
+                        index,
+                        transaction.id.timestamp,
+                        if transaction.id.timestamp > now {
+                            "in the future"
+                        } else {
+                            "before 1900"
+                        }
+                    ),
+                );
+            }
+        }
+
+        let mut duplicates: Vec<_> = positions.iter().filter(|(_, indices)| indices.len() > 1).collect();
+        duplicates.sort_by_key(|(_, indices)| indices[0]);
+        for (id, indices) in duplicates {
+            found(
+                DiscrepancyKind::DuplicateHistorical,
+                Some(id),
+                format!("Recorded {} times, at historical records {:?}", indices.len(), indices),
+            );
+        }
+
+        let Some(current) = snapshot.current.get(account_id) else {
+            found(
+                DiscrepancyKind::MissingAccount,
+                None,
+                format!("Account has {} historical records but no current transactions entry", history.len()),
+            );
+            continue;
+        };
+
+        let mut current: Vec<_> = current.iter().collect();
+        current.sort_by_key(|(id, _)| (id.timestamp, &id.payee, id.amount_cents, &id.currency, id.occurrence));
+        for (id, transaction) in current {
+            if transaction.account_id != *account_id {
+                found(
+                    DiscrepancyKind::MisfiledCurrent,
+                    Some(id),
+                    format!("Current transaction belongs to account {}", transaction.account_id),
+                );
+            } else if transaction.id != *id {
+                found(
+                    DiscrepancyKind::MisfiledCurrent,
+                    Some(id),
+                    format!(
+                        "Current transaction is keyed by this id but records {:?}",
+                        transaction.id
+                    ),
+                );
+            }
+            if !positions.contains_key(id) {
+                found(
+                    DiscrepancyKind::OrphanedCurrent,
+                    Some(id),
+                    "Current transaction has no historical record".to_string(),
+                );
+            }
+        }
+    }
+    discrepancies
+}
@@ -0,0 +1,53 @@
+use crate::config::InterestRate;
+use crate::types::{AccountBalance, InterestPeriod, ProjectedInterest};
+use chrono::NaiveDate;
+
+/// Rates are quoted per year of this many days
+const DAYS_PER_YEAR: f64 = 365.0;
+
+/// What each of `balances` would earn from `from` until `until` at `rates`.
+/// Days before the first rate takes effect earn nothing.
+pub fn project(
+    rates: &[InterestRate],
+    balances: &[AccountBalance],
+    from: NaiveDate,
+    until: NaiveDate,
+) -> Vec<ProjectedInterest> {
+    let mut rates: Vec<_> = rates.iter().collect();
+    rates.sort_by_key(|rate| rate.effective_from);
+    // Each rate lasts until the next takes effect, so of rates taking effect
+    // on the same day the last configured wins
+    let periods: Vec<_> = rates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, rate)| {
+            let start = rate.effective_from.max(from);
+            let end = rates.get(i + 1).map_or(until, |next| next.effective_from.min(until));
+            (start < end).then_some((start, end, rate.annual_percent))
+        })
+        .collect();
+
+    balances
+        .iter()
+        .map(|balance| {
+            let periods: Vec<_> = periods
+                .iter()
+                .map(|&(from, until, annual_percent)| {
+                    let years = (until - from).num_days() as f64 / DAYS_PER_YEAR;
+                    InterestPeriod {
+                        from,
+                        until,
+                        annual_percent,
+                        interest_cents: (balance.balance_cents as f64 * annual_percent / 100.0 * years).round() as i64,
+                    }
+                })
+                .collect();
+            ProjectedInterest {
+                currency: balance.currency.clone(),
+                balance_cents: balance.balance_cents,
+                interest_cents: periods.iter().map(|period| period.interest_cents).sum(),
+                periods,
+            }
+        })
+        .collect()
+}
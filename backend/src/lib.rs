@@ -0,0 +1,44 @@
+// The server's modules, as a library so the benches can reach them too
+
+pub mod archive;
+pub mod auth;
+pub mod backup;
+pub mod compression;
+pub mod config;
+pub mod currency;
+pub mod dashboard;
+pub mod doctor;
+pub mod error;
+pub mod fetch;
+pub mod frontend;
+pub mod handlers;
+pub mod hash_chain;
+pub mod idempotency;
+pub mod import;
+pub mod inbox;
+pub mod ingest;
+pub mod integrity;
+pub mod interest;
+pub mod limits;
+pub mod logging;
+pub mod migrate;
+pub mod oidc;
+pub mod openapi;
+pub mod payees;
+pub mod quarantine;
+pub mod query;
+pub mod ranges;
+pub mod selftest;
+pub mod statements;
+pub mod storage;
+pub mod store;
+pub mod subscriptions;
+pub mod text_entry;
+pub mod tls;
+pub mod types;
+pub mod unix_socket;
+pub mod users;
+pub mod utils;
+
+#[cfg(test)]
+mod testing;
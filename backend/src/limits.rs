@@ -0,0 +1,58 @@
+use crate::config::ConcurrencyConfig;
+use crate::error::ApiError;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use warp::http::StatusCode;
+
+/// A taken slot, held by a handler until it returns
+pub type Slot = OwnedSemaphorePermit;
+
+/// Caps how many requests of one kind are served at once
+#[derive(Clone)]
+pub struct Limit {
+    kind: &'static str,
+    slots: Arc<Semaphore>,
+    queue_timeout: Duration,
+}
+
+impl Limit {
+    fn new(kind: &'static str, slots: usize, queue_timeout: Duration) -> Self {
+        Self {
+            kind,
+            slots: Arc::new(Semaphore::new(slots)),
+            queue_timeout,
+        }
+    }
+
+    /// Wait for a free slot, which is taken until it is dropped
+    pub async fn acquire(&self) -> Result<Slot, ApiError> {
+        let busy = || ApiError {
+            message: format!("Too many {} requests at once, try again shortly", self.kind),
+            status: StatusCode::TOO_MANY_REQUESTS,
+        };
+        tokio::time::timeout(self.queue_timeout, self.slots.clone().acquire_owned())
+            .await
+            .map_err(|_| busy())?
+            .map_err(|_| busy())
+    }
+}
+
+/// One `Limit` per kind of expensive endpoint
+#[derive(Clone)]
+pub struct Limits {
+    pub reports: Limit,
+    pub exports: Limit,
+    pub imports: Limit,
+}
+
+impl Limits {
+    pub fn new(config: &ConcurrencyConfig) -> Self {
+        let queue_timeout = Duration::from_millis(config.queue_timeout_ms);
+        Self {
+            reports: Limit::new("report", config.reports, queue_timeout),
+            exports: Limit::new("export", config.exports, queue_timeout),
+            imports: Limit::new("import", config.imports, queue_timeout),
+        }
+    }
+}
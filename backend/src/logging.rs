@@ -0,0 +1,293 @@
+use crate::config::{LogFormat, LogLevel, LoggingConfig};
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Write as _};
+use std::io::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Span, Subscriber, metadata::LevelFilter};
+use warp::Reply;
+use warp::http::HeaderValue;
+use warp::trace::Info;
+
+/// Where this crate's own events come from, as opposed to its libraries'
+const OWN_TARGET: &str = env!("CARGO_CRATE_NAME");
+
+/// Longest request id taken from a client's `X-Request-Id` header
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The spans of the installed logger, for looking up the current request's id
+static SPANS: OnceLock<Arc<Spans>> = OnceLock::new();
+
+thread_local! {
+    /// The spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Log to stderr from here on, as `config` says
+pub fn init(config: &LoggingConfig) {
+    let spans = SPANS.get_or_init(|| Arc::new(Spans::default())).clone();
+    let level = match config.level {
+        LogLevel::Error => Level::ERROR,
+        LogLevel::Warn => Level::WARN,
+        LogLevel::Info => Level::INFO,
+        LogLevel::Debug => Level::DEBUG,
+        LogLevel::Trace => Level::TRACE,
+    };
+    let logger = Logger {
+        level,
+        format: config.format,
+        spans,
+    };
+    if tracing::subscriber::set_global_default(logger).is_err() {
+        eprintln!("Warning: Logging was already set up");
+    }
+}
+
+/// A span for each request, named by the client's `X-Request-Id` when it
+/// sends a usable one and a new id otherwise
+pub fn request_span(info: Info<'_>) -> Span {
+    let provided = info
+        .request_headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|byte| byte.is_ascii_graphic()));
+    let request_id = provided.map_or_else(|| uuid::Uuid::new_v4().to_string(), str::to_string);
+    tracing::info_span!("request", request_id = %request_id, method = %info.method(), path = %info.path())
+}
+
+/// Log how the request in the current span turned out, and tell the client
+/// its id
+pub fn finish_request(started: Instant, reply: impl Reply) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    let status = response.status().as_u16();
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    if response.status().is_server_error() {
+        tracing::error!(status, elapsed_ms, "Request failed");
+    } else if response.status().is_client_error() {
+        tracing::warn!(status, elapsed_ms, "Request refused");
+    } else {
+        tracing::info!(status, elapsed_ms, "Request served");
+    }
+    if let Some(id) = current_request_id().and_then(|id| HeaderValue::from_str(&id).ok()) {
+        response.headers_mut().insert("x-request-id", id);
+    }
+    response
+}
+
+fn current_request_id() -> Option<String> {
+    let spans = SPANS.get()?.by_id.lock().unwrap();
+    ENTERED.with(|entered| {
+        entered.borrow().iter().rev().find_map(|id| match spans.get(id)?.fields.get("request_id")? {
+            Value::String(id) => Some(id.clone()),
+            _ => None,
+        })
+    })
+}
+
+#[derive(Default)]
+struct Spans {
+    next_id: AtomicU64,
+    by_id: Mutex<HashMap<u64, SpanData>>,
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: Map<String, Value>,
+    // Handles to the span still around; it is forgotten when the last goes
+    handles: usize,
+}
+
+struct Logger {
+    level: Level,
+    format: LogFormat,
+    spans: Arc<Spans>,
+}
+
+impl Logger {
+    fn write(&self, event: &Event<'_>) {
+        let mut fields = Fields(Map::new());
+        event.record(&mut fields);
+        let Fields(mut fields) = fields;
+        let message = match fields.remove("message") {
+            Some(Value::String(message)) => message,
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+        let metadata = event.metadata();
+        let spans: Vec<(&'static str, Map<String, Value>)> = {
+            let spans = self.spans.by_id.lock().unwrap();
+            ENTERED.with(|entered| {
+                entered
+                    .borrow()
+                    .iter()
+                    .filter_map(|id| spans.get(id).map(|span| (span.name, span.fields.clone())))
+                    .collect()
+            })
+        };
+
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let mut line = String::new();
+        match self.format {
+            LogFormat::Text => {
+                let _ = write!(line, "{} {:>5} ", timestamp, metadata.level());
+                for (name, span_fields) in &spans {
+                    let _ = write!(line, "{}{{{}}}: ", name, text_fields(span_fields));
+                }
+                if !metadata.target().starts_with(OWN_TARGET) {
+                    let _ = write!(line, "{}: ", metadata.target());
+                }
+                line.push_str(&message);
+                if !fields.is_empty() {
+                    let _ = write!(line, " {}", text_fields(&fields));
+                }
+            }
+            LogFormat::Json => {
+                let mut object = Map::new();
+                object.insert("timestamp".to_string(), Value::String(timestamp));
+                object.insert("level".to_string(), Value::String(metadata.level().as_str().to_lowercase()));
+                object.insert("target".to_string(), Value::String(metadata.target().to_string()));
+                object.insert("message".to_string(), Value::String(message));
+                // Flat, so collectors can index request_id and the like directly
+                for (_, span_fields) in spans {
+                    object.extend(span_fields);
+                }
+                object.extend(fields);
+                line = Value::Object(object).to_string();
+            }
+        }
+        line.push('\n');
+        // Nowhere left to report a failure to write to stderr
+        let _ = std::io::stderr().lock().write_all(line.as_bytes());
+    }
+}
+
+fn text_fields(fields: &Map<String, Value>) -> String {
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|(key, value)| match value {
+            // Quoted only where they'd otherwise run into the next field
+            Value::String(value) if !value.contains(char::is_whitespace) => format!("{}={}", key, value),
+            value => format!("{}={}", key, value),
+        })
+        .collect();
+    fields.join(" ")
+}
+
+impl Subscriber for Logger {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.enabled(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        if !metadata.target().starts_with(OWN_TARGET) && self.level < Level::DEBUG {
+            return false;
+        }
+        // The crate's own spans carry request ids whatever the level
+        metadata.is_span() || *metadata.level() <= self.level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::from_level(self.level.max(Level::INFO)))
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let mut fields = Fields(Map::new());
+        attributes.record(&mut fields);
+        let id = self.spans.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.spans.by_id.lock().unwrap().insert(
+            id,
+            SpanData {
+                name: attributes.metadata().name(),
+                fields: fields.0,
+                handles: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(span) = self.spans.by_id.lock().unwrap().get_mut(&span.into_u64()) {
+            let mut fields = Fields(std::mem::take(&mut span.fields));
+            values.record(&mut fields);
+            span.fields = fields.0;
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        self.write(event);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(position) = entered.iter().rposition(|id| *id == span.into_u64()) {
+                entered.remove(position);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = self.spans.by_id.lock().unwrap().get_mut(&span.into_u64()) {
+            span.handles += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.by_id.lock().unwrap();
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.handles -= 1;
+        if data.handles > 0 {
+            return false;
+        }
+        spans.remove(&span.into_u64());
+        true
+    }
+}
+
+struct Fields(Map<String, Value>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name().to_string(), Value::String(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::String(value.to_string()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+}
@@ -1,71 +1,1060 @@
-mod error;
-mod handlers;
-mod store;
-mod types;
-mod utils;
+// warp nests a type per filter, and the route tree is deeper than the default allows
+#![recursion_limit = "256"]
 
+use backend::{
+    auth, backup, compression, config, dashboard, doctor, error, fetch, frontend, handlers, hash_chain, idempotency, inbox, limits, logging, migrate,
+    quarantine, selftest, statements, storage, store, subscriptions, tls, types, unix_socket, users, utils,
+};
+
+use auth::{Access, RequestAuth};
+use backup::Backups;
+use config::{Config, LoggingConfig, ServerConfig, StorageBackend};
+use dashboard::Dashboard;
 use error::handle_rejection;
+use fetch::StatementFetcher;
+use frontend::Frontend;
 use handlers::*;
+use hash_chain::HashChain;
+use idempotency::Idempotency;
+use inbox::Inbox;
+use limits::Limits;
+use quarantine::Quarantine;
+use statements::Statements;
 use store::TransactionStore;
-use utils::with_store;
+use subscriptions::Subscriptions;
+use types::Scope;
+use users::Users;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use utils::{
+    LATEST_API_VERSION, with_backups, with_config, with_frontend, with_hash_chain, with_header_api_version, with_idempotency,
+    with_path_api_version, with_quarantine, with_slot, with_status, with_store, with_subscriptions, with_user_dashboard, with_user_statements, with_user_store, with_users,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
 use warp::Filter;
+use warp::hyper::server::accept::{self, Accept};
+use warp::hyper::server::conn::{AddrIncoming, AddrStream};
+use warp::hyper::service::{Service, make_service_fn, service_fn};
+use warp::hyper::{Body, Request, Response, Server};
+
+/// Upper bound on the whole body of a batch import, multipart, or of the
+/// transactions pushed to an ingest webhook
+const MAX_BATCH_IMPORT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Upper bound on a statement file attached to an import
+const MAX_STATEMENT_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Upper bound on a saved dashboard layout
+const MAX_DASHBOARD_LAYOUT_BYTES: u64 = 1024 * 1024;
 
 #[tokio::main]
 async fn main() {
-    let store = TransactionStore::new();
+    let config = match Config::load().await {
+        Ok(config) => config,
+        Err(e) => {
+            // Serving with the defaults would serve everyone, whatever auth
+            // the config meant to set up
+            logging::init(&LoggingConfig::default());
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // Set up first, so everything after is logged as configured
+    logging::init(&config.logging);
+    let config = Arc::new(config);
+
+    // Maintenance subcommands run instead of the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let force = matches!(args.as_slice(), [flag] if flag == "--force");
+    if let Some((command, args)) = args.split_first().filter(|_| !force) {
+        match command.as_str() {
+            "doctor" => std::process::exit(doctor::run(&config, args).await),
+            "migrate" => std::process::exit(migrate::run(&config, args).await),
+            _ => {
+                eprintln!("Error: Unknown command {}", command);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // Taken before the backend is opened, so a second server can't migrate it
+    // from under the first
+    let (_lock_file, lock_check) = selftest::lock(&config.storage).await;
+    if !force && lock_check.as_ref().is_some_and(|check| !check.passed) {
+        selftest::report(lock_check.as_slice(), force);
+        std::process::exit(1);
+    }
+
+    let opened = async {
+        let cipher = storage::cipher_from_config(&config.storage).await?;
+        let storage = storage::from_config(&config.storage, cipher.clone()).await?;
+        Ok::<_, storage::StorageError>((storage, cipher))
+    };
+    let (storage, cipher) = match opened.await {
+        Ok(opened) => opened,
+        Err(e) => {
+            tracing::error!("Failed to open storage backend: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let schema_version = storage.schema_version();
+    let store = TransactionStore::new(
+        storage,
+        config.low_balance_thresholds.clone(),
+        config.period_locks.clone(),
+        config.require_if_match,
+    );
+
+    // Load existing data from the storage backend
+    if let Err(e) = store.load().await {
+        tracing::warn!("Failed to load existing data: {}", e);
+    }
+
+    let backups = match Backups::new(&config.backups, cipher.clone(), store.clone()) {
+        Ok(backups) => backups,
+        Err(e) => {
+            tracing::error!("Failed to set up backups: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Nothing has been written yet, so stopping here leaves the data as it was
+    let status = selftest::run(&config, lock_check, schema_version, &store, &backups, force).await;
+    if selftest::report(&status.checks, force) {
+        std::process::exit(1);
+    }
+    let status = Arc::new(status);
+
+    store.watch_storage();
+    store.spawn_flusher(Duration::from_millis(config.storage.flush_interval_ms));
+    store.spawn_compaction(Duration::from_secs(config.storage.compaction_interval_secs));
 
-    // Load existing data from files
-    if let Err(e) = store.load_from_files().await {
-        eprintln!("Warning: Failed to load existing data: {}", e);
+    let statements = Statements::new(&config.statements, cipher.clone(), store.clone());
+    let quarantine = Quarantine::new(&config.statements, cipher.clone());
+    if let Some(inbox_config) = &config.inbox {
+        let inbox = Inbox::new(inbox_config, config.clone(), store.clone(), statements.clone(), quarantine.clone());
+        inbox.spawn_watch(Duration::from_secs(inbox_config.poll_interval_secs));
     }
+    for (name, source) in &config.statement_sources {
+        let fetcher = StatementFetcher::new(
+            name,
+            source,
+            config.clone(),
+            store.clone(),
+            statements.clone(),
+            quarantine.clone(),
+        );
+        match fetcher {
+            Ok(fetcher) => fetcher.spawn_schedule(Duration::from_secs(source.interval_secs)),
+            Err(e) => {
+                tracing::error!("Failed to set up statement source {}: {}", name, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(interval_secs) = config.backups.interval_secs {
+        backups.spawn_schedule(Duration::from_secs(interval_secs));
+    }
+    // Catch up on anything a previous run didn't manage to push
+    backups.spawn_push();
+
+    let subscriptions = match Subscriptions::new(&config.subscriptions, store.clone()).await {
+        Ok(subscriptions) => subscriptions,
+        Err(e) => {
+            tracing::error!("Failed to load subscriptions: {}", e);
+            std::process::exit(1);
+        }
+    };
+    subscriptions.spawn_delivery(Duration::from_millis(config.subscriptions.poll_interval_ms));
+
+    let dashboard = match Dashboard::new(&config.dashboard).await {
+        Ok(dashboard) => dashboard,
+        Err(e) => {
+            tracing::error!("Failed to load the dashboard layout: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let hash_chain = match HashChain::new(&config.integrity, store.clone()).await {
+        Ok(hash_chain) => hash_chain,
+        Err(e) => {
+            tracing::error!("Failed to load the event log's hashes: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Nothing outlives the memory backend to be changed behind its back
+    if config.storage.backend != StorageBackend::Memory {
+        hash_chain.spawn_schedule(Duration::from_secs(config.integrity.interval_secs));
+    }
+
+    let users = match Users::from_config(&config).await {
+        Ok(users) => users,
+        Err(e) => {
+            tracing::error!("Failed to load users: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let access = match Access::from_config(&config, users.clone()).await {
+        Ok(access) => access,
+        Err(e) => {
+            tracing::error!("Failed to set up authentication: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let limits = Limits::new(&config.concurrency);
+    let frontend = config.frontend.as_ref().map(Frontend::new);
+    let idempotency = match Idempotency::new(&config.idempotency).await {
+        Ok(idempotency) => idempotency,
+        Err(e) => {
+            tracing::error!("Failed to load the responses kept for Idempotency-Key: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_headers(vec!["content-type"])
-        .allow_methods(vec!["GET", "POST", "PUT"]);
+        .allow_headers(vec!["authorization", "content-type", "x-request-id", "x-webhook-secret", "idempotency-key", "if-none-match", "if-match", "api-version", "range", "if-range"])
+        .expose_headers(vec!["x-request-id", "x-total-count", "x-as-of-revision", "idempotent-replayed", "etag", "api-version", "content-range"])
+        .allow_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"]);
 
-    // GET /transactions/current - Get current transactions
+    // GET /transactions/current?account_id=&from=&to=&payee=&currency=&min_amount=&max_amount=&meta.<key>=&sort=&limit=&offset=&as_of_revision=&as_of_date= - Get current transactions
     let get_current_transactions = warp::path!("transactions" / "current")
         .and(warp::get())
-        .and(with_store(store.clone()))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with_user_store(store.clone(), access.clone()))
         .and_then(get_current_transactions_handler);
 
-    // GET /transactions/all - Get all historical transactions
+    // GET /transactions/all?account_id=&from=&to=&payee=&currency=&min_amount=&max_amount=&meta.<key>=&sort=&limit=&offset=&as_of_revision=&as_of_date= - Get all historical transactions
     let get_all_transactions = warp::path!("transactions" / "all")
         .and(warp::get())
-        .and(with_store(store.clone()))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_slot(limits.reports.clone()))
         .and_then(get_all_transactions_handler);
 
-    // POST /transactions - Create a new transaction
+    // GET /accounts/:account_id/transactions/current?from=&to=&payee=&currency=&min_amount=&max_amount=&meta.<key>=&sort=&limit=&offset=&as_of_revision=&as_of_date= - Get one account's current transactions
+    let get_account_current_transactions = warp::path!("accounts" / String / "transactions" / "current")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(get_account_current_transactions_handler);
+
+    // GET /accounts/:account_id/transactions/all?from=&to=&payee=&currency=&min_amount=&max_amount=&meta.<key>=&sort=&limit=&offset=&as_of_revision=&as_of_date= - Get one account's historical transactions
+    let get_account_all_transactions = warp::path!("accounts" / String / "transactions" / "all")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_slot(limits.reports.clone()))
+        .and_then(get_account_all_transactions_handler);
+
+    // GET /transactions/query?q=&sort=&limit=&offset=&as_of_revision=&as_of_date= - Get current transactions matching a query such as amount<-50 AND payee~"uber"
+    let query_transactions = warp::path!("transactions" / "query")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(query_transactions_handler);
+
+    // GET /transactions/by-id/:uuid - Get one transaction by its uuid, with its memo and metadata
+    let get_transaction_by_uuid = warp::path!("transactions" / "by-id" / String)
+        .and(warp::get())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(get_transaction_by_uuid_handler);
+
+    // PUT /transactions/by-id/:uuid/memo?override_lock= - Update the memo of a transaction by its uuid; honours If-Match
+    let update_memo_by_uuid = warp::path!("transactions" / "by-id" / String / "memo")
+        .and(warp::put())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(update_memo_by_uuid_handler);
+
+    // PATCH /transactions/by-id/:uuid/metadata?override_lock= - Set or remove metadata keys of a transaction by its uuid; honours If-Match
+    let update_metadata_by_uuid = warp::path!("transactions" / "by-id" / String / "metadata")
+        .and(warp::patch())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(update_metadata_by_uuid_handler);
+
+    // PUT /transactions/by-id/:uuid?override_lock= - Correct the timestamp, payee or amount of a transaction by its uuid; honours If-Match
+    let edit_transaction_by_uuid = warp::path!("transactions" / "by-id" / String)
+        .and(warp::put())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_config(config.clone()))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(edit_transaction_by_uuid_handler);
+
+    // DELETE /transactions/by-id/:uuid?override_lock= - Delete a transaction by its uuid; honours If-Match
+    let delete_transaction_by_uuid = warp::path!("transactions" / "by-id" / String)
+        .and(warp::delete())
+        .and(access.scoped(Scope::Full))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(delete_transaction_by_uuid_handler);
+
+    // GET /transactions/:account_id/one?timestamp=&amount=&currency=&payee=&occurrence= - Get one transaction with its memo and metadata
+    let get_transaction = warp::path!("transactions" / String / "one")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(get_transaction_handler);
+
+    // POST /transactions/parse-text - Read a line such as "lunch 14.20 at Pho King yesterday" into a draft transaction to confirm
+    let parse_text = warp::path!("transactions" / "parse-text")
+        .and(warp::post())
+        .and(access.scoped(Scope::Read))
+        .and(warp::body::json())
+        .and(with_config(config.clone()))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(parse_text_handler);
+
+    // POST /transactions?override_lock= - Create a new transaction; retries with the same Idempotency-Key header get the first response
     let create_transaction = warp::path("transactions")
         .and(warp::post())
         .and(warp::body::json())
-        .and(with_store(store.clone()))
+        .and(access.scoped(Scope::Full))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_config(config.clone()))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_idempotency(idempotency.clone(), access.clone()))
         .and_then(create_transaction_handler);
 
-    // POST /transactions/bulk/:account_id - Upload CSV for bulk import
+    // POST /transactions/bulk/:account_id?profile=&override_lock=&foreign_currency= - Upload CSV for bulk import; honours Idempotency-Key
     let bulk_import = warp::path!("transactions" / "bulk" / String)
         .and(warp::post())
+        .and(access.scoped(Scope::Import))
         .and(warp::body::bytes())
-        .and(with_store(store.clone()))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_config(config.clone()))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_quarantine(quarantine.clone()))
+        .and(with_idempotency(idempotency.clone(), access.clone()))
+        .and(with_slot(limits.imports.clone()))
         .and_then(bulk_import_handler);
 
-    // PUT /transactions/:account_id/memo - Update transaction memo
+    // POST /transactions/import/batch?override_lock=&foreign_currency= - Upload several CSV files, each for its own account; honours Idempotency-Key
+    let batch_import = warp::path!("transactions" / "import" / "batch")
+        .and(warp::post())
+        .and(access.scoped(Scope::Import))
+        .and(warp::multipart::form().max_length(MAX_BATCH_IMPORT_BYTES))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_config(config.clone()))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_quarantine(quarantine.clone()))
+        .and(with_idempotency(idempotency.clone(), access.clone()))
+        .and(with_slot(limits.imports.clone()))
+        .and_then(batch_import_handler);
+
+    // PUT /transactions/:account_id/memo?timestamp=&amount=&currency=&payee=&occurrence=&override_lock= - Update transaction memo; honours If-Match
     let update_memo = warp::path!("transactions" / String / "memo")
         .and(warp::put())
+        .and(access.scoped(Scope::Full))
         .and(warp::body::json())
         .and(warp::query::<std::collections::HashMap<String, String>>())
-        .and(with_store(store.clone()))
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_user_store(store.clone(), access.clone()))
         .and_then(update_memo_handler);
 
-    let routes = get_current_transactions
+    // PUT /transactions/memos?override_lock= - Update the memos of several transactions at once; honours If-Match
+    let update_memos = warp::path!("transactions" / "memos")
+        .and(warp::put())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(update_memos_handler);
+
+    // PATCH /transactions/:account_id/metadata?timestamp=&amount=&currency=&payee=&occurrence=&override_lock= - Set or remove transaction metadata keys; honours If-Match
+    let update_metadata = warp::path!("transactions" / String / "metadata")
+        .and(warp::patch())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(update_metadata_handler);
+
+    // PUT /transactions/:account_id?timestamp=&amount=&currency=&payee=&occurrence=&override_lock= - Correct a transaction's timestamp, payee or amount; honours If-Match
+    let edit_transaction = warp::path!("transactions" / String)
+        .and(warp::put())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_config(config.clone()))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(edit_transaction_handler);
+
+    // DELETE /transactions/:account_id?timestamp=&amount=&currency=&payee=&occurrence=&override_lock= - Delete a transaction; honours If-Match
+    let delete_transaction = warp::path!("transactions" / String)
+        .and(warp::delete())
+        .and(access.scoped(Scope::Full))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(delete_transaction_handler);
+
+    // POST /ingest/webhook/:source - Receive pushed transactions from a configured source
+    let ingest_webhook = warp::path!("ingest" / "webhook" / String)
+        .and(warp::post())
+        .and(warp::header::optional::<String>("x-webhook-secret"))
+        .and(warp::body::content_length_limit(MAX_BATCH_IMPORT_BYTES))
+        .and(warp::body::json())
+        .and(with_config(config.clone()))
+        .and(with_store(store.clone()))
+        .and(with_slot(limits.imports.clone()))
+        .and_then(ingest_webhook_handler);
+
+    // POST /auth/register - Register a local user
+    let register = warp::path!("auth" / "register")
+        .and(warp::post())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and(with_users(users.clone()))
+        .and_then(register_handler);
+
+    // POST /auth/login - Sign in as a local user, for a session token and cookie
+    let login = warp::path!("auth" / "login")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_users(users.clone()))
+        .and_then(login_handler);
+
+    // POST /auth/logout - End the current session
+    let logout = warp::path!("auth" / "logout")
+        .and(warp::post())
+        .and(warp::header::headers_cloned())
+        .and(with_users(users.clone()))
+        .and_then(logout_handler);
+
+    // GET /auth/me - The signed-in local user
+    let current_user = warp::path!("auth" / "me")
+        .and(warp::get())
+        .and(warp::header::headers_cloned())
+        .and(with_users(users.clone()))
+        .and_then(current_user_handler);
+
+    // POST /auth/tokens - Issue an API token of the signed-in local user, scoped to read, import or full access
+    let create_token = warp::path!("auth" / "tokens")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(warp::header::headers_cloned())
+        .and(warp::body::json())
+        .and(with_users(users.clone()))
+        .and_then(create_token_handler);
+
+    // GET /auth/tokens - The signed-in local user's API tokens, without the tokens themselves
+    let list_tokens = warp::path!("auth" / "tokens")
+        .and(warp::get())
+        .and(warp::header::headers_cloned())
+        .and(with_users(users.clone()))
+        .and_then(list_tokens_handler);
+
+    // DELETE /auth/tokens/:id - Revoke one of the signed-in local user's API tokens
+    let revoke_token = warp::path!("auth" / "tokens" / String)
+        .and(warp::delete())
+        .and(access.scoped(Scope::Full))
+        .and(warp::header::headers_cloned())
+        .and(with_users(users.clone()))
+        .and_then(revoke_token_handler);
+
+    // GET /subscriptions - URLs registered to be told of changes
+    let list_subscriptions = warp::path!("subscriptions")
+        .and(warp::get())
+        .and(access.owner())
+        .and(with_subscriptions(subscriptions.clone()))
+        .and_then(list_subscriptions_handler);
+
+    // POST /subscriptions - Register a URL to be POSTed each change made from now on
+    let create_subscription = warp::path!("subscriptions")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(access.owner())
+        .and(with_subscriptions(subscriptions.clone()))
+        .and_then(create_subscription_handler);
+
+    // DELETE /subscriptions/:id - Stop telling a URL of changes
+    let delete_subscription = warp::path!("subscriptions" / String)
+        .and(warp::delete())
+        .and(access.scoped(Scope::Full))
+        .and(access.owner())
+        .and(with_subscriptions(subscriptions.clone()))
+        .and_then(delete_subscription_handler);
+
+    // GET /subscriptions/:id/deliveries - The latest attempts at delivering to a URL, newest first
+    let subscription_deliveries = warp::path!("subscriptions" / String / "deliveries")
+        .and(warp::get())
+        .and(access.owner())
+        .and(with_subscriptions(subscriptions.clone()))
+        .and_then(subscription_deliveries_handler);
+
+    // GET /accounts/:account_id/projected-interest?days= - What the account's balances would earn or be charged at its configured rates
+    let projected_interest = warp::path!("accounts" / String / "projected-interest")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_config(config.clone()))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(projected_interest_handler);
+
+    // GET /accounts?institution=&group=&type= - Every account with its details and balances
+    let list_accounts = warp::path!("accounts")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(list_accounts_handler);
+
+    // POST /accounts - Create an account for transactions to be recorded in
+    let create_account = warp::path!("accounts")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(create_account_handler);
+
+    // POST /accounts/merge?override_lock= - Move everything of one account into another and remove it
+    let merge_accounts = warp::path!("accounts" / "merge")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::body::json())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(merge_accounts_handler);
+
+    // GET /accounts/:account_id - An account's details and balances
+    let get_account = warp::path!("accounts" / String)
+        .and(warp::get())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(get_account_handler);
+
+    // PUT /accounts/:account_id - Replace an account's details
+    let update_account = warp::path!("accounts" / String)
+        .and(warp::put())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(update_account_handler);
+
+    // POST /accounts/:account_id/assert-balance - Record and check an expected balance
+    let assert_balance = warp::path!("accounts" / String / "assert-balance")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(assert_balance_handler);
+
+    // GET /accounts/:account_id/grants - Who the account is shared with; for its owner and admins
+    let account_grants = warp::path!("accounts" / String / "grants")
+        .and(warp::get())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(account_grants_handler);
+
+    // PUT /accounts/:account_id/grants/:user - Share the account with another user to read, or to read and change
+    let grant_access = warp::path!("accounts" / String / "grants" / String)
+        .and(warp::put())
+        .and(access.scoped(Scope::Full))
+        .and(warp::body::json())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(grant_access_handler);
+
+    // DELETE /accounts/:account_id/grants/:user - Stop sharing the account with a user
+    let revoke_access = warp::path!("accounts" / String / "grants" / String)
+        .and(warp::delete())
+        .and(access.scoped(Scope::Full))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(revoke_access_handler);
+
+    // PUT /accounts/:account_id/owner - Give the account to a user, such as one nobody owns; for admins
+    let give_account = warp::path!("accounts" / String / "owner")
+        .and(warp::put())
+        .and(access.scoped(Scope::Full))
+        .and(access.admin())
+        .and(warp::body::json())
+        .and(with_store(store.clone()))
+        .and_then(give_account_handler);
+
+    // GET /maintenance/check?institution=&group=&type=&round_to=&hide_cents=&as_of_revision=&as_of_date= - Re-check all balance assertions
+    let maintenance_check = warp::path!("maintenance" / "check")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_slot(limits.reports.clone()))
+        .and_then(maintenance_check_handler);
+
+    // GET /account-groups?by=&institution=&group=&type=&round_to=&hide_cents=&as_of_revision=&as_of_date= - Accounts put together by group or institution, with their totals
+    let account_groups = warp::path!("account-groups")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_slot(limits.reports.clone()))
+        .and_then(account_groups_handler);
+
+    // GET /net-worth?institution=&group=&type=&round_to=&hide_cents=&as_of_revision=&as_of_date= - Assets less what's owed on credit cards and loans
+    let net_worth = warp::path!("net-worth")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_slot(limits.reports.clone()))
+        .and_then(net_worth_handler);
+
+    // GET /months/:month/checklist - Check whether a month is ready to be closed
+    let month_checklist = warp::path!("months" / String / "checklist")
+        .and(warp::get())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(month_checklist_handler);
+
+    // POST /months/:month/close - Lock a month for every account once its checklist is complete
+    let close_month = warp::path!("months" / String / "close")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(close_month_handler);
+
+    // POST /imports/preview-mapping - Show how a profile would read a sample of a file
+    let preview_mapping = warp::path!("imports" / "preview-mapping")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_config(config.clone()))
+        .and(with_slot(limits.imports.clone()))
+        .and_then(preview_mapping_handler);
+
+    // GET /imports/metrics?account_id= - Statistics on past imports, per source
+    let import_metrics = warp::path!("imports" / "metrics")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(import_metrics_handler);
+
+    // GET /payees?prefix=&limit=&round_to=&hide_cents= - The most used payees, for autocomplete
+    let payees = warp::path!("payees")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(payees_handler);
+
+    // PUT /imports/:id/source?file_name= - Attach the original statement file to an import
+    let attach_statement = warp::path!("imports" / String / "source")
+        .and(warp::put())
+        .and(access.scoped(Scope::Import))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(MAX_STATEMENT_BYTES))
+        .and(warp::body::bytes())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_statements(statements.clone(), access.clone()))
+        .and_then(attach_statement_handler);
+
+    // GET /imports/:id/source - Download the statement file attached to an import; honours Range and If-Range
+    let get_statement = warp::path!("imports" / String / "source")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-range"))
+        .and(with_user_statements(statements.clone(), access.clone()))
+        .and(with_slot(limits.exports.clone()))
+        .and_then(get_statement_handler);
+
+    // GET /imports/:id/failures - The rows of an import that couldn't be read, quarantined until corrected
+    let import_failures = warp::path!("imports" / String / "failures")
+        .and(warp::get())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_quarantine(quarantine.clone()))
+        .and_then(import_failures_handler);
+
+    // POST /imports/:id/failures?override_lock=&foreign_currency= - Import corrected versions of quarantined rows as part of their import
+    let reprocess_import = warp::path!("imports" / String / "failures")
+        .and(warp::post())
+        .and(access.scoped(Scope::Import))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::body::json())
+        .and(with_config(config.clone()))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_quarantine(quarantine.clone()))
+        .and(with_slot(limits.imports.clone()))
+        .and_then(reprocess_import_handler);
+
+    // GET /bootstrap?round_to=&hide_cents= - Reference data and settings for the frontend to start with
+    let bootstrap = warp::path!("bootstrap")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_config(config.clone()))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and_then(bootstrap_handler);
+
+    // GET /dashboard/layout - The user's dashboard widgets and saved reports, the same on every device
+    let get_dashboard_layout = warp::path!("dashboard" / "layout")
+        .and(warp::get())
+        .and(with_user_dashboard(dashboard.clone(), access.clone()))
+        .and_then(get_dashboard_layout_handler);
+
+    // PUT /dashboard/layout - Save the user's dashboard widgets and saved reports; honours If-Match
+    let put_dashboard_layout = warp::path!("dashboard" / "layout")
+        .and(warp::put())
+        .and(access.scoped(Scope::Full))
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::body::content_length_limit(MAX_DASHBOARD_LAYOUT_BYTES))
+        .and(warp::body::json())
+        .and(with_user_dashboard(dashboard.clone(), access.clone()))
+        .and_then(put_dashboard_layout_handler);
+
+    // GET /events?after=&limit= - The log of every change, oldest first
+    let events = warp::path!("events")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_slot(limits.exports.clone()))
+        .and_then(events_handler);
+
+    // GET /export - Download everything as a single JSON archive; honours Range and If-Range
+    let export = warp::path!("export")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-range"))
+        .and(with_user_store(store.clone(), access.clone()))
+        .and(with_slot(limits.exports.clone()))
+        .and_then(export_handler);
+
+    // POST /import?mode=replace|merge - Load an archive made by GET /export
+    let import_archive = warp::path!("import")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(access.admin())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::body::json())
+        .and(with_store(store.clone()))
+        .and(with_slot(limits.imports.clone()))
+        .and_then(import_archive_handler);
+
+    // POST /admin/backup - Take a backup now
+    let create_backup = warp::path!("admin" / "backup")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(access.admin())
+        .and(with_backups(backups.clone()))
+        .and(with_slot(limits.exports.clone()))
+        .and_then(create_backup_handler);
+
+    // GET /admin/backup/remote - Status of pushing backups off the machine
+    let remote_backup_status = warp::path!("admin" / "backup" / "remote")
+        .and(warp::get())
+        .and(access.admin())
+        .and(with_backups(backups.clone()))
+        .and_then(remote_backup_status_handler);
+
+    // GET /snapshots/diff?a=&b= - Transactions added, removed or modified between two backups, b being the store now when absent
+    let snapshot_diff = warp::path!("snapshots" / "diff")
+        .and(warp::get())
+        .and(access.admin())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(with_backups(backups.clone()))
+        .and(with_slot(limits.reports.clone()))
+        .and_then(snapshot_diff_handler);
+
+    // GET /status - What the server found when it checked itself on startup, and whether changes are still saved
+    let status = warp::path!("status")
+        .and(warp::get())
+        .and(with_status(status))
+        .and(with_store(store.clone()))
+        .and_then(status_handler);
+
+    // GET /admin/verify - Cross-check current and historical transactions
+    let verify = warp::path!("admin" / "verify")
+        .and(warp::get())
+        .and(access.admin())
+        .and(with_store(store.clone()))
+        .and(with_slot(limits.reports.clone()))
+        .and_then(verify_handler);
+
+    // GET /integrity - Whether the event log still hashes as it did when last checked
+    let integrity = warp::path!("integrity")
+        .and(warp::get())
+        .and(access.admin())
+        .and(with_hash_chain(hash_chain))
+        .and_then(integrity_handler);
+
+    // GET /admin/memory - Approximate memory taken up per account
+    let memory = warp::path!("admin" / "memory")
+        .and(warp::get())
+        .and(access.admin())
+        .and(with_store(store.clone()))
+        .and(with_slot(limits.reports.clone()))
+        .and_then(memory_handler);
+
+    // POST /admin/compact - Release unused memory and compact storage
+    let compact = warp::path!("admin" / "compact")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(access.admin())
+        .and(with_store(store.clone()))
+        .and(with_slot(limits.exports.clone()))
+        .and_then(compact_handler);
+
+    // POST /admin/restore - Replace everything with a backup
+    let restore_backup = warp::path!("admin" / "restore")
+        .and(warp::post())
+        .and(access.scoped(Scope::Full))
+        .and(access.admin())
+        .and(warp::body::json())
+        .and(with_backups(backups.clone()))
+        .and(with_slot(limits.exports.clone()))
+        .and_then(restore_backup_handler);
+
+    // GET /openapi.json - An OpenAPI description of every route
+    let openapi = warp::path!("openapi.json")
+        .and(warp::get())
+        .and_then(openapi_handler);
+
+    // GET /docs - Swagger UI for the OpenAPI description
+    let swagger_ui = warp::path!("docs")
+        .and(warp::get())
+        .and_then(swagger_ui_handler);
+
+    // GET /*path - Files of the built frontend, and its index.html for the pages it routes itself
+    let frontend_files = warp::path::tail()
+        .and(warp::get())
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with_frontend(frontend))
+        .and_then(frontend_handler);
+
+    // Boxed in parts, as one chain of every route nests deep enough to
+    // overflow the stack of debug builds
+    let transaction_routes = get_current_transactions
         .or(get_all_transactions)
+        .or(get_account_current_transactions)
+        .or(get_account_all_transactions)
+        .or(query_transactions)
+        .or(get_transaction_by_uuid)
+        .or(update_memo_by_uuid)
+        .or(update_metadata_by_uuid)
+        .or(edit_transaction_by_uuid)
+        .or(delete_transaction_by_uuid)
+        .or(get_transaction)
+        .or(parse_text)
         .or(create_transaction)
         .or(bulk_import)
+        .or(batch_import)
         .or(update_memo)
+        .or(update_memos)
+        .or(update_metadata)
+        .or(edit_transaction)
+        .or(delete_transaction)
+        .boxed();
+    let account_routes = list_accounts
+        .or(create_account)
+        .or(merge_accounts)
+        .or(get_account)
+        .or(update_account)
+        .or(projected_interest)
+        .or(assert_balance)
+        .or(account_grants)
+        .or(grant_access)
+        .or(revoke_access)
+        .or(give_account)
+        .boxed();
+    let authenticated_routes = transaction_routes
+        .or(logout)
+        .or(current_user)
+        .or(create_token)
+        .or(list_tokens)
+        .or(revoke_token)
+        .or(list_subscriptions)
+        .or(create_subscription)
+        .or(delete_subscription)
+        .or(subscription_deliveries)
+        .or(account_routes)
+        .or(maintenance_check)
+        .or(net_worth)
+        .or(account_groups)
+        .or(month_checklist)
+        .or(close_month)
+        .or(preview_mapping)
+        .or(import_metrics)
+        .or(attach_statement)
+        .or(get_statement)
+        .or(import_failures)
+        .or(reprocess_import)
+        .or(payees)
+        .or(bootstrap)
+        .or(get_dashboard_layout)
+        .or(put_dashboard_layout)
+        .or(events)
+        .or(export)
+        .or(import_archive)
+        .or(create_backup)
+        .or(remote_backup_status)
+        .or(restore_backup)
+        .or(snapshot_diff)
+        .or(status)
+        .or(verify)
+        .or(integrity)
+        .or(memory)
+        .or(compact)
+        .or(openapi)
+        .or(swagger_ui)
+        .boxed();
+    // Webhooks are let in by their own secrets rather than the auth
+    // provider, and users have to register and sign in before they can be
+    let api = ingest_webhook
+        .or(register)
+        .or(login)
+        .or(access.authenticated().and(authenticated_routes))
+        .map(|reply| warp::reply::with_header(reply, "api-version", LATEST_API_VERSION.to_string()))
+        .boxed();
+
+    // GET /api - The API versions this server supports
+    let api_versions = warp::path!("api")
+        .and(warp::get())
+        .and_then(api_versions_handler);
+
+    // /api/v<n>/... - Every route above, at the version named in the path
+    let versioned_api = warp::path("api").and(with_path_api_version()).and(api.clone());
+
+    // /... - Every route above for clients written before the prefix, at the
+    // version an Api-Version header asks for or else the latest
+    let unversioned_api = with_header_api_version().and(api);
+
+    let routes = api_versions
+        .or(versioned_api)
+        .or(unversioned_api)
+        .or(frontend_files)
         .with(cors)
         .recover(handle_rejection);
 
-    println!("Server running on http://localhost:3030");
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+    // Compressed last, so error responses and frontend files are too
+    let response_compression = Arc::new(config.response_compression.clone());
+    let routes = routes
+        .and(warp::header::headers_cloned())
+        .and_then(move |reply, headers| {
+            let response_compression = response_compression.clone();
+            async move { compression::compress(reply, headers, &response_compression).await }
+        });
+
+    // Every line logged while serving a request carries its id
+    let routes = warp::any()
+        .map(Instant::now)
+        .and(routes)
+        .map(logging::finish_request)
+        .with(warp::trace(logging::request_span));
+    let service = warp::service(routes);
+
+    match (&config.server.unix_socket, &config.server.tls) {
+        (Some(_), Some(_)) => {
+            tracing::error!("Set either server.unix_socket or server.tls; the proxy in front of a socket serves HTTPS");
+            std::process::exit(1);
+        }
+        (Some(socket_config), None) => {
+            let (listener, socket) = match unix_socket::bind(socket_config).await {
+                Ok(bound) => bound,
+                Err(e) => {
+                    tracing::error!("Failed to listen on {}: {}", socket_config.path, e);
+                    std::process::exit(1);
+                }
+            };
+            tracing::info!("Server running on unix:{}", socket_config.path);
+            serve(service, accept::from_stream(unix_socket::incoming(listener)), |_| None).await;
+            drop(socket);
+        }
+        (None, None) => {
+            let address = tcp_address(&config.server);
+            let mut incoming = match AddrIncoming::bind(&address) {
+                Ok(incoming) => incoming,
+                Err(e) => {
+                    tracing::error!("Failed to listen on {}: {}", address, e);
+                    std::process::exit(1);
+                }
+            };
+            incoming.set_nodelay(true);
+            tracing::info!("Server running on http://{}", incoming.local_addr());
+            serve(service, incoming, |conn: &AddrStream| Some(conn.remote_addr())).await;
+        }
+        (None, Some(tls_config)) => {
+            let address = tcp_address(&config.server);
+            let acceptor = match tls::acceptor(tls_config).await {
+                Ok(acceptor) => acceptor,
+                Err(e) => {
+                    tracing::error!("Failed to set up TLS: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let listener = match tokio::net::TcpListener::bind(address).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    tracing::error!("Failed to listen on {}: {}", address, e);
+                    std::process::exit(1);
+                }
+            };
+            tracing::info!("Server running on https://{}", listener.local_addr().unwrap_or(address));
+            serve(service, accept::from_stream(tls::incoming(listener, acceptor)), |_| None).await;
+        }
+    }
+
+    // Persist whatever the flusher hasn't written yet
+    store.flush().await;
+    if let Some(failure) = store.write_failure() {
+        tracing::error!("{} changes were never saved: {}", store.unsaved(), failure);
+    }
+}
+
+/// Serve requests to `service` from the connections `incoming` accepts until
+/// shut down, giving each a `RequestAuth` with where `remote` says it came from
+async fn serve<S, I>(service: S, incoming: I, remote: fn(&I::Conn) -> Option<SocketAddr>)
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    I: Accept,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let make_service = make_service_fn(move |conn: &I::Conn| {
+        let remote = remote(conn);
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                request.extensions_mut().insert(RequestAuth::new(remote));
+                service.clone().call(request)
+            }))
+        }
+    });
+    if let Err(e) = Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+    {
+        tracing::error!("Server failed: {}", e);
+    }
+}
+
+fn tcp_address(config: &ServerConfig) -> SocketAddr {
+    match config.address.parse() {
+        Ok(address) => address,
+        Err(e) => {
+            tracing::error!("Invalid server address {}: {}", config.address, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to install SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
 }
@@ -0,0 +1,142 @@
+use crate::archive;
+use crate::config::{Config, StorageBackend, StorageConfig};
+use crate::storage::{self, Snapshot, Storage};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+const USAGE: &str = "Usage: backend migrate --from <json|sqlite|postgres> --to <json|sqlite|postgres>";
+
+/// `migrate --from <backend> --to <backend>`: copy everything from one storage
+/// backend to another, each located by the storage config, then read the
+/// target back and check it holds exactly what was copied. Run it with the
+/// server stopped. Returns the process exit code.
+pub async fn run(config: &Config, args: &[String]) -> i32 {
+    let (from, to) = match args {
+        [from_flag, from, to_flag, to] if from_flag == "--from" && to_flag == "--to" => {
+            match (parse_backend(from), parse_backend(to)) {
+                (Some(from), Some(to)) => (from, to),
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                }
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    if from == to {
+        eprintln!("Error: --from and --to must be different backends");
+        return 2;
+    }
+
+    match migrate(&config.storage, from, to).await {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+async fn migrate(config: &StorageConfig, from: StorageBackend, to: StorageBackend) -> Result<(), storage::StorageError> {
+    let cipher = storage::cipher_from_config(config).await?;
+    let open = |backend| {
+        let config = StorageConfig {
+            backend,
+            ..config.clone()
+        };
+        let cipher = cipher.clone();
+        async move { storage::from_config(&config, cipher).await }
+    };
+    let source = open(from).await?;
+    let target = open(to).await?;
+
+    let snapshot = source.load().await?;
+    let existing = target.load().await?;
+    if !existing.current.is_empty() || !existing.all.is_empty() {
+        return Err(format!("The {} backend already holds data; migrate into an empty one", name(to)).into());
+    }
+
+    let expected = Summary::of(&snapshot)?;
+    println!("Read from {}: {}", name(from), expected);
+    target.replace(&snapshot).await?;
+
+    let migrated = Summary::of(&reload(&target).await?)?;
+    println!("Written to {}: {}", name(to), migrated);
+    if migrated != expected {
+        return Err(format!("The {} backend doesn't hold what was written to it", name(to)).into());
+    }
+    println!("Migration from {} to {} verified", name(from), name(to));
+    Ok(())
+}
+
+/// Read `target` back the way the server would on its next start
+async fn reload(target: &Arc<dyn Storage>) -> Result<Snapshot, storage::StorageError> {
+    target.compact().await?;
+    target.load().await
+}
+
+/// Row counts and a checksum over the whole content, independent of how a
+/// backend orders what it loads
+#[derive(PartialEq)]
+struct Summary {
+    accounts: usize,
+    current_transactions: usize,
+    historical_transactions: usize,
+    balance_assertions: usize,
+    imports: usize,
+    checksum: String,
+}
+
+impl Summary {
+    fn of(snapshot: &Snapshot) -> Result<Self, serde_json::Error> {
+        let checksum = Sha256::digest(archive::to_json(snapshot)?)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Ok(Self {
+            accounts: snapshot.current.len(),
+            current_transactions: snapshot.current.values().map(|transactions| transactions.len()).sum(),
+            historical_transactions: snapshot.all.values().map(Vec::len).sum(),
+            balance_assertions: snapshot.balance_assertions.len(),
+            imports: snapshot.imports.len(),
+            checksum,
+        })
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} accounts, {} current and {} historical transactions, {} balance assertions, {} imports, sha256 {}",
+            self.accounts,
+            self.current_transactions,
+            self.historical_transactions,
+            self.balance_assertions,
+            self.imports,
+            self.checksum
+        )
+    }
+}
+
+// The memory backend keeps nothing between runs, so it can't take part
+fn parse_backend(name: &str) -> Option<StorageBackend> {
+    match name {
+        "json" => Some(StorageBackend::Json),
+        "sqlite" => Some(StorageBackend::Sqlite),
+        "postgres" => Some(StorageBackend::Postgres),
+        _ => None,
+    }
+}
+
+fn name(backend: StorageBackend) -> &'static str {
+    match backend {
+        StorageBackend::Json => "json",
+        StorageBackend::Memory => "memory",
+        StorageBackend::Sqlite => "sqlite",
+        StorageBackend::Postgres => "postgres",
+    }
+}
@@ -0,0 +1,396 @@
+use crate::auth::{AuthProvider, BEARER_CHALLENGE, Identity, credentials};
+use crate::error::AuthError;
+use crate::storage::StorageError;
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use reqwest::Client;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey, VerificationAlgorithm};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use warp::http::HeaderMap;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the provider's keys are trusted before being fetched again,
+/// as it rotates them now and then
+const KEYS_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// The least time between fetches of the keys, so tokens signed with an
+/// unknown key can't have them fetched on every request
+const KEYS_MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Tolerated difference between the provider's clock and ours
+const CLOCK_SKEW_SECS: i64 = 60;
+
+/// Users signed in with an OpenID Connect provider such as Authelia, Authentik
+/// or Keycloak, who send the ID token or JWT access token it issued them as a
+/// bearer token. The provider's endpoints are found by discovery from its
+/// issuer URL, and its signing keys are fetched from them as needed, so the
+/// server starts even while the provider is down.
+pub struct Oidc {
+    issuer: String,
+    audience: String,
+    username_claim: String,
+    user_map: HashMap<String, String>,
+    allowed_groups: Vec<String>,
+    client: Client,
+    keys: Mutex<Keys>,
+}
+
+#[derive(Default)]
+struct Keys {
+    keys: Vec<Jwk>,
+    fetched_at: Option<Instant>,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+/// A public key as JWKS lists them, of which only RSA and EC keys are used
+#[derive(Clone, Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    #[serde(rename = "use")]
+    use_: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+impl Oidc {
+    pub fn new(
+        issuer: &str,
+        audience: &str,
+        username_claim: &str,
+        user_map: &HashMap<String, String>,
+        allowed_groups: &[String],
+    ) -> Result<Self, StorageError> {
+        Ok(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            audience: audience.to_string(),
+            username_claim: username_claim.to_string(),
+            user_map: user_map.clone(),
+            allowed_groups: allowed_groups.to_vec(),
+            client: Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            keys: Mutex::new(Keys::default()),
+        })
+    }
+
+    /// The key `kid` of the provider, fetching its keys again when they are
+    /// old or don't include it
+    async fn key(&self, kid: Option<&str>) -> Result<Jwk, AuthError> {
+        let mut keys = self.keys.lock().await;
+        let find = |keys: &Keys| {
+            keys.keys
+                .iter()
+                .filter(|key| key.use_.as_deref().is_none_or(|use_| use_ == "sig"))
+                .find(|key| kid.is_none() || key.kid.as_deref() == kid)
+                .cloned()
+        };
+        let age = keys.fetched_at.map(|fetched_at| fetched_at.elapsed());
+        let mut found = find(&keys);
+        let stale = age.is_none_or(|age| age > KEYS_MAX_AGE);
+        let may_fetch = age.is_none_or(|age| age > KEYS_MIN_INTERVAL);
+        if (stale || found.is_none()) && may_fetch {
+            keys.fetched_at = Some(Instant::now());
+            match self.fetch_keys().await {
+                Ok(fetched) => keys.keys = fetched,
+                // Carry on with the keys fetched before while the provider is
+                // unreachable
+                Err(e) => tracing::warn!("Failed to fetch the keys of {}: {}", self.issuer, e),
+            }
+            found = find(&keys);
+        }
+        found.ok_or_else(|| AuthError::challenge("The token's signing key is unknown", BEARER_CHALLENGE))
+    }
+
+    async fn fetch_keys(&self) -> Result<Vec<Jwk>, StorageError> {
+        let discovery = self
+            .client
+            .get(format!("{}/.well-known/openid-configuration", self.issuer))
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let discovery: Discovery = serde_json::from_slice(&discovery)?;
+        if discovery.issuer.trim_end_matches('/') != self.issuer {
+            return Err(format!("The provider at {} names itself {}", self.issuer, discovery.issuer).into());
+        }
+        let set = self
+            .client
+            .get(&discovery.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(serde_json::from_slice::<JwkSet>(&set)?.keys)
+    }
+
+    /// The claims of `token` once its signature, issuer, audience and
+    /// lifetime are checked
+    async fn verify(&self, token: &str) -> Result<serde_json::Map<String, Value>, AuthError> {
+        let invalid = || AuthError::challenge("Invalid token", BEARER_CHALLENGE);
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        // What's signed is the header and payload as sent
+        let signed = &token[..header.len() + 1 + payload.len()];
+        let header: JwtHeader = decode_json(header).ok_or_else(invalid)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+
+        let key = self.key(header.kid.as_deref()).await?;
+        if !verify_signature(&header.alg, &key, signed.as_bytes(), &signature) {
+            return Err(invalid());
+        }
+
+        let claims: serde_json::Map<String, Value> = decode_json(payload).ok_or_else(invalid)?;
+        if claims.get("iss").and_then(Value::as_str).map(|iss| iss.trim_end_matches('/')) != Some(self.issuer.as_str()) {
+            return Err(AuthError::challenge("The token was issued by another provider", BEARER_CHALLENGE));
+        }
+        let audience_matches = match claims.get("aud") {
+            Some(Value::String(aud)) => *aud == self.audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(&self.audience)),
+            _ => false,
+        };
+        if !audience_matches {
+            return Err(AuthError::challenge("The token is meant for another client", BEARER_CHALLENGE));
+        }
+        let now = chrono::Utc::now().timestamp();
+        let expired = claims.get("exp").and_then(Value::as_i64).is_none_or(|exp| exp + CLOCK_SKEW_SECS < now);
+        let early = claims.get("nbf").and_then(Value::as_i64).is_some_and(|nbf| nbf - CLOCK_SKEW_SECS > now);
+        if expired || early {
+            return Err(AuthError::challenge("The token has expired or isn't valid yet", BEARER_CHALLENGE));
+        }
+        Ok(claims)
+    }
+
+    /// The user `claims` are of, as the config maps them
+    fn user(&self, claims: &serde_json::Map<String, Value>) -> Result<String, AuthError> {
+        let name = claims
+            .get(&self.username_claim)
+            .and_then(Value::as_str)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| AuthError::new(format!("The token has no {} claim", self.username_claim)))?;
+        if !self.allowed_groups.is_empty() {
+            let groups = claims.get("groups").and_then(Value::as_array);
+            let allowed = groups.is_some_and(|groups| {
+                groups
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|group| self.allowed_groups.iter().any(|allowed| allowed == group))
+            });
+            if !allowed {
+                return Err(AuthError::new(format!("{} isn't in a group allowed to use wdmmg", name)));
+            }
+        }
+        Ok(self.user_map.get(name).cloned().unwrap_or_else(|| name.to_string()))
+    }
+}
+
+#[async_trait]
+impl AuthProvider for Oidc {
+    async fn authenticate(&self, headers: &HeaderMap, _remote: Option<SocketAddr>) -> Result<Identity, AuthError> {
+        let token = credentials(headers, "bearer")
+            .ok_or_else(|| AuthError::challenge("A token from the OpenID Connect provider is required", BEARER_CHALLENGE))?;
+        let claims = self.verify(token).await?;
+        self.user(&claims).map(Identity::full)
+    }
+}
+
+fn decode_json<T: serde::de::DeserializeOwned>(part: &str) -> Option<T> {
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).ok()?).ok()
+}
+
+/// Whether `signature` is `key`'s of `message` by `alg`. Only asymmetric
+/// algorithms are accepted, as the provider's keys are public.
+fn verify_signature(alg: &str, key: &Jwk, message: &[u8], signature: &[u8]) -> bool {
+    let decode = |value: &Option<String>| value.as_deref().and_then(|value| URL_SAFE_NO_PAD.decode(value).ok());
+    let rsa = |algorithm: &'static signature::RsaParameters| {
+        let (Some(n), Some(e)) = (decode(&key.n), decode(&key.e)) else {
+            return false;
+        };
+        key.kty == "RSA" && RsaPublicKeyComponents { n, e }.verify(algorithm, message, signature).is_ok()
+    };
+    let ec = |curve: &str, algorithm: &'static dyn VerificationAlgorithm| {
+        let (Some(x), Some(y)) = (decode(&key.x), decode(&key.y)) else {
+            return false;
+        };
+        // Uncompressed, as 0x04 followed by both coordinates
+        let point = [&[4u8][..], &x, &y].concat();
+        key.kty == "EC"
+            && key.crv.as_deref() == Some(curve)
+            && UnparsedPublicKey::new(algorithm, point).verify(message, signature).is_ok()
+    };
+    match alg {
+        "RS256" => rsa(&signature::RSA_PKCS1_2048_8192_SHA256),
+        "RS384" => rsa(&signature::RSA_PKCS1_2048_8192_SHA384),
+        "RS512" => rsa(&signature::RSA_PKCS1_2048_8192_SHA512),
+        "PS256" => rsa(&signature::RSA_PSS_2048_8192_SHA256),
+        "PS384" => rsa(&signature::RSA_PSS_2048_8192_SHA384),
+        "PS512" => rsa(&signature::RSA_PSS_2048_8192_SHA512),
+        "ES256" => ec("P-256", &signature::ECDSA_P256_SHA256_FIXED),
+        "ES384" => ec("P-384", &signature::ECDSA_P384_SHA384_FIXED),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+    use serde_json::json;
+
+    const ISSUER: &str = "https://auth.example.com";
+
+    /// A provider whose one key, already fetched, is `key`'s
+    fn provider(key: &EcdsaKeyPair) -> Oidc {
+        // Uncompressed, as 0x04 followed by both coordinates
+        let point = key.public_key().as_ref();
+        let jwk = Jwk {
+            kty: "EC".to_string(),
+            kid: Some("one".to_string()),
+            use_: Some("sig".to_string()),
+            n: None,
+            e: None,
+            crv: Some("P-256".to_string()),
+            x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+            y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+        };
+        let user_map = HashMap::from([("alice@example.com".to_string(), "alice".to_string())]);
+        let mut oidc = Oidc::new(&format!("{}/", ISSUER), "wdmmg", "email", &user_map, &["finance".to_string()]).unwrap();
+        *oidc.keys.get_mut() = Keys {
+            keys: vec![jwk],
+            fetched_at: Some(Instant::now()),
+        };
+        oidc
+    }
+
+    fn key() -> EcdsaKeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+    }
+
+    fn token(key: &EcdsaKeyPair, header: Value, claims: Value) -> String {
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let signature = key.sign(&SystemRandom::new(), signed.as_bytes()).unwrap();
+        format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    }
+
+    fn claims(changes: Value) -> Value {
+        let now = chrono::Utc::now().timestamp();
+        let mut claims = json!({
+            "iss": ISSUER,
+            "aud": ["wdmmg", "other"],
+            "exp": now + 300,
+            "email": "alice@example.com",
+            "groups": ["finance"],
+        });
+        claims.as_object_mut().unwrap().extend(changes.as_object().unwrap().clone());
+        claims
+    }
+
+    fn header() -> Value {
+        json!({ "alg": "ES256", "kid": "one" })
+    }
+
+    #[tokio::test]
+    async fn lets_in_the_user_a_valid_token_names() {
+        let key = key();
+        let oidc = provider(&key);
+        let claims = oidc.verify(&token(&key, header(), claims(json!({})))).await.unwrap();
+        assert_eq!(oidc.user(&claims).unwrap(), "alice");
+    }
+
+    #[tokio::test]
+    async fn refuses_tokens_not_signed_by_the_provider() {
+        let key = key();
+        let oidc = provider(&key);
+        let refused = |token: String| {
+            let oidc = &oidc;
+            async move { oidc.verify(&token).await.unwrap_err().message }
+        };
+
+        // Bob's claims under a signature of Alice's
+        let signed = token(&key, header(), claims(json!({})));
+        let (_, signature) = signed.rsplit_once('.').unwrap();
+        let forged = token(&key, header(), claims(json!({ "email": "bob@example.com" })));
+        let (unsigned, _) = forged.rsplit_once('.').unwrap();
+        assert_eq!(refused(format!("{}.{}", unsigned, signature)).await, "Invalid token");
+
+        assert_eq!(refused(token(&self::key(), header(), claims(json!({})))).await, "Invalid token");
+        // A symmetric algorithm would let anyone with the public key sign tokens
+        let hs256 = json!({ "alg": "HS256", "kid": "one" });
+        assert_eq!(refused(token(&key, hs256, claims(json!({})))).await, "Invalid token");
+        assert_eq!(refused("not a token".to_string()).await, "Invalid token");
+    }
+
+    #[tokio::test]
+    async fn refuses_tokens_for_someone_else_or_out_of_date() {
+        let key = key();
+        let oidc = provider(&key);
+        let refused = |changes: Value| {
+            let (oidc, token) = (&oidc, token(&key, header(), claims(changes)));
+            async move { oidc.verify(&token).await.unwrap_err().message }
+        };
+        let now = chrono::Utc::now().timestamp();
+
+        assert_eq!(
+            refused(json!({ "iss": "https://evil.example.com" })).await,
+            "The token was issued by another provider"
+        );
+        assert_eq!(refused(json!({ "aud": "other" })).await, "The token is meant for another client");
+        let out_of_date = "The token has expired or isn't valid yet";
+        assert_eq!(refused(json!({ "exp": now - CLOCK_SKEW_SECS - 10 })).await, out_of_date);
+        assert_eq!(refused(json!({ "nbf": now + CLOCK_SKEW_SECS + 10 })).await, out_of_date);
+        assert_eq!(refused(json!({ "exp": null })).await, out_of_date);
+        // Within the tolerated clock skew
+        let skewed = token(&key, header(), claims(json!({ "exp": now - 10 })));
+        assert!(oidc.verify(&skewed).await.is_ok());
+    }
+
+    #[test]
+    fn lets_in_only_members_of_the_allowed_groups() {
+        let oidc = provider(&key());
+        let user = |changes: Value| {
+            let Value::Object(claims) = claims(changes) else { unreachable!() };
+            oidc.user(&claims).map_err(|e| e.message)
+        };
+        assert_eq!(user(json!({ "email": "carol@example.com" })), Ok("carol@example.com".to_string()));
+        assert_eq!(
+            user(json!({ "groups": ["staff"] })),
+            Err("alice@example.com isn't in a group allowed to use wdmmg".to_string())
+        );
+        assert_eq!(user(json!({ "email": "" })), Err("The token has no email claim".to_string()));
+    }
+}
@@ -0,0 +1,895 @@
+use crate::utils::LATEST_API_VERSION;
+use serde_json::{json, Map, Value};
+
+/// One query parameter or header an operation reads
+struct Param {
+    name: &'static str,
+    required: bool,
+    description: &'static str,
+}
+
+const fn param(name: &'static str, description: &'static str) -> Param {
+    Param {
+        name,
+        required: false,
+        description,
+    }
+}
+
+const fn required(name: &'static str, description: &'static str) -> Param {
+    Param {
+        name,
+        required: true,
+        description,
+    }
+}
+
+/// What the body of a request is
+struct Body {
+    content_type: &'static str,
+    description: &'static str,
+}
+
+const JSON_BODY: &str = "application/json";
+
+struct Operation {
+    method: &'static str,
+    /// With `{name}` for each path parameter
+    path: &'static str,
+    summary: &'static str,
+    query: &'static [&'static [Param]],
+    headers: &'static [Param],
+    body: Option<Body>,
+}
+
+/// Naming a transaction by what it is rather than by its uuid
+const TRANSACTION_ID: &[Param] = &[
+    required("timestamp", "RFC 3339 timestamp of the transaction"),
+    required("amount", "Amount in units of the currency, e.g. -12.50"),
+    required("currency", "Currency code, e.g. AUD"),
+    required("payee", "Payee, exactly as stored"),
+    param("occurrence", "Which of several identical transactions, counting from 0; 0 when absent"),
+];
+
+const OVERRIDE_LOCK: &[Param] = &[param(
+    "override_lock",
+    "true to change transactions in a locked or closed month",
+)];
+
+const FOREIGN_CURRENCY: &[Param] = &[param(
+    "foreign_currency",
+    "true to import rows in another currency than the account's",
+)];
+
+const ACCOUNT_SELECTOR: &[Param] = &[
+    param("institution", "Only accounts at this institution, ignoring case"),
+    param("group", "Only accounts in this group, ignoring case"),
+    param("type", "Only accounts of this type: checking, savings, credit_card, loan, cash or asset"),
+];
+
+const FILTER: &[Param] = &[
+    param("from", "Only transactions at or after this RFC 3339 timestamp"),
+    param("to", "Only transactions before this RFC 3339 timestamp"),
+    param("payee", "Only transactions whose payee contains this, ignoring case"),
+    param("currency", "Only transactions in this currency"),
+    param("min_amount", "Only transactions of at least this amount, in units"),
+    param("max_amount", "Only transactions of at most this amount, in units"),
+    param("meta.<key>", "Only transactions whose metadata key <key> has this value; repeatable"),
+];
+
+const ACCOUNT_FILTER: &[Param] = &[param("account_id", "Only transactions of this account")];
+
+const SORT: &[Param] = &[param(
+    "sort",
+    "timestamp, amount or payee, prefixed with - for descending; newest first when absent",
+)];
+
+const PAGE: &[Param] = &[
+    param("limit", "Return at most this many"),
+    param("offset", "Skip this many first"),
+];
+
+const AS_OF: &[Param] = &[
+    param("as_of_revision", "Show the store as it was just after this position in the event log"),
+    param(
+        "as_of_date",
+        "Show the store as it was at this RFC 3339 timestamp, or the end of this YYYY-MM-DD day in UTC",
+    ),
+];
+
+const ROUNDING: &[Param] = &[
+    param("round_to", "Round amounts half to even to a multiple of this, in units, e.g. 10; at most 1000000000"),
+    param("hide_cents", "true to round amounts to whole units"),
+];
+
+const IF_MATCH: &[Param] = &[param(
+    "If-Match",
+    "ETag the client last read; the change is refused with 409 when it no longer matches",
+)];
+
+const RANGE: &[Param] = &[
+    param("Range", "A single range of bytes, such as bytes=1048576-, to resume a download"),
+    param("If-Range", "ETag of the part already downloaded; the whole file is sent when it has changed"),
+];
+
+const IDEMPOTENCY_KEY: &[Param] = &[param(
+    "Idempotency-Key",
+    "Retries with the same key get the first response back instead of running again; reusing it for a different request is refused with 422",
+)];
+
+const OPERATIONS: &[Operation] = &[
+    Operation {
+        method: "get",
+        path: "/transactions/current",
+        summary: "Get current transactions",
+        query: &[ACCOUNT_FILTER, FILTER, SORT, PAGE, AS_OF],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/transactions/all",
+        summary: "Get all historical transactions",
+        query: &[ACCOUNT_FILTER, FILTER, SORT, PAGE, AS_OF],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/accounts/{account_id}/transactions/current",
+        summary: "Get one account's current transactions",
+        query: &[FILTER, SORT, PAGE, AS_OF],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/accounts/{account_id}/transactions/all",
+        summary: "Get one account's historical transactions",
+        query: &[FILTER, SORT, PAGE, AS_OF],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/transactions/query",
+        summary: "Get current transactions matching a query",
+        query: &[
+            &[required("q", "Query such as amount<-50 AND payee~\"uber\"")],
+            SORT,
+            PAGE,
+            AS_OF,
+        ],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/transactions/by-id/{uuid}",
+        summary: "Get one transaction by its uuid, with its memo and metadata",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "put",
+        path: "/transactions/by-id/{uuid}/memo",
+        summary: "Update the memo of a transaction by its uuid",
+        query: &[OVERRIDE_LOCK],
+        headers: IF_MATCH,
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "{\"memo\": \"...\"}",
+        }),
+    },
+    Operation {
+        method: "patch",
+        path: "/transactions/by-id/{uuid}/metadata",
+        summary: "Set or remove metadata keys of a transaction by its uuid",
+        query: &[OVERRIDE_LOCK],
+        headers: IF_MATCH,
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "Keys to set, with null for keys to remove",
+        }),
+    },
+    Operation {
+        method: "put",
+        path: "/transactions/by-id/{uuid}",
+        summary: "Correct the timestamp, payee or amount of a transaction by its uuid",
+        query: &[OVERRIDE_LOCK],
+        headers: IF_MATCH,
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "The fields to change",
+        }),
+    },
+    Operation {
+        method: "delete",
+        path: "/transactions/by-id/{uuid}",
+        summary: "Delete a transaction by its uuid",
+        query: &[OVERRIDE_LOCK],
+        headers: IF_MATCH,
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/transactions/{account_id}/one",
+        summary: "Get one transaction with its memo and metadata",
+        query: &[TRANSACTION_ID],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/transactions/parse-text",
+        summary: "Read a line such as \"lunch 14.20 at Pho King yesterday\" into a draft transaction to confirm",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "text to read, with account_id and currency for when the text and config don't say, and the client's today for relative dates",
+        }),
+    },
+    Operation {
+        method: "post",
+        path: "/transactions",
+        summary: "Create a new transaction",
+        query: &[OVERRIDE_LOCK],
+        headers: IDEMPOTENCY_KEY,
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "account_id, timestamp, amount, currency and payee of the transaction, allow_duplicate to record a further occurrence, and foreign_currency to record it in another currency than the account's",
+        }),
+    },
+    Operation {
+        method: "post",
+        path: "/transactions/bulk/{account_id}",
+        summary: "Upload CSV for bulk import",
+        query: &[
+            &[param("profile", "Import profile to read the file with; the default layout when absent")],
+            OVERRIDE_LOCK,
+            FOREIGN_CURRENCY,
+        ],
+        headers: IDEMPOTENCY_KEY,
+        body: Some(Body {
+            content_type: "text/csv",
+            description: "The statement file",
+        }),
+    },
+    Operation {
+        method: "post",
+        path: "/transactions/import/batch",
+        summary: "Upload several CSV files, each for its own account",
+        query: &[OVERRIDE_LOCK, FOREIGN_CURRENCY],
+        headers: IDEMPOTENCY_KEY,
+        body: Some(Body {
+            content_type: "multipart/form-data",
+            description: "A manifest part, a JSON array of {file, account_id, profile, foreign_currency}, and one file part per entry",
+        }),
+    },
+    Operation {
+        method: "put",
+        path: "/transactions/{account_id}/memo",
+        summary: "Update transaction memo",
+        query: &[TRANSACTION_ID, OVERRIDE_LOCK],
+        headers: IF_MATCH,
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "{\"memo\": \"...\"}",
+        }),
+    },
+    Operation {
+        method: "put",
+        path: "/transactions/memos",
+        summary: "Update the memos of several transactions at once",
+        query: &[OVERRIDE_LOCK],
+        headers: IF_MATCH,
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "{\"memos\": [{account_id, id, memo}]}, id being the transaction's timestamp, amount, currency, payee and occurrence",
+        }),
+    },
+    Operation {
+        method: "patch",
+        path: "/transactions/{account_id}/metadata",
+        summary: "Set or remove transaction metadata keys",
+        query: &[TRANSACTION_ID, OVERRIDE_LOCK],
+        headers: IF_MATCH,
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "Keys to set, with null for keys to remove",
+        }),
+    },
+    Operation {
+        method: "put",
+        path: "/transactions/{account_id}",
+        summary: "Correct a transaction's timestamp, payee or amount",
+        query: &[TRANSACTION_ID, OVERRIDE_LOCK],
+        headers: IF_MATCH,
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "The fields to change",
+        }),
+    },
+    Operation {
+        method: "delete",
+        path: "/transactions/{account_id}",
+        summary: "Delete a transaction",
+        query: &[TRANSACTION_ID, OVERRIDE_LOCK],
+        headers: IF_MATCH,
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/ingest/webhook/{source}",
+        summary: "Receive pushed transactions from a configured source",
+        query: &[],
+        headers: &[required("X-Webhook-Secret", "The secret configured for the source")],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "The payload, in the format of the source's mapper",
+        }),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/register",
+        summary: "Register a local user",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "username and password; once there is a user, only a signed-in one may register others \
+                          unless registration is open",
+        }),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/login",
+        summary: "Sign in as a local user, for a session token and cookie",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "username and password",
+        }),
+    },
+    Operation {
+        method: "post",
+        path: "/auth/logout",
+        summary: "End the current session",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/auth/me",
+        summary: "The signed-in local user",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/auth/tokens",
+        summary: "Issue an API token of the signed-in local user",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "name, and scope: read for reading only, import for importing statements only, without reading, or full",
+        }),
+    },
+    Operation {
+        method: "get",
+        path: "/auth/tokens",
+        summary: "The signed-in local user's API tokens, without the tokens themselves",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "delete",
+        path: "/auth/tokens/{id}",
+        summary: "Revoke one of the signed-in local user's API tokens",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/subscriptions",
+        summary: "URLs registered to be told of changes",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/subscriptions",
+        summary: "Register a URL to be POSTed each change made from now on; URLs at loopback, private or link-local addresses are refused unless their host is in subscriptions.allowed_hosts",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "url, events among created, imported, edited and deleted (all when absent), \
+                and a secret to sign deliveries with",
+        }),
+    },
+    Operation {
+        method: "delete",
+        path: "/subscriptions/{id}",
+        summary: "Stop telling a URL of changes",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/subscriptions/{id}/deliveries",
+        summary: "The latest attempts at delivering to a URL, newest first",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/accounts/{account_id}/projected-interest",
+        summary: "What the account's balances would earn or be charged at its configured rates",
+        query: &[&[param("days", "How many days from today to project over, 365 by default")]],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/accounts/{account_id}/assert-balance",
+        summary: "Record and check an expected balance",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "date, currency and the balance expected at the start of that date",
+        }),
+    },
+    Operation {
+        method: "get",
+        path: "/accounts",
+        summary: "Every account with its details and balances",
+        query: &[ACCOUNT_SELECTOR],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/accounts",
+        summary: "Create an account for transactions to be recorded in",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "account_id, display_name, and optionally institution, group, number_suffix, currency, type and opening_balance",
+        }),
+    },
+    Operation {
+        method: "post",
+        path: "/accounts/merge",
+        summary: "Move every transaction, balance assertion and import of one account into another and remove it; transactions in both are kept once",
+        query: &[OVERRIDE_LOCK],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "source_account_id, the account to remove, and target_account_id, the account to keep",
+        }),
+    },
+    Operation {
+        method: "get",
+        path: "/accounts/{account_id}",
+        summary: "An account's details and balances",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "put",
+        path: "/accounts/{account_id}",
+        summary: "Replace an account's details",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "display_name, and optionally institution, group, number_suffix, currency, type (checking, savings, credit_card, loan, cash or asset) and opening_balance, a {date, balance_cents, currency} balances count from",
+        }),
+    },
+    Operation {
+        method: "get",
+        path: "/accounts/{account_id}/grants",
+        summary: "Who the account is shared with; for its owner and admins",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "put",
+        path: "/accounts/{account_id}/grants/{user}",
+        summary: "Share the account with another user to read, or to read and change",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "permission: read or read_write",
+        }),
+    },
+    Operation {
+        method: "delete",
+        path: "/accounts/{account_id}/grants/{user}",
+        summary: "Stop sharing the account with a user",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "put",
+        path: "/accounts/{account_id}/owner",
+        summary: "Give the account to a user, such as one nobody owns; for admins",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "owner: the user the account is given to",
+        }),
+    },
+    Operation {
+        method: "get",
+        path: "/maintenance/check",
+        summary: "Re-check all balance assertions",
+        query: &[ACCOUNT_SELECTOR, ROUNDING, AS_OF],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/net-worth",
+        summary: "Assets less what's owed on credit cards and loans, per currency",
+        query: &[ACCOUNT_SELECTOR, ROUNDING, AS_OF],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/account-groups",
+        summary: "Accounts put together by group or institution, with their totals",
+        query: &[
+            &[param("by", "group or institution; group when absent")],
+            ACCOUNT_SELECTOR,
+            ROUNDING,
+            AS_OF,
+        ],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/months/{month}/checklist",
+        summary: "Check whether a month, as YYYY-MM, is ready to be closed",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/months/{month}/close",
+        summary: "Lock a month, as YYYY-MM, for every account once its checklist is complete",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/imports/preview-mapping",
+        summary: "Show how a profile would read a sample of a file",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "The sample and the profile to read it with",
+        }),
+    },
+    Operation {
+        method: "get",
+        path: "/imports/metrics",
+        summary: "Statistics on past imports, per source",
+        query: &[ACCOUNT_FILTER],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/payees",
+        summary: "The most used payees, for autocomplete",
+        query: &[
+            &[
+                param("prefix", "Only payees starting with this, ignoring case"),
+                param("limit", "Return at most this many; 10 when absent"),
+            ],
+            ROUNDING,
+        ],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "put",
+        path: "/imports/{id}/source",
+        summary: "Attach the original statement file to an import",
+        query: &[&[param("file_name", "Name to download the file as")]],
+        headers: &[],
+        body: Some(Body {
+            content_type: "application/octet-stream",
+            description: "The statement file",
+        }),
+    },
+    Operation {
+        method: "get",
+        path: "/imports/{id}/source",
+        summary: "Download the statement file attached to an import",
+        query: &[],
+        headers: RANGE,
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/imports/{id}/failures",
+        summary: "The rows of an import that couldn't be read, quarantined until corrected",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/imports/{id}/failures",
+        summary: "Import corrected versions of quarantined rows as part of their import",
+        query: &[OVERRIDE_LOCK, FOREIGN_CURRENCY],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "rows, each the position of a quarantined row with its timestamp, payee, amount and currency",
+        }),
+    },
+    Operation {
+        method: "get",
+        path: "/bootstrap",
+        summary: "Reference data and settings for the frontend to start with",
+        query: &[ROUNDING],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/dashboard/layout",
+        summary: "The user's dashboard widgets and saved reports, the same on every device",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "put",
+        path: "/dashboard/layout",
+        summary: "Save the user's dashboard widgets and saved reports",
+        query: &[],
+        headers: IF_MATCH,
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "widgets and saved_reports, each a list in the frontend's own format",
+        }),
+    },
+    Operation {
+        method: "get",
+        path: "/events",
+        summary: "The log of every change, oldest first",
+        query: &[&[
+            param("after", "Only events after this position in the log"),
+            param("limit", "Return at most this many"),
+        ]],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/export",
+        summary: "Download everything as a single JSON archive",
+        query: &[],
+        headers: RANGE,
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/import",
+        summary: "Load an archive made by GET /export",
+        query: &[&[param("mode", "replace or merge")]],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "The archive",
+        }),
+    },
+    Operation {
+        method: "post",
+        path: "/admin/backup",
+        summary: "Take a backup now",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/admin/backup/remote",
+        summary: "Status of pushing backups off the machine",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/snapshots/diff",
+        summary: "Transactions added, removed or modified between two backups",
+        query: &[&[
+            required("a", "File name of the earlier backup, or current"),
+            param("b", "File name of the later backup, or current; current when absent"),
+        ]],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/status",
+        summary: "What the server found when it checked itself on startup, and whether changes are still saved; 503 when it was started with --force despite a failure or the storage backend refused changes for good",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/admin/verify",
+        summary: "Cross-check current and historical transactions",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/integrity",
+        summary: "Whether the event log still hashes as it did when last checked",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "get",
+        path: "/admin/memory",
+        summary: "Approximate memory taken up per account",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/admin/compact",
+        summary: "Release unused memory and compact storage",
+        query: &[],
+        headers: &[],
+        body: None,
+    },
+    Operation {
+        method: "post",
+        path: "/admin/restore",
+        summary: "Replace everything with a backup",
+        query: &[],
+        headers: &[],
+        body: Some(Body {
+            content_type: JSON_BODY,
+            description: "{\"backup\": \"...\"}, a file name returned when the backup was taken",
+        }),
+    },
+];
+
+/// An OpenAPI 3 description of every route, relative to `/api/v<latest>`.
+/// It is kept by hand next to the routes in main.rs, so a route added there
+/// belongs here too.
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for operation in OPERATIONS {
+        let mut parameters: Vec<Value> = path_params(operation.path)
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": {"type": "string"},
+                })
+            })
+            .collect();
+        let query = operation.query.iter().flat_map(|params| params.iter()).map(|p| (p, "query"));
+        let headers = operation.headers.iter().map(|p| (p, "header"));
+        parameters.extend(query.chain(headers).map(|(param, location)| {
+            json!({
+                "name": param.name,
+                "in": location,
+                "required": param.required,
+                "description": param.description,
+                "schema": {"type": "string"},
+            })
+        }));
+
+        let mut entry = json!({
+            "summary": operation.summary,
+            "parameters": parameters,
+            "responses": {
+                "200": {"description": "Success"},
+                "default": {
+                    "description": "Error",
+                    "content": {JSON_BODY: {"schema": {"$ref": "#/components/schemas/Error"}}},
+                },
+            },
+        });
+        if let Some(body) = &operation.body {
+            entry["requestBody"] = json!({
+                "required": true,
+                "description": body.description,
+                "content": {body.content_type: {}},
+            });
+        }
+
+        let item = paths
+            .entry(operation.path.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+        item[operation.method] = entry;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "wdmmg",
+            "version": LATEST_API_VERSION.to_string(),
+            "description": "Every route is also served without the /api/v<n> prefix, at the version \
+                an Api-Version header asks for or else the latest.",
+        },
+        "servers": [{"url": format!("/api/v{}", LATEST_API_VERSION)}],
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "properties": {"message": {"type": "string"}},
+                },
+            },
+        },
+    })
+}
+
+/// The names in `{...}` in an OpenAPI path
+fn path_params(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+}
+
+/// A Swagger UI page for the document at `spec_url`. Swagger UI's script and
+/// styles come from a CDN rather than being built into the binary.
+pub fn swagger_ui(spec_url: &str) -> String {
+    format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>wdmmg API</title>
+<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+<script>SwaggerUIBundle({{ url: "{}", dom_id: "#swagger-ui" }});</script>
+</body>
+</html>
+"##,
+        spec_url
+    )
+}
@@ -0,0 +1,101 @@
+use crate::storage::CurrentMap;
+use crate::types::{PayeeStats, PayeeTotal, TransactionId};
+use chrono::{DateTime, Utc};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+/// Running totals per payee over the current transactions of every account,
+/// kept up to date as mutations are applied so ranking payees needs no scan
+#[derive(Debug, Clone, Default)]
+pub struct PayeeCounters {
+    payees: HashMap<String, PayeeCounter>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PayeeCounter {
+    count: usize,
+    // currency -> (transactions, cents)
+    totals: HashMap<String, (usize, i64)>,
+    // timestamp -> transactions at it, so the latest survives deletes
+    seen: BTreeMap<DateTime<Utc>, usize>,
+}
+
+impl PayeeCounters {
+    pub fn of(current: &CurrentMap) -> Self {
+        let mut counters = Self::default();
+        for id in current.values().flat_map(HashMap::keys) {
+            counters.add(id);
+        }
+        counters
+    }
+
+    pub fn add(&mut self, id: &TransactionId) {
+        let counter = self.payees.entry(id.payee.clone()).or_default();
+        counter.count += 1;
+        let total = counter.totals.entry(id.currency.clone()).or_default();
+        total.0 += 1;
+        total.1 += id.amount_cents;
+        *counter.seen.entry(id.timestamp).or_default() += 1;
+    }
+
+    pub fn remove(&mut self, id: &TransactionId) {
+        let Some(counter) = self.payees.get_mut(&id.payee) else {
+            return;
+        };
+        counter.count = counter.count.saturating_sub(1);
+        if counter.count == 0 {
+            self.payees.remove(&id.payee);
+            return;
+        }
+
+        if let Some(total) = counter.totals.get_mut(&id.currency) {
+            total.0 -= 1;
+            total.1 -= id.amount_cents;
+            if total.0 == 0 {
+                counter.totals.remove(&id.currency);
+            }
+        }
+        if let Some(seen) = counter.seen.get_mut(&id.timestamp) {
+            *seen -= 1;
+            if *seen == 0 {
+                counter.seen.remove(&id.timestamp);
+            }
+        }
+    }
+
+    /// Up to `limit` payees starting with `prefix`, ignoring case, the most
+    /// used first and the most recently used among equals
+    pub fn ranked(&self, prefix: Option<&str>, limit: usize) -> Vec<PayeeStats> {
+        let prefix = prefix.map(str::to_lowercase);
+        let mut matching: Vec<_> = self
+            .payees
+            .iter()
+            .filter(|(payee, _)| prefix.as_ref().is_none_or(|prefix| payee.to_lowercase().starts_with(prefix)))
+            .map(|(payee, counter)| (payee, counter, counter.seen.keys().next_back().copied()))
+            .collect();
+        matching.sort_by_key(|(payee, counter, last_seen)| (Reverse(counter.count), Reverse(*last_seen), *payee));
+
+        matching
+            .into_iter()
+            .take(limit)
+            .map(|(payee, counter, last_seen)| {
+                let mut totals: Vec<_> = counter
+                    .totals
+                    .iter()
+                    .map(|(currency, (count, total_cents))| PayeeTotal {
+                        currency: currency.clone(),
+                        count: *count,
+                        total_cents: *total_cents,
+                    })
+                    .collect();
+                totals.sort_by(|a, b| a.currency.cmp(&b.currency));
+                PayeeStats {
+                    payee: payee.clone(),
+                    count: counter.count,
+                    last_seen,
+                    totals,
+                }
+            })
+            .collect()
+    }
+}
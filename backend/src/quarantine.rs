@@ -0,0 +1,87 @@
+use crate::config::StatementConfig;
+use crate::error::ApiError;
+use crate::storage::{Cipher, StorageError, cipher};
+use crate::types::QuarantinedRow;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::{Mutex, MutexGuard};
+use warp::http::StatusCode;
+
+/// The rows of imports that couldn't be read, kept next to the statements
+/// until corrected versions of them are submitted, one file per import named
+/// after its id
+#[derive(Clone)]
+pub struct Quarantine {
+    dir: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    // Held from reading an import's rows until they are written back
+    changing: Arc<Mutex<()>>,
+}
+
+impl Quarantine {
+    pub fn new(config: &StatementConfig, cipher: Option<Arc<Cipher>>) -> Self {
+        Self {
+            dir: PathBuf::from(&config.dir).join("quarantine"),
+            cipher,
+            changing: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Keep the rows of the new import `import_id` that couldn't be read
+    pub async fn keep(&self, import_id: &str, rows: Vec<QuarantinedRow>) -> Result<(), StorageError> {
+        let _changing = self.changing.lock().await;
+        self.write(import_id, &rows).await
+    }
+
+    /// Hold off other changes to quarantined rows until the guard is dropped
+    pub async fn lock(&self) -> MutexGuard<'_, ()> {
+        self.changing.lock().await
+    }
+
+    /// The rows of the import `import_id` still waiting to be corrected, in
+    /// the order they were in the file
+    pub async fn rows(&self, import_id: &str) -> Result<Vec<QuarantinedRow>, ApiError> {
+        let data = match fs::read(self.path(import_id)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(internal("read", e.into())),
+        };
+        let data = cipher::open(self.cipher.as_deref(), data).map_err(|e| internal("read", e))?;
+        serde_json::from_slice(&data).map_err(|e| internal("read", e.into()))
+    }
+
+    /// Replace the rows of the import `import_id`, removing its file once
+    /// none are left. Callers hold `lock` from reading the rows.
+    pub async fn replace(&self, import_id: &str, rows: &[QuarantinedRow]) -> Result<(), ApiError> {
+        if rows.is_empty() {
+            return match fs::remove_file(self.path(import_id)).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(internal("save", e.into())),
+                _ => Ok(()),
+            };
+        }
+        self.write(import_id, rows).await.map_err(|e| internal("save", e))
+    }
+
+    async fn write(&self, import_id: &str, rows: &[QuarantinedRow]) -> Result<(), StorageError> {
+        let content = cipher::seal(self.cipher.as_deref(), serde_json::to_vec(rows)?)?;
+        fs::create_dir_all(&self.dir).await?;
+        let tmp_path = self.dir.join(format!("{}.json.tmp", import_id));
+        fs::write(&tmp_path, content).await?;
+        fs::rename(&tmp_path, self.path(import_id)).await?;
+        Ok(())
+    }
+
+    // Ids come from the store, which also keeps paths inside the directory
+    fn path(&self, import_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", import_id))
+    }
+}
+
+fn internal(action: &str, e: StorageError) -> ApiError {
+    ApiError {
+        message: format!("Failed to {} quarantined rows: {}", action, e),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
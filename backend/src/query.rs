@@ -0,0 +1,437 @@
+use crate::types::{Metadata, TransactionId};
+use chrono::{DateTime, Months, NaiveDate, TimeDelta, Utc};
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Text { field: TextField, op: TextOp, value: String },
+    Amount { op: Comparison, cents: i64 },
+    /// Timestamps from `from` up to but not including `to`
+    Date { op: Comparison, from: DateTime<Utc>, to: DateTime<Utc> },
+}
+
+#[derive(Debug, Clone)]
+pub enum TextField {
+    Account,
+    Payee,
+    Currency,
+    Meta(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TextOp {
+    Equal,
+    NotEqual,
+    /// Lowercase text contained anywhere, ignoring case
+    Contains,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Expr {
+    pub fn matches(&self, account_id: &str, id: &TransactionId, metadata: &Metadata) -> bool {
+        match self {
+            Self::And(left, right) => left.matches(account_id, id, metadata) && right.matches(account_id, id, metadata),
+            Self::Or(left, right) => left.matches(account_id, id, metadata) || right.matches(account_id, id, metadata),
+            Self::Not(expr) => !expr.matches(account_id, id, metadata),
+            Self::Condition(condition) => condition.matches(account_id, id, metadata),
+        }
+    }
+
+    /// Whether evaluating the expression looks at metadata, which callers
+    /// only need to look up when it does
+    pub fn uses_metadata(&self) -> bool {
+        match self {
+            Self::And(left, right) | Self::Or(left, right) => left.uses_metadata() || right.uses_metadata(),
+            Self::Not(expr) => expr.uses_metadata(),
+            Self::Condition(Condition::Text {
+                field: TextField::Meta(_),
+                ..
+            }) => true,
+            Self::Condition(_) => false,
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, account_id: &str, id: &TransactionId, metadata: &Metadata) -> bool {
+        match self {
+            Self::Text { field, op, value } => {
+                let text = match field {
+                    TextField::Account => account_id.to_string(),
+                    TextField::Payee => id.payee.clone(),
+                    TextField::Currency => id.currency.clone(),
+                    TextField::Meta(key) => match metadata.get(key) {
+                        Some(serde_json::Value::String(text)) => text.clone(),
+                        Some(value) => value.to_string(),
+                        // A missing key equals nothing and differs from everything
+                        None => return matches!(op, TextOp::NotEqual),
+                    },
+                };
+                match op {
+                    TextOp::Equal => text == *value,
+                    TextOp::NotEqual => text != *value,
+                    TextOp::Contains => text.to_lowercase().contains(value.as_str()),
+                }
+            }
+            Self::Amount { op, cents } => match op {
+                Comparison::Equal => id.amount_cents == *cents,
+                Comparison::NotEqual => id.amount_cents != *cents,
+                Comparison::Less => id.amount_cents < *cents,
+                Comparison::LessOrEqual => id.amount_cents <= *cents,
+                Comparison::Greater => id.amount_cents > *cents,
+                Comparison::GreaterOrEqual => id.amount_cents >= *cents,
+            },
+            Self::Date { op, from, to } => {
+                let within = id.timestamp >= *from && id.timestamp < *to;
+                match op {
+                    Comparison::Equal => within,
+                    Comparison::NotEqual => !within,
+                    Comparison::Less => id.timestamp < *from,
+                    Comparison::LessOrEqual => id.timestamp < *to,
+                    Comparison::Greater => id.timestamp >= *to,
+                    Comparison::GreaterOrEqual => id.timestamp >= *from,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+    Quoted(String),
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.extend(chars.next()),
+                        Some(c) => text.push(c),
+                        None => return Err("unterminated quoted value".to_string()),
+                    }
+                }
+                tokens.push(Token::Quoted(text));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parse a query such as `amount<-50 AND payee~"uber" AND date:2024-03`, or
+/// say what is wrong with it.
+///
+/// A condition is a field, an operator and a value with no spaces between
+/// them; values with spaces go in double quotes. Conditions combine with
+/// `AND`, `OR`, `NOT` and parentheses, and side by side they mean `AND`.
+///
+/// - `account`, `payee` and `currency` take `=` and `!=` for exact text and
+///   `~` for text contained anywhere, ignoring case
+/// - `amount` takes `=`, `!=`, `<`, `<=`, `>` and `>=`, and `:` for `=`
+/// - `date` takes a year, month, day or RFC 3339 timestamp. `:` and `=` mean
+///   within it, and the comparisons are against the whole period.
+/// - `meta.<key>` compares a metadata value like `account` does. A string
+///   value is compared as its text, anything else as its JSON.
+pub fn parse(query: &str) -> Result<Expr, String> {
+    let tokens = tokenize(query)?;
+    if tokens.is_empty() {
+        return Err("the query is empty".to_string());
+    }
+    let mut parser = Parser { tokens, position: 0 };
+    let expr = parser.or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(Token::Close) => Err("unmatched ')'".to_string()),
+        Some(token) => Err(format!("unexpected {}", describe(token))),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("OR") {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    /// Conditions side by side are joined with AND as well
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        loop {
+            if self.keyword("AND") {
+                self.next();
+            } else if self.peek().is_none() || self.peek() == Some(&Token::Close) || self.keyword("OR") {
+                return Ok(expr);
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.keyword("NOT") {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Some(Token::Word(word)) => self.condition(&word).map(Expr::Condition),
+            Some(token) => Err(format!("expected a condition, found {}", describe(&token))),
+            None => Err("expected a condition at the end of the query".to_string()),
+        }
+    }
+
+    fn condition(&mut self, word: &str) -> Result<Condition, String> {
+        let Some(start) = word.find(['=', '!', '<', '>', '~', ':']) else {
+            return Err(format!("expected a condition like field<op>value, found '{}'", word));
+        };
+        let field = &word[..start];
+        let rest = &word[start..];
+        let op = ["<=", ">=", "!=", "<", ">", "=", "~", ":"]
+            .into_iter()
+            .find(|op| rest.starts_with(op))
+            .ok_or_else(|| format!("unknown operator in '{}'", word))?;
+        let mut value = rest[op.len()..].to_string();
+        if value.is_empty() {
+            value = match self.next() {
+                Some(Token::Quoted(text)) => text,
+                _ => return Err(format!("missing value after '{}'", word)),
+            };
+        }
+
+        match field {
+            "amount" => {
+                let amount: f64 = value.parse().map_err(|_| format!("invalid amount '{}'", value))?;
+                Ok(Condition::Amount {
+                    op: comparison(op, field)?,
+                    cents: (amount * 100.0).round() as i64,
+                })
+            }
+            "date" => {
+                let (from, to) = period(&value)?;
+                Ok(Condition::Date {
+                    op: comparison(op, field)?,
+                    from,
+                    to,
+                })
+            }
+            _ => {
+                let field = match field {
+                    "account" => TextField::Account,
+                    "payee" => TextField::Payee,
+                    "currency" => TextField::Currency,
+                    _ => match field.strip_prefix("meta.") {
+                        Some(key) if !key.is_empty() => TextField::Meta(key.to_string()),
+                        _ => return Err(format!("unknown field '{}'", field)),
+                    },
+                };
+                let op = match op {
+                    "=" | ":" => TextOp::Equal,
+                    "!=" => TextOp::NotEqual,
+                    "~" => {
+                        value = value.to_lowercase();
+                        TextOp::Contains
+                    }
+                    _ => return Err(format!("'{}' can't be used with text fields", op)),
+                };
+                Ok(Condition::Text { field, op, value })
+            }
+        }
+    }
+}
+
+fn comparison(op: &str, field: &str) -> Result<Comparison, String> {
+    match op {
+        "=" | ":" => Ok(Comparison::Equal),
+        "!=" => Ok(Comparison::NotEqual),
+        "<" => Ok(Comparison::Less),
+        "<=" => Ok(Comparison::LessOrEqual),
+        ">" => Ok(Comparison::Greater),
+        ">=" => Ok(Comparison::GreaterOrEqual),
+        _ => Err(format!("'{}' can't be used with {}", op, field)),
+    }
+}
+
+/// The span of time a date value covers: a year, a month, a day, or the
+/// single instant of a timestamp
+fn period(value: &str) -> Result<(DateTime<Utc>, DateTime<Utc>), String> {
+    let invalid = || format!("invalid date '{}', expected YYYY, YYYY-MM, YYYY-MM-DD or a timestamp", value);
+    if let Ok(timestamp) = value.parse::<DateTime<Utc>>() {
+        return Ok((timestamp, timestamp + TimeDelta::nanoseconds(1)));
+    }
+
+    let (first_day, months) = match value.len() {
+        4 => (NaiveDate::parse_from_str(&format!("{}-01-01", value), "%Y-%m-%d"), Some(12)),
+        7 => (NaiveDate::parse_from_str(&format!("{}-01", value), "%Y-%m-%d"), Some(1)),
+        10 => (NaiveDate::parse_from_str(value, "%Y-%m-%d"), None),
+        _ => return Err(invalid()),
+    };
+    let first_day = first_day.map_err(|_| invalid())?;
+    let end = match months {
+        Some(months) => first_day.checked_add_months(Months::new(months)),
+        None => first_day.succ_opt(),
+    }
+    .ok_or_else(invalid)?;
+    Ok((
+        first_day.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+        end.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+    ))
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Open => "'('".to_string(),
+        Token::Close => "')'".to_string(),
+        Token::Word(word) => format!("'{}'", word),
+        Token::Quoted(text) => format!("\"{}\"", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(timestamp: &str, amount_cents: i64, payee: &str) -> TransactionId {
+        TransactionId {
+            timestamp: timestamp.parse().unwrap(),
+            amount_cents,
+            currency: "USD".to_string(),
+            payee: payee.to_string(),
+            occurrence: 0,
+        }
+    }
+
+    fn matches(query: &str, id: &TransactionId, metadata: &Metadata) -> bool {
+        parse(query).unwrap().matches("checking", id, metadata)
+    }
+
+    #[test]
+    fn combines_conditions_with_and_or_not_and_parentheses() {
+        let uber = id("2024-03-15T08:30:00Z", -6_250, "Uber Trip");
+        let none = Metadata::new();
+        assert!(matches(r#"amount<-50 AND payee~"uber" AND date:2024-03"#, &uber, &none));
+        assert!(matches("amount<-50 payee~uber", &uber, &none));
+        assert!(!matches("amount<-50 AND date:2024-04", &uber, &none));
+        assert!(matches("date:2024-04 OR account=checking", &uber, &none));
+        assert!(matches("NOT (payee=Cafe OR currency!=USD)", &uber, &none));
+        assert!(!matches("NOT payee~TRIP", &uber, &none));
+    }
+
+    #[test]
+    fn compares_dates_against_the_whole_period() {
+        let late = id("2024-03-31T23:59:59Z", 100, "Cafe");
+        let none = Metadata::new();
+        assert!(matches("date=2024-03-31", &late, &none));
+        assert!(matches("date<=2024-03", &late, &none));
+        assert!(!matches("date<2024-03", &late, &none));
+        assert!(matches("date<2025", &late, &none));
+        assert!(matches("date>=2024-03-31T23:59:59Z", &late, &none));
+        assert!(!matches("date>2024-03-31T23:59:59Z", &late, &none));
+    }
+
+    #[test]
+    fn compares_metadata_as_text_and_treats_missing_keys_as_unequal() {
+        let cafe = id("2024-03-01T12:00:00Z", -450, "Cafe");
+        let metadata = Metadata::from([
+            ("category".to_string(), serde_json::json!("Food & Drink")),
+            ("split".to_string(), serde_json::json!(2)),
+        ]);
+        assert!(matches(r#"meta.category="Food & Drink""#, &cafe, &metadata));
+        assert!(matches("meta.category~drink", &cafe, &metadata));
+        assert!(matches("meta.split=2", &cafe, &metadata));
+        assert!(!matches("meta.project=x", &cafe, &metadata));
+        assert!(matches("meta.project!=x", &cafe, &metadata));
+        assert!(parse("meta.category=x").unwrap().uses_metadata());
+        assert!(!parse("payee=x OR NOT amount>0").unwrap().uses_metadata());
+    }
+
+    #[test]
+    fn says_what_is_wrong_with_a_query() {
+        let error = |query: &str| parse(query).unwrap_err();
+        assert_eq!(error("  "), "the query is empty");
+        assert_eq!(error("(payee=Cafe"), "missing ')'");
+        assert_eq!(error("payee=Cafe)"), "unmatched ')'");
+        assert_eq!(error(r#"payee="Cafe"#), "unterminated quoted value");
+        assert_eq!(error("memo=x"), "unknown field 'memo'");
+        assert_eq!(error("payee<x"), "'<' can't be used with text fields");
+        assert_eq!(error("amount~5"), "'~' can't be used with amount");
+        assert_eq!(error("amount=five"), "invalid amount 'five'");
+        assert!(error("date:2024-13").starts_with("invalid date '2024-13'"));
+        assert_eq!(error("payee"), "expected a condition like field<op>value, found 'payee'");
+        assert_eq!(error("payee= AND"), "missing value after 'payee='");
+        assert_eq!(error("payee=x AND"), "expected a condition at the end of the query");
+    }
+}
@@ -0,0 +1,78 @@
+use crate::error::ApiError;
+use warp::http::header::{ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_RANGE, CONTENT_TYPE, ETAG, HeaderValue};
+use warp::http::{Response, StatusCode};
+use warp::hyper::Body;
+
+/// A file to download whole or, to resume an interrupted download, in part
+pub struct Download {
+    pub body: Vec<u8>,
+    pub content_type: String,
+    /// Already safe to put in a header
+    pub file_name: String,
+    /// Tells the client whether what it has is still part of the same file
+    pub etag: String,
+}
+
+/// The byte range a `Range` header asks for, as an inclusive start and exclusive
+/// end. `None` when the header isn't a single byte range, which is then
+/// ignored and the whole file sent; `Some(Err(()))` when it lies past the end.
+fn byte_range(header: &str, len: usize) -> Option<Result<(usize, usize), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    let range = match (start.is_empty(), end.is_empty()) {
+        // The last `end` bytes
+        (true, false) => {
+            let suffix: usize = end.parse().ok()?;
+            (suffix > 0).then(|| (len.saturating_sub(suffix), len))
+        }
+        (false, _) => {
+            let start: usize = start.parse().ok()?;
+            let end = match end.parse::<usize>() {
+                Ok(last) if last >= start => (last + 1).min(len),
+                Ok(_) => return None,
+                Err(_) if end.is_empty() => len,
+                Err(_) => return None,
+            };
+            (start < len).then_some((start, end))
+        }
+        (true, true) => return None,
+    };
+    Some(range.ok_or(()))
+}
+
+/// Answer with `download`, or the part of it `range` asks for when the
+/// client's copy, named by `if_range`, is of the same file
+pub fn reply(download: Download, range: Option<String>, if_range: Option<String>) -> Result<Response<Body>, ApiError> {
+    let len = download.body.len();
+    // Only an exact entity tag will do; a date can't tell two exports of the same second apart
+    let same_file = if_range.is_none_or(|if_range| if_range.trim() == download.etag);
+    let range = range.filter(|_| same_file).and_then(|range| byte_range(&range, len));
+
+    let mut builder = Response::builder()
+        .header(CONTENT_TYPE, &download.content_type)
+        .header(CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", download.file_name))
+        .header(ETAG, &download.etag)
+        .header(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    let response = match range {
+        None => builder.status(StatusCode::OK).body(Body::from(download.body)),
+        Some(Ok((start, end))) => {
+            builder = builder.header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end - 1, len));
+            let mut body = download.body;
+            body.truncate(end);
+            body.drain(..start);
+            builder.status(StatusCode::PARTIAL_CONTENT).body(Body::from(body))
+        }
+        Some(Err(())) => builder
+            .header(CONTENT_RANGE, format!("bytes */{}", len))
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .body(Body::empty()),
+    };
+    response.map_err(|e| ApiError {
+        message: format!("Failed to build the download: {}", e),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+    })
+}
@@ -0,0 +1,225 @@
+use crate::backup::Backups;
+use crate::config::{Config, StorageBackend, StorageConfig};
+use crate::storage::SchemaVersion;
+use crate::store::TransactionStore;
+use crate::types::{StartupCheck, StartupCheckKind, StatusResponse};
+use chrono::{TimeDelta, Utc};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// How far behind the newest recorded change the clock may be, for the
+/// jitter of clocks being corrected
+const CLOCK_TOLERANCE_MINUTES: i64 = 5;
+
+/// How old the newest backup may get without scheduled backups
+const UNSCHEDULED_BACKUP_MAX_AGE_DAYS: i64 = 7;
+
+/// Held while the server runs so a second one can't write the same files,
+/// and removed again when it stops
+pub struct LockFile {
+    path: PathBuf,
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Take the lock of the backends a single process writes to, kept next to
+/// their data as `<data_dir>.lock` or `<sqlite_path>.lock`. A lock left by a
+/// process that is no longer running is taken over.
+pub async fn lock(config: &StorageConfig) -> (Option<LockFile>, Option<StartupCheck>) {
+    let path = match config.backend {
+        StorageBackend::Json => PathBuf::from(format!("{}.lock", config.data_dir.trim_end_matches('/'))),
+        StorageBackend::Sqlite => PathBuf::from(format!("{}.lock", config.sqlite_path)),
+        // Shared backends keep their own writers apart, and memory has nothing to share
+        StorageBackend::Memory | StorageBackend::Postgres => return (None, None),
+    };
+    let check = |passed: bool, detail: String| StartupCheck {
+        kind: StartupCheckKind::Lock,
+        passed,
+        fatal: true,
+        detail,
+    };
+
+    let pid = std::process::id().to_string();
+    let taken = match fs::OpenOptions::new().write(true).create_new(true).open(&path).await {
+        Ok(_) => None,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            let holder = fs::read_to_string(&path).await.unwrap_or_default().trim().to_string();
+            if holder.is_empty() {
+                // Another server may be between creating the file and writing to it
+                let detail = format!("{} names no process; remove it if no server is running", path.display());
+                return (None, Some(check(false, detail)));
+            }
+            if holder != pid && Path::new("/proc").join(&holder).exists() {
+                let detail = format!("{} is held by process {}", path.display(), holder);
+                return (None, Some(check(false, detail)));
+            }
+            Some(holder)
+        }
+        Err(e) => {
+            let detail = format!("Failed to create {}: {}", path.display(), e);
+            return (None, Some(check(false, detail)));
+        }
+    };
+    if let Err(e) = fs::write(&path, &pid).await {
+        let detail = format!("Failed to write {}: {}", path.display(), e);
+        return (None, Some(check(false, detail)));
+    }
+
+    let detail = match taken {
+        Some(holder) => format!("Took over {}, left by process {} which is gone", path.display(), holder),
+        None => format!("Holding {}", path.display()),
+    };
+    (Some(LockFile { path }), Some(check(true, detail)))
+}
+
+/// Log the checks that failed, and whether that stops the server starting
+pub fn report(checks: &[StartupCheck], forced: bool) -> bool {
+    let mut refused = false;
+    for check in checks.iter().filter(|check| !check.passed) {
+        if check.fatal && !forced {
+            tracing::error!("Self-test {:?} failed: {}", check.kind, check.detail);
+        } else {
+            tracing::warn!("Self-test {:?} failed: {}", check.kind, check.detail);
+        }
+        refused |= check.fatal && !forced;
+    }
+    if refused {
+        tracing::error!("Refusing to start, as running could damage the data; run with --force to start anyway");
+    }
+    refused
+}
+
+/// Check that the server can run without damaging its data. `lock` is what
+/// `lock` found and `schema` what the backend reported when it was opened.
+pub async fn run(
+    config: &Config,
+    lock: Option<StartupCheck>,
+    schema: Option<SchemaVersion>,
+    store: &TransactionStore,
+    backups: &Backups,
+    forced: bool,
+) -> StatusResponse {
+    let mut checks = Vec::new();
+    if let Some(dir) = data_dir(&config.storage) {
+        checks.push(data_dir_writable(&dir).await);
+    }
+    if let Some(schema) = schema {
+        checks.push(StartupCheck {
+            kind: StartupCheckKind::SchemaVersion,
+            passed: schema.found <= schema.supported,
+            fatal: true,
+            detail: if schema.found <= schema.supported {
+                format!("Schema at version {}", schema.supported)
+            } else {
+                format!(
+                    "Schema at version {} was written by a newer build, which knows up to version {}",
+                    schema.found, schema.supported
+                )
+            },
+        });
+    }
+    checks.push(clock(store).await);
+    checks.extend(lock);
+    checks.push(backups_recent(config, backups).await);
+
+    StatusResponse {
+        started_at: Utc::now(),
+        ok: checks.iter().all(|check| check.passed || !check.fatal),
+        forced,
+        checks,
+        write_failure: None,
+    }
+}
+
+/// The directory the storage backend writes its files in, if it has any
+fn data_dir(config: &StorageConfig) -> Option<PathBuf> {
+    match config.backend {
+        StorageBackend::Json => Some(PathBuf::from(&config.data_dir)),
+        StorageBackend::Sqlite => Some(
+            Path::new(&config.sqlite_path)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map_or_else(|| PathBuf::from("."), Path::to_path_buf),
+        ),
+        StorageBackend::Memory | StorageBackend::Postgres => None,
+    }
+}
+
+async fn data_dir_writable(dir: &Path) -> StartupCheck {
+    let probe = dir.join(".write-test");
+    let written = async {
+        fs::create_dir_all(dir).await?;
+        fs::write(&probe, b"").await?;
+        fs::remove_file(&probe).await
+    };
+    let (passed, detail) = match written.await {
+        Ok(()) => (true, format!("{} is writable", dir.display())),
+        Err(e) => (false, format!("{} is not writable: {}", dir.display(), e)),
+    };
+    StartupCheck {
+        kind: StartupCheckKind::DataDirWritable,
+        passed,
+        fatal: true,
+        detail,
+    }
+}
+
+/// Changes are logged and locked periods judged by the clock, so one set
+/// back would record changes out of order
+async fn clock(store: &TransactionStore) -> StartupCheck {
+    let newest = async {
+        let seq = store.revision().await?;
+        Ok::<_, crate::storage::StorageError>(store.events(seq.saturating_sub(1), 1).await?.pop())
+    };
+    let (passed, detail) = match newest.await {
+        Ok(Some(logged)) => {
+            let behind = logged.event.recorded_at - Utc::now();
+            if behind > TimeDelta::minutes(CLOCK_TOLERANCE_MINUTES) {
+                let detail = format!(
+                    "The clock is {} minutes behind the newest change, recorded at {}",
+                    behind.num_minutes(),
+                    logged.event.recorded_at
+                );
+                (false, detail)
+            } else {
+                (true, format!("The clock isn't behind the newest change, recorded at {}", logged.event.recorded_at))
+            }
+        }
+        Ok(None) => (true, "No changes recorded yet".to_string()),
+        Err(e) => (false, format!("Failed to read the event log: {}", e)),
+    };
+    StartupCheck {
+        kind: StartupCheckKind::Clock,
+        passed,
+        fatal: true,
+        detail,
+    }
+}
+
+/// Within two scheduled intervals, or a week without a schedule
+async fn backups_recent(config: &Config, backups: &Backups) -> StartupCheck {
+    let max_age = match config.backups.interval_secs {
+        Some(interval_secs) => TimeDelta::seconds(2 * interval_secs as i64),
+        None => TimeDelta::days(UNSCHEDULED_BACKUP_MAX_AGE_DAYS),
+    };
+    let (passed, detail) = match backups.latest().await {
+        Ok(Some(taken_at)) => {
+            let age = Utc::now().naive_utc() - taken_at;
+            let detail = format!("The newest backup was taken at {}Z", taken_at);
+            (age <= max_age, detail)
+        }
+        Ok(None) => (false, format!("No backups in {}", config.backups.dir)),
+        Err(e) => (false, format!("Failed to list backups: {}", e)),
+    };
+    StartupCheck {
+        kind: StartupCheckKind::BackupsRecent,
+        passed,
+        fatal: false,
+        detail,
+    }
+}
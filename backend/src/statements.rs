@@ -0,0 +1,101 @@
+use crate::config::StatementConfig;
+use crate::error::ApiError;
+use crate::storage::{Cipher, StorageError, cipher};
+use crate::store::TransactionStore;
+use crate::types::StatementFile;
+use chrono::Utc;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use warp::http::StatusCode;
+
+/// The original statement files of imports, kept on disk next to whichever
+/// storage backend holds the parsed data. The store records which import has
+/// one; the file itself is named after the import's id.
+#[derive(Clone)]
+pub struct Statements {
+    dir: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    store: TransactionStore,
+}
+
+impl Statements {
+    pub fn new(config: &StatementConfig, cipher: Option<Arc<Cipher>>, store: TransactionStore) -> Self {
+        Self {
+            dir: PathBuf::from(&config.dir),
+            cipher,
+            store,
+        }
+    }
+
+    /// The statements of the imports `owner` may see, as
+    /// [`TransactionStore::for_owner`] has it
+    pub fn for_owner(&self, owner: Option<String>) -> Self {
+        Self {
+            store: self.store.for_owner(owner),
+            ..self.clone()
+        }
+    }
+
+    /// Keep `data` as the statement of the import `import_id`, replacing any
+    /// attached before
+    pub async fn attach(
+        &self,
+        import_id: &str,
+        file_name: String,
+        content_type: String,
+        data: Vec<u8>,
+    ) -> Result<StatementFile, ApiError> {
+        // Ids come from the store, which also keeps paths inside the directory
+        if self.store.import_record(import_id).is_none() {
+            return Err(not_found("Import not found"));
+        }
+
+        let statement = StatementFile {
+            file_name,
+            content_type,
+            size: data.len(),
+            attached_at: Utc::now(),
+        };
+        self.write(import_id, data).await.map_err(|e| ApiError {
+            message: format!("Failed to save statement: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+        self.store.attach_statement(import_id, statement.clone())?;
+        Ok(statement)
+    }
+
+    /// The statement attached to the import `import_id` and its contents
+    pub async fn read(&self, import_id: &str) -> Result<(StatementFile, Vec<u8>), ApiError> {
+        let record = self.store.import_record(import_id).ok_or_else(|| not_found("Import not found"))?;
+        let statement = record
+            .statement
+            .ok_or_else(|| not_found("No statement is attached to this import"))?;
+
+        let data = fs::read(self.dir.join(import_id)).await.map_err(|e| ApiError {
+            message: format!("Failed to read statement: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+        let data = cipher::open(self.cipher.as_deref(), data).map_err(|e| ApiError {
+            message: format!("Failed to read statement: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+        Ok((statement, data))
+    }
+
+    async fn write(&self, import_id: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        let content = cipher::seal(self.cipher.as_deref(), data)?;
+        fs::create_dir_all(&self.dir).await?;
+        let tmp_path = self.dir.join(format!("{}.tmp", import_id));
+        fs::write(&tmp_path, content).await?;
+        fs::rename(&tmp_path, self.dir.join(import_id)).await?;
+        Ok(())
+    }
+}
+
+fn not_found(message: &str) -> ApiError {
+    ApiError {
+        message: message.to_string(),
+        status: StatusCode::NOT_FOUND,
+    }
+}
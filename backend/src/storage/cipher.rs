@@ -0,0 +1,134 @@
+use super::StorageError;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs;
+
+/// Marks the start of every encrypted file, so plain JSON written before
+/// encryption was enabled can still be told apart and read
+const MAGIC: &[u8] = b"WDMMG-AES256GCM\n";
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+
+/// Holds the salt the key is derived with, next to the files it encrypts
+const KEY_FILE: &str = "key.json";
+// Encrypted into the key file so a wrong passphrase is caught on startup
+// rather than as a corrupt file later
+const KEY_CHECK: &[u8] = b"wdmmg";
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    salt: String,
+    check: String,
+}
+
+/// AES-256-GCM with a key derived from a passphrase by Argon2
+pub struct Cipher {
+    cipher: Aes256Gcm,
+}
+
+/// Whether the files in `dir` have been encrypted, and so need a passphrase to read
+pub fn is_encrypted_dir(dir: &Path) -> bool {
+    dir.join(KEY_FILE).exists()
+}
+
+impl Cipher {
+    /// Derive the key for the files in `dir`, creating its key file with a
+    /// fresh salt the first time
+    pub async fn for_dir(dir: &Path, passphrase: &str) -> Result<Self, StorageError> {
+        let key_path = dir.join(KEY_FILE);
+        if !key_path.exists() {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let cipher = Self::derive(passphrase, &salt)?;
+            let key_file = KeyFile {
+                salt: BASE64.encode(salt),
+                check: BASE64.encode(cipher.encrypt(KEY_CHECK)?),
+            };
+            fs::create_dir_all(dir).await?;
+            fs::write(&key_path, serde_json::to_string_pretty(&key_file)?).await?;
+            return Ok(cipher);
+        }
+
+        let key_file: KeyFile = serde_json::from_str(&fs::read_to_string(&key_path).await?)?;
+        let cipher = Self::derive(passphrase, &BASE64.decode(key_file.salt)?)?;
+        match cipher.decrypt(&BASE64.decode(key_file.check)?) {
+            Ok(check) if check == KEY_CHECK => Ok(cipher),
+            _ => Err("Wrong encryption passphrase for the data directory".into()),
+        }
+    }
+
+    fn derive(passphrase: &str, salt: &[u8]) -> Result<Self, StorageError> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| format!("Failed to derive encryption key: {}", e))?;
+        Ok(Self {
+            cipher: Aes256Gcm::new(&key.into()),
+        })
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| "Failed to encrypt data")?;
+        Ok([MAGIC, nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let data = data.strip_prefix(MAGIC).ok_or("Data is not encrypted")?;
+        if data.len() < NONCE_LEN {
+            return Err("Encrypted data is truncated".into());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        Ok(self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Encrypted data is corrupt or was written with another key")?)
+    }
+}
+
+/// Prepare file contents for writing, encrypting them when a cipher is configured
+pub fn seal(cipher: Option<&Cipher>, plaintext: Vec<u8>) -> Result<Vec<u8>, StorageError> {
+    match cipher {
+        Some(cipher) => cipher.encrypt(&plaintext),
+        None => Ok(plaintext),
+    }
+}
+
+/// Recover file contents, whether or not they were written encrypted
+pub fn open(cipher: Option<&Cipher>, data: Vec<u8>) -> Result<Vec<u8>, StorageError> {
+    if !data.starts_with(MAGIC) {
+        return Ok(data);
+    }
+    match cipher {
+        Some(cipher) => cipher.decrypt(&data),
+        None => Err("Data is encrypted but no encryption passphrase is configured".into()),
+    }
+}
+
+/// Like `seal`, for a single journal line. Encrypted lines are base64 so
+/// they stay on one line.
+pub fn seal_line(cipher: Option<&Cipher>, line: String) -> Result<String, StorageError> {
+    match cipher {
+        Some(cipher) => Ok(BASE64.encode(cipher.encrypt(line.as_bytes())?)),
+        None => Ok(line),
+    }
+}
+
+/// Like `open`, for a single journal line. Plain lines are JSON objects, so
+/// anything not starting with `{` is taken to be encrypted.
+pub fn open_line(cipher: Option<&Cipher>, line: &str) -> Result<String, StorageError> {
+    if line.starts_with('{') {
+        return Ok(line.to_string());
+    }
+    let plaintext = open(cipher, BASE64.decode(line)?)?;
+    Ok(String::from_utf8(plaintext)?)
+}
@@ -0,0 +1,720 @@
+use super::cipher::{self, Cipher};
+use super::{CurrentMap, Event, LoggedEvent, Mutation, Snapshot, Storage, StorageError, current_by_account};
+use crate::config::{Compression, Month};
+use crate::types::{AccountDetails, BalanceAssertion, CurrentTransaction, HistoricalTransaction, ImportRecord, Metadata, Permission};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+const ACCOUNTS_DIR: &str = "accounts";
+// Used while `replace` swaps in a whole new set of accounts
+const STAGING_DIR: &str = "accounts.staging";
+const REPLACED_DIR: &str = "accounts.replaced";
+const ACCOUNT_FILE: &str = "account.json";
+const CURRENT_FILE: &str = "current.json";
+const ALL_FILE: &str = "all.json";
+const BALANCE_ASSERTIONS_FILE: &str = "balance_assertions.json";
+const IMPORTS_FILE: &str = "imports.json";
+const CLOSED_MONTH_FILE: &str = "closed_month.json";
+const OWNER_FILE: &str = "owner.json";
+const GRANTS_FILE: &str = "grants.json";
+const JOURNAL_FILE: &str = "journal.jsonl";
+const EVENTS_FILE: &str = "events.jsonl";
+
+/// Every zstd frame starts with these bytes, which no JSON document can
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+const ZSTD_LEVEL: i32 = 3;
+
+// Monolithic files in the working directory, written by older versions
+const LEGACY_CURRENT_FILE: &str = "current_transactions.json";
+const LEGACY_ALL_FILE: &str = "all_transactions.json";
+const LEGACY_BALANCE_ASSERTIONS_FILE: &str = "balance_assertions.json";
+const LEGACY_JOURNAL_FILE: &str = "journal.jsonl";
+
+#[derive(Deserialize)]
+struct CurrentFile(#[serde(deserialize_with = "current_by_account::deserialize")] CurrentMap);
+
+/// JSON files under the data directory, one directory per
+/// account, plus an append-only journal of the mutations made since the
+/// files were last written. Writes only append to the journal; compaction
+/// folds it back into the files of the accounts it touched. Every event is
+/// also appended to the event log, which nothing ever rewrites.
+/// With a cipher, every file and journal or log line is encrypted before it is written.
+/// Account files can also be compressed; journal lines are too short to gain from it.
+pub struct JsonFileStorage {
+    dir: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    compression: Compression,
+    // Held while touching the journal or event log so appends never interleave with compaction
+    journal: Mutex<()>,
+}
+
+impl JsonFileStorage {
+    pub fn new(dir: impl Into<PathBuf>, cipher: Option<Arc<Cipher>>, compression: Compression) -> Self {
+        Self {
+            dir: dir.into(),
+            cipher,
+            compression,
+            journal: Mutex::new(()),
+        }
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.dir.join(JOURNAL_FILE)
+    }
+
+    fn events_path(&self) -> PathBuf {
+        self.dir.join(EVENTS_FILE)
+    }
+
+    fn account_dir(&self, account_id: &str) -> PathBuf {
+        self.dir.join(ACCOUNTS_DIR).join(encode_account_id(account_id))
+    }
+
+    async fn read_account(&self, account_id: &str, snapshot: &mut Snapshot) -> Result<(), StorageError> {
+        let dir = self.account_dir(account_id);
+
+        // The transaction files exist only once the account does; an account
+        // directory can also hold just the record of a failed import
+        if dir.join(CURRENT_FILE).exists() {
+            let current: Vec<CurrentTransaction> = self.read_json_or_default(&dir.join(CURRENT_FILE)).await?;
+            snapshot.current.insert(
+                account_id.to_string(),
+                current.into_iter().map(|t| (t.id.clone(), t)).collect(),
+            );
+        }
+        if dir.join(ALL_FILE).exists() {
+            let all: Vec<HistoricalTransaction> = self.read_json_or_default(&dir.join(ALL_FILE)).await?;
+            snapshot.all.insert(account_id.to_string(), all);
+        }
+
+        let details: Option<AccountDetails> = self.read_json_or_default(&dir.join(ACCOUNT_FILE)).await?;
+        if let Some(details) = details {
+            snapshot.accounts.insert(account_id.to_string(), details);
+            snapshot.current.entry(account_id.to_string()).or_default();
+        }
+
+        let assertions: Vec<BalanceAssertion> =
+            self.read_json_or_default(&dir.join(BALANCE_ASSERTIONS_FILE)).await?;
+        snapshot.balance_assertions.extend(assertions);
+
+        let imports: Vec<ImportRecord> = self.read_json_or_default(&dir.join(IMPORTS_FILE)).await?;
+        snapshot.imports.extend(imports);
+
+        let closed_month: Option<Month> = self.read_json_or_default(&dir.join(CLOSED_MONTH_FILE)).await?;
+        if let Some(month) = closed_month {
+            snapshot.closed_months.insert(account_id.to_string(), month);
+        }
+
+        let owner: Option<String> = self.read_json_or_default(&dir.join(OWNER_FILE)).await?;
+        if let Some(owner) = owner {
+            snapshot.account_owners.insert(account_id.to_string(), owner);
+        }
+
+        let grants: HashMap<String, Permission> = self.read_json_or_default(&dir.join(GRANTS_FILE)).await?;
+        if !grants.is_empty() {
+            snapshot.account_grants.insert(account_id.to_string(), grants);
+        }
+
+        Ok(())
+    }
+
+    async fn read_all_accounts(&self) -> Result<Snapshot, StorageError> {
+        let mut snapshot = Snapshot::default();
+        let accounts_dir = self.dir.join(ACCOUNTS_DIR);
+        if !accounts_dir.exists() {
+            return Ok(snapshot);
+        }
+
+        let mut entries = fs::read_dir(&accounts_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let Some(account_id) = name.to_str().and_then(decode_account_id) else {
+                tracing::warn!("Skipping unrecognised account directory {:?}", name);
+                continue;
+            };
+            self.read_account(&account_id, &mut snapshot).await?;
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Write `account_id`'s part of `snapshot` into `dir`
+    async fn write_account(&self, dir: &Path, account_id: &str, snapshot: &Snapshot) -> Result<(), StorageError> {
+        fs::create_dir_all(&dir).await?;
+
+        if let Some(details) = snapshot.accounts.get(account_id) {
+            self.write_json(&dir.join(ACCOUNT_FILE), details).await?;
+        }
+        if let Some(transactions) = snapshot.current.get(account_id) {
+            let current: Vec<_> = transactions.values().collect();
+            self.write_json(&dir.join(CURRENT_FILE), &current).await?;
+        }
+        if let Some(all) = snapshot.all.get(account_id) {
+            self.write_json(&dir.join(ALL_FILE), all).await?;
+        }
+
+        let assertions: Vec<_> = snapshot
+            .balance_assertions
+            .iter()
+            .filter(|assertion| assertion.account_id == account_id)
+            .collect();
+        self.write_json(&dir.join(BALANCE_ASSERTIONS_FILE), &assertions).await?;
+
+        let imports: Vec<_> = snapshot
+            .imports
+            .iter()
+            .filter(|record| record.account_id == account_id)
+            .collect();
+        self.write_json(&dir.join(IMPORTS_FILE), &imports).await?;
+
+        if let Some(month) = snapshot.closed_months.get(account_id) {
+            self.write_json(&dir.join(CLOSED_MONTH_FILE), month).await?;
+        }
+        if let Some(owner) = snapshot.account_owners.get(account_id) {
+            self.write_json(&dir.join(OWNER_FILE), owner).await?;
+        }
+        // Written even when empty, so revoking the last grant is kept
+        let grants = snapshot.account_grants.get(account_id).cloned().unwrap_or_default();
+        self.write_json(&dir.join(GRANTS_FILE), &grants).await?;
+
+        Ok(())
+    }
+
+    /// Finish or undo a `replace` that was cut short. Until the staged accounts
+    /// are renamed into place the replaced ones are still the real data.
+    async fn recover_interrupted_replace(&self) -> Result<(), StorageError> {
+        let (accounts_dir, staging_dir, replaced_dir) = (
+            self.dir.join(ACCOUNTS_DIR),
+            self.dir.join(STAGING_DIR),
+            self.dir.join(REPLACED_DIR),
+        );
+
+        if replaced_dir.exists() && !accounts_dir.exists() {
+            let replaced_journal = replaced_dir.join(JOURNAL_FILE);
+            if replaced_journal.exists() {
+                fs::rename(&replaced_journal, self.journal_path()).await?;
+            }
+            fs::rename(&replaced_dir, &accounts_dir).await?;
+        }
+        if replaced_dir.exists() {
+            fs::remove_dir_all(&replaced_dir).await?;
+        }
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir).await?;
+        }
+        Ok(())
+    }
+
+    /// Split the monolithic files of older versions into per-account files,
+    /// keeping the originals alongside with a `.migrated` suffix
+    async fn migrate_legacy_files(&self) -> Result<(), StorageError> {
+        let legacy_files = [
+            LEGACY_CURRENT_FILE,
+            LEGACY_ALL_FILE,
+            LEGACY_BALANCE_ASSERTIONS_FILE,
+            LEGACY_JOURNAL_FILE,
+        ];
+        let present: Vec<_> = legacy_files.into_iter().filter(|file| Path::new(file).exists()).collect();
+        if present.is_empty() {
+            return Ok(());
+        }
+        if self.dir.join(ACCOUNTS_DIR).exists() {
+            return Err(format!(
+                "Found both legacy data files ({}) and per-account data in {}; move one of them aside",
+                present.join(", "),
+                self.dir.display()
+            )
+            .into());
+        }
+
+        let mut snapshot = Snapshot::default();
+        if Path::new(LEGACY_CURRENT_FILE).exists() {
+            let content = fs::read_to_string(LEGACY_CURRENT_FILE).await?;
+            snapshot.current = serde_json::from_str::<CurrentFile>(&content)?.0;
+        }
+        snapshot.all = self.read_json_or_default(Path::new(LEGACY_ALL_FILE)).await?;
+        snapshot.balance_assertions =
+            self.read_json_or_default(Path::new(LEGACY_BALANCE_ASSERTIONS_FILE)).await?;
+        self.replay_journal(Path::new(LEGACY_JOURNAL_FILE), &mut snapshot).await?;
+
+        let accounts: HashSet<_> = snapshot.current.keys().chain(snapshot.all.keys()).cloned().collect();
+        for account_id in &accounts {
+            let dir = self.account_dir(account_id);
+            if snapshot.has_account(account_id) {
+                self.write_account(&dir, account_id, &snapshot).await?;
+            } else if dir.exists() {
+                // Merged into another account, so its files would only bring it back
+                fs::remove_dir_all(&dir).await?;
+            }
+        }
+
+        for file in present {
+            fs::rename(file, format!("{}.migrated", file)).await?;
+        }
+        tracing::info!(
+            "Migrated {} accounts from legacy data files into {}",
+            accounts.len(),
+            self.dir.display()
+        );
+        Ok(())
+    }
+
+    /// Replay the journal at `path` on top of `snapshot`, returning the mutations applied
+    async fn replay_journal(&self, path: &Path, snapshot: &mut Snapshot) -> Result<Vec<Mutation>, StorageError> {
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+
+        let content = fs::read_to_string(path).await?;
+        let lines: Vec<_> = content.lines().filter(|line| !line.is_empty()).collect();
+        let mut mutations = Vec::with_capacity(lines.len());
+        for (index, line) in lines.iter().enumerate() {
+            let parsed = cipher::open_line(self.cipher.as_deref(), line)
+                .and_then(|line| Ok(serde_json::from_str::<Mutation>(&line)?));
+            match parsed {
+                Ok(mutation) => {
+                    snapshot.apply(&mutation);
+                    mutations.push(mutation);
+                }
+                // A torn final line is a write cut short by a crash, which was never
+                // acknowledged. Drop it so the next append starts on a fresh line.
+                Err(_) if index == lines.len() - 1 => {
+                    let valid: String = lines[..index].iter().map(|line| format!("{}\n", line)).collect();
+                    fs::write(path, valid).await?;
+                }
+                Err(e) => return Err(format!("Corrupt journal entry {}: {}", index + 1, e).into()),
+            }
+        }
+        Ok(mutations)
+    }
+
+    /// Every event in the log, oldest first. Like the journal, a torn final
+    /// line was never acknowledged and is dropped from the file.
+    async fn read_events(&self) -> Result<Vec<Event>, StorageError> {
+        let path = self.events_path();
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+
+        let content = fs::read_to_string(&path).await?;
+        let lines: Vec<_> = content.lines().filter(|line| !line.is_empty()).collect();
+        let mut events = Vec::with_capacity(lines.len());
+        for (index, line) in lines.iter().enumerate() {
+            let parsed = cipher::open_line(self.cipher.as_deref(), line)
+                .and_then(|line| Ok(serde_json::from_str::<Event>(&line)?));
+            match parsed {
+                Ok(event) => events.push(event),
+                Err(_) if index == lines.len() - 1 => {
+                    let valid: String = lines[..index].iter().map(|line| format!("{}\n", line)).collect();
+                    fs::write(&path, valid).await?;
+                }
+                Err(e) => return Err(format!("Corrupt event log entry {}: {}", index + 1, e).into()),
+            }
+        }
+        Ok(events)
+    }
+
+    async fn read_json_or_default<T: DeserializeOwned + Default>(&self, path: &Path) -> Result<T, StorageError> {
+        if !path.exists() {
+            return Ok(T::default());
+        }
+        // Files are read whichever way they were written, so changing the
+        // compression setting takes effect as files are rewritten
+        let mut content = cipher::open(self.cipher.as_deref(), fs::read(path).await?)?;
+        if content.starts_with(ZSTD_MAGIC) {
+            content = zstd::decode_all(content.as_slice())?;
+        }
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    async fn write_json<T: Serialize + ?Sized>(&self, path: &Path, value: &T) -> Result<(), StorageError> {
+        let content = match self.compression {
+            Compression::None => serde_json::to_vec_pretty(value)?,
+            Compression::Zstd => zstd::encode_all(serde_json::to_vec(value)?.as_slice(), ZSTD_LEVEL)?,
+        };
+        let content = cipher::seal(self.cipher.as_deref(), content)?;
+        write_atomically(path, content).await
+    }
+}
+
+/// A problem `JsonFileStorage::doctor` found in the data directory
+pub struct Finding {
+    pub path: PathBuf,
+    pub problem: String,
+    pub repaired: bool,
+}
+
+impl JsonFileStorage {
+    /// Check the data directory for damage and broken invariants, fixing what
+    /// can be fixed without losing data when `repair` is set.
+    /// Only safe to run while no server is using the directory.
+    pub async fn doctor(&self, repair: bool) -> Result<Vec<Finding>, StorageError> {
+        let mut findings = Vec::new();
+        let mut report = |path: &Path, problem: String, repaired: bool| {
+            findings.push(Finding {
+                path: path.to_path_buf(),
+                problem,
+                repaired,
+            })
+        };
+
+        for path in find_temporary_files(&self.dir).await? {
+            if repair {
+                fs::remove_file(&path).await?;
+            }
+            report(&path, "Leftover temporary file from an interrupted write".to_string(), repair);
+        }
+
+        let accounts_dir = self.dir.join(ACCOUNTS_DIR);
+        for file in [LEGACY_CURRENT_FILE, LEGACY_ALL_FILE, LEGACY_BALANCE_ASSERTIONS_FILE, LEGACY_JOURNAL_FILE] {
+            if accounts_dir.exists() && Path::new(file).exists() {
+                report(
+                    Path::new(file),
+                    "Legacy data file alongside per-account data; the server will refuse to start".to_string(),
+                    false,
+                );
+            }
+        }
+
+        if accounts_dir.exists() {
+            let mut entries = fs::read_dir(&accounts_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name();
+                let Some(account_id) = name.to_str().and_then(decode_account_id) else {
+                    report(&entry.path(), "Directory name is not an encoded account id".to_string(), false);
+                    continue;
+                };
+                self.check_account(&account_id, repair, &mut report).await?;
+            }
+        }
+
+        self.check_journal(repair, &mut report).await?;
+        Ok(findings)
+    }
+
+    async fn check_account(
+        &self,
+        account_id: &str,
+        repair: bool,
+        report: &mut impl FnMut(&Path, String, bool),
+    ) -> Result<(), StorageError> {
+        let dir = self.account_dir(account_id);
+        let (current_path, all_path, assertions_path) = (
+            dir.join(CURRENT_FILE),
+            dir.join(ALL_FILE),
+            dir.join(BALANCE_ASSERTIONS_FILE),
+        );
+
+        let current = self.read_json_or_default::<Vec<CurrentTransaction>>(&current_path).await;
+        let all = self.read_json_or_default::<Vec<HistoricalTransaction>>(&all_path).await;
+        let assertions = self.read_json_or_default::<Vec<BalanceAssertion>>(&assertions_path).await;
+        let (mut current, mut all, mut assertions) = match (current, all, assertions) {
+            (Ok(current), Ok(all), Ok(assertions)) => (current, all, assertions),
+            (current, all, assertions) => {
+                for (path, result) in [
+                    (&current_path, current.err()),
+                    (&all_path, all.err()),
+                    (&assertions_path, assertions.err()),
+                ] {
+                    if let Some(e) = result {
+                        report(path, format!("Unreadable: {}", e), false);
+                    }
+                }
+                return Ok(());
+            }
+        };
+        let mut changed = false;
+
+        // Records are filed under the account they belong to
+        let misfiled = current.iter().filter(|t| t.account_id != account_id).count()
+            + all.iter().filter(|t| t.account_id != account_id).count()
+            + assertions.iter().filter(|a| a.account_id != account_id).count();
+        if misfiled > 0 {
+            report(
+                &dir,
+                format!("{} records name a different account than {}", misfiled, account_id),
+                repair,
+            );
+            current.iter_mut().for_each(|t| t.account_id = account_id.to_string());
+            all.iter_mut().for_each(|t| t.account_id = account_id.to_string());
+            assertions.iter_mut().for_each(|a| a.account_id = account_id.to_string());
+            changed = true;
+        }
+
+        // Every current transaction was recorded in the history when it was created
+        let recorded: HashSet<_> = all.iter().map(|t| t.id.clone()).collect();
+        let unrecorded: Vec<_> = current.iter().filter(|t| !recorded.contains(&t.id)).collect();
+        if !unrecorded.is_empty() {
+            report(
+                &all_path,
+                format!("{} current transactions are missing from the history", unrecorded.len()),
+                repair,
+            );
+            let missing: Vec<_> = unrecorded
+                .into_iter()
+                .map(|t| HistoricalTransaction {
+                    account_id: t.account_id.clone(),
+                    uuid: t.uuid.clone(),
+                    id: t.id.clone(),
+                    memo: None,
+                    metadata: Metadata::new(),
+                })
+                .collect();
+            all.extend(missing);
+            changed = true;
+        }
+
+        // A newer assertion for the same currency and date replaces the old one
+        let mut seen = HashSet::new();
+        let mut deduplicated: Vec<_> = assertions
+            .iter()
+            .rev()
+            .filter(|a| seen.insert((a.currency.clone(), a.date)))
+            .cloned()
+            .collect();
+        deduplicated.reverse();
+        if deduplicated.len() != assertions.len() {
+            report(
+                &assertions_path,
+                format!("{} balance assertions are superseded duplicates", assertions.len() - deduplicated.len()),
+                repair,
+            );
+            assertions = deduplicated;
+            changed = true;
+        }
+
+        if repair && changed {
+            self.write_json(&current_path, &current).await?;
+            self.write_json(&all_path, &all).await?;
+            self.write_json(&assertions_path, &assertions).await?;
+        }
+        Ok(())
+    }
+
+    async fn check_journal(
+        &self,
+        repair: bool,
+        report: &mut impl FnMut(&Path, String, bool),
+    ) -> Result<(), StorageError> {
+        let path = self.journal_path();
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(&path).await?;
+        let lines: Vec<_> = content.lines().filter(|line| !line.is_empty()).collect();
+        for (index, line) in lines.iter().enumerate() {
+            let parsed = cipher::open_line(self.cipher.as_deref(), line)
+                .and_then(|line| Ok(serde_json::from_str::<Mutation>(&line)?));
+            match parsed {
+                Ok(_) => {}
+                // Same treatment as on load: the write was never acknowledged
+                Err(_) if index == lines.len() - 1 => {
+                    if repair {
+                        let valid: String = lines[..index].iter().map(|line| format!("{}\n", line)).collect();
+                        fs::write(&path, valid).await?;
+                    }
+                    report(&path, "Incomplete final entry from an interrupted write".to_string(), repair);
+                }
+                Err(e) => report(&path, format!("Corrupt entry {}: {}", index + 1, e), false),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every `*.tmp` file under `dir`, at any depth
+async fn find_temporary_files(dir: &Path) -> Result<Vec<PathBuf>, StorageError> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if !dir.exists() {
+            continue;
+        }
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|extension| extension == "tmp") {
+                found.push(path);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Write to a temporary file and rename it over `path`, so readers never see a partial file
+async fn write_atomically(path: &Path, content: Vec<u8>) -> Result<(), StorageError> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, content).await?;
+    fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Account ids are arbitrary strings, so anything beyond a conservative set of
+/// characters is percent-encoded before being used as a directory name
+fn encode_account_id(account_id: &str) -> String {
+    account_id
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn decode_account_id(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[async_trait]
+impl Storage for JsonFileStorage {
+    async fn load(&self) -> Result<Snapshot, StorageError> {
+        let _journal = self.journal.lock().await;
+        self.recover_interrupted_replace().await?;
+        self.migrate_legacy_files().await?;
+        let mut snapshot = self.read_all_accounts().await?;
+        self.replay_journal(&self.journal_path(), &mut snapshot).await?;
+        // Drops a torn final line before anything is appended after it
+        self.read_events().await?;
+        Ok(snapshot)
+    }
+
+    async fn append(&self, events: &[Event]) -> Result<(), StorageError> {
+        let mut journal_lines = String::new();
+        let mut event_lines = String::new();
+        for event in events {
+            let mutation = serde_json::to_string(&event.mutation)?;
+            journal_lines.push_str(&cipher::seal_line(self.cipher.as_deref(), mutation)?);
+            journal_lines.push('\n');
+            event_lines.push_str(&cipher::seal_line(self.cipher.as_deref(), serde_json::to_string(event)?)?);
+            event_lines.push('\n');
+        }
+
+        let _journal = self.journal.lock().await;
+        fs::create_dir_all(&self.dir).await?;
+        for (path, lines) in [(self.journal_path(), journal_lines), (self.events_path(), event_lines)] {
+            let mut file = fs::OpenOptions::new().create(true).append(true).open(path).await?;
+            file.write_all(lines.as_bytes()).await?;
+            file.sync_data().await?;
+        }
+        Ok(())
+    }
+
+    async fn events(&self, after: u64, limit: usize) -> Result<Vec<LoggedEvent>, StorageError> {
+        let _journal = self.journal.lock().await;
+        Ok(self
+            .read_events()
+            .await?
+            .into_iter()
+            .enumerate()
+            .skip(after as usize)
+            .take(limit)
+            .map(|(index, event)| LoggedEvent {
+                seq: index as u64 + 1,
+                event,
+            })
+            .collect())
+    }
+
+    async fn latest_seq(&self) -> Result<u64, StorageError> {
+        let _journal = self.journal.lock().await;
+        Ok(self.read_events().await?.len() as u64)
+    }
+
+    async fn compact(&self) -> Result<(), StorageError> {
+        let _journal = self.journal.lock().await;
+        let journal_path = self.journal_path();
+
+        // Only the accounts the journal touched need reading and rewriting
+        let mutations = self.replay_journal(&journal_path, &mut Snapshot::default()).await?;
+        if mutations.is_empty() {
+            return Ok(());
+        }
+        let accounts: HashSet<_> = mutations.iter().flat_map(Mutation::account_ids).collect();
+
+        let mut snapshot = Snapshot::default();
+        for account_id in &accounts {
+            self.read_account(account_id, &mut snapshot).await?;
+        }
+        for mutation in &mutations {
+            snapshot.apply(mutation);
+        }
+        for account_id in &accounts {
+            let dir = self.account_dir(account_id);
+            if snapshot.has_account(account_id) {
+                self.write_account(&dir, account_id, &snapshot).await?;
+            } else if dir.exists() {
+                // Merged into another account, so its files would only bring it back
+                fs::remove_dir_all(&dir).await?;
+            }
+        }
+
+        // Everything in the journal is now part of the account files
+        fs::write(&journal_path, "").await?;
+        Ok(())
+    }
+
+    async fn replace(&self, snapshot: &Snapshot) -> Result<(), StorageError> {
+        let _journal = self.journal.lock().await;
+        self.recover_interrupted_replace().await?;
+        let (accounts_dir, staging_dir, replaced_dir) = (
+            self.dir.join(ACCOUNTS_DIR),
+            self.dir.join(STAGING_DIR),
+            self.dir.join(REPLACED_DIR),
+        );
+
+        let accounts: HashSet<_> = snapshot
+            .current
+            .keys()
+            .chain(snapshot.accounts.keys())
+            .chain(snapshot.all.keys())
+            .chain(snapshot.balance_assertions.iter().map(|assertion| &assertion.account_id))
+            .chain(snapshot.imports.iter().map(|record| &record.account_id))
+            .chain(snapshot.closed_months.keys())
+            .chain(snapshot.account_owners.keys())
+            .chain(snapshot.account_grants.keys())
+            .collect();
+        fs::create_dir_all(&staging_dir).await?;
+        for account_id in accounts {
+            let dir = staging_dir.join(encode_account_id(account_id));
+            self.write_account(&dir, account_id, snapshot).await?;
+        }
+
+        // Set the old accounts and their journal aside together, so an
+        // interruption at any point leaves one complete set to recover
+        if accounts_dir.exists() {
+            fs::rename(&accounts_dir, &replaced_dir).await?;
+        } else {
+            fs::create_dir_all(&replaced_dir).await?;
+        }
+        let journal_path = self.journal_path();
+        if journal_path.exists() {
+            fs::rename(&journal_path, replaced_dir.join(JOURNAL_FILE)).await?;
+        }
+        fs::rename(&staging_dir, &accounts_dir).await?;
+        fs::remove_dir_all(&replaced_dir).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use super::{Event, LoggedEvent, Snapshot, Storage, StorageError};
+use async_trait::async_trait;
+use std::sync::Mutex;
+
+/// Keeps persisted data in memory only, so nothing survives a restart.
+/// Useful for demos and for exercising the store without touching disk.
+#[derive(Default)]
+pub struct MemoryStorage {
+    snapshot: Mutex<Snapshot>,
+    events: Mutex<Vec<Event>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn load(&self) -> Result<Snapshot, StorageError> {
+        Ok(self.snapshot.lock().unwrap().clone())
+    }
+
+    async fn append(&self, events: &[Event]) -> Result<(), StorageError> {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        for event in events {
+            snapshot.apply(&event.mutation);
+        }
+        self.events.lock().unwrap().extend_from_slice(events);
+        Ok(())
+    }
+
+    async fn events(&self, after: u64, limit: usize) -> Result<Vec<LoggedEvent>, StorageError> {
+        Ok(self
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .skip(after as usize)
+            .take(limit)
+            .map(|(index, event)| LoggedEvent {
+                seq: index as u64 + 1,
+                event: event.clone(),
+            })
+            .collect())
+    }
+
+    async fn latest_seq(&self) -> Result<u64, StorageError> {
+        Ok(self.events.lock().unwrap().len() as u64)
+    }
+
+    async fn replace(&self, snapshot: &Snapshot) -> Result<(), StorageError> {
+        *self.snapshot.lock().unwrap() = snapshot.clone();
+        Ok(())
+    }
+}
@@ -0,0 +1,989 @@
+pub mod cipher;
+pub mod json;
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+use crate::config::{Month, StorageBackend, StorageConfig};
+use crate::payees::PayeeCounters;
+use crate::types::{
+    AccountDetails, BalanceAssertion, CurrentTransaction, HistoricalTransaction, ImportRecord, Metadata, Permission, StatementFile,
+    TransactionId,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+pub use cipher::Cipher;
+pub use json::JsonFileStorage;
+pub use memory::MemoryStorage;
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+pub type StorageError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A write a backend refused because another process changed the same data
+/// first. Retrying it can't succeed; the writer has to reload instead.
+#[derive(Debug)]
+pub struct Conflict(pub String);
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// Whether a write that failed with `e` may succeed when tried again: the
+/// backend couldn't be reached, was busy or was out of space, rather than
+/// refusing what was written
+pub fn is_transient(e: &(dyn std::error::Error + 'static)) -> bool {
+    use std::io::ErrorKind;
+    use tokio_postgres::error::SqlState;
+
+    let mut cause = Some(e);
+    while let Some(e) = cause {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                ErrorKind::Interrupted
+                    | ErrorKind::WouldBlock
+                    | ErrorKind::TimedOut
+                    | ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::StorageFull
+            );
+        }
+        if let Some(e) = e.downcast_ref::<tokio_postgres::Error>() {
+            if e.is_closed() {
+                return true;
+            }
+            // Errors without a code come from the connection, which has its own cause
+            if let Some(code) = e.code() {
+                return code.code().starts_with("08")
+                    || [
+                        SqlState::T_R_SERIALIZATION_FAILURE,
+                        SqlState::T_R_DEADLOCK_DETECTED,
+                        SqlState::TOO_MANY_CONNECTIONS,
+                        SqlState::ADMIN_SHUTDOWN,
+                        SqlState::CRASH_SHUTDOWN,
+                        SqlState::CANNOT_CONNECT_NOW,
+                    ]
+                    .contains(code);
+            }
+        }
+        if e.is::<deadpool_postgres::PoolError>() {
+            return true;
+        }
+        if let Some(rusqlite::Error::SqliteFailure(e, _)) = e.downcast_ref::<rusqlite::Error>() {
+            return matches!(
+                e.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked | rusqlite::ErrorCode::DiskFull
+            );
+        }
+        cause = e.source();
+    }
+    false
+}
+
+pub type CurrentMap = HashMap<String, HashMap<TransactionId, CurrentTransaction>>; // account_id -> transactions
+
+/// Everything the store holds, as loaded from and saved to a storage backend
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(with = "current_by_account")]
+    pub current: CurrentMap,
+    pub all: HashMap<String, Vec<HistoricalTransaction>>, // account_id -> transactions
+    #[serde(default)]
+    pub balance_assertions: Vec<BalanceAssertion>,
+    #[serde(default)]
+    pub imports: Vec<ImportRecord>,
+    /// account_id -> what it is, for accounts created with `POST /accounts`
+    #[serde(default)]
+    pub accounts: HashMap<String, AccountDetails>,
+    /// account_id -> last month closed through the month-end checklist
+    #[serde(default)]
+    pub closed_months: HashMap<String, Month>,
+    /// account_id -> the user it belongs to. Accounts created by a signed-in
+    /// user have one; the rest, such as those from before auth was set up,
+    /// are seen only by admins until one gives them an owner.
+    #[serde(default)]
+    pub account_owners: HashMap<String, String>,
+    /// account_id -> user -> what its owner has let them do with it
+    #[serde(default)]
+    pub account_grants: HashMap<String, HashMap<String, Permission>>,
+    /// Derived from `current` and never persisted. Backends load snapshots
+    /// without it, so the store rebuilds it with `reindex`.
+    #[serde(skip)]
+    pub payees: PayeeCounters,
+    /// Which transaction each uuid names, as account_id and id. Derived like
+    /// `payees`, from the current transactions and the history.
+    #[serde(skip)]
+    pub uuids: HashMap<String, (String, TransactionId)>,
+    /// The store version each transaction, by uuid, last changed at. Kept by
+    /// the store, as only it knows versions, and never persisted; one missing
+    /// is unchanged since the snapshot was loaded, at `loaded_version`.
+    #[serde(skip)]
+    pub revisions: HashMap<String, u64>,
+    #[serde(skip)]
+    pub loaded_version: u64,
+    /// The store version the state is at, moved on by every change. Kept by
+    /// the store like `revisions`.
+    #[serde(skip)]
+    pub version: u64,
+}
+
+/// JSON object keys must be strings, so current transactions are persisted as a
+/// list per account and re-keyed by their id on load
+pub mod current_by_account {
+    use super::CurrentMap;
+    use crate::types::CurrentTransaction;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S: Serializer>(current: &CurrentMap, serializer: S) -> Result<S::Ok, S::Error> {
+        current
+            .iter()
+            .map(|(account_id, transactions)| (account_id, transactions.values().collect()))
+            .collect::<HashMap<_, Vec<_>>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CurrentMap, D::Error> {
+        let lists = HashMap::<String, Vec<CurrentTransaction>>::deserialize(deserializer)?;
+        Ok(lists
+            .into_iter()
+            .map(|(account_id, transactions)| {
+                let transactions = transactions.into_iter().map(|t| (t.id.clone(), t)).collect();
+                (account_id, transactions)
+            })
+            .collect())
+    }
+}
+
+/// A single change to the store, already validated against the current state.
+/// Backends that can write incrementally persist these instead of a whole snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Mutation {
+    Created {
+        transaction: HistoricalTransaction,
+    },
+    Imported {
+        account_id: String,
+        // Inclusive timestamp range whose current transactions are replaced
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        transactions: Vec<HistoricalTransaction>,
+    },
+    MemoUpdated {
+        account_id: String,
+        id: TransactionId,
+        memo: Option<String>,
+    },
+    // Replaces the whole map, like MemoUpdated replaces the memo
+    MetadataUpdated {
+        account_id: String,
+        id: TransactionId,
+        metadata: Metadata,
+    },
+    // Re-keys the transaction in the current transactions and every record of it in the history
+    Edited {
+        account_id: String,
+        id: TransactionId,
+        new_id: TransactionId,
+    },
+    // Removes the transaction from the current transactions and every record of it from the history
+    Deleted {
+        account_id: String,
+        id: TransactionId,
+    },
+    BalanceAsserted {
+        assertion: BalanceAssertion,
+    },
+    ImportRecorded {
+        record: ImportRecord,
+    },
+    StatementAttached {
+        account_id: String,
+        import_id: String,
+        statement: StatementFile,
+    },
+    // Counts corrected rows of an import, submitted again, towards it instead of its errors
+    ImportCorrected {
+        account_id: String,
+        import_id: String,
+        imported: usize,
+        duplicates: usize,
+    },
+    // Locks the account's transactions up to the end of the month
+    MonthClosed {
+        account_id: String,
+        month: Month,
+    },
+    // Creates an account with no transactions yet
+    AccountCreated {
+        account_id: String,
+        details: AccountDetails,
+    },
+    // Replaces the account's details, or sets them for an account that had none
+    AccountUpdated {
+        account_id: String,
+        details: AccountDetails,
+    },
+    // Gives a new account to the user who created it, ahead of the mutation
+    // creating it, or an existing one to the user an admin names
+    AccountOwned {
+        account_id: String,
+        owner: String,
+    },
+    // Shares the account with another user, replacing what they were let do before
+    AccessGranted {
+        account_id: String,
+        user: String,
+        permission: Permission,
+    },
+    AccessRevoked {
+        account_id: String,
+        user: String,
+    },
+    // Moves everything recorded for the source account into the account and
+    // removes the source. Transactions the account has already are dropped
+    // from the source, history and all.
+    AccountsMerged {
+        account_id: String,
+        source_account_id: String,
+    },
+}
+
+/// A committed mutation as kept in the event log, which holds every change
+/// ever made and is never rewritten
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub recorded_at: DateTime<Utc>,
+    pub mutation: Mutation,
+    /// Set when the mutation changed a locked period and was allowed by an override
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lock_override: bool,
+}
+
+/// An event read back from the log
+#[derive(Debug, Clone, Serialize)]
+pub struct LoggedEvent {
+    /// Position in the log, from 1, in the order events were committed
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Mutation {
+    /// Earliest transaction timestamp this mutation changes, if it changes any transactions
+    pub fn earliest_timestamp(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Created { transaction } => Some(transaction.id.timestamp),
+            // Replacing the range changes everything in it, even where nothing is imported
+            Self::Imported { from, .. } => Some(*from),
+            Self::MemoUpdated { id, .. } | Self::MetadataUpdated { id, .. } | Self::Deleted { id, .. } => {
+                Some(id.timestamp)
+            }
+            Self::Edited { id, new_id, .. } => Some(id.timestamp.min(new_id.timestamp)),
+            Self::BalanceAsserted { .. }
+            | Self::ImportRecorded { .. }
+            | Self::StatementAttached { .. }
+            | Self::ImportCorrected { .. }
+            | Self::MonthClosed { .. }
+            | Self::AccountCreated { .. }
+            | Self::AccountUpdated { .. }
+            | Self::AccountOwned { .. }
+            | Self::AccessGranted { .. }
+            | Self::AccessRevoked { .. }
+            | Self::AccountsMerged { .. } => None,
+        }
+    }
+
+    /// The account this mutation changes
+    pub fn account_id(&self) -> &str {
+        match self {
+            Self::Created { transaction } => &transaction.account_id,
+            Self::Imported { account_id, .. }
+            | Self::MemoUpdated { account_id, .. }
+            | Self::MetadataUpdated { account_id, .. }
+            | Self::Edited { account_id, .. }
+            | Self::Deleted { account_id, .. }
+            | Self::StatementAttached { account_id, .. }
+            | Self::ImportCorrected { account_id, .. }
+            | Self::MonthClosed { account_id, .. }
+            | Self::AccountCreated { account_id, .. }
+            | Self::AccountUpdated { account_id, .. }
+            | Self::AccountOwned { account_id, .. }
+            | Self::AccessGranted { account_id, .. }
+            | Self::AccessRevoked { account_id, .. }
+            | Self::AccountsMerged { account_id, .. } => account_id,
+            Self::BalanceAsserted { assertion } => &assertion.account_id,
+            Self::ImportRecorded { record } => &record.account_id,
+        }
+    }
+
+    /// Whether this mutation changes who may see an account
+    pub fn changes_access(&self) -> bool {
+        matches!(
+            self,
+            Self::AccountOwned { .. } | Self::AccessGranted { .. } | Self::AccessRevoked { .. } | Self::AccountsMerged { .. }
+        )
+    }
+
+    /// Every account this mutation changes: `account_id`, and the account
+    /// merged into it
+    pub fn account_ids(&self) -> Vec<&str> {
+        match self {
+            Self::AccountsMerged {
+                account_id,
+                source_account_id,
+            } => vec![account_id, source_account_id],
+            _ => vec![self.account_id()],
+        }
+    }
+}
+
+impl Snapshot {
+    /// Rebuild everything derived from the persisted data
+    pub fn reindex(&mut self) {
+        self.payees = PayeeCounters::of(&self.current);
+        self.uuids = HashMap::new();
+        let current = self.current.values().flat_map(HashMap::values).map(|t| (&t.uuid, &t.account_id, &t.id));
+        let all = self.all.values().flatten().map(|t| (&t.uuid, &t.account_id, &t.id));
+        for (uuid, account_id, id) in current.chain(all) {
+            if !uuid.is_empty() {
+                self.uuids.insert(uuid.clone(), (account_id.clone(), id.clone()));
+            }
+        }
+    }
+
+    /// Give a uuid to every transaction persisted without one, the same for
+    /// all of its records, returning whether any were missing. Called before
+    /// `reindex` wherever a snapshot comes from outside the store.
+    pub fn assign_uuids(&mut self) -> bool {
+        let mut assigned = false;
+        let mut account_ids: Vec<_> = self.current.keys().chain(self.all.keys()).cloned().collect();
+        account_ids.sort_unstable();
+        account_ids.dedup();
+        for account_id in account_ids {
+            let mut uuids: HashMap<TransactionId, String> = HashMap::new();
+            let all = self.all.get_mut(&account_id).into_iter().flatten().map(|t| (&mut t.uuid, &t.id));
+            let current = self.current.get_mut(&account_id).into_iter().flat_map(HashMap::values_mut);
+            let records: Vec<_> = all.chain(current.map(|t| (&mut t.uuid, &t.id))).collect();
+
+            // Records that already have one lend it to the others
+            for (uuid, id) in &records {
+                if !uuid.is_empty() {
+                    uuids.entry((*id).clone()).or_insert_with(|| uuid.to_string());
+                }
+            }
+            for (uuid, id) in records {
+                if uuid.is_empty() {
+                    *uuid = uuids
+                        .entry(id.clone())
+                        .or_insert_with(|| Uuid::new_v4().to_string())
+                        .clone();
+                    assigned = true;
+                }
+            }
+        }
+        assigned
+    }
+
+    pub fn apply(&mut self, mutation: &Mutation) {
+        match mutation {
+            Mutation::Created { transaction } => {
+                let replaced = self
+                    .current
+                    .entry(transaction.account_id.clone())
+                    .or_default()
+                    .insert(transaction.id.clone(), current_of(transaction));
+                if replaced.is_none() {
+                    self.payees.add(&transaction.id);
+                }
+                self.uuids
+                    .insert(transaction.uuid.clone(), (transaction.account_id.clone(), transaction.id.clone()));
+                self.all
+                    .entry(transaction.account_id.clone())
+                    .or_default()
+                    .push(transaction.clone());
+            }
+            Mutation::Imported {
+                account_id,
+                from,
+                to,
+                transactions,
+            } => {
+                let current = self.current.entry(account_id.clone()).or_default();
+                current.retain(|id, _| {
+                    let kept = id.timestamp < *from || id.timestamp > *to;
+                    if !kept {
+                        self.payees.remove(id);
+                    }
+                    kept
+                });
+                for transaction in transactions {
+                    if current.insert(transaction.id.clone(), current_of(transaction)).is_none() {
+                        self.payees.add(&transaction.id);
+                    }
+                    self.uuids
+                        .insert(transaction.uuid.clone(), (account_id.clone(), transaction.id.clone()));
+                }
+                // Rows the account already had keep their one historical record
+                let all = self.all.entry(account_id.clone()).or_default();
+                let recorded: HashSet<String> = all.iter().map(|t| t.uuid.clone()).collect();
+                all.extend(transactions.iter().filter(|t| !recorded.contains(&t.uuid)).cloned());
+            }
+            Mutation::MemoUpdated {
+                account_id,
+                id,
+                memo,
+            } => {
+                if let Some(transaction) = self
+                    .all
+                    .get_mut(account_id)
+                    .and_then(|transactions| transactions.iter_mut().find(|t| t.id == *id))
+                {
+                    transaction.memo = memo.clone();
+                }
+            }
+            Mutation::MetadataUpdated {
+                account_id,
+                id,
+                metadata,
+            } => {
+                if let Some(transaction) = self
+                    .all
+                    .get_mut(account_id)
+                    .and_then(|transactions| transactions.iter_mut().find(|t| t.id == *id))
+                {
+                    transaction.metadata = metadata.clone();
+                }
+            }
+            Mutation::Edited {
+                account_id,
+                id,
+                new_id,
+            } => {
+                if let Some(current) = self.current.get_mut(account_id)
+                    && let Some(mut transaction) = current.remove(id)
+                {
+                    transaction.id = new_id.clone();
+                    current.insert(new_id.clone(), transaction);
+                    self.payees.remove(id);
+                    self.payees.add(new_id);
+                }
+                for transaction in self.all.get_mut(account_id).into_iter().flatten() {
+                    if transaction.id == *id {
+                        transaction.id = new_id.clone();
+                        self.uuids
+                            .insert(transaction.uuid.clone(), (account_id.clone(), new_id.clone()));
+                    }
+                }
+            }
+            Mutation::Deleted { account_id, id } => {
+                if let Some(current) = self.current.get_mut(account_id)
+                    && let Some(transaction) = current.remove(id)
+                {
+                    self.payees.remove(id);
+                    self.uuids.remove(&transaction.uuid);
+                }
+                if let Some(transactions) = self.all.get_mut(account_id) {
+                    transactions.retain(|t| {
+                        let kept = t.id != *id;
+                        if !kept {
+                            self.uuids.remove(&t.uuid);
+                        }
+                        kept
+                    });
+                }
+            }
+            Mutation::BalanceAsserted { assertion } => {
+                // A newer assertion for the same account, currency and date replaces the old one
+                self.balance_assertions.retain(|existing| {
+                    existing.account_id != assertion.account_id
+                        || existing.currency != assertion.currency
+                        || existing.date != assertion.date
+                });
+                self.balance_assertions.push(assertion.clone());
+            }
+            Mutation::ImportRecorded { record } => {
+                self.imports.push(record.clone());
+            }
+            Mutation::StatementAttached {
+                import_id,
+                statement,
+                ..
+            } => {
+                if let Some(record) = self
+                    .imports
+                    .iter_mut()
+                    .find(|record| record.id.as_deref() == Some(import_id))
+                {
+                    record.statement = Some(statement.clone());
+                }
+            }
+            Mutation::ImportCorrected {
+                import_id,
+                imported,
+                duplicates,
+                ..
+            } => {
+                if let Some(record) = self
+                    .imports
+                    .iter_mut()
+                    .find(|record| record.id.as_deref() == Some(import_id))
+                {
+                    record.imported += imported;
+                    record.duplicates += duplicates;
+                    record.errors = record.errors.saturating_sub(imported + duplicates);
+                }
+            }
+            Mutation::MonthClosed { account_id, month } => {
+                let closed = self.closed_months.entry(account_id.clone()).or_insert(*month);
+                *closed = (*closed).max(*month);
+            }
+            Mutation::AccountCreated { account_id, details } => {
+                self.accounts.insert(account_id.clone(), details.clone());
+                self.current.entry(account_id.clone()).or_default();
+            }
+            Mutation::AccountUpdated { account_id, details } => {
+                self.accounts.insert(account_id.clone(), details.clone());
+            }
+            Mutation::AccountOwned { account_id, owner } => {
+                self.account_owners.insert(account_id.clone(), owner.clone());
+            }
+            Mutation::AccessGranted {
+                account_id,
+                user,
+                permission,
+            } => {
+                self.account_grants
+                    .entry(account_id.clone())
+                    .or_default()
+                    .insert(user.clone(), *permission);
+            }
+            Mutation::AccessRevoked { account_id, user } => {
+                if let Some(grants) = self.account_grants.get_mut(account_id) {
+                    grants.remove(user);
+                    if grants.is_empty() {
+                        self.account_grants.remove(account_id);
+                    }
+                }
+            }
+            Mutation::AccountsMerged {
+                account_id,
+                source_account_id,
+            } => {
+                let mut dropped = HashSet::new();
+                let source = self.current.remove(source_account_id).unwrap_or_default();
+                let current = self.current.entry(account_id.clone()).or_default();
+                for (id, mut transaction) in source {
+                    match current.entry(id) {
+                        Entry::Occupied(entry) => {
+                            self.payees.remove(entry.key());
+                            self.uuids.remove(&transaction.uuid);
+                            dropped.insert(transaction.uuid);
+                        }
+                        Entry::Vacant(entry) => {
+                            transaction.account_id = account_id.clone();
+                            self.uuids
+                                .insert(transaction.uuid.clone(), (account_id.clone(), entry.key().clone()));
+                            entry.insert(transaction);
+                        }
+                    }
+                }
+
+                let source = self.all.remove(source_account_id).unwrap_or_default();
+                let all = self.all.entry(account_id.clone()).or_default();
+                for mut transaction in source {
+                    if !dropped.contains(&transaction.uuid) {
+                        transaction.account_id = account_id.clone();
+                        if let Some((uuid_account_id, _)) = self.uuids.get_mut(&transaction.uuid) {
+                            *uuid_account_id = account_id.clone();
+                        }
+                        all.push(transaction);
+                    }
+                }
+
+                // Where both accounts assert a balance for the same date, the account's own stands
+                let asserted: HashSet<_> = self
+                    .balance_assertions
+                    .iter()
+                    .filter(|assertion| assertion.account_id == *account_id)
+                    .map(|assertion| (assertion.currency.clone(), assertion.date))
+                    .collect();
+                self.balance_assertions.retain_mut(|assertion| {
+                    if assertion.account_id != *source_account_id {
+                        return true;
+                    }
+                    assertion.account_id = account_id.clone();
+                    !asserted.contains(&(assertion.currency.clone(), assertion.date))
+                });
+                for record in &mut self.imports {
+                    if record.account_id == *source_account_id {
+                        record.account_id = account_id.clone();
+                    }
+                }
+
+                // The account keeps its own details, lock and access; it only
+                // takes the source's details when it has none
+                if let Some(details) = self.accounts.remove(source_account_id) {
+                    self.accounts.entry(account_id.clone()).or_insert(details);
+                }
+                self.closed_months.remove(source_account_id);
+                self.account_owners.remove(source_account_id);
+                self.account_grants.remove(source_account_id);
+            }
+        }
+    }
+
+    /// Whether transactions may be recorded for `account_id`: it was created,
+    /// or has had transactions since before accounts were created
+    pub fn knows_account(&self, account_id: &str) -> bool {
+        self.accounts.contains_key(account_id) || self.current.contains_key(account_id)
+    }
+
+    /// Whether anything at all is recorded for `account_id`
+    pub fn has_account(&self, account_id: &str) -> bool {
+        self.accounts.contains_key(account_id)
+            || self.current.contains_key(account_id)
+            || self.all.contains_key(account_id)
+            || self.balance_assertions.iter().any(|assertion| assertion.account_id == account_id)
+            || self.imports.iter().any(|record| record.account_id == account_id)
+            || self.closed_months.contains_key(account_id)
+            || self.account_owners.contains_key(account_id)
+            || self.account_grants.contains_key(account_id)
+    }
+
+    /// Whether `user` may see `account_id`: it is theirs or shared with them
+    pub fn visible_to(&self, account_id: &str, user: &str) -> bool {
+        self.permission(account_id, user).is_some()
+    }
+
+    /// Whether `user` may change `account_id`: it is theirs or shared with
+    /// them to change
+    pub fn writable_by(&self, account_id: &str, user: &str) -> bool {
+        self.permission(account_id, user) == Some(Permission::ReadWrite)
+    }
+
+    /// What `user` may do with `account_id`, owners doing everything. An
+    /// account nobody owns is left to admins, who see the whole store.
+    fn permission(&self, account_id: &str, user: &str) -> Option<Permission> {
+        match self.account_owners.get(account_id) {
+            None => None,
+            Some(owner) if owner == user => Some(Permission::ReadWrite),
+            Some(_) => self.account_grants.get(account_id)?.get(user).copied(),
+        }
+    }
+
+    /// The part of the state `owner` may see, derived data included.
+    /// Ownership is judged by `owners`, as the state can be an earlier one.
+    pub fn visible_part(&self, owners: &Snapshot, owner: &str) -> Snapshot {
+        let visible = |account_id: &String| owners.visible_to(account_id, owner);
+        let mut part = Snapshot {
+            current: self
+                .current
+                .iter()
+                .filter(|(account_id, _)| visible(account_id))
+                .map(|(account_id, transactions)| (account_id.clone(), transactions.clone()))
+                .collect(),
+            all: self
+                .all
+                .iter()
+                .filter(|(account_id, _)| visible(account_id))
+                .map(|(account_id, transactions)| (account_id.clone(), transactions.clone()))
+                .collect(),
+            accounts: self
+                .accounts
+                .iter()
+                .filter(|(account_id, _)| visible(account_id))
+                .map(|(account_id, details)| (account_id.clone(), details.clone()))
+                .collect(),
+            balance_assertions: self
+                .balance_assertions
+                .iter()
+                .filter(|assertion| visible(&assertion.account_id))
+                .cloned()
+                .collect(),
+            imports: self.imports.iter().filter(|record| visible(&record.account_id)).cloned().collect(),
+            closed_months: self
+                .closed_months
+                .iter()
+                .filter(|(account_id, _)| visible(account_id))
+                .map(|(account_id, month)| (account_id.clone(), *month))
+                .collect(),
+            account_owners: self
+                .account_owners
+                .iter()
+                .filter(|(account_id, _)| visible(account_id))
+                .map(|(account_id, owner)| (account_id.clone(), owner.clone()))
+                .collect(),
+            account_grants: self
+                .account_grants
+                .iter()
+                .filter(|(account_id, _)| visible(account_id))
+                .map(|(account_id, grants)| (account_id.clone(), grants.clone()))
+                .collect(),
+            loaded_version: self.loaded_version,
+            version: self.version,
+            ..Snapshot::default()
+        };
+        part.reindex();
+        part.revisions = self
+            .revisions
+            .iter()
+            .filter(|(uuid, _)| part.uuids.contains_key(*uuid))
+            .map(|(uuid, version)| (uuid.clone(), *version))
+            .collect();
+        part
+    }
+}
+
+fn current_of(transaction: &HistoricalTransaction) -> CurrentTransaction {
+    CurrentTransaction {
+        account_id: transaction.account_id.clone(),
+        uuid: transaction.uuid.clone(),
+        id: transaction.id.clone(),
+    }
+}
+
+/// Metadata as the SQL backends keep it: JSON text, or NULL when there is none
+fn metadata_column(metadata: &Metadata) -> Result<Option<String>, serde_json::Error> {
+    if metadata.is_empty() {
+        return Ok(None);
+    }
+    serde_json::to_string(metadata).map(Some)
+}
+
+fn metadata_from_column(column: Option<String>) -> Result<Metadata, serde_json::Error> {
+    match column {
+        Some(column) => serde_json::from_str(&column),
+        None => Ok(Metadata::new()),
+    }
+}
+
+/// Where the store persists its data.
+/// The store keeps its working state in memory and serves reads from it, so
+/// backends are only called on startup, after every mutation, and for upkeep.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Read everything persisted so far. An empty backend yields an empty snapshot.
+    async fn load(&self) -> Result<Snapshot, StorageError>;
+
+    /// Persist `events`, in order, both to the event log and to the persisted
+    /// state. They have already been applied to the in-memory state. A backend
+    /// shared with other processes fails with `Conflict`, and persists none of
+    /// them, when they clash with what another process wrote.
+    async fn append(&self, events: &[Event]) -> Result<(), StorageError>;
+
+    /// Up to `limit` events from the log, oldest first, starting after position `after`
+    async fn events(&self, after: u64, limit: usize) -> Result<Vec<LoggedEvent>, StorageError>;
+
+    /// Position of the newest event in the log, or 0 while it is empty
+    async fn latest_seq(&self) -> Result<u64, StorageError>;
+
+    /// Replace the persisted state with `snapshot`, as a single atomic step.
+    /// The event log is kept as it is.
+    async fn replace(&self, snapshot: &Snapshot) -> Result<(), StorageError>;
+
+    /// Fold any incrementally written data back into its compact form.
+    /// Called periodically; backends without such a step do nothing.
+    async fn compact(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Signals when another process has written to the backend, so the
+    /// in-memory state should be reloaded. Only shared backends provide one.
+    fn changes(&self) -> Option<watch::Receiver<()>> {
+        None
+    }
+
+    /// The schema the backend was found at when opened, for backends with
+    /// schema migrations
+    fn schema_version(&self) -> Option<SchemaVersion> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SchemaVersion {
+    /// Migrations already applied when the backend was opened
+    pub found: u64,
+    /// Migrations this build knows; a newer schema was written by a newer build
+    pub supported: u64,
+}
+
+/// The cipher configured for encrypting data at rest, if any
+pub async fn cipher_from_config(config: &StorageConfig) -> Result<Option<Arc<Cipher>>, StorageError> {
+    match &config.encryption_passphrase_env {
+        Some(var) => {
+            let passphrase =
+                std::env::var(var).map_err(|_| format!("Environment variable {} is not set", var))?;
+            let cipher = Cipher::for_dir(Path::new(&config.data_dir), &passphrase).await?;
+            Ok(Some(Arc::new(cipher)))
+        }
+        // Refuse to start rather than append plain JSON to an encrypted journal
+        None if cipher::is_encrypted_dir(Path::new(&config.data_dir)) => Err(format!(
+            "{} is encrypted; set storage.encryption_passphrase_env to read it",
+            config.data_dir
+        )
+        .into()),
+        None => Ok(None),
+    }
+}
+
+pub async fn from_config(
+    config: &StorageConfig,
+    cipher: Option<Arc<Cipher>>,
+) -> Result<Arc<dyn Storage>, StorageError> {
+    Ok(match config.backend {
+        StorageBackend::Json => Arc::new(JsonFileStorage::new(&config.data_dir, cipher, config.compression)),
+        StorageBackend::Memory => Arc::new(MemoryStorage::new()),
+        StorageBackend::Sqlite => Arc::new(SqliteStorage::open(&config.sqlite_path)?),
+        StorageBackend::Postgres => Arc::new(
+            PostgresStorage::connect(&config.postgres_url, config.postgres_pool_size, config.postgres_ca_file.as_deref())
+                .await?,
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::json::JsonFileStorage;
+    use super::memory::MemoryStorage;
+    use super::sqlite::SqliteStorage;
+    use super::*;
+    use crate::config::{Compression, Config};
+    use crate::import::{ImportOptions, import_csv};
+    use crate::quarantine::Quarantine;
+    use crate::testing::{account, store_on, temp_path, transaction};
+    use crate::types::{CreateTransactionRequest, EditTransactionRequest};
+
+    /// The events left by a session that touches most of what backends keep:
+    /// accounts and their owners, transactions, memos, metadata, edits, deletions and imports
+    async fn session() -> Vec<Event> {
+        let storage = Arc::new(MemoryStorage::new());
+        let admin = store_on(storage.clone());
+        let alice = admin.for_owner(Some("alice".to_string()));
+        for account_id in ["checking", "savings/joint"] {
+            alice.create_account(account(account_id)).unwrap();
+        }
+
+        let cafe = alice.create_transaction(transaction("checking", "Cafe"), false).await.unwrap();
+        let bakery = alice.create_transaction(transaction("checking", "Bakery"), false).await.unwrap();
+        let salary = CreateTransactionRequest {
+            amount: 1200.0,
+            ..transaction("savings/joint", "Salary")
+        };
+        let salary = alice.create_transaction(salary, false).await.unwrap();
+        alice
+            .update_transaction_memo("checking".to_string(), cafe.id.clone(), Some("flat white".to_string()), false, None)
+            .await
+            .unwrap();
+        let metadata = Metadata::from([("category".to_string(), serde_json::json!("food"))]);
+        alice
+            .update_transaction_metadata("checking".to_string(), cafe.id, metadata, false, None)
+            .await
+            .unwrap();
+        let edit = EditTransactionRequest {
+            timestamp: None,
+            payee: Some("Employer".to_string()),
+            amount: None,
+        };
+        alice.edit_transaction("savings/joint".to_string(), salary.id, edit, false, None).await.unwrap();
+        alice.delete_transaction("checking".to_string(), bakery.id, false, None).await.unwrap();
+        // An overlapping statement imported twice, with a row repeated in it
+        let config = Config::default();
+        let quarantine = Quarantine::new(&config.statements, None);
+        let statement = b"timestamp,payee,amount,currency
+2024-03-01T12:00:00Z,Cafe,-4.50,USD
+2024-03-02T12:00:00Z,Grocer,-20.00,USD
+2024-03-02T12:00:00Z,Grocer,-20.00,USD
+";
+        for _ in 0..2 {
+            let options = ImportOptions::default();
+            import_csv(&alice, &quarantine, &config, "checking".to_string(), None, statement, options).await.unwrap();
+        }
+
+        admin.flush().await;
+        storage.events(0, usize::MAX).await.unwrap().into_iter().map(|logged| logged.event).collect()
+    }
+
+    /// What a backend holds, in a form two backends can be compared by
+    async fn contents(storage: &dyn Storage) -> (serde_json::Value, serde_json::Value, u64) {
+        let mut snapshot = serde_json::to_value(storage.load().await.unwrap()).unwrap();
+        // An account's current transactions are listed in no particular order
+        for transactions in snapshot["current"].as_object_mut().unwrap().values_mut() {
+            transactions.as_array_mut().unwrap().sort_by_key(|t| t["uuid"].to_string());
+        }
+        let events = serde_json::to_value(storage.events(0, usize::MAX).await.unwrap()).unwrap();
+        (snapshot, events, storage.latest_seq().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn backends_keep_the_same_data() {
+        let events = session().await;
+        let (first, rest) = events.split_at(events.len() / 2);
+        let dirs = [temp_path("storage-json"), temp_path("storage-zstd")];
+        let database = temp_path("storage.sqlite");
+        let backends: Vec<(&str, Box<dyn Storage>)> = vec![
+            ("memory", Box::new(MemoryStorage::new())),
+            ("json", Box::new(JsonFileStorage::new(&dirs[0], None, Compression::None))),
+            ("json with zstd", Box::new(JsonFileStorage::new(&dirs[1], None, Compression::Zstd))),
+            ("sqlite", Box::new(SqliteStorage::open(&database).unwrap())),
+        ];
+
+        let mut expected = None;
+        for (name, storage) in &backends {
+            storage.append(first).await.unwrap();
+            storage.compact().await.unwrap();
+            storage.append(rest).await.unwrap();
+            let appended = contents(storage.as_ref()).await;
+            assert_eq!(appended.2, events.len() as u64, "{}", name);
+            storage.compact().await.unwrap();
+            assert_eq!(contents(storage.as_ref()).await, appended, "{} changed on compaction", name);
+            match &expected {
+                None => expected = Some(appended),
+                Some(expected) => assert_eq!(&appended, expected, "{} differs from memory", name),
+            }
+        }
+
+        // Replacing keeps the event log but swaps in the new state
+        let mut replacement = backends[0].1.load().await.unwrap();
+        replacement.all.remove("checking");
+        replacement.current.remove("checking");
+        replacement.accounts.remove("checking");
+        replacement.account_owners.remove("checking");
+        for (name, storage) in &backends {
+            storage.replace(&replacement).await.unwrap();
+            let (snapshot, logged, _) = contents(storage.as_ref()).await;
+            assert_eq!(snapshot, serde_json::to_value(&replacement).unwrap(), "{}", name);
+            assert_eq!(logged, expected.as_ref().unwrap().1, "{}", name);
+        }
+
+        // The files alone are enough for a backend opened afresh
+        let replaced = (serde_json::to_value(&replacement).unwrap(), expected.unwrap().1, events.len() as u64);
+        for (dir, compression) in [(&dirs[0], Compression::None), (&dirs[1], Compression::Zstd)] {
+            let reopened = JsonFileStorage::new(dir, None, compression);
+            assert_eq!(contents(&reopened).await, replaced);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+        drop(backends);
+        let reopened = SqliteStorage::open(&database).unwrap();
+        assert_eq!(contents(&reopened).await, replaced);
+        drop(reopened);
+        std::fs::remove_file(&database).unwrap();
+    }
+}
@@ -0,0 +1,982 @@
+use super::{Conflict, Event, LoggedEvent, Mutation, SchemaVersion, Snapshot, Storage, StorageError};
+use crate::config::Month;
+use crate::types::{AccountDetails, BalanceAssertion, HistoricalTransaction, ImportRecord, Metadata, Permission, TransactionId};
+use async_trait::async_trait;
+use deadpool_postgres::{GenericClient, Pool, PoolConfig, Runtime};
+use futures_util::StreamExt;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio_postgres::AsyncMessage;
+use tokio_postgres::error::SqlState;
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+/// Schema migrations, applied in order and recorded in `schema_migrations`
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE accounts (
+        id TEXT PRIMARY KEY
+    );
+
+    CREATE TABLE current_transactions (
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        timestamp TIMESTAMPTZ NOT NULL,
+        amount_cents BIGINT NOT NULL,
+        currency TEXT NOT NULL,
+        payee TEXT NOT NULL,
+        PRIMARY KEY (account_id, timestamp, amount_cents, currency, payee)
+    );
+
+    CREATE TABLE historical_transactions (
+        seq BIGSERIAL PRIMARY KEY,
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        timestamp TIMESTAMPTZ NOT NULL,
+        amount_cents BIGINT NOT NULL,
+        currency TEXT NOT NULL,
+        payee TEXT NOT NULL,
+        memo TEXT
+    );
+    CREATE INDEX historical_transactions_by_id
+        ON historical_transactions (account_id, timestamp, amount_cents, currency, payee);
+
+    CREATE TABLE balance_assertions (
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        date DATE NOT NULL,
+        currency TEXT NOT NULL,
+        balance_cents BIGINT NOT NULL,
+        PRIMARY KEY (account_id, currency, date)
+    );
+"#, r#"
+    -- No foreign key: a failed import is recorded even for an account that doesn't exist yet
+    CREATE TABLE import_records (
+        seq BIGSERIAL PRIMARY KEY,
+        account_id TEXT NOT NULL,
+        source TEXT NOT NULL,
+        imported_at TIMESTAMPTZ NOT NULL,
+        rows BIGINT NOT NULL,
+        imported BIGINT NOT NULL,
+        duplicates BIGINT NOT NULL,
+        errors BIGINT NOT NULL
+    );
+"#, r#"
+    -- Every mutation ever committed, as JSON; rows are only ever inserted
+    CREATE TABLE events (
+        seq BIGSERIAL PRIMARY KEY,
+        recorded_at TIMESTAMPTZ NOT NULL,
+        mutation TEXT NOT NULL
+    );
+"#, r#"
+    ALTER TABLE events ADD COLUMN lock_override BOOLEAN NOT NULL DEFAULT FALSE;
+"#, r#"
+    -- A transaction's occurrence is part of its id, and so of the primary key
+    ALTER TABLE current_transactions ADD COLUMN occurrence BIGINT NOT NULL DEFAULT 0;
+    ALTER TABLE current_transactions DROP CONSTRAINT current_transactions_pkey;
+    ALTER TABLE current_transactions
+        ADD PRIMARY KEY (account_id, timestamp, amount_cents, currency, payee, occurrence);
+
+    ALTER TABLE historical_transactions ADD COLUMN occurrence BIGINT NOT NULL DEFAULT 0;
+    DROP INDEX historical_transactions_by_id;
+    CREATE INDEX historical_transactions_by_id
+        ON historical_transactions (account_id, timestamp, amount_cents, currency, payee, occurrence);
+"#, r#"
+    -- JSON object, NULL when a transaction has no metadata
+    ALTER TABLE historical_transactions ADD COLUMN metadata TEXT;
+"#, r#"
+    -- The statement is JSON describing the attached file, NULL when there is none
+    ALTER TABLE import_records ADD COLUMN id TEXT;
+    ALTER TABLE import_records ADD COLUMN statement TEXT;
+"#, r#"
+    -- NULL in rows written before transactions had uuids; the server fills them in when it loads them
+    ALTER TABLE current_transactions ADD COLUMN uuid TEXT;
+    ALTER TABLE historical_transactions ADD COLUMN uuid TEXT;
+"#, r#"
+    -- The last month closed through the month-end checklist, as YYYY-MM
+    CREATE TABLE closed_months (
+        account_id TEXT PRIMARY KEY,
+        month TEXT NOT NULL
+    );
+"#, r#"
+    -- The user each account created by a signed-in user belongs to
+    CREATE TABLE account_owners (
+        account_id TEXT PRIMARY KEY,
+        owner TEXT NOT NULL
+    );
+"#, r#"
+    -- Other users each account is shared with, and what they may do with it
+    CREATE TABLE account_grants (
+        account_id TEXT NOT NULL,
+        grantee TEXT NOT NULL,
+        permission TEXT NOT NULL,
+        PRIMARY KEY (account_id, grantee)
+    );
+"#, r#"
+    -- What each account created with POST /accounts is, as JSON
+    ALTER TABLE accounts ADD COLUMN details TEXT;
+"#];
+
+/// Channel other instances' writes are announced on
+const CHANGES_CHANNEL: &str = "wdmmg_changes";
+
+/// A PostgreSQL database shared by any number of server instances.
+/// Every write is followed by a NOTIFY so the other instances reload their
+/// in-memory state instead of serving stale data.
+///
+/// Each instance checks changes against its own copy and writes them a moment
+/// later, so two instances can take changes that clash: the same transaction
+/// created twice, or an edit of one the other just edited or deleted. The
+/// database refuses the later batch with `Conflict`, and the instance that
+/// made it drops its unsaved changes and reloads. Memos and metadata set on
+/// both instances at once are not a clash; the later write wins.
+pub struct PostgresStorage {
+    pool: Pool,
+    instance_id: String,
+    changes: watch::Receiver<()>,
+    schema_version: SchemaVersion,
+    // Dedicated connection that LISTENs; dropping it would stop notifications
+    _listener: tokio_postgres::Client,
+}
+
+impl PostgresStorage {
+    /// Connect with TLS as the `sslmode` of `url` asks: `prefer`, the default,
+    /// uses it when the server offers it. The server's certificate is checked
+    /// against the CA certificates in `ca_file`, or the public web roots.
+    pub async fn connect(url: &str, pool_size: usize, ca_file: Option<&str>) -> Result<Self, StorageError> {
+        let tls = tls_connector(ca_file)?;
+        let config = deadpool_postgres::Config {
+            url: Some(url.to_string()),
+            pool: Some(PoolConfig::new(pool_size)),
+            ..Default::default()
+        };
+        let pool = config.create_pool(Some(Runtime::Tokio1), tls.clone())?;
+
+        let found = migrate(&pool).await?;
+
+        let instance_id = uuid::Uuid::new_v4().to_string();
+        let (listener, changes) = listen_for_changes(url, tls, instance_id.clone()).await?;
+
+        Ok(Self {
+            pool,
+            instance_id,
+            changes,
+            schema_version: SchemaVersion {
+                found,
+                supported: MIGRATIONS.len() as u64,
+            },
+            _listener: listener,
+        })
+    }
+}
+
+fn tls_connector(ca_file: Option<&str>) -> Result<MakeRustlsConnect, StorageError> {
+    let mut roots = RootCertStore::empty();
+    match ca_file {
+        Some(path) => {
+            let certs = CertificateDer::pem_file_iter(path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            if certs.is_empty() {
+                return Err(format!("{} holds no certificate", path).into());
+            }
+            roots.add_parsable_certificates(certs);
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(MakeRustlsConnect::new(config))
+}
+
+/// Apply the migrations not applied yet, returning how many had been
+async fn migrate(pool: &Pool) -> Result<u64, StorageError> {
+    let mut client = pool.get().await?;
+    let tx = client.transaction().await?;
+
+    // Instances starting at the same time take turns, so each migration runs once
+    tx.execute("SELECT pg_advisory_xact_lock(hashtext('wdmmg_migrations'))", &[])
+        .await?;
+    tx.batch_execute("CREATE TABLE IF NOT EXISTS schema_migrations (version BIGINT PRIMARY KEY)")
+        .await?;
+    let applied: i64 = tx
+        .query_one("SELECT COUNT(*) FROM schema_migrations", &[])
+        .await?
+        .get(0);
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        tx.batch_execute(migration).await?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version) VALUES ($1)",
+            &[&(index as i64 + 1)],
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(applied as u64)
+}
+
+/// Open a dedicated connection that LISTENs for writes made by other instances
+async fn listen_for_changes(
+    url: &str,
+    tls: MakeRustlsConnect,
+    instance_id: String,
+) -> Result<(tokio_postgres::Client, watch::Receiver<()>), StorageError> {
+    let (client, mut connection) = tokio_postgres::connect(url, tls).await?;
+    let (sender, receiver) = watch::channel(());
+
+    tokio::spawn(async move {
+        let mut messages = futures_util::stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notification(notification))
+                    if notification.payload() != instance_id =>
+                {
+                    sender.send_replace(());
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("Lost connection listening for database changes: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    client
+        .batch_execute(&format!("LISTEN {}", CHANGES_CHANNEL))
+        .await?;
+
+    Ok((client, receiver))
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn load(&self) -> Result<Snapshot, StorageError> {
+        let client = self.pool.get().await?;
+        let mut snapshot = Snapshot::default();
+
+        // Accounts exist even when all their transactions have been replaced
+        for row in client.query("SELECT id, details FROM accounts", &[]).await? {
+            let account_id: String = row.get(0);
+            if let Some(details) = row.get::<_, Option<String>>(1) {
+                snapshot.accounts.insert(account_id.clone(), serde_json::from_str(&details)?);
+            }
+            snapshot.current.entry(account_id).or_default();
+        }
+
+        let rows = client
+            .query(
+                "SELECT account_id, timestamp, amount_cents, currency, payee, occurrence, uuid
+                 FROM current_transactions",
+                &[],
+            )
+            .await?;
+        for row in rows {
+            let transaction = HistoricalTransaction {
+                account_id: row.get(0),
+                uuid: row.get::<_, Option<String>>(6).unwrap_or_default(),
+                id: transaction_id(&row, 1),
+                memo: None,
+                metadata: Metadata::new(),
+            };
+            snapshot
+                .current
+                .entry(transaction.account_id.clone())
+                .or_default()
+                .insert(transaction.id.clone(), super::current_of(&transaction));
+        }
+
+        let rows = client
+            .query(
+                "SELECT account_id, timestamp, amount_cents, currency, payee, occurrence, memo, metadata, uuid
+                 FROM historical_transactions ORDER BY seq",
+                &[],
+            )
+            .await?;
+        for row in rows {
+            let transaction = HistoricalTransaction {
+                account_id: row.get(0),
+                uuid: row.get::<_, Option<String>>(8).unwrap_or_default(),
+                id: transaction_id(&row, 1),
+                memo: row.get(6),
+                metadata: super::metadata_from_column(row.get(7))?,
+            };
+            snapshot
+                .all
+                .entry(transaction.account_id.clone())
+                .or_default()
+                .push(transaction);
+        }
+
+        let rows = client
+            .query(
+                "SELECT account_id, date, balance_cents, currency FROM balance_assertions",
+                &[],
+            )
+            .await?;
+        for row in rows {
+            snapshot.balance_assertions.push(BalanceAssertion {
+                account_id: row.get(0),
+                date: row.get(1),
+                balance_cents: row.get(2),
+                currency: row.get(3),
+            });
+        }
+
+        let rows = client
+            .query(
+                "SELECT account_id, source, imported_at, rows, imported, duplicates, errors, id, statement
+                 FROM import_records ORDER BY seq",
+                &[],
+            )
+            .await?;
+        for row in rows {
+            let statement: Option<String> = row.get(8);
+            snapshot.imports.push(ImportRecord {
+                id: row.get(7),
+                account_id: row.get(0),
+                source: row.get(1),
+                imported_at: row.get(2),
+                rows: row.get::<_, i64>(3) as usize,
+                imported: row.get::<_, i64>(4) as usize,
+                duplicates: row.get::<_, i64>(5) as usize,
+                errors: row.get::<_, i64>(6) as usize,
+                statement: statement.map(|statement| serde_json::from_str(&statement)).transpose()?,
+            });
+        }
+
+        let rows = client.query("SELECT account_id, month FROM closed_months", &[]).await?;
+        for row in rows {
+            let month: String = row.get(1);
+            snapshot.closed_months.insert(row.get(0), month.parse()?);
+        }
+
+        let rows = client.query("SELECT account_id, owner FROM account_owners", &[]).await?;
+        for row in rows {
+            snapshot.account_owners.insert(row.get(0), row.get(1));
+        }
+
+        let rows = client.query("SELECT account_id, grantee, permission FROM account_grants", &[]).await?;
+        for row in rows {
+            let permission: String = row.get(2);
+            snapshot.account_grants.entry(row.get(0)).or_default().insert(row.get(1), permission.parse()?);
+        }
+
+        Ok(snapshot)
+    }
+
+    async fn append(&self, events: &[Event]) -> Result<(), StorageError> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        for event in events {
+            tx.execute(
+                "INSERT INTO events (recorded_at, mutation, lock_override) VALUES ($1, $2, $3)",
+                &[&event.recorded_at, &serde_json::to_string(&event.mutation)?, &event.lock_override],
+            )
+            .await?;
+            match &event.mutation {
+                Mutation::Created { transaction } => {
+                    insert_account(&tx, &transaction.account_id).await?;
+                    insert_current(&tx, &transaction.account_id, &transaction.uuid, &transaction.id).await?;
+                    insert_historical(&tx, transaction).await?;
+                }
+                Mutation::Imported {
+                    account_id,
+                    from,
+                    to,
+                    transactions,
+                } => {
+                    insert_account(&tx, account_id).await?;
+                    tx.execute(
+                        "DELETE FROM current_transactions
+                         WHERE account_id = $1 AND timestamp >= $2 AND timestamp <= $3",
+                        &[account_id, from, to],
+                    )
+                    .await?;
+                    for transaction in transactions {
+                        insert_current(&tx, account_id, &transaction.uuid, &transaction.id).await?;
+                        // Rows the account already had keep their one historical record
+                        let recorded: bool = tx
+                            .query_one(
+                                "SELECT EXISTS (SELECT 1 FROM historical_transactions WHERE account_id = $1 AND uuid = $2)",
+                                &[account_id, &transaction.uuid],
+                            )
+                            .await?
+                            .get(0);
+                        if !recorded {
+                            insert_historical(&tx, transaction).await?;
+                        }
+                    }
+                }
+                Mutation::MemoUpdated {
+                    account_id,
+                    id,
+                    memo,
+                } => {
+                    // Matches the in-memory store, which updates the first matching record
+                    let updated = tx.execute(
+                        "UPDATE historical_transactions SET memo = $7 WHERE seq = (
+                             SELECT MIN(seq) FROM historical_transactions
+                             WHERE account_id = $1 AND timestamp = $2 AND amount_cents = $3
+                                 AND currency = $4 AND payee = $5 AND occurrence = $6
+                         )",
+                        &[
+                            account_id,
+                            &id.timestamp,
+                            &id.amount_cents,
+                            &id.currency,
+                            &id.payee,
+                            &i64::from(id.occurrence),
+                            memo,
+                        ],
+                    )
+                    .await?;
+                    if updated == 0 {
+                        return Err(gone(account_id, id));
+                    }
+                }
+                Mutation::MetadataUpdated {
+                    account_id,
+                    id,
+                    metadata,
+                } => {
+                    let updated = tx.execute(
+                        "UPDATE historical_transactions SET metadata = $7 WHERE seq = (
+                             SELECT MIN(seq) FROM historical_transactions
+                             WHERE account_id = $1 AND timestamp = $2 AND amount_cents = $3
+                                 AND currency = $4 AND payee = $5 AND occurrence = $6
+                         )",
+                        &[
+                            account_id,
+                            &id.timestamp,
+                            &id.amount_cents,
+                            &id.currency,
+                            &id.payee,
+                            &i64::from(id.occurrence),
+                            &super::metadata_column(metadata)?,
+                        ],
+                    )
+                    .await?;
+                    if updated == 0 {
+                        return Err(gone(account_id, id));
+                    }
+                }
+                Mutation::Edited {
+                    account_id,
+                    id,
+                    new_id,
+                } => {
+                    let mut updated = 0;
+                    for table in ["current_transactions", "historical_transactions"] {
+                        updated += tx.execute(
+                            &format!(
+                                "UPDATE {} SET timestamp = $7, amount_cents = $8, payee = $9, occurrence = $10
+                                 WHERE account_id = $1 AND timestamp = $2 AND amount_cents = $3
+                                     AND currency = $4 AND payee = $5 AND occurrence = $6",
+                                table
+                            ),
+                            &[
+                                account_id,
+                                &id.timestamp,
+                                &id.amount_cents,
+                                &id.currency,
+                                &id.payee,
+                                &i64::from(id.occurrence),
+                                &new_id.timestamp,
+                                &new_id.amount_cents,
+                                &new_id.payee,
+                                &i64::from(new_id.occurrence),
+                            ],
+                        )
+                        .await
+                        .map_err(clash)?;
+                    }
+                    if updated == 0 {
+                        return Err(gone(account_id, id));
+                    }
+                }
+                Mutation::Deleted { account_id, id } => {
+                    let mut deleted = 0;
+                    for table in ["current_transactions", "historical_transactions"] {
+                        deleted += tx.execute(
+                            &format!(
+                                "DELETE FROM {} WHERE account_id = $1 AND timestamp = $2
+                                     AND amount_cents = $3 AND currency = $4 AND payee = $5 AND occurrence = $6",
+                                table
+                            ),
+                            &[
+                                account_id,
+                                &id.timestamp,
+                                &id.amount_cents,
+                                &id.currency,
+                                &id.payee,
+                                &i64::from(id.occurrence),
+                            ],
+                        )
+                        .await?;
+                    }
+                    if deleted == 0 {
+                        return Err(gone(account_id, id));
+                    }
+                }
+                Mutation::BalanceAsserted { assertion } => {
+                    insert_assertion(&tx, assertion).await?;
+                }
+                Mutation::ImportRecorded { record } => {
+                    insert_import(&tx, record).await?;
+                }
+                Mutation::StatementAttached {
+                    import_id,
+                    statement,
+                    ..
+                } => {
+                    tx.execute(
+                        "UPDATE import_records SET statement = $2 WHERE id = $1",
+                        &[import_id, &serde_json::to_string(statement)?],
+                    )
+                    .await?;
+                }
+                Mutation::ImportCorrected {
+                    import_id,
+                    imported,
+                    duplicates,
+                    ..
+                } => {
+                    tx.execute(
+                        "UPDATE import_records
+                         SET imported = imported + $2, duplicates = duplicates + $3,
+                             errors = GREATEST(errors - $2 - $3, 0)
+                         WHERE id = $1",
+                        &[import_id, &(*imported as i64), &(*duplicates as i64)],
+                    )
+                    .await?;
+                }
+                Mutation::MonthClosed { account_id, month } => {
+                    insert_closed_month(&tx, account_id, month).await?;
+                }
+                Mutation::AccountCreated { account_id, details }
+                | Mutation::AccountUpdated { account_id, details } => {
+                    insert_account_details(&tx, account_id, details).await?;
+                }
+                Mutation::AccountOwned { account_id, owner } => {
+                    insert_account_owner(&tx, account_id, owner).await?;
+                }
+                Mutation::AccessGranted {
+                    account_id,
+                    user,
+                    permission,
+                } => {
+                    insert_account_grant(&tx, account_id, user, *permission).await?;
+                }
+                Mutation::AccessRevoked { account_id, user } => {
+                    tx.execute(
+                        "DELETE FROM account_grants WHERE account_id = $1 AND grantee = $2",
+                        &[account_id, user],
+                    )
+                    .await?;
+                }
+                Mutation::AccountsMerged {
+                    account_id,
+                    source_account_id,
+                } => {
+                    insert_account(&tx, account_id).await?;
+                    // Matches the in-memory store: what the account has already is
+                    // dropped, history and all, then the rest changes account
+                    for statement in [
+                        "DELETE FROM historical_transactions WHERE account_id = $2 AND uuid IN (
+                             SELECT source.uuid FROM current_transactions AS source
+                             JOIN current_transactions AS target ON target.account_id = $1
+                                 AND target.timestamp = source.timestamp AND target.amount_cents = source.amount_cents
+                                 AND target.currency = source.currency AND target.payee = source.payee
+                                 AND target.occurrence = source.occurrence
+                             WHERE source.account_id = $2
+                         )",
+                        "DELETE FROM current_transactions WHERE account_id = $2 AND EXISTS (
+                             SELECT 1 FROM current_transactions AS target WHERE target.account_id = $1
+                                 AND target.timestamp = current_transactions.timestamp
+                                 AND target.amount_cents = current_transactions.amount_cents
+                                 AND target.currency = current_transactions.currency
+                                 AND target.payee = current_transactions.payee
+                                 AND target.occurrence = current_transactions.occurrence
+                         )",
+                        "UPDATE current_transactions SET account_id = $1 WHERE account_id = $2",
+                        "UPDATE historical_transactions SET account_id = $1 WHERE account_id = $2",
+                        "DELETE FROM balance_assertions WHERE account_id = $2 AND EXISTS (
+                             SELECT 1 FROM balance_assertions AS target WHERE target.account_id = $1
+                                 AND target.currency = balance_assertions.currency AND target.date = balance_assertions.date
+                         )",
+                        "UPDATE balance_assertions SET account_id = $1 WHERE account_id = $2",
+                        "UPDATE import_records SET account_id = $1 WHERE account_id = $2",
+                        "UPDATE accounts SET details = (SELECT details FROM accounts AS source WHERE source.id = $2)
+                         WHERE id = $1 AND details IS NULL",
+                    ] {
+                        tx.execute(statement, &[account_id, source_account_id]).await?;
+                    }
+                    for statement in [
+                        "DELETE FROM closed_months WHERE account_id = $1",
+                        "DELETE FROM account_owners WHERE account_id = $1",
+                        "DELETE FROM account_grants WHERE account_id = $1",
+                        "DELETE FROM accounts WHERE id = $1",
+                    ] {
+                        tx.execute(statement, &[source_account_id]).await?;
+                    }
+                }
+            }
+        }
+        notify(&tx, &self.instance_id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn replace(&self, snapshot: &Snapshot) -> Result<(), StorageError> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        tx.batch_execute(
+            "DELETE FROM account_grants;
+             DELETE FROM account_owners;
+             DELETE FROM closed_months;
+             DELETE FROM import_records;
+             DELETE FROM balance_assertions;
+             DELETE FROM historical_transactions;
+             DELETE FROM current_transactions;
+             DELETE FROM accounts;",
+        )
+        .await?;
+        for (account_id, details) in &snapshot.accounts {
+            insert_account_details(&tx, account_id, details).await?;
+        }
+        for (account_id, transactions) in &snapshot.current {
+            insert_account(&tx, account_id).await?;
+            for transaction in transactions.values() {
+                insert_current(&tx, account_id, &transaction.uuid, &transaction.id).await?;
+            }
+        }
+        for transaction in snapshot.all.values().flatten() {
+            insert_account(&tx, &transaction.account_id).await?;
+            insert_historical(&tx, transaction).await?;
+        }
+        for assertion in &snapshot.balance_assertions {
+            insert_account(&tx, &assertion.account_id).await?;
+            insert_assertion(&tx, assertion).await?;
+        }
+        for record in &snapshot.imports {
+            insert_import(&tx, record).await?;
+        }
+        for (account_id, month) in &snapshot.closed_months {
+            insert_closed_month(&tx, account_id, month).await?;
+        }
+        for (account_id, owner) in &snapshot.account_owners {
+            insert_account_owner(&tx, account_id, owner).await?;
+        }
+        for (account_id, grants) in &snapshot.account_grants {
+            for (grantee, permission) in grants {
+                insert_account_grant(&tx, account_id, grantee, *permission).await?;
+            }
+        }
+        notify(&tx, &self.instance_id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn events(&self, after: u64, limit: usize) -> Result<Vec<LoggedEvent>, StorageError> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT seq, recorded_at, mutation, lock_override FROM events WHERE seq > $1 ORDER BY seq LIMIT $2",
+                &[&(after as i64), &(limit as i64)],
+            )
+            .await?;
+        let mut events = Vec::with_capacity(rows.len());
+        for row in rows {
+            events.push(LoggedEvent {
+                seq: row.get::<_, i64>(0) as u64,
+                event: Event {
+                    recorded_at: row.get(1),
+                    mutation: serde_json::from_str(row.get(2))?,
+                    lock_override: row.get(3),
+                },
+            });
+        }
+        Ok(events)
+    }
+
+    async fn latest_seq(&self) -> Result<u64, StorageError> {
+        let client = self.pool.get().await?;
+        let row = client.query_one("SELECT COALESCE(MAX(seq), 0) FROM events", &[]).await?;
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+
+    fn changes(&self) -> Option<watch::Receiver<()>> {
+        Some(self.changes.clone())
+    }
+
+    fn schema_version(&self) -> Option<SchemaVersion> {
+        Some(self.schema_version)
+    }
+}
+
+fn transaction_id(row: &tokio_postgres::Row, start: usize) -> TransactionId {
+    TransactionId {
+        timestamp: row.get(start),
+        amount_cents: row.get(start + 1),
+        currency: row.get(start + 2),
+        payee: row.get(start + 3),
+        occurrence: row.get::<_, i64>(start + 4) as u32,
+    }
+}
+
+/// A write refused for a transaction another instance saved first
+fn clash(e: tokio_postgres::Error) -> StorageError {
+    if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+        Box::new(Conflict(format!("Another instance saved the same transaction first: {}", e)))
+    } else {
+        Box::new(e)
+    }
+}
+
+/// A change of a transaction another instance has already changed or deleted
+fn gone(account_id: &str, id: &TransactionId) -> StorageError {
+    Box::new(Conflict(format!(
+        "The transaction of {} with {} at {} was changed or deleted by another instance",
+        account_id, id.payee, id.timestamp
+    )))
+}
+
+async fn notify(client: &impl GenericClient, instance_id: &str) -> Result<(), StorageError> {
+    // Delivered when the transaction commits, and not at all if it rolls back
+    client
+        .execute("SELECT pg_notify($1, $2)", &[&CHANGES_CHANNEL, &instance_id])
+        .await?;
+    Ok(())
+}
+
+async fn insert_account(client: &impl GenericClient, account_id: &str) -> Result<(), StorageError> {
+    client
+        .execute(
+            "INSERT INTO accounts (id) VALUES ($1) ON CONFLICT DO NOTHING",
+            &[&account_id],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_account_details(
+    client: &impl GenericClient,
+    account_id: &str,
+    details: &AccountDetails,
+) -> Result<(), StorageError> {
+    client
+        .execute(
+            "INSERT INTO accounts (id, details) VALUES ($1, $2)
+             ON CONFLICT (id) DO UPDATE SET details = excluded.details",
+            &[&account_id, &serde_json::to_string(details)?],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_current(
+    client: &impl GenericClient,
+    account_id: &str,
+    uuid: &str,
+    id: &TransactionId,
+) -> Result<(), StorageError> {
+    client
+        .execute(
+            "INSERT INTO current_transactions (account_id, timestamp, amount_cents, currency, payee, occurrence, uuid)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            &[
+                &account_id,
+                &id.timestamp,
+                &id.amount_cents,
+                &id.currency,
+                &id.payee,
+                &i64::from(id.occurrence),
+                &uuid,
+            ],
+        )
+        .await
+        .map_err(clash)?;
+    Ok(())
+}
+
+async fn insert_historical(
+    client: &impl GenericClient,
+    transaction: &HistoricalTransaction,
+) -> Result<(), StorageError> {
+    let id = &transaction.id;
+    client
+        .execute(
+            "INSERT INTO historical_transactions
+             (account_id, timestamp, amount_cents, currency, payee, occurrence, memo, metadata, uuid)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            &[
+                &transaction.account_id,
+                &id.timestamp,
+                &id.amount_cents,
+                &id.currency,
+                &id.payee,
+                &i64::from(id.occurrence),
+                &transaction.memo,
+                &super::metadata_column(&transaction.metadata)?,
+                &transaction.uuid,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_assertion(
+    client: &impl GenericClient,
+    assertion: &BalanceAssertion,
+) -> Result<(), StorageError> {
+    client
+        .execute(
+            "INSERT INTO balance_assertions (account_id, date, currency, balance_cents)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (account_id, currency, date)
+             DO UPDATE SET balance_cents = EXCLUDED.balance_cents",
+            &[
+                &assertion.account_id,
+                &assertion.date,
+                &assertion.currency,
+                &assertion.balance_cents,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_import(client: &impl GenericClient, record: &ImportRecord) -> Result<(), StorageError> {
+    client
+        .execute(
+            "INSERT INTO import_records
+             (account_id, source, imported_at, rows, imported, duplicates, errors, id, statement)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            &[
+                &record.account_id,
+                &record.source,
+                &record.imported_at,
+                &(record.rows as i64),
+                &(record.imported as i64),
+                &(record.duplicates as i64),
+                &(record.errors as i64),
+                &record.id,
+                &record.statement.as_ref().map(serde_json::to_string).transpose()?,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+// Months only ever move forward, whatever order they are written in
+async fn insert_closed_month(client: &impl GenericClient, account_id: &str, month: &Month) -> Result<(), StorageError> {
+    client
+        .execute(
+            "INSERT INTO closed_months (account_id, month) VALUES ($1, $2)
+             ON CONFLICT (account_id) DO UPDATE SET month = GREATEST(closed_months.month, excluded.month)",
+            &[&account_id, &month.to_string()],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_account_owner(client: &impl GenericClient, account_id: &str, owner: &str) -> Result<(), StorageError> {
+    client
+        .execute(
+            "INSERT INTO account_owners (account_id, owner) VALUES ($1, $2)
+             ON CONFLICT (account_id) DO UPDATE SET owner = excluded.owner",
+            &[&account_id, &owner],
+        )
+        .await?;
+    Ok(())
+}
+
+async fn insert_account_grant(
+    client: &impl GenericClient,
+    account_id: &str,
+    grantee: &str,
+    permission: Permission,
+) -> Result<(), StorageError> {
+    client
+        .execute(
+            "INSERT INTO account_grants (account_id, grantee, permission) VALUES ($1, $2, $3)
+             ON CONFLICT (account_id, grantee) DO UPDATE SET permission = excluded.permission",
+            &[&account_id, &grantee, &permission.as_str()],
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStorage;
+    use crate::testing::{account, store_on, transaction};
+    use chrono::{SubsecRound, Utc};
+
+    /// A database the tests may empty, such as postgres://localhost/wdmmg_test.
+    /// They are skipped when it isn't set.
+    const DATABASE_URL: &str = "WDMMG_TEST_POSTGRES_URL";
+
+    fn event(mutation: Mutation) -> Event {
+        Event {
+            recorded_at: Utc::now().trunc_subsecs(6),
+            mutation,
+            lock_override: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_changes_that_clash_with_another_instances() {
+        let Ok(url) = std::env::var(DATABASE_URL) else {
+            return;
+        };
+        let first = PostgresStorage::connect(&url, 2, None).await.unwrap();
+        let second = Arc::new(PostgresStorage::connect(&url, 2, None).await.unwrap());
+        first.replace(&Snapshot::default()).await.unwrap();
+
+        // The same transaction, created by both
+        let memory = Arc::new(MemoryStorage::new());
+        let store = store_on(memory.clone());
+        store.create_account(account("checking")).unwrap();
+        let cafe = store.create_transaction(transaction("checking", "Cafe"), false).await.unwrap();
+        store.flush().await;
+        let created: Vec<Event> = memory.events(0, usize::MAX).await.unwrap().into_iter().map(|logged| logged.event).collect();
+        first.append(&created).await.unwrap();
+        let refused = second.append(&created).await.unwrap_err();
+        assert!(refused.is::<Conflict>(), "{}", refused);
+
+        // An edit of a transaction the other has deleted
+        let deleted = Mutation::Deleted {
+            account_id: "checking".to_string(),
+            id: cafe.id.clone(),
+        };
+        first.append(&[event(deleted)]).await.unwrap();
+        let edited = Mutation::Edited {
+            account_id: "checking".to_string(),
+            id: cafe.id.clone(),
+            new_id: TransactionId {
+                payee: "Bakery".to_string(),
+                ..cafe.id.clone()
+            },
+        };
+        let refused = second.append(&[event(edited)]).await.unwrap_err();
+        assert!(refused.is::<Conflict>(), "{}", refused);
+        assert!(second.load().await.unwrap().current["checking"].is_empty());
+
+        // The store whose changes were refused drops them and takes what was saved
+        let store = store_on(second.clone());
+        store.load().await.unwrap();
+        store.create_transaction(transaction("checking", "Cafe"), false).await.unwrap();
+        first.append(&created[created.len() - 1..]).await.unwrap();
+        store.flush().await;
+        let state = store.snapshot();
+        let current: Vec<_> = state.current["checking"].values().map(|t| &t.uuid).collect();
+        assert_eq!(current, vec![&cafe.uuid]);
+        assert_eq!(second.load().await.unwrap().current["checking"].len(), 1);
+    }
+}
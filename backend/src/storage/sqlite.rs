@@ -0,0 +1,731 @@
+use super::{Event, LoggedEvent, Mutation, SchemaVersion, Snapshot, Storage, StorageError};
+use crate::config::Month;
+use crate::types::{AccountDetails, BalanceAssertion, HistoricalTransaction, ImportRecord, Metadata, Permission, TransactionId};
+use async_trait::async_trait;
+use rusqlite::{Connection, Transaction, params};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many have run.
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE accounts (
+        id TEXT PRIMARY KEY
+    );
+
+    CREATE TABLE current_transactions (
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        timestamp TEXT NOT NULL,
+        amount_cents INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        payee TEXT NOT NULL,
+        PRIMARY KEY (account_id, timestamp, amount_cents, currency, payee)
+    );
+
+    CREATE TABLE historical_transactions (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        timestamp TEXT NOT NULL,
+        amount_cents INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        payee TEXT NOT NULL,
+        memo TEXT
+    );
+    CREATE INDEX historical_transactions_by_id
+        ON historical_transactions (account_id, timestamp, amount_cents, currency, payee);
+
+    CREATE TABLE balance_assertions (
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        date TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        balance_cents INTEGER NOT NULL,
+        PRIMARY KEY (account_id, currency, date)
+    );
+"#, r#"
+    -- No foreign key: a failed import is recorded even for an account that doesn't exist yet
+    CREATE TABLE import_records (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        account_id TEXT NOT NULL,
+        source TEXT NOT NULL,
+        imported_at TEXT NOT NULL,
+        rows INTEGER NOT NULL,
+        imported INTEGER NOT NULL,
+        duplicates INTEGER NOT NULL,
+        errors INTEGER NOT NULL
+    );
+"#, r#"
+    -- Every mutation ever committed, as JSON; rows are only ever inserted
+    CREATE TABLE events (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        recorded_at TEXT NOT NULL,
+        mutation TEXT NOT NULL
+    );
+"#, r#"
+    ALTER TABLE events ADD COLUMN lock_override INTEGER NOT NULL DEFAULT 0;
+"#, r#"
+    -- A transaction's occurrence is part of its id, so the primary key has to be rebuilt
+    CREATE TABLE current_transactions_new (
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        timestamp TEXT NOT NULL,
+        amount_cents INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        payee TEXT NOT NULL,
+        occurrence INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (account_id, timestamp, amount_cents, currency, payee, occurrence)
+    );
+    INSERT INTO current_transactions_new (account_id, timestamp, amount_cents, currency, payee)
+        SELECT account_id, timestamp, amount_cents, currency, payee FROM current_transactions;
+    DROP TABLE current_transactions;
+    ALTER TABLE current_transactions_new RENAME TO current_transactions;
+
+    ALTER TABLE historical_transactions ADD COLUMN occurrence INTEGER NOT NULL DEFAULT 0;
+    DROP INDEX historical_transactions_by_id;
+    CREATE INDEX historical_transactions_by_id
+        ON historical_transactions (account_id, timestamp, amount_cents, currency, payee, occurrence);
+"#, r#"
+    -- JSON object, NULL when a transaction has no metadata
+    ALTER TABLE historical_transactions ADD COLUMN metadata TEXT;
+"#, r#"
+    -- The statement is JSON describing the attached file, NULL when there is none
+    ALTER TABLE import_records ADD COLUMN id TEXT;
+    ALTER TABLE import_records ADD COLUMN statement TEXT;
+"#, r#"
+    -- NULL in rows written before transactions had uuids; the server fills them in when it loads them
+    ALTER TABLE current_transactions ADD COLUMN uuid TEXT;
+    ALTER TABLE historical_transactions ADD COLUMN uuid TEXT;
+"#, r#"
+    -- The last month closed through the month-end checklist, as YYYY-MM
+    CREATE TABLE closed_months (
+        account_id TEXT PRIMARY KEY,
+        month TEXT NOT NULL
+    );
+"#, r#"
+    -- The user each account created by a signed-in user belongs to
+    CREATE TABLE account_owners (
+        account_id TEXT PRIMARY KEY,
+        owner TEXT NOT NULL
+    );
+"#, r#"
+    -- Other users each account is shared with, and what they may do with it
+    CREATE TABLE account_grants (
+        account_id TEXT NOT NULL,
+        grantee TEXT NOT NULL,
+        permission TEXT NOT NULL,
+        PRIMARY KEY (account_id, grantee)
+    );
+"#, r#"
+    -- What each account created with POST /accounts is, as JSON
+    ALTER TABLE accounts ADD COLUMN details TEXT;
+"#];
+
+/// A single SQLite database file with a table per entity.
+/// Mutations are written as individual row changes rather than a full rewrite.
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+    schema_version: SchemaVersion,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        let found = migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            schema_version: SchemaVersion {
+                found,
+                supported: MIGRATIONS.len() as u64,
+            },
+        })
+    }
+
+    /// Run blocking database work off the async runtime
+    async fn run<T, F>(&self, work: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, StorageError> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || work(&mut conn.lock().unwrap())).await?
+    }
+}
+
+/// Apply the migrations not applied yet, returning how many had been
+fn migrate(conn: &mut Connection) -> Result<u64, StorageError> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    let tx = conn.transaction()?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index as i64 + 1)?;
+    }
+    tx.commit()?;
+    Ok(version as u64)
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn load(&self) -> Result<Snapshot, StorageError> {
+        self.run(|conn| {
+            let mut snapshot = Snapshot::default();
+
+            // Accounts exist even when all their transactions have been replaced
+            let mut stmt = conn.prepare("SELECT id, details FROM accounts")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))?;
+            for row in rows {
+                let (account_id, details) = row?;
+                if let Some(details) = details {
+                    snapshot.accounts.insert(account_id.clone(), serde_json::from_str(&details)?);
+                }
+                snapshot.current.entry(account_id).or_default();
+            }
+
+            let mut stmt = conn.prepare(
+                "SELECT account_id, timestamp, amount_cents, currency, payee, occurrence, uuid
+                 FROM current_transactions",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, transaction_id(row, 1)?, row.get::<_, Option<String>>(6)?))
+            })?;
+            for row in rows {
+                let (account_id, id, uuid) = row?;
+                let transaction = HistoricalTransaction {
+                    account_id: account_id.clone(),
+                    uuid: uuid.unwrap_or_default(),
+                    id,
+                    memo: None,
+                    metadata: Metadata::new(),
+                };
+                snapshot
+                    .current
+                    .entry(account_id)
+                    .or_default()
+                    .insert(transaction.id.clone(), super::current_of(&transaction));
+            }
+
+            let mut stmt = conn.prepare(
+                "SELECT account_id, timestamp, amount_cents, currency, payee, occurrence, memo, metadata, uuid
+                 FROM historical_transactions ORDER BY seq",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                let transaction = HistoricalTransaction {
+                    account_id: row.get(0)?,
+                    uuid: row.get::<_, Option<String>>(8)?.unwrap_or_default(),
+                    id: transaction_id(row, 1)?,
+                    memo: row.get(6)?,
+                    metadata: Metadata::new(),
+                };
+                Ok((transaction, row.get::<_, Option<String>>(7)?))
+            })?;
+            for row in rows {
+                let (mut transaction, metadata) = row?;
+                transaction.metadata = super::metadata_from_column(metadata)?;
+                snapshot
+                    .all
+                    .entry(transaction.account_id.clone())
+                    .or_default()
+                    .push(transaction);
+            }
+
+            let mut stmt = conn.prepare(
+                "SELECT account_id, date, balance_cents, currency FROM balance_assertions",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok(BalanceAssertion {
+                    account_id: row.get(0)?,
+                    date: row.get(1)?,
+                    balance_cents: row.get(2)?,
+                    currency: row.get(3)?,
+                })
+            })?;
+            for assertion in rows {
+                snapshot.balance_assertions.push(assertion?);
+            }
+
+            let mut stmt = conn.prepare(
+                "SELECT account_id, source, imported_at, rows, imported, duplicates, errors, id, statement
+                 FROM import_records ORDER BY seq",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                let record = ImportRecord {
+                    id: row.get(7)?,
+                    account_id: row.get(0)?,
+                    source: row.get(1)?,
+                    imported_at: row.get(2)?,
+                    rows: row.get::<_, i64>(3)? as usize,
+                    imported: row.get::<_, i64>(4)? as usize,
+                    duplicates: row.get::<_, i64>(5)? as usize,
+                    errors: row.get::<_, i64>(6)? as usize,
+                    statement: None,
+                };
+                Ok((record, row.get::<_, Option<String>>(8)?))
+            })?;
+            for row in rows {
+                let (mut record, statement) = row?;
+                record.statement = statement.map(|statement| serde_json::from_str(&statement)).transpose()?;
+                snapshot.imports.push(record);
+            }
+
+            let mut stmt = conn.prepare("SELECT account_id, month FROM closed_months")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            for row in rows {
+                let (account_id, month) = row?;
+                snapshot.closed_months.insert(account_id, month.parse()?);
+            }
+
+            let mut stmt = conn.prepare("SELECT account_id, owner FROM account_owners")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            for row in rows {
+                let (account_id, owner) = row?;
+                snapshot.account_owners.insert(account_id, owner);
+            }
+
+            let mut stmt = conn.prepare("SELECT account_id, grantee, permission FROM account_grants")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+            })?;
+            for row in rows {
+                let (account_id, grantee, permission) = row?;
+                snapshot.account_grants.entry(account_id).or_default().insert(grantee, permission.parse()?);
+            }
+
+            Ok(snapshot)
+        })
+        .await
+    }
+
+    async fn append(&self, events: &[Event]) -> Result<(), StorageError> {
+        let events = events.to_vec();
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            for event in &events {
+                tx.execute(
+                    "INSERT INTO events (recorded_at, mutation, lock_override) VALUES (?1, ?2, ?3)",
+                    params![event.recorded_at, serde_json::to_string(&event.mutation)?, event.lock_override],
+                )?;
+                match &event.mutation {
+                    Mutation::Created { transaction } => {
+                        insert_account(&tx, &transaction.account_id)?;
+                        insert_current(&tx, &transaction.account_id, &transaction.uuid, &transaction.id)?;
+                        insert_historical(&tx, transaction)?;
+                    }
+                    Mutation::Imported {
+                        account_id,
+                        from,
+                        to,
+                        transactions,
+                    } => {
+                        insert_account(&tx, account_id)?;
+                        tx.execute(
+                            "DELETE FROM current_transactions
+                             WHERE account_id = ?1 AND timestamp >= ?2 AND timestamp <= ?3",
+                            params![account_id, from, to],
+                        )?;
+                        for transaction in transactions {
+                            insert_current(&tx, account_id, &transaction.uuid, &transaction.id)?;
+                            // Rows the account already had keep their one historical record
+                            let recorded: bool = tx.query_row(
+                                "SELECT EXISTS (SELECT 1 FROM historical_transactions WHERE account_id = ?1 AND uuid = ?2)",
+                                params![account_id, transaction.uuid],
+                                |row| row.get(0),
+                            )?;
+                            if !recorded {
+                                insert_historical(&tx, transaction)?;
+                            }
+                        }
+                    }
+                    Mutation::MemoUpdated {
+                        account_id,
+                        id,
+                        memo,
+                    } => {
+                        // Matches the in-memory store, which updates the first matching record
+                        tx.execute(
+                            "UPDATE historical_transactions SET memo = ?7 WHERE seq = (
+                                 SELECT MIN(seq) FROM historical_transactions
+                                 WHERE account_id = ?1 AND timestamp = ?2 AND amount_cents = ?3
+                                     AND currency = ?4 AND payee = ?5 AND occurrence = ?6
+                             )",
+                            params![
+                                account_id,
+                                id.timestamp,
+                                id.amount_cents,
+                                id.currency,
+                                id.payee,
+                                id.occurrence,
+                                memo
+                            ],
+                        )?;
+                    }
+                    Mutation::MetadataUpdated {
+                        account_id,
+                        id,
+                        metadata,
+                    } => {
+                        tx.execute(
+                            "UPDATE historical_transactions SET metadata = ?7 WHERE seq = (
+                                 SELECT MIN(seq) FROM historical_transactions
+                                 WHERE account_id = ?1 AND timestamp = ?2 AND amount_cents = ?3
+                                     AND currency = ?4 AND payee = ?5 AND occurrence = ?6
+                             )",
+                            params![
+                                account_id,
+                                id.timestamp,
+                                id.amount_cents,
+                                id.currency,
+                                id.payee,
+                                id.occurrence,
+                                super::metadata_column(metadata)?
+                            ],
+                        )?;
+                    }
+                    Mutation::Edited {
+                        account_id,
+                        id,
+                        new_id,
+                    } => {
+                        for table in ["current_transactions", "historical_transactions"] {
+                            tx.execute(
+                                &format!(
+                                    "UPDATE {} SET timestamp = ?7, amount_cents = ?8, payee = ?9, occurrence = ?10
+                                     WHERE account_id = ?1 AND timestamp = ?2 AND amount_cents = ?3
+                                         AND currency = ?4 AND payee = ?5 AND occurrence = ?6",
+                                    table
+                                ),
+                                params![
+                                    account_id,
+                                    id.timestamp,
+                                    id.amount_cents,
+                                    id.currency,
+                                    id.payee,
+                                    id.occurrence,
+                                    new_id.timestamp,
+                                    new_id.amount_cents,
+                                    new_id.payee,
+                                    new_id.occurrence
+                                ],
+                            )?;
+                        }
+                    }
+                    Mutation::Deleted { account_id, id } => {
+                        for table in ["current_transactions", "historical_transactions"] {
+                            tx.execute(
+                                &format!(
+                                    "DELETE FROM {} WHERE account_id = ?1 AND timestamp = ?2
+                                         AND amount_cents = ?3 AND currency = ?4 AND payee = ?5 AND occurrence = ?6",
+                                    table
+                                ),
+                                params![account_id, id.timestamp, id.amount_cents, id.currency, id.payee, id.occurrence],
+                            )?;
+                        }
+                    }
+                    Mutation::BalanceAsserted { assertion } => {
+                        insert_assertion(&tx, assertion)?;
+                    }
+                    Mutation::ImportRecorded { record } => {
+                        insert_import(&tx, record)?;
+                    }
+                    Mutation::StatementAttached {
+                        import_id,
+                        statement,
+                        ..
+                    } => {
+                        tx.execute(
+                            "UPDATE import_records SET statement = ?2 WHERE id = ?1",
+                            params![import_id, serde_json::to_string(statement)?],
+                        )?;
+                    }
+                    Mutation::ImportCorrected {
+                        import_id,
+                        imported,
+                        duplicates,
+                        ..
+                    } => {
+                        tx.execute(
+                            "UPDATE import_records
+                             SET imported = imported + ?2, duplicates = duplicates + ?3,
+                                 errors = MAX(errors - ?2 - ?3, 0)
+                             WHERE id = ?1",
+                            params![import_id, *imported as i64, *duplicates as i64],
+                        )?;
+                    }
+                    Mutation::MonthClosed { account_id, month } => {
+                        insert_closed_month(&tx, account_id, month)?;
+                    }
+                    Mutation::AccountCreated { account_id, details }
+                    | Mutation::AccountUpdated { account_id, details } => {
+                        insert_account_details(&tx, account_id, details)?;
+                    }
+                    Mutation::AccountOwned { account_id, owner } => {
+                        insert_account_owner(&tx, account_id, owner)?;
+                    }
+                    Mutation::AccessGranted {
+                        account_id,
+                        user,
+                        permission,
+                    } => {
+                        insert_account_grant(&tx, account_id, user, *permission)?;
+                    }
+                    Mutation::AccessRevoked { account_id, user } => {
+                        tx.execute(
+                            "DELETE FROM account_grants WHERE account_id = ?1 AND grantee = ?2",
+                            params![account_id, user],
+                        )?;
+                    }
+                    Mutation::AccountsMerged {
+                        account_id,
+                        source_account_id,
+                    } => {
+                        insert_account(&tx, account_id)?;
+                        // Matches the in-memory store: what the account has already is
+                        // dropped, history and all, then the rest changes account
+                        for statement in [
+                            "DELETE FROM historical_transactions WHERE account_id = ?2 AND uuid IN (
+                                 SELECT source.uuid FROM current_transactions AS source
+                                 JOIN current_transactions AS target ON target.account_id = ?1
+                                     AND target.timestamp = source.timestamp AND target.amount_cents = source.amount_cents
+                                     AND target.currency = source.currency AND target.payee = source.payee
+                                     AND target.occurrence = source.occurrence
+                                 WHERE source.account_id = ?2
+                             )",
+                            "DELETE FROM current_transactions WHERE account_id = ?2 AND EXISTS (
+                                 SELECT 1 FROM current_transactions AS target WHERE target.account_id = ?1
+                                     AND target.timestamp = current_transactions.timestamp
+                                     AND target.amount_cents = current_transactions.amount_cents
+                                     AND target.currency = current_transactions.currency
+                                     AND target.payee = current_transactions.payee
+                                     AND target.occurrence = current_transactions.occurrence
+                             )",
+                            "UPDATE current_transactions SET account_id = ?1 WHERE account_id = ?2",
+                            "UPDATE historical_transactions SET account_id = ?1 WHERE account_id = ?2",
+                            "DELETE FROM balance_assertions WHERE account_id = ?2 AND EXISTS (
+                                 SELECT 1 FROM balance_assertions AS target WHERE target.account_id = ?1
+                                     AND target.currency = balance_assertions.currency AND target.date = balance_assertions.date
+                             )",
+                            "UPDATE balance_assertions SET account_id = ?1 WHERE account_id = ?2",
+                            "UPDATE import_records SET account_id = ?1 WHERE account_id = ?2",
+                            "UPDATE accounts SET details = (SELECT details FROM accounts AS source WHERE source.id = ?2)
+                             WHERE id = ?1 AND details IS NULL",
+                        ] {
+                            tx.execute(statement, params![account_id, source_account_id])?;
+                        }
+                        for statement in [
+                            "DELETE FROM closed_months WHERE account_id = ?1",
+                            "DELETE FROM account_owners WHERE account_id = ?1",
+                            "DELETE FROM account_grants WHERE account_id = ?1",
+                            "DELETE FROM accounts WHERE id = ?1",
+                        ] {
+                            tx.execute(statement, [source_account_id])?;
+                        }
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn events(&self, after: u64, limit: usize) -> Result<Vec<LoggedEvent>, StorageError> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT seq, recorded_at, mutation, lock_override FROM events WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+            )?;
+            let rows = stmt.query_map(params![after as i64, limit as i64], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get::<_, String>(2)?, row.get(3)?))
+            })?;
+            let mut events = Vec::new();
+            for row in rows {
+                let (seq, recorded_at, mutation, lock_override) = row?;
+                events.push(LoggedEvent {
+                    seq: seq as u64,
+                    event: Event {
+                        recorded_at,
+                        mutation: serde_json::from_str(&mutation)?,
+                        lock_override,
+                    },
+                });
+            }
+            Ok(events)
+        })
+        .await
+    }
+
+    async fn latest_seq(&self) -> Result<u64, StorageError> {
+        self.run(|conn| {
+            let seq: i64 = conn.query_row("SELECT COALESCE(MAX(seq), 0) FROM events", [], |row| row.get(0))?;
+            Ok(seq as u64)
+        })
+        .await
+    }
+
+    async fn replace(&self, snapshot: &Snapshot) -> Result<(), StorageError> {
+        let snapshot = snapshot.clone();
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute_batch(
+                "DELETE FROM account_grants;
+                 DELETE FROM account_owners;
+                 DELETE FROM closed_months;
+                 DELETE FROM import_records;
+                 DELETE FROM balance_assertions;
+                 DELETE FROM historical_transactions;
+                 DELETE FROM current_transactions;
+                 DELETE FROM accounts;",
+            )?;
+            for (account_id, details) in &snapshot.accounts {
+                insert_account_details(&tx, account_id, details)?;
+            }
+            for (account_id, transactions) in &snapshot.current {
+                insert_account(&tx, account_id)?;
+                for transaction in transactions.values() {
+                    insert_current(&tx, account_id, &transaction.uuid, &transaction.id)?;
+                }
+            }
+            for transaction in snapshot.all.values().flatten() {
+                insert_account(&tx, &transaction.account_id)?;
+                insert_historical(&tx, transaction)?;
+            }
+            for assertion in &snapshot.balance_assertions {
+                insert_account(&tx, &assertion.account_id)?;
+                insert_assertion(&tx, assertion)?;
+            }
+            for record in &snapshot.imports {
+                insert_import(&tx, record)?;
+            }
+            for (account_id, month) in &snapshot.closed_months {
+                insert_closed_month(&tx, account_id, month)?;
+            }
+            for (account_id, owner) in &snapshot.account_owners {
+                insert_account_owner(&tx, account_id, owner)?;
+            }
+            for (account_id, grants) in &snapshot.account_grants {
+                for (grantee, permission) in grants {
+                    insert_account_grant(&tx, account_id, grantee, *permission)?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    fn schema_version(&self) -> Option<SchemaVersion> {
+        Some(self.schema_version)
+    }
+}
+
+fn transaction_id(row: &rusqlite::Row, start: usize) -> rusqlite::Result<TransactionId> {
+    Ok(TransactionId {
+        timestamp: row.get(start)?,
+        amount_cents: row.get(start + 1)?,
+        currency: row.get(start + 2)?,
+        payee: row.get(start + 3)?,
+        occurrence: row.get(start + 4)?,
+    })
+}
+
+fn insert_account(tx: &Transaction, account_id: &str) -> rusqlite::Result<()> {
+    tx.execute("INSERT OR IGNORE INTO accounts (id) VALUES (?1)", [account_id])?;
+    Ok(())
+}
+
+fn insert_account_details(tx: &Transaction, account_id: &str, details: &AccountDetails) -> Result<(), StorageError> {
+    tx.execute(
+        "INSERT INTO accounts (id, details) VALUES (?1, ?2)
+         ON CONFLICT (id) DO UPDATE SET details = excluded.details",
+        params![account_id, serde_json::to_string(details)?],
+    )?;
+    Ok(())
+}
+
+fn insert_current(tx: &Transaction, account_id: &str, uuid: &str, id: &TransactionId) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT OR REPLACE INTO current_transactions
+         (account_id, timestamp, amount_cents, currency, payee, occurrence, uuid) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![account_id, id.timestamp, id.amount_cents, id.currency, id.payee, id.occurrence, uuid],
+    )?;
+    Ok(())
+}
+
+fn insert_historical(tx: &Transaction, transaction: &HistoricalTransaction) -> Result<(), StorageError> {
+    let id = &transaction.id;
+    tx.execute(
+        "INSERT INTO historical_transactions
+         (account_id, timestamp, amount_cents, currency, payee, occurrence, memo, metadata, uuid)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            transaction.account_id,
+            id.timestamp,
+            id.amount_cents,
+            id.currency,
+            id.payee,
+            id.occurrence,
+            transaction.memo,
+            super::metadata_column(&transaction.metadata)?,
+            transaction.uuid
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_assertion(tx: &Transaction, assertion: &BalanceAssertion) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT OR REPLACE INTO balance_assertions (account_id, date, currency, balance_cents)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            assertion.account_id,
+            assertion.date,
+            assertion.currency,
+            assertion.balance_cents
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_import(tx: &Transaction, record: &ImportRecord) -> Result<(), StorageError> {
+    tx.execute(
+        "INSERT INTO import_records
+         (account_id, source, imported_at, rows, imported, duplicates, errors, id, statement)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            record.account_id,
+            record.source,
+            record.imported_at,
+            record.rows as i64,
+            record.imported as i64,
+            record.duplicates as i64,
+            record.errors as i64,
+            record.id,
+            record.statement.as_ref().map(serde_json::to_string).transpose()?
+        ],
+    )?;
+    Ok(())
+}
+
+// Months only ever move forward, whatever order they are written in
+fn insert_closed_month(tx: &Transaction, account_id: &str, month: &Month) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO closed_months (account_id, month) VALUES (?1, ?2)
+         ON CONFLICT (account_id) DO UPDATE SET month = MAX(month, excluded.month)",
+        params![account_id, month.to_string()],
+    )?;
+    Ok(())
+}
+
+fn insert_account_owner(tx: &Transaction, account_id: &str, owner: &str) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO account_owners (account_id, owner) VALUES (?1, ?2)
+         ON CONFLICT (account_id) DO UPDATE SET owner = excluded.owner",
+        params![account_id, owner],
+    )?;
+    Ok(())
+}
+
+fn insert_account_grant(tx: &Transaction, account_id: &str, grantee: &str, permission: Permission) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO account_grants (account_id, grantee, permission) VALUES (?1, ?2, ?3)
+         ON CONFLICT (account_id, grantee) DO UPDATE SET permission = excluded.permission",
+        params![account_id, grantee, permission.as_str()],
+    )?;
+    Ok(())
+}
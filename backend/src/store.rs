@@ -1,149 +1,889 @@
+use crate::archive;
+use crate::config::Month;
 use crate::error::ApiError;
+use crate::integrity;
+use crate::query::Expr;
+use crate::utils::etag_matches;
+use crate::storage::{self, Conflict, Event, LoggedEvent, Mutation, Snapshot, Storage, StorageError};
 use crate::types::{
-    BulkImportResponse, CreateTransactionRequest, CurrentTransaction, HistoricalTransaction,
-    TransactionId,
+    AccountBalance, AccountClass, AccountDetails, AccountGrant, AccountGroup, AccountGrouping, AccountSelector, AccountGrantsResponse, AccountMemory, AsOf, AccountSummary, AccountType, AssertBalanceRequest, BalanceAssertion, BalanceAssertionResult, BulkImportResponse,
+    ClosingCheck, ClosingCheckKind, CompactResponse, CreateAccountRequest, CreateTransactionRequest, CurrentTransaction, EditTransactionRequest, HistoricalTransaction, ImportMetricsResponse, MemoUpdate, Metadata,
+    Discrepancy, ImportRecord, ImportSourceMetrics, LowBalance, MemoryResponse, MergeAccountsRequest, MergeAccountsResponse, MonthClosingResponse, NetWorth, Page, PayeeStats, Permission, StatementFile, TransactionFilter, TransactionId, TransactionSort,
 };
-use chrono::{DateTime, Utc};
-use std::collections::HashMap;
-use std::path::Path;
+use arc_swap::ArcSwap;
+use chrono::{DateTime, SubsecRound, Utc};
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-use tokio::fs;
+use std::time::Duration;
+use uuid::Uuid;
+use tokio::sync::Notify;
 
-pub type CurrentTransactions =
-    Arc<Mutex<HashMap<String, HashMap<TransactionId, CurrentTransaction>>>>; // account_id -> transactions
-pub type AllTransactions = Arc<Mutex<HashMap<String, Vec<HistoricalTransaction>>>>; // account_id -> transactions
+/// Events read from the log at a time while rebuilding an earlier state
+const REPLAY_BATCH_SIZE: usize = 1000;
+
+/// Longest account id `POST /accounts` accepts
+const MAX_ACCOUNT_ID_LEN: usize = 100;
+
+/// Each user's part of the state, with the version it was taken at
+type Parts = HashMap<Arc<str>, (u64, Arc<Snapshot>)>;
+
+/// The state as it was before the last change, and the events of that change
+type Spare = Option<(Arc<Snapshot>, Vec<Event>)>;
+
+/// The store as it was at an earlier revision, rebuilt from the event log
+#[derive(Clone)]
+pub struct Past {
+    /// Position in the log of the last event it includes
+    pub revision: u64,
+    /// When that event was recorded
+    recorded_at: Option<DateTime<Utc>>,
+    state: Arc<Snapshot>,
+}
+
+impl Past {
+    /// Entity tag for the state at `revision`, which never changes, and so
+    /// doesn't change across restarts either
+    pub fn etag(&self) -> String {
+        format!("\"rev-{}\"", self.revision)
+    }
+}
 
 #[derive(Clone)]
 pub struct TransactionStore {
-    current: CurrentTransactions,
-    all: AllTransactions,
+    // Swapped whole for a newer one by writers, so readers take it without a
+    // lock and never wait for a change being made, nor writers for them
+    state: Arc<ArcSwap<Snapshot>>,
+    // The state one change behind, which the next change brings up to date
+    // and is applied to in place rather than copying the state, unless a
+    // reader still holds it. It costs a second copy of the state in memory.
+    spare: Arc<Mutex<Spare>>,
+    // Held while changing the state, so changes are made one at a time
+    writing: Arc<Mutex<()>>,
+    storage: Arc<dyn Storage>,
+    // Applied in memory but not yet handed to the storage backend, oldest first
+    pending: Arc<Mutex<Vec<Event>>>,
+    dirty: Arc<Notify>,
+    // Held while flushing or reloading so batches reach the backend in order
+    flushing: Arc<tokio::sync::Mutex<()>>,
+    // Why pending mutations can't be saved, once the backend has refused them
+    // for good. No more are taken until the server is restarted.
+    write_failure: Arc<Mutex<Option<String>>>,
+    // account_id -> currency -> minimum balance in cents
+    low_balance_thresholds: Arc<HashMap<String, HashMap<String, i64>>>,
+    // account_id -> last reconciled month
+    period_locks: Arc<HashMap<String, Month>>,
+    // Tells versions apart from those of an earlier run, which also start at 0
+    instance_id: Arc<str>,
+    // Whether edits of existing transactions must carry an If-Match header
+    require_if_match: bool,
+    // The state last rebuilt for a time-travel query, which later ones
+    // continue from rather than replaying the log from its start
+    last_past: Arc<Mutex<Option<Past>>>,
+    // The user whose part of the state this handle reads and changes, or
+    // None for all of it
+    owner: Option<Arc<str>>,
+    // Kept up to date by every change to the accounts each user sees, and
+    // taken again only once who sees what changes
+    parts: Arc<Mutex<Parts>>,
 }
 
 impl TransactionStore {
-    pub fn new() -> Self {
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        low_balance_thresholds: HashMap<String, HashMap<String, f64>>,
+        period_locks: HashMap<String, Month>,
+        require_if_match: bool,
+    ) -> Self {
+        let low_balance_thresholds = low_balance_thresholds
+            .into_iter()
+            .map(|(account_id, thresholds)| {
+                let thresholds = thresholds
+                    .into_iter()
+                    .map(|(currency, threshold)| (currency, (threshold * 100.0).round() as i64))
+                    .collect();
+                (account_id, thresholds)
+            })
+            .collect();
+
         Self {
-            current: Arc::new(Mutex::new(HashMap::new())),
-            all: Arc::new(Mutex::new(HashMap::new())),
+            state: Arc::new(ArcSwap::from_pointee(Snapshot::default())),
+            spare: Arc::new(Mutex::new(None)),
+            writing: Arc::new(Mutex::new(())),
+            storage,
+            pending: Arc::new(Mutex::new(Vec::new())),
+            dirty: Arc::new(Notify::new()),
+            flushing: Arc::new(tokio::sync::Mutex::new(())),
+            write_failure: Arc::new(Mutex::new(None)),
+            low_balance_thresholds: Arc::new(low_balance_thresholds),
+            period_locks: Arc::new(period_locks),
+            instance_id: Uuid::new_v4().simple().to_string().into(),
+            require_if_match,
+            last_past: Arc::new(Mutex::new(None)),
+            owner: None,
+            parts: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
-    pub async fn load_from_files(&self) -> Result<(), Box<dyn std::error::Error>> {
-        // Load current transactions
-        if Path::new("current_transactions.json").exists() {
-            let content = fs::read_to_string("current_transactions.json").await?;
-            let data: HashMap<String, HashMap<TransactionId, CurrentTransaction>> =
-                serde_json::from_str(&content)?;
-            *self.current.lock().unwrap() = data;
+    /// The store as `owner` may see and change it: the accounts they own and
+    /// those shared with them. None is the whole store, as background jobs
+    /// and admins see it.
+    pub fn for_owner(&self, owner: Option<String>) -> Self {
+        Self {
+            owner: owner.map(Arc::from),
+            ..self.clone()
         }
+    }
 
-        // Load all transactions
-        if Path::new("all_transactions.json").exists() {
-            let content = fs::read_to_string("all_transactions.json").await?;
-            let data: HashMap<String, Vec<HistoricalTransaction>> = serde_json::from_str(&content)?;
-            *self.all.lock().unwrap() = data;
+    pub async fn load(&self) -> Result<(), StorageError> {
+        let _flushing = self.flushing.lock().await;
+        self.reload().await
+    }
+
+    /// `load`, with the flushing lock already held
+    async fn reload(&self) -> Result<(), StorageError> {
+        let mut snapshot = self.storage.load().await?;
+        // Transactions persisted before they had uuids get them once and for all
+        if snapshot.assign_uuids() {
+            self.storage.replace(&snapshot).await?;
         }
+        snapshot.reindex();
 
+        // Mutations the backend hasn't seen yet still belong in memory
+        let _writing = self.writing.lock().unwrap();
+        for event in self.pending.lock().unwrap().iter() {
+            snapshot.apply(&event.mutation);
+        }
+        // Nothing tells which transactions another process changed, so every one gets a new revision
+        snapshot.version = self.state.load().version + 1;
+        snapshot.loaded_version = snapshot.version;
+        self.state.store(Arc::new(snapshot));
+        *self.spare.lock().unwrap() = None;
         Ok(())
     }
 
-    pub async fn save_to_files(&self) -> Result<(), Box<dyn std::error::Error>> {
-        // Save current transactions
-        let current_json = {
-            let current = self.current.lock().unwrap();
-            serde_json::to_string_pretty(&*current)?
+    /// The state as it is now, or the owner's part of it. It stays as it was
+    /// for as long as it is held, while changes made meanwhile go to a copy.
+    fn read(&self) -> Arc<Snapshot> {
+        let state = self.state.load_full();
+        let Some(owner) = &self.owner else {
+            return state;
         };
-        fs::write("current_transactions.json", current_json).await?;
+        if let Some((taken_at, part)) = self.parts.lock().unwrap().get(owner)
+            && *taken_at == state.version
+        {
+            return part.clone();
+        }
+        // Taken with no lock held, so other users' reads and writes go on meanwhile
+        let part = Arc::new(state.visible_part(&state, owner));
+        let mut parts = self.parts.lock().unwrap();
+        if parts.get(owner).is_none_or(|(taken_at, _)| *taken_at < state.version) {
+            parts.insert(owner.clone(), (state.version, part.clone()));
+        }
+        part
+    }
+
+    /// The whole state, whoever's part of the store this is
+    fn read_whole(&self) -> Arc<Snapshot> {
+        self.state.load_full()
+    }
+
+    /// Entity tag for the state of the store, which changes whenever the
+    /// state does. Read it before reading the state, so a response is never
+    /// tagged newer than its contents.
+    pub fn etag(&self) -> String {
+        self.tag(self.state.load().version)
+    }
 
-        // Save all transactions
-        let all_json = {
-            let all = self.all.lock().unwrap();
-            serde_json::to_string_pretty(&*all)?
+    /// Entity tag for the store at `version`. A transaction is tagged with the
+    /// version it last changed at, so its tag is the store's at that moment.
+    fn tag(&self, version: u64) -> String {
+        format!("\"{}-{}\"", self.instance_id, version)
+    }
+
+    fn transaction_tag(&self, state: &Snapshot, uuid: &str) -> String {
+        self.tag(state.revisions.get(uuid).copied().unwrap_or(state.loaded_version))
+    }
+
+    /// Refuse an edit of the transaction `uuid` unless `if_match` names its
+    /// current revision, so a client can't overwrite a change it hasn't seen.
+    /// Without the header the edit goes ahead, unless If-Match is required.
+    fn check_if_match(&self, state: &Snapshot, uuid: &str, if_match: Option<&str>) -> Result<(), ApiError> {
+        self.check_tag(if_match, &self.transaction_tag(state, uuid), "Transaction")
+    }
+
+    fn check_tag(&self, if_match: Option<&str>, current: &str, what: &str) -> Result<(), ApiError> {
+        match if_match {
+            None if self.require_if_match => Err(ApiError {
+                message: "An If-Match header with the ETag last read is required".to_string(),
+                status: warp::http::StatusCode::PRECONDITION_REQUIRED,
+            }),
+            Some(if_match) if !etag_matches(if_match, current) => Err(ApiError {
+                message: format!("{} has changed since it was read; fetch it again and retry", what),
+                status: warp::http::StatusCode::CONFLICT,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Persist pending mutations at most once per `interval`, coalescing
+    /// everything committed in between into a single write
+    pub fn spawn_flusher(&self, interval: Duration) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            loop {
+                store.dirty.notified().await;
+                tokio::time::sleep(interval).await;
+                store.flush().await;
+            }
+        });
+    }
+
+    /// Replace the whole store with what `build` makes of the current state, in
+    /// the storage backend and in memory. Mutations committed while the backend
+    /// is being rewritten are applied on top rather than lost.
+    pub async fn replace_with<F, R>(&self, build: F) -> Result<R, ApiError>
+    where
+        F: FnOnce(&Snapshot) -> Result<(Snapshot, R), ApiError>,
+    {
+        if self.owner.is_some() {
+            return Err(ApiError {
+                message: "Only an admin may replace the whole store".to_string(),
+                status: warp::http::StatusCode::FORBIDDEN,
+            });
+        }
+        let _flushing = self.flushing.lock().await;
+        let (mut snapshot, result, seen) = {
+            let _writing = self.writing.lock().unwrap();
+            let (mut snapshot, result) = build(&self.read())?;
+            // Archives and backups can predate uuids
+            snapshot.assign_uuids();
+            (snapshot, result, self.pending.lock().unwrap().len())
         };
-        fs::write("all_transactions.json", all_json).await?;
 
-        Ok(())
+        self.storage.replace(&snapshot).await.map_err(|e| ApiError {
+            message: format!("Failed to save data: {}", e),
+            status: warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+        // Mutations queued before `build` ran are part of the state it started from
+        snapshot.reindex();
+        let _writing = self.writing.lock().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        pending.drain(..seen);
+        for event in pending.iter() {
+            snapshot.apply(&event.mutation);
+        }
+        snapshot.version = self.state.load().version + 1;
+        snapshot.loaded_version = snapshot.version;
+        self.state.store(Arc::new(snapshot));
+        *self.spare.lock().unwrap() = None;
+        Ok(result)
     }
 
-    /// Get all current transactions across all accounts
-    pub fn get_current_transactions(&self) -> Vec<CurrentTransaction> {
-        let current = self.current.lock().unwrap();
-        let mut all_transactions = Vec::new();
-        for transactions in current.values() {
-            all_transactions.extend(transactions.values().cloned());
+    /// Hand every pending mutation to the storage backend now. Once the
+    /// backend has refused them for good, they are kept but not tried again.
+    pub async fn flush(&self) {
+        let _flushing = self.flushing.lock().await;
+        if self.write_failure.lock().unwrap().is_some() {
+            return;
+        }
+        let events = std::mem::take(&mut *self.pending.lock().unwrap());
+        if events.is_empty() {
+            return;
+        }
+
+        if let Err(e) = self.storage.append(&events).await {
+            if let Some(conflict) = e.downcast_ref::<Conflict>() {
+                // They were checked against a copy another instance had changed
+                // meanwhile; what it saved wins
+                tracing::error!("Dropped {} changes that clash with another instance's: {}", events.len(), conflict);
+                if let Err(e) = self.reload().await {
+                    tracing::warn!("Failed to reload changed data: {}", e);
+                }
+                return;
+            }
+            // Put them back ahead of anything committed since
+            self.pending.lock().unwrap().splice(0..0, events);
+            if storage::is_transient(&*e) {
+                tracing::error!("Failed to save data, will retry: {}", e);
+                self.dirty.notify_one();
+            } else {
+                tracing::error!("Failed to save data, refusing further changes until restarted: {}", e);
+                *self.write_failure.lock().unwrap() = Some(e.to_string());
+            }
         }
-        all_transactions
     }
 
-    /// Get all historical transactions across all accounts
-    pub fn get_all_transactions(&self) -> Vec<HistoricalTransaction> {
-        let all = self.all.lock().unwrap();
-        let mut all_transactions = Vec::new();
-        for transactions in all.values() {
-            all_transactions.extend(transactions.iter().cloned());
+    /// Why changes can no longer be saved, once the storage backend has
+    /// refused them for good
+    pub fn write_failure(&self) -> Option<String> {
+        self.write_failure.lock().unwrap().clone()
+    }
+
+    /// How many committed mutations the storage backend hasn't saved yet
+    pub fn unsaved(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Position of the newest event in the log, counting everything committed
+    /// so far. It changes whenever the data does.
+    pub async fn revision(&self) -> Result<u64, StorageError> {
+        self.flush().await;
+        self.storage.latest_seq().await
+    }
+
+    /// Up to `limit` events from the log after position `after`, oldest
+    /// first, including everything committed so far. Only those of the
+    /// owner's accounts are included.
+    pub async fn events(&self, after: u64, limit: usize) -> Result<Vec<LoggedEvent>, StorageError> {
+        self.flush().await;
+        let Some(owner) = &self.owner else {
+            return self.storage.events(after, limit).await;
+        };
+
+        // Read on past other users' events until there are enough of theirs
+        let batch_size = limit.max(REPLAY_BATCH_SIZE);
+        let mut events = Vec::new();
+        let mut after = after;
+        while events.len() < limit {
+            let batch = self.storage.events(after, batch_size).await?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            after = last.seq;
+            let exhausted = batch.len() < batch_size;
+            let whole = self.read_whole();
+            events.extend(
+                batch
+                    .into_iter()
+                    .filter(|logged| whole.visible_to(logged.event.mutation.account_id(), owner)),
+            );
+            if exhausted {
+                break;
+            }
         }
-        all_transactions
+        events.truncate(limit);
+        Ok(events)
     }
 
-    /// Create a new transaction
-    pub async fn create_transaction(
-        &self,
-        request: CreateTransactionRequest,
-    ) -> Result<CurrentTransaction, ApiError> {
-        let transaction_id = TransactionId {
-            timestamp: request.timestamp,
-            amount_cents: (request.amount * 100.0).round() as i64,
-            currency: request.currency,
-            payee: request.payee,
+    /// The store as it was at `as_of`, rebuilt by replaying the event log.
+    /// Changes made without an event, such as restoring a backup or loading
+    /// an archive, aren't in the log, so neither are they in the result.
+    pub async fn as_of(&self, as_of: AsOf) -> Result<Past, ApiError> {
+        let internal = |e: StorageError| ApiError {
+            message: format!("Failed to read the event log: {}", e),
+            status: warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let latest = self.revision().await.map_err(internal)?;
+        if let AsOf::Revision(revision) = as_of
+            && revision > latest
+        {
+            return Err(ApiError {
+                message: format!("Revision {} is past the latest, {}", revision, latest),
+                status: warp::http::StatusCode::BAD_REQUEST,
+            });
+        }
+        let includes = |logged: &LoggedEvent| match as_of {
+            AsOf::Revision(revision) => logged.seq <= revision,
+            AsOf::Date(date) => logged.event.recorded_at <= date,
         };
 
-        let current_transaction = CurrentTransaction {
-            account_id: request.account_id.clone(),
-            id: transaction_id.clone(),
+        let last_past = self.last_past.lock().unwrap().clone();
+        let start = last_past.filter(|past| match as_of {
+            AsOf::Revision(revision) => past.revision <= revision,
+            AsOf::Date(date) => past.recorded_at.is_none_or(|recorded_at| recorded_at <= date),
+        });
+        let (mut revision, mut recorded_at, mut state) = match start {
+            Some(past) => (past.revision, past.recorded_at, (*past.state).clone()),
+            None => (0, None, Snapshot::default()),
         };
+        'replay: loop {
+            let events = self.storage.events(revision, REPLAY_BATCH_SIZE).await.map_err(internal)?;
+            for logged in &events {
+                if !includes(logged) {
+                    break 'replay;
+                }
+                state.apply(&logged.event.mutation);
+                revision = logged.seq;
+                recorded_at = Some(logged.event.recorded_at);
+            }
+            if events.len() < REPLAY_BATCH_SIZE {
+                break;
+            }
+        }
 
-        let historical_transaction = HistoricalTransaction {
-            account_id: request.account_id.clone(),
-            id: transaction_id.clone(),
-            memo: None,
+        let past = Past {
+            revision,
+            recorded_at,
+            state: Arc::new(state),
         };
+        *self.last_past.lock().unwrap() = Some(past.clone());
+        Ok(past)
+    }
 
-        // Add to current transactions
-        {
-            let mut current = self.current.lock().unwrap();
-            let account_transactions = current
-                .entry(request.account_id.clone())
-                .or_insert_with(HashMap::new);
+    /// The state as it was at `past`, or as it is now
+    fn read_at(&self, past: Option<&Past>) -> Arc<Snapshot> {
+        match (past, &self.owner) {
+            (None, _) => self.read(),
+            (Some(past), None) => past.state.clone(),
+            // Accounts belong to whoever owns them now, not whoever did then
+            (Some(past), Some(owner)) => Arc::new(past.state.visible_part(&self.read_whole(), owner)),
+        }
+    }
 
-            if account_transactions.contains_key(&transaction_id) {
+    /// Reload whenever the storage backend reports writes from another process
+    pub fn watch_storage(&self) {
+        let Some(mut changes) = self.storage.changes() else {
+            return;
+        };
+
+        let store = self.clone();
+        tokio::spawn(async move {
+            while changes.changed().await.is_ok() {
+                if let Err(e) = store.load().await {
+                    tracing::warn!("Failed to reload changed data: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Periodically let the storage backend compact what it has written incrementally
+    pub fn spawn_compaction(&self, interval: Duration) {
+        let storage = self.storage.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = storage.compact().await {
+                    tracing::warn!("Failed to compact storage: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Validate and build a mutation against the current state, apply it, and
+    /// queue it for the background flusher. Mutations that change transactions
+    /// in a locked period are refused unless `override_lock` is set. Returns
+    /// the version of the state the mutation made.
+    fn commit<F>(&self, override_lock: bool, build: F) -> Result<u64, ApiError>
+    where
+        F: FnOnce(&Snapshot) -> Result<Mutation, ApiError>,
+    {
+        self.commit_all(override_lock, |state| build(state).map(|mutation| vec![mutation]))
+    }
+
+    /// Like `commit`, for several mutations that are applied all together or,
+    /// when any of them is refused, not at all. They reach the storage backend
+    /// in a single batch.
+    fn commit_all<F>(&self, override_lock: bool, build: F) -> Result<u64, ApiError>
+    where
+        F: FnOnce(&Snapshot) -> Result<Vec<Mutation>, ApiError>,
+    {
+        let _writing = self.writing.lock().unwrap();
+        if let Some(failure) = &*self.write_failure.lock().unwrap() {
+            return Err(ApiError {
+                message: format!("Changes can't be saved, so none are taken until the server is restarted: {}", failure),
+                status: warp::http::StatusCode::SERVICE_UNAVAILABLE,
+            });
+        }
+        let current = self.read();
+        let state = &*current;
+        let mutations = self.claim_accounts(build(state)?)?;
+        require_known_accounts(&self.read_whole(), &mutations)?;
+
+        let mut events = Vec::with_capacity(mutations.len());
+        for mutation in mutations {
+            let earliest = earliest_change(state, &mutation);
+            let locked = mutation.account_ids().into_iter().find_map(|account_id| {
+                self.locked_month(state, account_id)
+                    .filter(|month| earliest.is_some_and(|timestamp| timestamp < month.end()))
+                    .map(|month| (account_id.to_string(), month))
+            });
+            if let Some((account_id, month)) = &locked
+                && !override_lock
+            {
                 return Err(ApiError {
-                    message: "Transaction already exists".to_string(),
-                    status: warp::http::StatusCode::CONFLICT,
+                    message: format!(
+                        "Transactions up to {} are locked for account {}; pass override_lock=true to change them",
+                        month, account_id
+                    ),
+                    status: warp::http::StatusCode::LOCKED,
                 });
             }
-
-            account_transactions.insert(transaction_id.clone(), current_transaction.clone());
+            events.push(Event {
+                // Microseconds are as precise as every storage backend keeps timestamps
+                recorded_at: Utc::now().trunc_subsecs(6),
+                lock_override: locked.is_some(),
+                mutation,
+            });
+        }
+        if events.is_empty() {
+            return Ok(self.read_whole().version);
         }
 
-        // Add to historical transactions
-        {
-            let mut all = self.all.lock().unwrap();
-            let account_transactions = all.entry(request.account_id).or_insert_with(Vec::new);
-            account_transactions.push(historical_transaction);
+        let mut account_ids: Vec<_> = events.iter().map(|event| event.mutation.account_id().to_string()).collect();
+        account_ids.sort_unstable();
+        account_ids.dedup();
+        let was_low: Vec<_> = account_ids
+            .iter()
+            .map(|account_id| self.low_balances(state, account_id))
+            .collect();
+        // Let go of before applying, or this user's part would be taken again
+        drop(current);
+
+        let version = {
+            let mut spare = self.spare.lock().unwrap();
+            let caught_up = spare.take().and_then(|(mut spare, behind)| {
+                advance(Arc::get_mut(&mut spare)?, &behind);
+                Some(spare)
+            });
+            let mut next = caught_up.unwrap_or_else(|| Arc::new((*self.read_whole()).clone()));
+            let state = Arc::make_mut(&mut next);
+            let changed = advance(state, &events);
+            self.update_parts(state, &events, &changed, state.version);
+            let version = state.version;
+            // Readers still holding the state it replaces keep it as it was
+            *spare = Some((self.state.swap(next), events.clone()));
+            version
+        };
+        self.pending.lock().unwrap().extend(events);
+        self.dirty.notify_one();
+        let state = self.read_whole();
+
+        // Only warn when a balance first dips below its threshold, not on every later change
+        for (account_id, was_low) in account_ids.iter().zip(was_low) {
+            for low in self.low_balances(&state, account_id) {
+                if !was_low.iter().any(|previous| previous.currency == low.currency) {
+                    tracing::warn!(
+                        "Balance of account {} is {:.2} {}, below its threshold of {:.2}",
+                        low.account_id,
+                        low.balance_cents as f64 / 100.0,
+                        low.currency,
+                        low.threshold_cents as f64 / 100.0
+                    );
+                }
+            }
         }
+        Ok(version)
+    }
+
+    /// Apply `events` to each user's part of the state as well, so it needn't
+    /// be taken again. Parts that were out of date, that a reader still holds
+    /// or whose user the events change access for are dropped instead, to be
+    /// taken again when next read.
+    fn update_parts(&self, state: &Snapshot, events: &[Event], changed: &[String], version: u64) {
+        let mut parts = self.parts.lock().unwrap();
+        parts.retain(|owner, (taken_at, part)| {
+            let Some(part) = Arc::get_mut(part).filter(|_| *taken_at + 1 == version) else {
+                return false;
+            };
+            for event in events {
+                if event.mutation.changes_access() {
+                    return false;
+                }
+                if state.visible_to(event.mutation.account_id(), owner) {
+                    part.apply(&event.mutation);
+                }
+            }
+            for uuid in changed {
+                if part.uuids.contains_key(uuid) {
+                    part.revisions.insert(uuid.clone(), version);
+                } else {
+                    part.revisions.remove(uuid);
+                }
+            }
+            *taken_at = version;
+            true
+        });
+    }
 
-        // Save to files
-        if let Err(e) = self.save_to_files().await {
-            eprintln!("Warning: Failed to save data: {}", e);
+    /// Refuse `mutations` of another user's accounts, unless shared with the
+    /// owner of this part of the store to change, and give that owner the
+    /// accounts they create, ahead of the mutations that do
+    fn claim_accounts(&self, mutations: Vec<Mutation>) -> Result<Vec<Mutation>, ApiError> {
+        let Some(owner) = &self.owner else {
+            return Ok(mutations);
+        };
+        let whole = self.read_whole();
+        let mut claimed = Vec::new();
+        for account_id in mutations.iter().flat_map(Mutation::account_ids) {
+            if !whole.has_account(account_id) {
+                if !claimed.iter().any(|claim: &Mutation| claim.account_id() == account_id) {
+                    claimed.push(Mutation::AccountOwned {
+                        account_id: account_id.to_string(),
+                        owner: owner.to_string(),
+                    });
+                }
+                continue;
+            }
+            if !whole.writable_by(account_id, owner) {
+                let message = if whole.visible_to(account_id, owner) {
+                    format!("Account {} is shared with you to read only", account_id)
+                } else if !whole.account_owners.contains_key(account_id) {
+                    format!("Account {} has no owner yet; an admin must give it one", account_id)
+                } else {
+                    format!("Account {} belongs to another user", account_id)
+                };
+                return Err(ApiError {
+                    message,
+                    status: warp::http::StatusCode::FORBIDDEN,
+                });
+            }
         }
+        claimed.extend(mutations);
+        Ok(claimed)
+    }
+
+    /// The last month whose transactions are locked for `account_id`, by the
+    /// configuration or by closing it
+    fn locked_month(&self, state: &Snapshot, account_id: &str) -> Option<Month> {
+        self.period_locks
+            .get(account_id)
+            .into_iter()
+            .chain(state.closed_months.get(account_id))
+            .max()
+            .copied()
+    }
+
+    /// Whether every account's transactions are locked through `month`
+    fn month_closed(&self, state: &Snapshot, month: Month) -> bool {
+        state
+            .current
+            .keys()
+            .all(|account_id| self.locked_month(state, account_id) >= Some(month))
+    }
+
+    /// The currencies in which `account_id` is below its configured minimum balance
+    fn low_balances(&self, state: &Snapshot, account_id: &str) -> Vec<LowBalance> {
+        let Some(thresholds) = self.low_balance_thresholds.get(account_id) else {
+            return vec![];
+        };
+
+        thresholds
+            .iter()
+            .filter_map(|(currency, &threshold_cents)| {
+                let balance_cents = balances_at(state, account_id, None).get(currency).copied().unwrap_or(0);
+
+                (balance_cents < threshold_cents).then(|| LowBalance {
+                    account_id: account_id.to_string(),
+                    currency: currency.clone(),
+                    balance_cents,
+                    threshold_cents,
+                })
+            })
+            .collect()
+    }
 
-        Ok(current_transaction)
+    /// The account and id of the transaction named by `uuid`
+    pub fn resolve_uuid(&self, uuid: &str) -> Option<(String, TransactionId)> {
+        self.read().uuids.get(uuid).cloned()
     }
 
-    /// Bulk import transactions from CSV data
+    /// The historical record of one transaction, memo included, and its
+    /// entity tag. An id recorded more than once yields its first record, the
+    /// one memo updates change.
+    pub fn transaction(&self, account_id: &str, id: &TransactionId) -> Option<(HistoricalTransaction, String)> {
+        let state = self.read();
+        let transaction = state.all.get(account_id)?.iter().find(|t| t.id == *id)?.clone();
+        let etag = self.transaction_tag(&state, &transaction.uuid);
+        Some((transaction, etag))
+    }
+
+    /// Serialize a page of the current transactions `filter` matches, in `sort`
+    /// order, as a JSON array, straight from a snapshot of the state rather
+    /// than from a cloned copy, along with how many match in all. Ties are ordered by
+    /// account and then by id, so pages stay put while nothing changes. The
+    /// state is that at `past` when it's set.
+    pub fn current_transactions_json(
+        &self,
+        filter: &TransactionFilter,
+        sort: TransactionSort,
+        page: Page,
+        past: Option<&Past>,
+    ) -> Result<(Vec<u8>, usize), serde_json::Error> {
+        self.matching_current_json(
+            |account_id| filter.includes_account(account_id),
+            !filter.metadata.is_empty(),
+            |_, id, metadata| filter.matches(id) && filter.matches_metadata(metadata),
+            sort,
+            page,
+            past,
+        )
+    }
+
+    /// Serialize a page of the current transactions `query` matches, like
+    /// `current_transactions_json` does
+    pub fn query_transactions_json(
+        &self,
+        query: &Expr,
+        sort: TransactionSort,
+        page: Page,
+        past: Option<&Past>,
+    ) -> Result<(Vec<u8>, usize), serde_json::Error> {
+        self.matching_current_json(
+            |_| true,
+            query.uses_metadata(),
+            |account_id, id, metadata| query.matches(account_id, id, metadata),
+            sort,
+            page,
+            past,
+        )
+    }
+
+    /// The current transactions of the accounts `include_account` names that
+    /// `matches` accepts, with their metadata when `uses_metadata` is set
+    fn matching_current_json(
+        &self,
+        include_account: impl Fn(&str) -> bool,
+        uses_metadata: bool,
+        matches: impl Fn(&str, &TransactionId, &Metadata) -> bool,
+        sort: TransactionSort,
+        page: Page,
+        past: Option<&Past>,
+    ) -> Result<(Vec<u8>, usize), serde_json::Error> {
+        let state = self.read_at(past);
+        let mut accounts: Vec<_> = state
+            .current
+            .iter()
+            .filter(|(account_id, _)| include_account(account_id))
+            .collect();
+        accounts.sort_unstable_by_key(|(account_id, _)| *account_id);
+        let no_metadata = Metadata::new();
+        let mut transactions: Vec<_> = accounts
+            .into_iter()
+            .flat_map(|(account_id, transactions)| {
+                // Metadata is kept in the history, on the first record of a transaction
+                let mut metadata = HashMap::new();
+                if uses_metadata {
+                    for t in state.all.get(account_id).into_iter().flatten() {
+                        metadata.entry(&t.id).or_insert(&t.metadata);
+                    }
+                }
+                let mut transactions: Vec<_> = transactions
+                    .values()
+                    .filter(|t| matches(account_id, &t.id, metadata.get(&t.id).copied().unwrap_or(&no_metadata)))
+                    .collect();
+                transactions.sort_unstable_by_key(|t| {
+                    (t.id.timestamp, &t.id.payee, t.id.amount_cents, &t.id.currency, t.id.occurrence)
+                });
+                transactions
+            })
+            .collect();
+        transactions.sort_by(|a, b| sort.compare(&a.id, &b.id));
+        Ok((serde_json::to_vec(page.slice(&transactions))?, transactions.len()))
+    }
+
+    /// Serialize a page of the historical transactions `filter` matches, in
+    /// `sort` order, as a JSON array, straight from a snapshot of the state
+    /// rather than from a cloned copy, along with how many match in all. Ties are
+    /// ordered by account and then as recorded. The state is that at `past`
+    /// when it's set.
+    pub fn all_transactions_json(
+        &self,
+        filter: &TransactionFilter,
+        sort: TransactionSort,
+        page: Page,
+        past: Option<&Past>,
+    ) -> Result<(Vec<u8>, usize), serde_json::Error> {
+        let state = self.read_at(past);
+        let mut accounts: Vec<_> = state
+            .all
+            .iter()
+            .filter(|(account_id, _)| filter.includes_account(account_id))
+            .collect();
+        accounts.sort_unstable_by_key(|(account_id, _)| *account_id);
+        let mut transactions: Vec<_> = accounts
+            .into_iter()
+            .flat_map(|(_, transactions)| transactions)
+            .filter(|t| filter.matches(&t.id) && filter.matches_metadata(&t.metadata))
+            .collect();
+        transactions.sort_by(|a, b| sort.compare(&a.id, &b.id));
+        Ok((serde_json::to_vec(page.slice(&transactions))?, transactions.len()))
+    }
+
+    /// A copy of everything the store holds
+    pub fn snapshot(&self) -> Snapshot {
+        (*self.read()).clone()
+    }
+
+    /// Serialize the whole store, as the storage backends load it, straight from a snapshot of the state
+    pub fn snapshot_json(&self) -> Result<Vec<u8>, serde_json::Error> {
+        let state = self.read();
+        serde_json::to_vec(&*state)
+    }
+
+    /// Serialize the whole store as an export archive, straight from a snapshot of the state
+    pub fn export_json(&self) -> Result<Vec<u8>, serde_json::Error> {
+        let state = self.read();
+        archive::to_json(&state)
+    }
+
+    /// Create a new transaction. An identical one is refused unless the request
+    /// allows duplicates, in which case it becomes the next free occurrence.
+    pub async fn create_transaction(
+        &self,
+        request: CreateTransactionRequest,
+        override_lock: bool,
+    ) -> Result<CurrentTransaction, ApiError> {
+        let mut transaction_id = TransactionId {
+            timestamp: request.timestamp,
+            amount_cents: (request.amount * 100.0).round() as i64,
+            currency: request.currency,
+            payee: request.payee,
+            occurrence: 0,
+        };
+        // Null means no value, as it does when updating metadata
+        let metadata: Metadata = request.metadata.into_iter().filter(|(_, value)| !value.is_null()).collect();
+        let uuid = Uuid::new_v4().to_string();
+
+        self.commit(override_lock, |state| {
+            let exists = state
+                .current
+                .get(&request.account_id)
+                .is_some_and(|transactions| transactions.contains_key(&transaction_id));
+
+            if !request.foreign_currency {
+                check_currency(state, &request.account_id, std::iter::once(transaction_id.currency.as_str()))?;
+            }
+
+            if exists {
+                if !request.allow_duplicate {
+                    return Err(ApiError {
+                        message: "Transaction already exists".to_string(),
+                        status: warp::http::StatusCode::CONFLICT,
+                    });
+                }
+                while has_transaction(state, &request.account_id, &transaction_id) {
+                    transaction_id.occurrence += 1;
+                }
+            }
+
+            Ok(Mutation::Created {
+                transaction: HistoricalTransaction {
+                    account_id: request.account_id.clone(),
+                    uuid: uuid.clone(),
+                    id: transaction_id.clone(),
+                    memo: None,
+                    metadata,
+                },
+            })
+        })?;
+
+        Ok(CurrentTransaction {
+            account_id: request.account_id,
+            uuid,
+            id: transaction_id,
+        })
+    }
+
+    /// Store a batch of imported transactions, the last stage of the import
+    /// pipeline. Ones the account already has are counted as duplicates, and
+    /// keep their uuid and historical record.
     pub async fn bulk_import_transactions(
         &self,
         account_id: String,
         new_transactions: Vec<(TransactionId, CurrentTransaction, HistoricalTransaction)>,
+        override_lock: bool,
     ) -> Result<BulkImportResponse, ApiError> {
         if new_transactions.is_empty() {
             return Err(ApiError {
@@ -152,91 +892,1405 @@ impl TransactionStore {
             });
         }
 
-        // Find date range covered by new transactions
-        let mut min_date: Option<DateTime<Utc>> = None;
-        let mut max_date: Option<DateTime<Utc>> = None;
+        // Find date range covered by new transactions; existing current
+        // transactions in that range are replaced by the import
+        let timestamps = new_transactions.iter().map(|(id, _, _)| id.timestamp);
+        let from = timestamps.clone().min().unwrap();
+        let to = timestamps.max().unwrap();
+        let rows = new_transactions.len();
+        let mut duplicates = 0;
 
-        for (transaction_id, _, _) in &new_transactions {
-            let date = transaction_id.timestamp;
-            min_date = Some(min_date.map_or(date, |min| min.min(date)));
-            max_date = Some(max_date.map_or(date, |max| max.max(date)));
-        }
+        self.commit(override_lock, |state| {
+            // A row repeated within the statement is another occurrence of the
+            // same transaction, as when one is created with allow_duplicate
+            let mut seen: HashSet<TransactionId> = HashSet::new();
+            let transactions: Vec<HistoricalTransaction> = new_transactions
+                .into_iter()
+                .map(|(mut id, _, mut historical_transaction)| {
+                    while seen.contains(&id) {
+                        id.occurrence += 1;
+                    }
+                    seen.insert(id.clone());
+                    // A transaction imported again keeps the uuid it was first given
+                    historical_transaction.uuid =
+                        uuid_of(state, &account_id, &id).unwrap_or_else(|| Uuid::new_v4().to_string());
+                    historical_transaction.id = id;
+                    historical_transaction
+                })
+                .collect();
 
-        let mut imported = 0;
+            // Re-importing an overlapping statement brings back transactions we already have
+            if let Some(current) = state.current.get(&account_id) {
+                duplicates = transactions.iter().filter(|t| current.contains_key(&t.id)).count();
+            }
 
-        // Update current transactions
-        {
-            let mut current = self.current.lock().unwrap();
-            let account_transactions = current
-                .entry(account_id.clone())
-                .or_insert_with(HashMap::new);
+            Ok(Mutation::Imported {
+                account_id,
+                from,
+                to,
+                transactions,
+            })
+        })?;
+
+        // Rows the account already had are duplicates, not imports
+        Ok(BulkImportResponse {
+            import_id: None,
+            imported: rows - duplicates,
+            duplicates,
+            errors: vec![],
+        })
+    }
 
-            // Remove existing transactions in the date range
-            if let (Some(min_date), Some(max_date)) = (min_date, max_date) {
-                account_transactions
-                    .retain(|id, _| id.timestamp < min_date || id.timestamp > max_date);
+    /// Update a transaction memo, returning the transaction's new entity tag
+    pub async fn update_transaction_memo(
+        &self,
+        account_id: String,
+        transaction_id: TransactionId,
+        new_memo: Option<String>,
+        override_lock: bool,
+        if_match: Option<&str>,
+    ) -> Result<String, ApiError> {
+        let version = self.commit(override_lock, |state| {
+            let mutation = memo_update(state, account_id, transaction_id, new_memo)?;
+            if let Mutation::MemoUpdated { account_id, id, .. } = &mutation
+                && let Some(uuid) = uuid_of(state, account_id, id)
+            {
+                self.check_if_match(state, &uuid, if_match)?;
             }
+            Ok(mutation)
+        })?;
+        Ok(self.tag(version))
+    }
 
-            // Add new transactions
-            for (transaction_id, current_transaction, _) in &new_transactions {
-                account_transactions.insert(transaction_id.clone(), current_transaction.clone());
-                imported += 1;
+    /// Update the memos of several transactions at once; if any of them can't
+    /// be updated, none are. `if_match` is checked against the tag of the
+    /// whole store, which is returned as it is afterwards.
+    pub async fn update_transaction_memos(
+        &self,
+        updates: Vec<MemoUpdate>,
+        override_lock: bool,
+        if_match: Option<&str>,
+    ) -> Result<(usize, String), ApiError> {
+        let count = updates.len();
+        let version = self.commit_all(override_lock, |state| {
+            self.check_tag(if_match, &self.etag(), "The store")?;
+            updates
+                .into_iter()
+                .enumerate()
+                .map(|(index, update)| {
+                    memo_update(state, update.account_id, update.id, update.memo).map_err(|e| ApiError {
+                        message: format!("Entry {}: {}", index, e.message),
+                        status: e.status,
+                    })
+                })
+                .collect()
+        })?;
+        Ok((count, self.tag(version)))
+    }
+
+    /// Set or, where the value is null, remove metadata keys of a transaction,
+    /// returning all of its metadata afterwards and its new entity tag
+    pub async fn update_transaction_metadata(
+        &self,
+        account_id: String,
+        transaction_id: TransactionId,
+        changes: Metadata,
+        override_lock: bool,
+        if_match: Option<&str>,
+    ) -> Result<(Metadata, String), ApiError> {
+        let mut updated = Metadata::new();
+        let version = self.commit(override_lock, |state| {
+            let account_transactions = state.all.get(&account_id).ok_or(ApiError {
+                message: "Account not found".to_string(),
+                status: warp::http::StatusCode::NOT_FOUND,
+            })?;
+
+            // The first record is the one memo and metadata updates change
+            let transaction = account_transactions.iter().find(|t| t.id == transaction_id).ok_or(ApiError {
+                message: "Transaction not found".to_string(),
+                status: warp::http::StatusCode::NOT_FOUND,
+            })?;
+            self.check_if_match(state, &transaction.uuid, if_match)?;
+
+            updated = transaction.metadata.clone();
+            for (key, value) in changes {
+                if value.is_null() {
+                    updated.remove(&key);
+                } else {
+                    updated.insert(key, value);
+                }
             }
-        }
+            Ok(Mutation::MetadataUpdated {
+                account_id,
+                id: transaction_id,
+                metadata: updated.clone(),
+            })
+        })?;
 
-        // Add to historical transactions
-        {
-            let mut all = self.all.lock().unwrap();
-            let account_transactions = all.entry(account_id).or_insert_with(Vec::new);
+        Ok((updated, self.tag(version)))
+    }
+
+    /// Correct the timestamp, payee or amount of a transaction, re-keying it in
+    /// the current transactions and the history. Returns it with its new entity tag.
+    pub async fn edit_transaction(
+        &self,
+        account_id: String,
+        transaction_id: TransactionId,
+        request: EditTransactionRequest,
+        override_lock: bool,
+        if_match: Option<&str>,
+    ) -> Result<(CurrentTransaction, String), ApiError> {
+        let new_id = TransactionId {
+            timestamp: request.timestamp.unwrap_or(transaction_id.timestamp),
+            amount_cents: match request.amount {
+                Some(amount) => (amount * 100.0).round() as i64,
+                None => transaction_id.amount_cents,
+            },
+            currency: transaction_id.currency.clone(),
+            payee: request.payee.unwrap_or_else(|| transaction_id.payee.clone()),
+            occurrence: transaction_id.occurrence,
+        };
 
-            for (_, _, historical_transaction) in new_transactions {
-                account_transactions.push(historical_transaction);
+        let mut uuid = String::new();
+        let version = self.commit(override_lock, |state| {
+            uuid = uuid_of(state, &account_id, &transaction_id).ok_or(ApiError {
+                message: "Transaction not found".to_string(),
+                status: warp::http::StatusCode::NOT_FOUND,
+            })?;
+            self.check_if_match(state, &uuid, if_match)?;
+            if new_id != transaction_id && has_transaction(state, &account_id, &new_id) {
+                return Err(ApiError {
+                    message: "Transaction already exists".to_string(),
+                    status: warp::http::StatusCode::CONFLICT,
+                });
             }
-        }
 
-        // Save to files
-        if let Err(e) = self.save_to_files().await {
-            eprintln!("Warning: Failed to save data: {}", e);
-        }
+            Ok(Mutation::Edited {
+                account_id: account_id.clone(),
+                id: transaction_id,
+                new_id: new_id.clone(),
+            })
+        })?;
 
-        Ok(BulkImportResponse {
-            imported,
-            duplicates: 0,
-            errors: vec![],
-        })
+        let transaction = CurrentTransaction {
+            account_id,
+            uuid,
+            id: new_id,
+        };
+        Ok((transaction, self.tag(version)))
     }
 
-    /// Update a transaction memo
-    pub async fn update_transaction_memo(
+    /// Remove a transaction from the current transactions and the history
+    pub async fn delete_transaction(
         &self,
         account_id: String,
         transaction_id: TransactionId,
-        new_memo: Option<String>,
+        override_lock: bool,
+        if_match: Option<&str>,
     ) -> Result<(), ApiError> {
-        // Update memo in a scope to release the lock
-        {
-            let mut all = self.all.lock().unwrap();
-            let account_transactions = all.get_mut(&account_id).ok_or(ApiError {
-                message: "Account not found".to_string(),
+        self.commit(override_lock, |state| {
+            let uuid = uuid_of(state, &account_id, &transaction_id).ok_or(ApiError {
+                message: "Transaction not found".to_string(),
                 status: warp::http::StatusCode::NOT_FOUND,
             })?;
+            self.check_if_match(state, &uuid, if_match)?;
+
+            Ok(Mutation::Deleted {
+                account_id,
+                id: transaction_id,
+            })
+        })?;
+        Ok(())
+    }
+
+    /// Record a balance assertion and check it against the current transactions
+    pub async fn assert_balance(
+        &self,
+        account_id: String,
+        request: AssertBalanceRequest,
+    ) -> Result<BalanceAssertionResult, ApiError> {
+        let assertion = BalanceAssertion {
+            account_id,
+            date: request.date,
+            balance_cents: (request.balance * 100.0).round() as i64,
+            currency: request.currency,
+        };
+
+        let recorded = assertion.clone();
+        self.commit(false, |state| {
+            if !state.current.contains_key(&recorded.account_id) {
+                return Err(ApiError {
+                    message: "Account not found".to_string(),
+                    status: warp::http::StatusCode::NOT_FOUND,
+                });
+            }
+
+            Ok(Mutation::BalanceAsserted {
+                assertion: recorded,
+            })
+        })?;
+
+        let state = self.read();
+        Ok(check_assertion(&state, assertion))
+    }
+
+    /// Go through the month-end checklist for `month` without closing it
+    pub fn month_checklist(&self, month: Month) -> MonthClosingResponse {
+        let state = self.read();
+        let checks = closing_checks(&state, month);
+        MonthClosingResponse {
+            month,
+            complete: checks.iter().all(|check| check.passed),
+            closed: self.month_closed(&state, month),
+            checks,
+        }
+    }
+
+    /// Go through the month-end checklist for `month` and, when it is
+    /// complete, close the month for every account, locking their
+    /// transactions up to its end
+    pub fn close_month(&self, month: Month) -> Result<MonthClosingResponse, ApiError> {
+        // Checked and closed as one change, so nothing changes in between
+        let mut checks = Vec::new();
+        self.commit_all(false, |state| {
+            checks = closing_checks(state, month);
+            if !checks.iter().all(|check| check.passed) {
+                return Ok(vec![]);
+            }
+            Ok(state
+                .current
+                .keys()
+                .filter(|account_id| state.closed_months.get(*account_id).is_none_or(|closed| *closed < month))
+                .map(|account_id| Mutation::MonthClosed {
+                    account_id: account_id.clone(),
+                    month,
+                })
+                .collect())
+        })?;
+
+        let state = self.read();
+        Ok(MonthClosingResponse {
+            month,
+            complete: checks.iter().all(|check| check.passed),
+            closed: self.month_closed(&state, month),
+            checks,
+        })
+    }
+
+    /// Re-check every recorded balance assertion, returning the ones that no
+    /// longer hold, now or at `past`
+    pub fn failed_balance_assertions(&self, past: Option<&Past>, selector: &AccountSelector) -> Vec<BalanceAssertionResult> {
+        let state = self.read_at(past);
+        let now = self.read();
+        state
+            .balance_assertions
+            .iter()
+            .filter(|assertion| selector.matches(now.accounts.get(&assertion.account_id)))
+            .map(|assertion| check_assertion(&state, assertion.clone()))
+            .filter(|result| !result.passed)
+            .collect()
+    }
+
+    /// Approximate in-memory size of every account, including space allocated
+    /// but not yet used
+    pub fn memory(&self) -> MemoryResponse {
+        memory_of(&self.read())
+    }
+
+    /// Give back memory the in-memory state has allocated but isn't using,
+    /// then let the storage backend compact what it has written incrementally
+    pub async fn compact(&self) -> Result<CompactResponse, StorageError> {
+        let (bytes_before, bytes_after) = {
+            let _writing = self.writing.lock().unwrap();
+            let current = self.read_whole();
+            let bytes_before = memory_of(&current).total_bytes;
+            // Shrunk in a copy, which readers are given in place of the state
+            let mut state = (*current).clone();
+            state.current.shrink_to_fit();
+            for transactions in state.current.values_mut() {
+                transactions.shrink_to_fit();
+            }
+            state.all.shrink_to_fit();
+            for transactions in state.all.values_mut() {
+                transactions.shrink_to_fit();
+            }
+            state.balance_assertions.shrink_to_fit();
+            state.imports.shrink_to_fit();
+            let bytes_after = memory_of(&state).total_bytes;
+            self.state.store(Arc::new(state));
+            // The spare takes as much again, and the next change makes it afresh
+            *self.spare.lock().unwrap() = None;
+            (bytes_before, bytes_after)
+        };
+
+        self.storage.compact().await?;
+        Ok(CompactResponse {
+            bytes_before,
+            bytes_after,
+        })
+    }
+
+    /// Up to `limit` payees starting with `prefix`, the most used first
+    pub fn payees(&self, prefix: Option<&str>, limit: usize) -> Vec<PayeeStats> {
+        self.read().payees.ranked(prefix, limit)
+    }
+
+    /// Every inconsistency between the current and historical transactions
+    pub fn verify(&self) -> Vec<Discrepancy> {
+        let state = self.read();
+        integrity::check(&state, Utc::now())
+    }
+
+    /// Keep the outcome of an import for `import_metrics`, returning the id
+    /// it is recorded under
+    pub fn record_import(&self, mut record: ImportRecord) -> Result<String, ApiError> {
+        // Microseconds are as precise as every storage backend keeps timestamps
+        record.imported_at = record.imported_at.trunc_subsecs(6);
+        let id = Uuid::new_v4().to_string();
+        record.id = Some(id.clone());
+        self.commit(false, |_| Ok(Mutation::ImportRecorded { record }))?;
+        Ok(id)
+    }
+
+    pub fn import_record(&self, import_id: &str) -> Option<ImportRecord> {
+        let state = self.read();
+        state.imports.iter().find(|record| record.id.as_deref() == Some(import_id)).cloned()
+    }
 
-            let transaction = account_transactions
-                .iter_mut()
-                .find(|t| t.id == transaction_id)
+    /// Note on an import that its statement file has been kept, replacing any
+    /// attached before
+    pub fn attach_statement(&self, import_id: &str, statement: StatementFile) -> Result<(), ApiError> {
+        self.commit(false, |state| {
+            let record = state
+                .imports
+                .iter()
+                .find(|record| record.id.as_deref() == Some(import_id))
                 .ok_or(ApiError {
-                    message: "Transaction not found".to_string(),
+                    message: "Import not found".to_string(),
                     status: warp::http::StatusCode::NOT_FOUND,
                 })?;
+            Ok(Mutation::StatementAttached {
+                account_id: record.account_id.clone(),
+                import_id: import_id.to_string(),
+                statement,
+            })
+        })?;
+        Ok(())
+    }
+
+    /// Create corrected versions of rows of the import `import_id` that couldn't
+    /// be read, and count them towards that import. Transactions the account
+    /// already has are counted as duplicates. Unlike importing a file this
+    /// leaves the rest of the range the rows fall in as it is.
+    pub fn import_corrections(
+        &self,
+        import_id: &str,
+        transactions: Vec<(TransactionId, CurrentTransaction, HistoricalTransaction)>,
+        override_lock: bool,
+    ) -> Result<(usize, usize), ApiError> {
+        let (mut imported, mut duplicates) = (0, 0);
+        self.commit_all(override_lock, |state| {
+            let record = state
+                .imports
+                .iter()
+                .find(|record| record.id.as_deref() == Some(import_id))
+                .ok_or(ApiError {
+                    message: "Import not found".to_string(),
+                    status: warp::http::StatusCode::NOT_FOUND,
+                })?;
+            let account_id = &record.account_id;
+
+            let current = state.current.get(account_id);
+            let mut seen = HashSet::new();
+            let mut mutations = Vec::new();
+            for (id, _, mut transaction) in transactions {
+                if current.is_some_and(|current| current.contains_key(&id)) || !seen.insert(id.clone()) {
+                    duplicates += 1;
+                    continue;
+                }
+                transaction.account_id = account_id.clone();
+                transaction.uuid = uuid_of(state, account_id, &id).unwrap_or_else(|| Uuid::new_v4().to_string());
+                mutations.push(Mutation::Created { transaction });
+                imported += 1;
+            }
+            mutations.push(Mutation::ImportCorrected {
+                account_id: account_id.clone(),
+                import_id: import_id.to_string(),
+                imported,
+                duplicates,
+            });
+            Ok(mutations)
+        })?;
+        Ok((imported, duplicates))
+    }
+
+    /// Per-source totals and the full history of imports, optionally for one account only
+    pub fn import_metrics(&self, account_id: Option<&str>) -> ImportMetricsResponse {
+        let state = self.read();
+        let imports: Vec<_> = state
+            .imports
+            .iter()
+            .filter(|record| account_id.is_none_or(|account_id| record.account_id == account_id))
+            .cloned()
+            .collect();
+
+        let mut by_source: HashMap<&str, Vec<&ImportRecord>> = HashMap::new();
+        for record in &imports {
+            by_source.entry(&record.source).or_default().push(record);
+        }
+
+        let mut sources: Vec<_> = by_source
+            .into_iter()
+            .map(|(source, records)| {
+                let rows = records.iter().map(|r| r.rows).sum();
+                let imported = records.iter().map(|r| r.imported).sum();
+                let duplicates = records.iter().map(|r| r.duplicates).sum();
+                let errors = records.iter().map(|r| r.errors).sum();
+                ImportSourceMetrics {
+                    source: source.to_string(),
+                    imports: records.len(),
+                    rows,
+                    imported,
+                    duplicates,
+                    errors,
+                    error_rate: ratio(errors, rows),
+                    duplicate_rate: ratio(duplicates, imported),
+                }
+            })
+            .collect();
+        sources.sort_by(|a, b| a.source.cmp(&b.source));
+
+        ImportMetricsResponse { sources, imports }
+    }
+
+    /// Every account `selector` picks with its balance in each currency it has
+    /// transactions in, ordered by account id
+    pub fn accounts(&self, selector: &AccountSelector) -> Vec<AccountSummary> {
+        let state = self.read();
+        let mut accounts: Vec<_> = state
+            .current
+            .keys()
+            .filter(|account_id| selector.matches(state.accounts.get(*account_id)))
+            .map(|account_id| account_summary(&state, account_id))
+            .collect();
+        accounts.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+        accounts
+    }
+
+    pub fn account(&self, account_id: &str) -> Result<AccountSummary, ApiError> {
+        let state = self.read();
+        if !state.knows_account(account_id) {
+            return Err(ApiError {
+                message: "Account not found".to_string(),
+                status: warp::http::StatusCode::NOT_FOUND,
+            });
+        }
+        Ok(account_summary(&state, account_id))
+    }
+
+    /// Assets, liabilities and net worth in each currency over the accounts
+    /// `selector` picks, now or at `past`, ordered by currency. Accounts are
+    /// picked, and count on the side their type puts them on, by their
+    /// details now, whatever they were then.
+    pub fn net_worth(&self, past: Option<&Past>, selector: &AccountSelector) -> Vec<NetWorth> {
+        let state = self.read_at(past);
+        let now = self.read();
+        let account_ids = state
+            .current
+            .keys()
+            .filter(|account_id| selector.matches(now.accounts.get(*account_id)));
+        totals_of(&state, &now, account_ids)
+    }
+
+    /// The accounts `selector` picks put together by institution or group,
+    /// each with its totals as `net_worth` has them, ordered by name with the
+    /// accounts that have none last
+    pub fn account_groups(&self, grouping: AccountGrouping, past: Option<&Past>, selector: &AccountSelector) -> Vec<AccountGroup> {
+        let state = self.read_at(past);
+        let now = self.read();
+        let mut groups: HashMap<Option<String>, Vec<&String>> = HashMap::new();
+        for account_id in now.current.keys() {
+            let details = now.accounts.get(account_id);
+            if !selector.matches(details) {
+                continue;
+            }
+            let name = details.and_then(|details| match grouping {
+                AccountGrouping::Institution => details.institution.clone(),
+                AccountGrouping::Group => details.group.clone(),
+            });
+            groups.entry(name).or_default().push(account_id);
+        }
+
+        let mut groups: Vec<_> = groups
+            .into_iter()
+            .map(|(name, mut accounts)| {
+                accounts.sort();
+                AccountGroup {
+                    totals: totals_of(&state, &now, accounts.iter().copied()),
+                    accounts: accounts.into_iter().cloned().collect(),
+                    name,
+                }
+            })
+            .collect();
+        groups.sort_by(|a, b| (a.name.is_none(), &a.name).cmp(&(b.name.is_none(), &b.name)));
+        groups
+    }
+
+    /// Create an account with no transactions yet, for transactions to be
+    /// recorded in
+    pub fn create_account(&self, request: CreateAccountRequest) -> Result<AccountSummary, ApiError> {
+        let account_id = request.account_id.trim().to_string();
+        if account_id.is_empty() || account_id.len() > MAX_ACCOUNT_ID_LEN {
+            return Err(ApiError {
+                message: format!("account_id must be 1 to {} characters long", MAX_ACCOUNT_ID_LEN),
+                status: warp::http::StatusCode::BAD_REQUEST,
+            });
+        }
+        let details = validate_account_details(request.details)?;
+        self.commit(false, |state| {
+            if state.knows_account(&account_id) {
+                return Err(ApiError {
+                    message: format!("Account {} already exists", account_id),
+                    status: warp::http::StatusCode::CONFLICT,
+                });
+            }
+            Ok(Mutation::AccountCreated {
+                account_id: account_id.clone(),
+                details,
+            })
+        })?;
+        self.account(&account_id)
+    }
+
+    /// Replace the details of `account_id`
+    pub fn update_account(&self, account_id: &str, details: AccountDetails) -> Result<AccountSummary, ApiError> {
+        let details = validate_account_details(details)?;
+        self.commit(false, |state| {
+            if !state.knows_account(account_id) {
+                return Err(ApiError {
+                    message: "Account not found".to_string(),
+                    status: warp::http::StatusCode::NOT_FOUND,
+                });
+            }
+            Ok(Mutation::AccountUpdated {
+                account_id: account_id.to_string(),
+                details,
+            })
+        })?;
+        self.account(account_id)
+    }
+
+    /// Move every transaction of `source_account_id`, current and historical,
+    /// into `target_account_id` along with its balance assertions and import
+    /// records, then remove it. For an account moved to a new number by its
+    /// bank; transactions imported into both are kept only once.
+    pub fn merge_accounts(&self, request: MergeAccountsRequest, override_lock: bool) -> Result<MergeAccountsResponse, ApiError> {
+        let MergeAccountsRequest {
+            source_account_id,
+            target_account_id,
+        } = request;
+        if source_account_id == target_account_id {
+            return Err(ApiError {
+                message: "An account can't be merged into itself".to_string(),
+                status: warp::http::StatusCode::BAD_REQUEST,
+            });
+        }
 
-            transaction.memo = new_memo;
-        } // Lock is automatically dropped here
+        let (mut moved, mut duplicates) = (0, 0);
+        self.commit(override_lock, |state| {
+            for account_id in [&source_account_id, &target_account_id] {
+                if !state.knows_account(account_id) {
+                    return Err(ApiError {
+                        message: format!("Account {} not found", account_id),
+                        status: warp::http::StatusCode::NOT_FOUND,
+                    });
+                }
+            }
+            let target = state.current.get(&target_account_id);
+            for id in state.current.get(&source_account_id).into_iter().flat_map(HashMap::keys) {
+                if target.is_some_and(|target| target.contains_key(id)) {
+                    duplicates += 1;
+                } else {
+                    moved += 1;
+                }
+            }
+            Ok(Mutation::AccountsMerged {
+                account_id: target_account_id.clone(),
+                source_account_id: source_account_id.clone(),
+            })
+        })?;
+        Ok(MergeAccountsResponse {
+            account: self.account(&target_account_id)?,
+            source_account_id,
+            moved,
+            duplicates,
+        })
+    }
 
-        // Save to files
-        if let Err(e) = self.save_to_files().await {
-            eprintln!("Warning: Failed to save data: {}", e);
+    /// Refuse transactions for `account_id` in `currencies` unless the account
+    /// is kept in every one of them, or in no currency in particular
+    pub fn require_currency<'a>(&self, account_id: &str, currencies: impl Iterator<Item = &'a str>) -> Result<(), ApiError> {
+        check_currency(&self.read_whole(), account_id, currencies)
+    }
+
+    /// Refuse to go on with something for `account_id` unless transactions
+    /// may be recorded for it
+    pub fn require_account(&self, account_id: &str) -> Result<(), ApiError> {
+        if !self.read_whole().knows_account(account_id) {
+            return Err(unknown_account(account_id));
         }
+        Ok(())
+    }
 
+    /// The balance of `account_id` in each currency it has transactions or an
+    /// opening balance in, ordered by currency
+    pub fn account_balances(&self, account_id: &str) -> Vec<AccountBalance> {
+        balances_of(&self.read(), account_id)
+    }
+
+    /// Who `account_id` is shared with, which only its owner and admins may see
+    pub fn account_grants(&self, account_id: &str) -> Result<AccountGrantsResponse, ApiError> {
+        let owner = self.account_owner_managing(account_id)?;
+        let state = self.read_whole();
+        let mut grants: Vec<_> = state
+            .account_grants
+            .get(account_id)
+            .into_iter()
+            .flatten()
+            .map(|(user, permission)| AccountGrant {
+                user: user.clone(),
+                permission: *permission,
+            })
+            .collect();
+        grants.sort_by(|a, b| a.user.cmp(&b.user));
+        Ok(AccountGrantsResponse {
+            account_id: account_id.to_string(),
+            owner,
+            grants,
+        })
+    }
+
+    /// Let `user` do what `permission` allows with `account_id`, replacing
+    /// any grant they had
+    pub fn grant_access(&self, account_id: &str, user: &str, permission: Permission) -> Result<AccountGrantsResponse, ApiError> {
+        let owner = self.account_owner_managing(account_id)?;
+        if user == owner {
+            return Err(ApiError {
+                message: format!("{} owns account {} already", user, account_id),
+                status: warp::http::StatusCode::BAD_REQUEST,
+            });
+        }
+        self.commit(false, |_| {
+            Ok(Mutation::AccessGranted {
+                account_id: account_id.to_string(),
+                user: user.to_string(),
+                permission,
+            })
+        })?;
+        self.account_grants(account_id)
+    }
+
+    /// Stop sharing `account_id` with `user`
+    pub fn revoke_access(&self, account_id: &str, user: &str) -> Result<(), ApiError> {
+        self.account_owner_managing(account_id)?;
+        self.commit(false, |state| {
+            if !state.account_grants.get(account_id).is_some_and(|grants| grants.contains_key(user)) {
+                return Err(ApiError {
+                    message: format!("Account {} isn't shared with {}", account_id, user),
+                    status: warp::http::StatusCode::NOT_FOUND,
+                });
+            }
+            Ok(Mutation::AccessRevoked {
+                account_id: account_id.to_string(),
+                user: user.to_string(),
+            })
+        })?;
         Ok(())
     }
+
+    /// Give `account_id` to `owner`, as admins do with accounts from before
+    /// auth was set up, which nobody owns. The new owner's grant, if they had
+    /// one, goes, as owning the account lets them do everything.
+    pub fn give_account(&self, account_id: &str, owner: &str) -> Result<AccountGrantsResponse, ApiError> {
+        self.commit_all(false, |state| {
+            if !state.has_account(account_id) {
+                return Err(ApiError {
+                    message: "Account not found".to_string(),
+                    status: warp::http::StatusCode::NOT_FOUND,
+                });
+            }
+            let mut mutations = vec![Mutation::AccountOwned {
+                account_id: account_id.to_string(),
+                owner: owner.to_string(),
+            }];
+            if state.account_grants.get(account_id).is_some_and(|grants| grants.contains_key(owner)) {
+                mutations.push(Mutation::AccessRevoked {
+                    account_id: account_id.to_string(),
+                    user: owner.to_string(),
+                });
+            }
+            Ok(mutations)
+        })?;
+        self.account_grants(account_id)
+    }
+
+    /// The owner of `account_id`, when this part of the store may manage who
+    /// it is shared with: it is theirs, or this is an admin's whole store
+    fn account_owner_managing(&self, account_id: &str) -> Result<String, ApiError> {
+        let state = self.read_whole();
+        let visible = self.owner.as_ref().is_none_or(|owner| state.visible_to(account_id, owner));
+        if !state.has_account(account_id) || !visible {
+            return Err(ApiError {
+                message: "Account not found".to_string(),
+                status: warp::http::StatusCode::NOT_FOUND,
+            });
+        }
+        let Some(owner) = state.account_owners.get(account_id) else {
+            return Err(ApiError {
+                message: format!("Account {} has no owner to share it yet; an admin must give it one", account_id),
+                status: warp::http::StatusCode::CONFLICT,
+            });
+        };
+        if self.owner.as_deref().is_some_and(|me| me != owner.as_str()) {
+            return Err(ApiError {
+                message: format!("Only the owner of account {} may share it", account_id),
+                status: warp::http::StatusCode::FORBIDDEN,
+            });
+        }
+        Ok(owner.clone())
+    }
+
+    /// Every account and currency whose balance is below its configured
+    /// threshold, now or at `past`
+    pub fn low_balance_accounts(&self, past: Option<&Past>, selector: &AccountSelector) -> Vec<LowBalance> {
+        let state = self.read_at(past);
+        let whole = self.read_whole();
+        self.low_balance_thresholds
+            .keys()
+            .filter(|account_id| self.owner.as_ref().is_none_or(|owner| whole.visible_to(account_id, owner)))
+            .filter(|account_id| selector.matches(whole.accounts.get(*account_id)))
+            .flat_map(|account_id| self.low_balances(&state, account_id))
+            .collect()
+    }
+}
+
+fn account_summary(state: &Snapshot, account_id: &str) -> AccountSummary {
+    let transactions = state.current.get(account_id);
+    AccountSummary {
+        account_id: account_id.to_string(),
+        details: state.accounts.get(account_id).cloned(),
+        owner: state.account_owners.get(account_id).cloned(),
+        class: account_class(state, account_id),
+        current_transactions: transactions.map_or(0, HashMap::len),
+        balances: balances_of(state, account_id),
+    }
+}
+
+/// What `account_ids` add up to in `state`, each on the side of net worth
+/// its type in `now` puts it
+fn totals_of<'a>(state: &Snapshot, now: &Snapshot, account_ids: impl Iterator<Item = &'a String>) -> Vec<NetWorth> {
+    let mut totals: HashMap<String, NetWorth> = HashMap::new();
+    for account_id in account_ids {
+        let class = account_class(now, account_id);
+        for (currency, balance_cents) in balances_at(state, account_id, None) {
+            let total = totals.entry(currency.clone()).or_insert_with(|| NetWorth {
+                currency,
+                assets_cents: 0,
+                liabilities_cents: 0,
+                net_worth_cents: 0,
+            });
+            match class {
+                AccountClass::Asset => total.assets_cents += balance_cents,
+                AccountClass::Liability => total.liabilities_cents -= balance_cents,
+            }
+            total.net_worth_cents += balance_cents;
+        }
+    }
+    let mut totals: Vec<_> = totals.into_values().collect();
+    totals.sort_by(|a, b| a.currency.cmp(&b.currency));
+    totals
+}
+
+fn account_class(state: &Snapshot, account_id: &str) -> AccountClass {
+    state
+        .accounts
+        .get(account_id)
+        .and_then(|details| details.account_type)
+        .map_or(AccountClass::Asset, AccountType::class)
+}
+
+/// Trim `details` and check there's a name and the currencies are currency
+/// codes, the opening balance's the account's own
+fn validate_account_details(mut details: AccountDetails) -> Result<AccountDetails, ApiError> {
+    let bad_request = |message: &str| ApiError {
+        message: message.to_string(),
+        status: warp::http::StatusCode::BAD_REQUEST,
+    };
+    details.display_name = details.display_name.trim().to_string();
+    if details.display_name.is_empty() {
+        return Err(bad_request("display_name must not be empty"));
+    }
+    for field in [&mut details.institution, &mut details.group, &mut details.number_suffix] {
+        *field = field.take().map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+    }
+    let is_code = |currency: &str| currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase());
+    if details.currency.as_deref().is_some_and(|currency| !is_code(currency)) {
+        return Err(bad_request("currency must be a three-letter code such as USD"));
+    }
+    if let Some(opening) = &details.opening_balance {
+        if !is_code(&opening.currency) {
+            return Err(bad_request("opening_balance.currency must be a three-letter code such as USD"));
+        }
+        if details.currency.as_ref().is_some_and(|currency| *currency != opening.currency) {
+            return Err(bad_request("opening_balance must be in the account's currency"));
+        }
+    }
+    Ok(details)
+}
+
+fn check_currency<'a>(state: &Snapshot, account_id: &str, currencies: impl Iterator<Item = &'a str>) -> Result<(), ApiError> {
+    let Some(expected) = state.accounts.get(account_id).and_then(|details| details.currency.as_deref()) else {
+        return Ok(());
+    };
+    let mut others: Vec<_> = currencies.filter(|currency| *currency != expected).collect();
+    if others.is_empty() {
+        return Ok(());
+    }
+    others.sort_unstable();
+    others.dedup();
+    Err(ApiError {
+        message: format!(
+            "Account {} is kept in {}, not {}; if it is the right account, mark the transactions foreign_currency",
+            account_id,
+            expected,
+            others.join(" or ")
+        ),
+        status: warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+    })
+}
+
+/// Refuse transactions for accounts that were never created, unless an
+/// earlier mutation among `mutations` creates them
+fn require_known_accounts(state: &Snapshot, mutations: &[Mutation]) -> Result<(), ApiError> {
+    let mut created = HashSet::new();
+    for mutation in mutations {
+        match mutation {
+            Mutation::AccountCreated { account_id, .. } => {
+                created.insert(account_id.as_str());
+            }
+            Mutation::Created { .. } | Mutation::Imported { .. } => {
+                let account_id = mutation.account_id();
+                if !state.knows_account(account_id) && !created.contains(account_id) {
+                    return Err(unknown_account(account_id));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn unknown_account(account_id: &str) -> ApiError {
+    ApiError {
+        message: format!("Account {} doesn't exist; create it with POST /accounts first", account_id),
+        status: warp::http::StatusCode::NOT_FOUND,
+    }
+}
+
+fn memory_of(state: &Snapshot) -> MemoryResponse {
+    let id_bytes = |id: &TransactionId| id.currency.capacity() + id.payee.capacity();
+
+    let mut account_ids: Vec<_> = state.current.keys().chain(state.all.keys()).collect();
+    account_ids.sort_unstable();
+    account_ids.dedup();
+    let mut accounts: Vec<_> = account_ids
+        .into_iter()
+        .map(|account_id| {
+            let current = state.current.get(account_id);
+            let historical = state.all.get(account_id);
+            let current_bytes = current.map_or(0, |transactions| {
+                transactions.capacity() * size_of::<(TransactionId, CurrentTransaction)>()
+                    + transactions
+                        .iter()
+                        .map(|(id, t)| id_bytes(id) + id_bytes(&t.id) + t.account_id.capacity())
+                        .sum::<usize>()
+            });
+            let historical_bytes = historical.map_or(0, |transactions| {
+                transactions.capacity() * size_of::<HistoricalTransaction>()
+                    + transactions
+                        .iter()
+                        .map(|t| id_bytes(&t.id) + t.account_id.capacity() + t.memo.as_ref().map_or(0, String::capacity))
+                        .sum::<usize>()
+            });
+            AccountMemory {
+                account_id: account_id.clone(),
+                current_transactions: current.map_or(0, HashMap::len),
+                current_capacity: current.map_or(0, HashMap::capacity),
+                historical_transactions: historical.map_or(0, Vec::len),
+                historical_capacity: historical.map_or(0, Vec::capacity),
+                bytes: account_id.capacity() + current_bytes + historical_bytes,
+            }
+        })
+        .collect();
+    accounts.sort_by_key(|account| std::cmp::Reverse(account.bytes));
+
+    let assertion_bytes = state.balance_assertions.capacity() * size_of::<BalanceAssertion>()
+        + state
+            .balance_assertions
+            .iter()
+            .map(|assertion| assertion.account_id.capacity() + assertion.currency.capacity())
+            .sum::<usize>();
+    let import_bytes = state.imports.capacity() * size_of::<ImportRecord>()
+        + state
+            .imports
+            .iter()
+            .map(|record| record.account_id.capacity() + record.source.capacity())
+            .sum::<usize>();
+    MemoryResponse {
+        total_bytes: accounts.iter().map(|account| account.bytes).sum::<usize>() + assertion_bytes + import_bytes,
+        accounts,
+    }
+}
+
+fn memo_update(
+    state: &Snapshot,
+    account_id: String,
+    transaction_id: TransactionId,
+    memo: Option<String>,
+) -> Result<Mutation, ApiError> {
+    let account_transactions = state.all.get(&account_id).ok_or(ApiError {
+        message: "Account not found".to_string(),
+        status: warp::http::StatusCode::NOT_FOUND,
+    })?;
+
+    if !account_transactions.iter().any(|t| t.id == transaction_id) {
+        return Err(ApiError {
+            message: "Transaction not found".to_string(),
+            status: warp::http::StatusCode::NOT_FOUND,
+        });
+    }
+
+    Ok(Mutation::MemoUpdated {
+        account_id,
+        id: transaction_id,
+        memo,
+    })
+}
+
+/// Whether `id` is among the account's current transactions or in its history
+fn has_transaction(state: &Snapshot, account_id: &str, id: &TransactionId) -> bool {
+    state
+        .current
+        .get(account_id)
+        .is_some_and(|transactions| transactions.contains_key(id))
+        || state
+            .all
+            .get(account_id)
+            .is_some_and(|transactions| transactions.iter().any(|t| t.id == *id))
+}
+
+/// The uuid of `id` among the account's current transactions or in its history
+fn uuid_of(state: &Snapshot, account_id: &str, id: &TransactionId) -> Option<String> {
+    if let Some(transaction) = state.current.get(account_id).and_then(|transactions| transactions.get(id)) {
+        return Some(transaction.uuid.clone());
+    }
+    state
+        .all
+        .get(account_id)?
+        .iter()
+        .find(|t| t.id == *id)
+        .map(|t| t.uuid.clone())
+}
+
+/// The uuids of the transactions `mutation` changes, looked up before it is applied
+/// Apply the events of a change to `state`, moving it on to the next
+/// version, and return the uuids of the transactions they changed
+fn advance(state: &mut Snapshot, events: &[Event]) -> Vec<String> {
+    let mut changed = Vec::new();
+    for event in events {
+        changed.extend(changed_uuids(state, &event.mutation));
+        state.apply(&event.mutation);
+    }
+    state.version += 1;
+    for uuid in &changed {
+        if state.uuids.contains_key(uuid) {
+            state.revisions.insert(uuid.clone(), state.version);
+        } else {
+            state.revisions.remove(uuid);
+        }
+    }
+    changed
+}
+
+fn changed_uuids(state: &Snapshot, mutation: &Mutation) -> Vec<String> {
+    match mutation {
+        Mutation::Created { transaction } => vec![transaction.uuid.clone()],
+        // Those dropped from the current transactions change as much as those imported
+        Mutation::Imported {
+            account_id,
+            from,
+            to,
+            transactions,
+        } => state
+            .current
+            .get(account_id)
+            .into_iter()
+            .flat_map(HashMap::values)
+            .filter(|t| t.id.timestamp >= *from && t.id.timestamp <= *to)
+            .map(|t| t.uuid.clone())
+            .chain(transactions.iter().map(|t| t.uuid.clone()))
+            .collect(),
+        Mutation::MemoUpdated { account_id, id, .. }
+        | Mutation::MetadataUpdated { account_id, id, .. }
+        | Mutation::Edited { account_id, id, .. }
+        | Mutation::Deleted { account_id, id } => uuid_of(state, account_id, id).into_iter().collect(),
+        Mutation::BalanceAsserted { .. }
+        | Mutation::ImportRecorded { .. }
+        | Mutation::StatementAttached { .. }
+        | Mutation::ImportCorrected { .. }
+        | Mutation::MonthClosed { .. }
+        | Mutation::AccountCreated { .. }
+        | Mutation::AccountUpdated { .. }
+        | Mutation::AccountOwned { .. }
+        | Mutation::AccessGranted { .. }
+        | Mutation::AccessRevoked { .. } => vec![],
+        // Everything of the source changes account, and what the account has already is dropped
+        Mutation::AccountsMerged { source_account_id, .. } => state
+            .current
+            .get(source_account_id)
+            .into_iter()
+            .flat_map(HashMap::values)
+            .map(|t| t.uuid.clone())
+            .chain(state.all.get(source_account_id).into_iter().flatten().map(|t| t.uuid.clone()))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Earliest transaction timestamp `mutation` changes, if it changes any
+/// transactions; merging an account changes every one it has
+fn earliest_change(state: &Snapshot, mutation: &Mutation) -> Option<DateTime<Utc>> {
+    match mutation {
+        Mutation::AccountsMerged { source_account_id, .. } => state
+            .all
+            .get(source_account_id)
+            .into_iter()
+            .flatten()
+            .map(|t| t.id.timestamp)
+            .min(),
+        _ => mutation.earliest_timestamp(),
+    }
+}
+
+/// The balance of `account_id` in each currency just before `until`, or
+/// after every transaction. From the date of its opening balance on, that is
+/// the opening balance and the transactions since; before it, the
+/// transactions up to then.
+fn balances_at(state: &Snapshot, account_id: &str, until: Option<DateTime<Utc>>) -> HashMap<String, i64> {
+    let opening = state
+        .accounts
+        .get(account_id)
+        .and_then(|details| details.opening_balance.as_ref())
+        .map(|opening| (opening, opening.date.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+        .filter(|(_, opened)| until.is_none_or(|until| until >= *opened));
+
+    let mut balances = HashMap::new();
+    if let Some((opening, _)) = opening {
+        balances.insert(opening.currency.clone(), opening.balance_cents);
+    }
+    for id in state.current.get(account_id).into_iter().flat_map(HashMap::keys) {
+        let after_opening = opening.is_none_or(|(_, opened)| id.timestamp >= opened);
+        if after_opening && until.is_none_or(|until| id.timestamp < until) {
+            *balances.entry(id.currency.clone()).or_default() += id.amount_cents;
+        }
+    }
+    balances
+}
+
+fn balances_of(state: &Snapshot, account_id: &str) -> Vec<AccountBalance> {
+    let mut balances: Vec<_> = balances_at(state, account_id, None)
+        .into_iter()
+        .map(|(currency, balance_cents)| AccountBalance { currency, balance_cents })
+        .collect();
+    balances.sort_by(|a, b| a.currency.cmp(&b.currency));
+    balances
+}
+
+fn ratio(part: usize, whole: usize) -> f64 {
+    if whole == 0 { 0.0 } else { part as f64 / whole as f64 }
+}
+
+/// Every account's checks for closing `month`, ordered by account id
+fn closing_checks(state: &Snapshot, month: Month) -> Vec<ClosingCheck> {
+    let end = month.end();
+    let mut account_ids: Vec<_> = state.current.keys().collect();
+    account_ids.sort();
+
+    let mut checks = Vec::new();
+    for account_id in account_ids {
+        let transactions = &state.current[account_id];
+
+        // A quiet account may have nothing after the month, but then an import
+        // since it ended shows there was nothing more to import
+        let latest = transactions.keys().map(|id| id.timestamp).max();
+        let imported_since = state
+            .imports
+            .iter()
+            .filter(|record| record.account_id == *account_id && record.imported_at >= end)
+            .map(|record| record.imported_at)
+            .max();
+        let (passed, detail) = match (latest, imported_since) {
+            (Some(latest), _) if latest >= end => (true, format!("Latest transaction is dated {}", latest)),
+            (_, Some(imported_at)) => (true, format!("Imported on {} with nothing dated after {}", imported_at, month)),
+            _ => (false, format!("Nothing dated after {} and no import since it ended", month)),
+        };
+        checks.push(ClosingCheck {
+            kind: ClosingCheckKind::ImportedThroughMonthEnd,
+            account_id: account_id.clone(),
+            currency: None,
+            passed,
+            detail,
+        });
+
+        // An assertion is of the balance at the start of its day
+        let closing_date = end.date_naive();
+        let mut currencies: Vec<_> = transactions
+            .keys()
+            .filter(|id| id.timestamp < end)
+            .map(|id| &id.currency)
+            .collect();
+        currencies.sort();
+        currencies.dedup();
+        for currency in currencies {
+            let assertion = state.balance_assertions.iter().find(|assertion| {
+                assertion.account_id == *account_id && assertion.currency == *currency && assertion.date == closing_date
+            });
+            let (passed, detail) = match assertion {
+                Some(assertion) => {
+                    let result = check_assertion(state, assertion.clone());
+                    let detail = format!(
+                        "Asserted {:.2}, actual {:.2}",
+                        result.assertion.balance_cents as f64 / 100.0,
+                        result.actual_cents as f64 / 100.0
+                    );
+                    (result.passed, detail)
+                }
+                None => (false, format!("No balance assertion dated {}", closing_date)),
+            };
+            checks.push(ClosingCheck {
+                kind: ClosingCheckKind::BalanceAsserted,
+                account_id: account_id.clone(),
+                currency: Some(currency.clone()),
+                passed,
+                detail,
+            });
+        }
+    }
+    checks
+}
+
+fn check_assertion(state: &Snapshot, assertion: BalanceAssertion) -> BalanceAssertionResult {
+    let start_of_day = assertion.date.and_hms_opt(0, 0, 0).unwrap().and_utc();
+    let actual_cents = balances_at(state, &assertion.account_id, Some(start_of_day))
+        .get(&assertion.currency)
+        .copied()
+        .unwrap_or(0);
+
+    BalanceAssertionResult {
+        passed: actual_cents == assertion.balance_cents,
+        actual_cents,
+        assertion,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::import::{ImportOptions, import_csv};
+    use crate::quarantine::Quarantine;
+    use crate::storage::memory::MemoryStorage;
+    use crate::testing::{account, store, store_on, transaction};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use warp::http::StatusCode;
+
+    /// Keeps what it is given in memory, but fails to append it the first `failures` times
+    struct Failing {
+        inner: MemoryStorage,
+        failures: AtomicUsize,
+        error: fn() -> StorageError,
+    }
+
+    impl Failing {
+        fn new(failures: usize, error: fn() -> StorageError) -> Arc<Self> {
+            Arc::new(Self {
+                inner: MemoryStorage::new(),
+                failures: AtomicUsize::new(failures),
+                error,
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Storage for Failing {
+        async fn load(&self) -> Result<Snapshot, StorageError> {
+            self.inner.load().await
+        }
+
+        async fn append(&self, events: &[Event]) -> Result<(), StorageError> {
+            if self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1)).is_ok() {
+                return Err((self.error)());
+            }
+            self.inner.append(events).await
+        }
+
+        async fn events(&self, after: u64, limit: usize) -> Result<Vec<LoggedEvent>, StorageError> {
+            self.inner.events(after, limit).await
+        }
+
+        async fn latest_seq(&self) -> Result<u64, StorageError> {
+            self.inner.latest_seq().await
+        }
+
+        async fn replace(&self, snapshot: &Snapshot) -> Result<(), StorageError> {
+            self.inner.replace(snapshot).await
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_new_accounts_to_whoever_creates_them() {
+        let admin = store();
+        let alice = admin.for_owner(Some("alice".to_string()));
+        let bob = admin.for_owner(Some("bob".to_string()));
+        alice.create_account(account("wallet")).unwrap();
+        alice.create_transaction(transaction("wallet", "Cafe"), false).await.unwrap();
+
+        assert_eq!(admin.snapshot().account_owners.get("wallet").map(String::as_str), Some("alice"));
+        assert!(alice.snapshot().current.contains_key("wallet"));
+        assert!(!bob.snapshot().current.contains_key("wallet"));
+        let refused = bob.create_transaction(transaction("wallet", "Bakery"), false).await.unwrap_err();
+        assert_eq!(refused.status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn leaves_accounts_nobody_owns_to_admins_until_given_away() {
+        let admin = store();
+        let alice = admin.for_owner(Some("alice".to_string()));
+        admin.create_account(account("cash")).unwrap();
+        admin.create_transaction(transaction("cash", "Cafe"), false).await.unwrap();
+
+        assert!(!alice.snapshot().current.contains_key("cash"));
+        let refused = alice.create_transaction(transaction("cash", "Bakery"), false).await.unwrap_err();
+        assert_eq!(refused.status, StatusCode::FORBIDDEN);
+
+        let grants = admin.give_account("cash", "alice").unwrap();
+        assert_eq!(grants.owner, "alice");
+        assert!(alice.snapshot().current.contains_key("cash"));
+        alice.create_transaction(transaction("cash", "Bakery"), false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_each_users_part_up_to_date_rather_than_taking_it_again() {
+        let admin = store();
+        let alice = admin.for_owner(Some("alice".to_string()));
+        alice.create_account(account("wallet")).unwrap();
+        alice.create_transaction(transaction("wallet", "Cafe"), false).await.unwrap();
+        let taken = Arc::as_ptr(&alice.read());
+
+        alice.create_transaction(transaction("wallet", "Bakery"), false).await.unwrap();
+        let part = alice.read();
+        assert_eq!(Arc::as_ptr(&part), taken);
+        let whole = admin.read_whole();
+        let fresh = whole.visible_part(&whole, "alice");
+        assert_eq!(serde_json::to_value(&*part).unwrap(), serde_json::to_value(&fresh).unwrap());
+        assert_eq!(part.revisions, fresh.revisions);
+        assert_eq!(part.payees.ranked(None, 10).len(), 2);
+        assert_eq!(fresh.payees.ranked(None, 10).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn leaves_the_state_a_reader_holds_as_it_was() {
+        let store = store();
+        store.create_account(account("wallet")).unwrap();
+        store.create_transaction(transaction("wallet", "Cafe"), false).await.unwrap();
+        let held = store.read_whole();
+
+        // The second change would go to the held state as the spare, were it not held
+        for payee in ["Bakery", "Grocer", "Florist"] {
+            store.create_transaction(transaction("wallet", payee), false).await.unwrap();
+        }
+        assert_eq!(held.current["wallet"].len(), 1);
+        let state = store.read_whole();
+        assert_eq!(state.current["wallet"].len(), 4);
+        assert_eq!(state.version, held.version + 3);
+        assert_eq!(store.etag(), store.tag(state.version));
+        assert_eq!(state.revisions.len(), 4);
+        assert!(state.revisions.values().all(|version| *version <= state.version));
+    }
+
+    #[tokio::test]
+    async fn imports_a_row_repeated_in_one_statement_as_another_occurrence() {
+        let store = store();
+        let config = Config::default();
+        let quarantine = Quarantine::new(&config.statements, None);
+        store.create_account(account("checking")).unwrap();
+        let csv = b"timestamp,payee,amount,currency
+2024-03-01T12:00:00Z,Cafe,-4.50,USD
+2024-03-01T12:00:00Z,Cafe,-4.50,USD
+2024-03-02T12:00:00Z,Bakery,-3.00,USD
+";
+        let import = || import_csv(&store, &quarantine, &config, "checking".to_string(), None, csv, ImportOptions::default());
+
+        let first = import().await.unwrap();
+        assert_eq!((first.imported, first.duplicates), (3, 0));
+        let state = store.snapshot();
+        let mut occurrences: Vec<u32> = state.current["checking"].keys().filter(|id| id.payee == "Cafe").map(|id| id.occurrence).collect();
+        occurrences.sort();
+        assert_eq!(occurrences, vec![0, 1]);
+        let uuids: HashSet<&String> = state.all["checking"].iter().map(|t| &t.uuid).collect();
+        assert_eq!((state.all["checking"].len(), uuids.len()), (3, 3));
+
+        // The same statement again brings nothing new, and records nothing again
+        let again = import().await.unwrap();
+        assert_eq!((again.imported, again.duplicates), (0, 3));
+        let state = store.snapshot();
+        assert_eq!(state.current["checking"].len(), 3);
+        assert_eq!(state.all["checking"].len(), 3);
+        assert!(integrity::check(&state, Utc::now()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn saves_changes_once_the_backend_can_be_reached_again() {
+        let storage = Failing::new(1, || std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into());
+        let store = store_on(storage.clone());
+        store.create_account(account("wallet")).unwrap();
+
+        store.flush().await;
+        assert_eq!(store.unsaved(), 1);
+        assert!(store.write_failure().is_none());
+        store.create_transaction(transaction("wallet", "Cafe"), false).await.unwrap();
+        store.flush().await;
+        assert_eq!(store.unsaved(), 0);
+        assert_eq!(storage.inner.latest_seq().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn takes_no_more_changes_once_the_backend_refuses_them_for_good() {
+        let storage = Failing::new(usize::MAX, || "UNIQUE constraint failed".into());
+        let store = store_on(storage.clone());
+        store.create_account(account("wallet")).unwrap();
+
+        store.flush().await;
+        assert!(store.write_failure().unwrap().contains("UNIQUE constraint failed"));
+        let refused = store.create_transaction(transaction("wallet", "Cafe"), false).await.unwrap_err();
+        assert_eq!(refused.status, StatusCode::SERVICE_UNAVAILABLE);
+
+        // The refused change is kept, but not tried again
+        store.flush().await;
+        assert_eq!(store.unsaved(), 1);
+        assert_eq!(storage.failures.load(Ordering::SeqCst), usize::MAX - 1);
+        assert_eq!(storage.inner.latest_seq().await.unwrap(), 0);
+    }
 }
@@ -0,0 +1,473 @@
+use crate::config::SubscriptionConfig;
+use crate::error::ApiError;
+use crate::storage::{LoggedEvent, Mutation, StorageError};
+use crate::store::TransactionStore;
+use crate::types::{CreateSubscriptionRequest, DeliveryAttempt, Subscription, SubscriptionEvent, SubscriptionResponse};
+use chrono::{TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::sync::Mutex;
+use warp::http::StatusCode;
+
+/// Events read from the log per subscriber and check
+const BATCH_SIZE: usize = 100;
+
+/// What a subscriber is POSTed for each change
+#[derive(Serialize)]
+struct Delivery<'a> {
+    subscription_id: &'a str,
+    event: SubscriptionEvent,
+    #[serde(flatten)]
+    logged: &'a LoggedEvent,
+}
+
+/// URLs told of changes to the store as they happen. Deliveries follow the
+/// event log, so each subscriber gets every change it asked for, in order,
+/// at least once: a failed delivery holds back the ones after it until it
+/// goes through, retried after waits that double with each failure.
+#[derive(Clone)]
+pub struct Subscriptions {
+    path: PathBuf,
+    client: Client,
+    targets: Targets,
+    store: TransactionStore,
+    retry_initial_secs: u64,
+    retry_max_secs: u64,
+    delivery_log_size: usize,
+    // As kept in the file, which is rewritten whenever they change
+    entries: Arc<Mutex<Vec<Subscription>>>,
+}
+
+impl Subscriptions {
+    pub async fn new(config: &SubscriptionConfig, store: TransactionStore) -> Result<Self, StorageError> {
+        let path = PathBuf::from(&config.file);
+        let entries = if path.exists() {
+            serde_json::from_slice(&fs::read(&path).await?)?
+        } else {
+            Vec::new()
+        };
+
+        let targets = Targets {
+            allowed_hosts: Arc::new(config.allowed_hosts.iter().map(|host| host.to_ascii_lowercase()).collect()),
+        };
+        Ok(Self {
+            path,
+            // A redirect could lead anywhere, so it counts as a failed delivery
+            client: Client::builder()
+                .timeout(Duration::from_secs(config.timeout_secs))
+                .dns_resolver(Arc::new(targets.clone()))
+                .redirect(Policy::none())
+                .build()?,
+            targets,
+            store,
+            retry_initial_secs: config.retry_initial_secs,
+            retry_max_secs: config.retry_max_secs,
+            delivery_log_size: config.delivery_log_size,
+            entries: Arc::new(Mutex::new(entries)),
+        })
+    }
+
+    /// Send new changes every `interval`, starting now
+    pub fn spawn_delivery(&self, interval: Duration) {
+        let subscriptions = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut seen_etag = None;
+            loop {
+                ticker.tick().await;
+                // Reading the log is the expensive part, so skip it while
+                // nothing has changed and nothing is waiting to be retried
+                let etag = subscriptions.store.etag();
+                if seen_etag.as_ref() == Some(&etag) && !subscriptions.retrying().await {
+                    continue;
+                }
+                match subscriptions.deliver().await {
+                    Ok(true) => seen_etag = Some(etag),
+                    Ok(false) => seen_etag = None,
+                    Err(e) => tracing::warn!("Failed to deliver changes to subscribers: {}", e),
+                }
+            }
+        });
+    }
+
+    /// The subscriptions `owner` registered, or every one for None
+    pub async fn list(&self, owner: Option<&str>) -> Vec<SubscriptionResponse> {
+        self.entries
+            .lock()
+            .await
+            .iter()
+            .filter(|subscription| registered_by(subscription, owner))
+            .map(SubscriptionResponse::from)
+            .collect()
+    }
+
+    /// Register a URL for `owner`, to be sent the changes made from now on
+    pub async fn create(
+        &self,
+        request: CreateSubscriptionRequest,
+        owner: Option<String>,
+    ) -> Result<SubscriptionResponse, ApiError> {
+        self.targets.check(&request.url).await.map_err(|message| ApiError {
+            message,
+            status: StatusCode::BAD_REQUEST,
+        })?;
+        if request.events.is_empty() {
+            return Err(ApiError {
+                message: "events must name at least one kind of change".to_string(),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+        let delivered_seq = self.store.revision().await.map_err(|e| ApiError {
+            message: format!("Failed to read the event log: {}", e),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+        let subscription = Subscription {
+            id: uuid::Uuid::new_v4().to_string(),
+            url: request.url,
+            events: request.events,
+            secret: request.secret,
+            owner,
+            created_at: Utc::now(),
+            delivered_seq,
+            last_error: None,
+            failures: 0,
+            retry_at: None,
+            deliveries: Vec::new(),
+        };
+        let response = SubscriptionResponse::from(&subscription);
+        let mut entries = self.entries.lock().await;
+        entries.push(subscription);
+        self.save(&entries).await.map_err(internal)?;
+        Ok(response)
+    }
+
+    pub async fn delete(&self, id: &str, owner: Option<&str>) -> Result<(), ApiError> {
+        let mut entries = self.entries.lock().await;
+        let before = entries.len();
+        entries.retain(|subscription| subscription.id != id || !registered_by(subscription, owner));
+        if entries.len() == before {
+            return Err(ApiError {
+                message: "Subscription not found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+        self.save(&entries).await.map_err(internal)
+    }
+
+    /// The latest attempts at delivering to subscription `id`, newest first
+    pub async fn deliveries(&self, id: &str, owner: Option<&str>) -> Result<Vec<DeliveryAttempt>, ApiError> {
+        let entries = self.entries.lock().await;
+        let subscription = entries
+            .iter()
+            .find(|subscription| subscription.id == id && registered_by(subscription, owner))
+            .ok_or_else(|| ApiError {
+                message: "Subscription not found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            })?;
+        Ok(subscription.deliveries.iter().rev().cloned().collect())
+    }
+
+    async fn retrying(&self) -> bool {
+        self.entries.lock().await.iter().any(|subscription| subscription.last_error.is_some())
+    }
+
+    /// Send each subscriber the events after the last it was sent, up to a
+    /// batch, stopping at its first failed delivery. Subscribers waiting to
+    /// retry a delivery are skipped until it's time. Returns whether every
+    /// subscriber that could be reached is caught up.
+    async fn deliver(&self) -> Result<bool, StorageError> {
+        let subscriptions = self.entries.lock().await.clone();
+        let mut caught_up = true;
+        for subscription in subscriptions {
+            if subscription.retry_at.is_some_and(|retry_at| retry_at > Utc::now()) {
+                continue;
+            }
+            let events = self
+                .store
+                .for_owner(subscription.owner.clone())
+                .events(subscription.delivered_seq, BATCH_SIZE)
+                .await?;
+            if events.is_empty() {
+                continue;
+            }
+
+            let mut delivered_seq = subscription.delivered_seq;
+            let mut failures = subscription.failures;
+            let mut last_error = None;
+            let mut attempts = Vec::new();
+            for logged in &events {
+                if let Some(event) = kind(&logged.event.mutation).filter(|event| subscription.events.contains(event)) {
+                    let attempt = self.send(&subscription, event, logged, failures + 1).await?;
+                    let error = attempt.error.clone();
+                    attempts.push(attempt);
+                    if let Some(error) = error {
+                        failures += 1;
+                        last_error = Some(error);
+                        break;
+                    }
+                    failures = 0;
+                }
+                delivered_seq = logged.seq;
+            }
+            caught_up &= last_error.is_some() || events.len() < BATCH_SIZE;
+
+            // It may have been deleted while its events were being sent
+            let mut entries = self.entries.lock().await;
+            if let Some(entry) = entries.iter_mut().find(|entry| entry.id == subscription.id) {
+                entry.delivered_seq = delivered_seq;
+                entry.retry_at = last_error.is_some().then(|| Utc::now() + self.backoff(failures));
+                entry.last_error = last_error;
+                entry.failures = failures;
+                entry.deliveries.extend(attempts);
+                let excess = entry.deliveries.len().saturating_sub(self.delivery_log_size);
+                entry.deliveries.drain(..excess);
+                self.save(&entries).await?;
+            }
+        }
+        Ok(caught_up)
+    }
+
+    /// How long to wait before retrying a delivery that has failed `failures` times in a row
+    fn backoff(&self, failures: u32) -> TimeDelta {
+        let doublings = failures.saturating_sub(1).min(32);
+        let secs = self.retry_initial_secs.saturating_mul(1 << doublings).min(self.retry_max_secs);
+        TimeDelta::seconds(secs as i64)
+    }
+
+    /// Try once to deliver `logged`, recording how it went
+    async fn send(
+        &self,
+        subscription: &Subscription,
+        event: SubscriptionEvent,
+        logged: &LoggedEvent,
+        attempt: u32,
+    ) -> Result<DeliveryAttempt, StorageError> {
+        let body = serde_json::to_vec(&Delivery {
+            subscription_id: &subscription.id,
+            event,
+            logged,
+        })?;
+        let mut request = self
+            .client
+            .post(&subscription.url)
+            .header("content-type", "application/json");
+        if let Some(secret) = &subscription.secret {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+            mac.update(&body);
+            let signature: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+            request = request.header("x-wdmmg-signature", format!("sha256={}", signature));
+        }
+
+        let attempted_at = Utc::now();
+        let started = Instant::now();
+        // Also refuses subscriptions registered before their address was checked
+        let sent = match self.targets.check(&subscription.url).await {
+            Ok(()) => request.body(body).send().await.map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        let (status, error) = match sent {
+            Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                Some(format!("{} answered {}", subscription.url, response.status())),
+            ),
+            Err(e) => (None, Some(e)),
+        };
+        Ok(DeliveryAttempt {
+            seq: logged.seq,
+            event,
+            attempted_at,
+            attempt,
+            status,
+            error,
+            duration_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    async fn save(&self, entries: &[Subscription]) -> Result<(), StorageError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(entries)?).await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+/// Where subscriptions may send changes: public addresses, and loopback,
+/// private or link-local ones only of the hosts configured as allowed. It is
+/// also the delivery client's resolver, so a name that resolves elsewhere
+/// by the time of delivery is refused then.
+#[derive(Clone)]
+struct Targets {
+    allowed_hosts: Arc<HashSet<String>>,
+}
+
+impl Targets {
+    /// Refuse `url` unless it is http(s) and its host may be sent to
+    async fn check(&self, url: &str) -> Result<(), String> {
+        let url = Url::parse(url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or("url must be an http:// or https:// URL")?;
+        let host = url.host_str().ok_or("url must name a host")?;
+        // IP addresses are connected to as they are, without the resolver
+        let literal = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>();
+        let addresses = match literal {
+            Ok(ip) => vec![ip],
+            Err(_) => tokio::net::lookup_host((host, url.port_or_known_default().unwrap_or(0)))
+                .await
+                .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+                .map(|address| address.ip())
+                .collect(),
+        };
+        self.refuse_internal(host, addresses.into_iter())
+    }
+
+    fn refuse_internal(&self, host: &str, mut addresses: impl Iterator<Item = IpAddr>) -> Result<(), String> {
+        if self.allowed_hosts.contains(&host.to_ascii_lowercase()) {
+            return Ok(());
+        }
+        let Some(ip) = addresses.find(|ip| is_internal(*ip)) else {
+            return Ok(());
+        };
+        let what = if host.trim_start_matches('[').trim_end_matches(']') == ip.to_string() {
+            host.to_string()
+        } else {
+            format!("{} is at {}, which", host, ip)
+        };
+        Err(format!(
+            "{} is a loopback, private or link-local address; add it to subscriptions.allowed_hosts to send to it",
+            what
+        ))
+    }
+}
+
+impl Resolve for Targets {
+    fn resolve(&self, name: Name) -> Resolving {
+        let targets = self.clone();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            targets.refuse_internal(name.as_str(), addresses.iter().map(SocketAddr::ip))?;
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `ip` is somewhere other than the public internet: this machine,
+/// a private or link-local network, or not a single host at all
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || first == 0
+                // Shared by carrier-grade NAT, 100.64.0.0/10
+                || (first == 100 && second & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+            }
+        },
+    }
+}
+
+/// Which kind of change a mutation is to subscribers, if any
+fn kind(mutation: &Mutation) -> Option<SubscriptionEvent> {
+    match mutation {
+        Mutation::Created { .. } => Some(SubscriptionEvent::Created),
+        Mutation::Imported { .. } => Some(SubscriptionEvent::Imported),
+        Mutation::MemoUpdated { .. } | Mutation::MetadataUpdated { .. } | Mutation::Edited { .. } => {
+            Some(SubscriptionEvent::Edited)
+        }
+        Mutation::Deleted { .. } => Some(SubscriptionEvent::Deleted),
+        Mutation::BalanceAsserted { .. }
+        | Mutation::ImportRecorded { .. }
+        | Mutation::StatementAttached { .. }
+        | Mutation::ImportCorrected { .. }
+        | Mutation::MonthClosed { .. }
+        | Mutation::AccountCreated { .. }
+        | Mutation::AccountUpdated { .. }
+        | Mutation::AccountOwned { .. }
+        | Mutation::AccessGranted { .. }
+        | Mutation::AccessRevoked { .. }
+        | Mutation::AccountsMerged { .. } => None,
+    }
+}
+
+/// Whether `owner` registered `subscription`, as every one counts as for None
+fn registered_by(subscription: &Subscription, owner: Option<&str>) -> bool {
+    owner.is_none_or(|owner| subscription.owner.as_deref() == Some(owner))
+}
+
+fn internal(e: StorageError) -> ApiError {
+    ApiError {
+        message: format!("Failed to save subscriptions: {}", e),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(allowed_hosts: &[&str]) -> Targets {
+        Targets {
+            allowed_hosts: Arc::new(allowed_hosts.iter().map(|host| host.to_string()).collect()),
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_urls_at_loopback_private_and_link_local_addresses() {
+        let targets = targets(&[]);
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8080/hook",
+            "http://[::1]/hook",
+            "http://10.1.2.3/hook",
+            "https://192.168.1.20/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:172.16.0.1]/hook",
+            "http://100.64.0.1/hook",
+            "http://0.0.0.0/hook",
+        ] {
+            let refused = targets.check(url).await.unwrap_err();
+            assert!(refused.contains("subscriptions.allowed_hosts"), "{}: {}", url, refused);
+        }
+        assert!(targets.check("ftp://198.51.100.7/hook").await.is_err());
+        targets.check("https://93.184.216.34/hook").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_to_private_hosts_only_once_allowed() {
+        let targets = targets(&["localhost", "192.168.1.20"]);
+        targets.check("http://localhost:8080/hook").await.unwrap();
+        targets.check("http://192.168.1.20/hook").await.unwrap();
+        assert!(targets.check("http://192.168.1.21/hook").await.is_err());
+    }
+}
@@ -0,0 +1,46 @@
+// Fixtures the unit tests share
+
+use crate::storage::Storage;
+use crate::storage::memory::MemoryStorage;
+use crate::store::TransactionStore;
+use crate::types::{CreateAccountRequest, CreateTransactionRequest, Metadata};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A path in the temp directory for the test `name`, with nothing left at
+/// it from an earlier run
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("wdmmg-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&path);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// A store on `storage`, with no interest rates or account groups configured
+pub fn store_on(storage: Arc<dyn Storage>) -> TransactionStore {
+    TransactionStore::new(storage, HashMap::new(), HashMap::new(), false)
+}
+
+/// A store that keeps everything in memory
+pub fn store() -> TransactionStore {
+    store_on(Arc::new(MemoryStorage::new()))
+}
+
+pub fn account(account_id: &str) -> CreateAccountRequest {
+    serde_json::from_value(serde_json::json!({ "account_id": account_id, "display_name": account_id })).unwrap()
+}
+
+/// A purchase of 4.50 USD at `payee`
+pub fn transaction(account_id: &str, payee: &str) -> CreateTransactionRequest {
+    CreateTransactionRequest {
+        account_id: account_id.to_string(),
+        timestamp: "2024-03-01T12:00:00Z".parse().unwrap(),
+        payee: payee.to_string(),
+        amount: -4.5,
+        currency: "USD".to_string(),
+        allow_duplicate: false,
+        foreign_currency: false,
+        metadata: Metadata::new(),
+    }
+}
@@ -0,0 +1,357 @@
+use crate::config::TextEntryConfig;
+use chrono::{Datelike, NaiveDate, TimeDelta, Weekday};
+
+/// Currency codes recognised next to an amount whatever their case. Others
+/// are only taken in capitals after the amount, as in `1200 THB`, so words
+/// and merchants such as `CVS 12.50` aren't taken for them.
+const COMMON_CURRENCIES: &[&str] = &[
+    "aud", "brl", "cad", "chf", "cny", "czk", "dkk", "eur", "gbp", "hkd", "huf", "inr", "jpy", "krw", "mxn", "nok",
+    "nzd", "pln", "sek", "sgd", "try", "usd", "zar",
+];
+
+const CURRENCY_SYMBOLS: &[(char, &str)] = &[('$', "USD"), ('€', "EUR"), ('£', "GBP"), ('¥', "JPY"), ('₹', "INR")];
+
+/// Words saying money came in rather than went out
+const INCOME_WORDS: &[&str] = &[
+    "deposit", "earned", "got", "income", "received", "refund", "refunded", "reimbursed", "reimbursement", "salary",
+];
+
+/// Words before the payee
+const PAYEE_MARKERS: &[&str] = &["at", "@", "from", "to"];
+
+/// Words that say nothing about the transaction, left out of a payee made
+/// up of the words left over
+const FILLER_WORDS: &[&str] = &["a", "an", "bought", "for", "on", "paid", "spent", "the"];
+
+/// What a line such as `lunch 14.20 at Pho King yesterday` says
+#[derive(Debug)]
+pub struct ParsedText {
+    /// Negative for money spent, as on a statement
+    pub amount: f64,
+    /// None when the text names no currency
+    pub currency: Option<String>,
+    pub payee: String,
+    /// None when the text has no date in it
+    pub date: Option<NaiveDate>,
+    pub category: Option<String>,
+    /// Words nothing above was read from
+    pub ignored: Vec<String>,
+}
+
+struct Word<'a> {
+    original: &'a str,
+    lower: String,
+    used: bool,
+}
+
+/// Read `text`, relative dates counting back from `today`
+pub fn parse(text: &str, today: NaiveDate, config: &TextEntryConfig) -> Result<ParsedText, String> {
+    let mut words: Vec<Word> = text
+        .split_whitespace()
+        .map(|word| word.trim_end_matches([',', '.', '!', '?', ';', ':']))
+        .filter(|word| !word.is_empty())
+        .map(|original| Word {
+            original,
+            lower: original.to_lowercase(),
+            used: false,
+        })
+        .collect();
+
+    let date = take_date(&mut words, today);
+    let (amount, currency) = take_amount(&mut words).ok_or("No amount found in the text")?;
+    let income = words.iter().any(|word| INCOME_WORDS.contains(&word.lower.as_str()));
+    let amount = match amount {
+        Amount::Signed(amount) => amount,
+        Amount::Unsigned(amount) if income => amount,
+        Amount::Unsigned(amount) => -amount,
+    };
+
+    let category = category(config, &words);
+    let payee = take_payee(&mut words).ok_or("No payee found in the text; name one after \"at\"")?;
+
+    let ignored = words
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| category.as_ref().is_none_or(|(word, _)| word != i))
+        .map(|(_, word)| word)
+        .filter(|word| !word.used && !FILLER_WORDS.contains(&word.lower.as_str()))
+        .filter(|word| !INCOME_WORDS.contains(&word.lower.as_str()))
+        .map(|word| word.original.to_string())
+        .collect();
+    Ok(ParsedText {
+        amount,
+        currency,
+        payee,
+        date,
+        category: category.map(|(_, category)| category),
+        ignored,
+    })
+}
+
+/// The first date phrase: `today`, `yesterday`, `day before yesterday`, `3
+/// days ago`, `a week ago`, `last week`, a weekday, optionally after `last`,
+/// or a date such as `2024-03-05`
+fn take_date(words: &mut [Word], today: NaiveDate) -> Option<NaiveDate> {
+    for start in 0..words.len() {
+        let phrase: Vec<&str> = words[start..].iter().take(3).map(|word| word.lower.as_str()).collect();
+        let (date, len) = match phrase.as_slice() {
+            ["day", "before", "yesterday", ..] => (today - TimeDelta::days(2), 3),
+            ["today", ..] => (today, 1),
+            ["yesterday", ..] => (today - TimeDelta::days(1), 1),
+            ["last", "week", ..] => (today - TimeDelta::weeks(1), 2),
+            [count, unit, "ago", ..] => {
+                let count: i64 = match *count {
+                    "a" | "an" | "one" => 1,
+                    count => match count.parse() {
+                        Ok(count) if count <= 3660 => count,
+                        _ => continue,
+                    },
+                };
+                let days = match unit.trim_end_matches('s') {
+                    "day" => count,
+                    "week" => count * 7,
+                    _ => continue,
+                };
+                (today - TimeDelta::days(days), 3)
+            }
+            ["last", day, ..] if weekday(day).is_some() => (last_weekday(today, weekday(day)?, false), 2),
+            [day, ..] if weekday(day).is_some() => (last_weekday(today, weekday(day)?, true), 1),
+            [date, ..] => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                Ok(date) => (date, 1),
+                Err(_) => continue,
+            },
+            [] => continue,
+        };
+        // `on monday`, `on 2024-03-05`
+        let first = if start > 0 && words[start - 1].lower == "on" { start - 1 } else { start };
+        for word in &mut words[first..start + len] {
+            word.used = true;
+        }
+        return Some(date);
+    }
+    None
+}
+
+fn weekday(word: &str) -> Option<Weekday> {
+    let day = word.parse::<Weekday>().ok()?;
+    // chrono takes `mon` as well; only whole names are dates here
+    (word.len() > 3).then_some(day)
+}
+
+/// The latest `day` before `today`, or on it when `including_today`
+fn last_weekday(today: NaiveDate, day: Weekday, including_today: bool) -> NaiveDate {
+    let mut back = (7 + today.weekday().num_days_from_monday() - day.num_days_from_monday()) % 7;
+    if back == 0 && !including_today {
+        back = 7;
+    }
+    today - TimeDelta::days(i64::from(back))
+}
+
+enum Amount {
+    /// Written with a sign, which says which way it went
+    Signed(f64),
+    Unsigned(f64),
+}
+
+/// The amount most likely meant, with the currency written by it: the first
+/// that has a currency or decimals, or else the first number of all
+fn take_amount(words: &mut [Word]) -> Option<(Amount, Option<String>)> {
+    let mut fallback = None;
+    for index in 0..words.len() {
+        if words[index].used {
+            continue;
+        }
+        let Some((amount, symbol)) = read_amount(words[index].original) else {
+            continue;
+        };
+        let code = [(index.checked_sub(1), false), (Some(index + 1), true)]
+            .into_iter()
+            .find_map(|(i, after)| {
+                let word = words.get(i?).filter(|word| !word.used)?;
+                Some((i?, currency_code(word.original, after)?))
+            });
+        let currency = symbol.map(str::to_string).or_else(|| code.as_ref().map(|(_, code)| code.clone()));
+        let marked = currency.is_some() || words[index].original.contains(['.', ',']);
+        if marked || fallback.is_none() {
+            fallback = Some((index, code, amount, currency));
+        }
+        if marked {
+            break;
+        }
+    }
+
+    let (index, code, amount, currency) = fallback?;
+    words[index].used = true;
+    if let Some((code, _)) = code {
+        words[code].used = true;
+    }
+    Some((amount, currency))
+}
+
+/// A number such as `14.20`, `-5`, `+1,200.50`, `€3,50` or `12$`, with the
+/// currency of its symbol
+fn read_amount(word: &str) -> Option<(Amount, Option<&'static str>)> {
+    let (sign, rest) = match word.chars().next()? {
+        '-' => (Some(-1.0), &word[1..]),
+        '+' => (Some(1.0), &word[1..]),
+        _ => (None, word),
+    };
+    let mut symbol = None;
+    let mut rest = rest;
+    for &(character, code) in CURRENCY_SYMBOLS {
+        if let Some(stripped) = rest.strip_prefix(character).or_else(|| rest.strip_suffix(character)) {
+            symbol = Some(code);
+            rest = stripped;
+        }
+    }
+    if rest.is_empty() || !rest.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    // The last separator is the decimal point when two digits or fewer
+    // follow it, as in 3,50; otherwise separators group thousands
+    let decimal = rest
+        .rfind(['.', ','])
+        .filter(|&at| rest.len() - at - 1 <= 2);
+    let number: String = rest
+        .char_indices()
+        .filter_map(|(at, c)| match c {
+            '.' | ',' if Some(at) == decimal => Some('.'),
+            '.' | ',' => None,
+            c => Some(c),
+        })
+        .collect();
+    if !number.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    let amount: f64 = number.parse().ok()?;
+    let amount = match sign {
+        Some(sign) => Amount::Signed(sign * amount),
+        None => Amount::Unsigned(amount),
+    };
+    Some((amount, symbol))
+}
+
+/// `word` as a currency code, when it is one next to an amount, `after` it or before
+fn currency_code(word: &str, after: bool) -> Option<String> {
+    let is_code = word.len() == 3 && word.chars().all(|c| c.is_ascii_alphabetic());
+    let known = COMMON_CURRENCIES.contains(&word.to_lowercase().as_str());
+    let capitals = after && word.chars().all(|c| c.is_ascii_uppercase());
+    (is_code && (known || capitals)).then(|| word.to_uppercase())
+}
+
+/// The category of the first word that is one of its keywords, or their
+/// plural, and where that word is
+fn category(config: &TextEntryConfig, words: &[Word]) -> Option<(usize, String)> {
+    let mut categories: Vec<_> = config.categories.iter().collect();
+    // Keywords in more than one category pick the same one every time
+    categories.sort_by_key(|(name, _)| *name);
+    words.iter().enumerate().filter(|(_, word)| !word.used).find_map(|(i, word)| {
+        let singular = word.lower.strip_suffix('s').unwrap_or(&word.lower);
+        categories
+            .iter()
+            .find(|(_, keywords)| {
+                keywords.iter().map(|keyword| keyword.to_lowercase()).any(|keyword| keyword == word.lower || keyword == singular)
+            })
+            .map(|(name, _)| (i, name.to_string()))
+    })
+}
+
+/// The words after `at` up to the next one already read, or else every word
+/// left that says something
+fn take_payee(words: &mut [Word]) -> Option<String> {
+    let marker = words
+        .iter()
+        .position(|word| !word.used && PAYEE_MARKERS.contains(&word.lower.as_str()));
+    let indices: Vec<usize> = match marker {
+        Some(marker) => (marker + 1..words.len()).take_while(|&i| !words[i].used).collect(),
+        None => (0..words.len())
+            .filter(|&i| !words[i].used && !FILLER_WORDS.contains(&words[i].lower.as_str()))
+            .filter(|&i| !INCOME_WORDS.contains(&words[i].lower.as_str()))
+            .collect(),
+    };
+    if indices.is_empty() {
+        return None;
+    }
+    if let Some(marker) = marker {
+        words[marker].used = true;
+    }
+    let payee: Vec<&str> = indices.iter().map(|&i| words[i].original).collect();
+    for i in indices {
+        words[i].used = true;
+    }
+    Some(payee.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Wednesday
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, 6).unwrap()
+    }
+
+    fn read(text: &str) -> ParsedText {
+        parse(text, today(), &TextEntryConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn reads_amount_payee_date_and_category() {
+        let parsed = read("lunch 14.20 at Pho King yesterday");
+        assert_eq!(parsed.amount, -14.2);
+        assert_eq!(parsed.currency, None);
+        assert_eq!(parsed.payee, "Pho King");
+        assert_eq!(parsed.date, NaiveDate::from_ymd_opt(2024, 3, 5));
+        assert_eq!(parsed.category.as_deref(), Some("dining"));
+        assert!(parsed.ignored.is_empty());
+    }
+
+    #[test]
+    fn counts_income_and_signed_amounts_as_money_coming_in() {
+        let refund = read("got refund 25 EUR from Amazon on 2024-03-01");
+        assert_eq!(refund.amount, 25.0);
+        assert_eq!(refund.currency.as_deref(), Some("EUR"));
+        assert_eq!(refund.payee, "Amazon");
+        assert_eq!(refund.date, NaiveDate::from_ymd_opt(2024, 3, 1));
+        assert!(refund.ignored.is_empty());
+
+        let salary = read("+1,200.50 from ACME");
+        assert_eq!(salary.amount, 1200.5);
+        assert_eq!(salary.payee, "ACME");
+    }
+
+    #[test]
+    fn reads_currency_symbols_codes_and_decimal_commas() {
+        let cafe = read("€3,50 at Bäckerei");
+        assert_eq!(cafe.amount, -3.5);
+        assert_eq!(cafe.currency.as_deref(), Some("EUR"));
+
+        let hotel = read("1200 THB at Hotel");
+        assert_eq!(hotel.amount, -1200.0);
+        assert_eq!(hotel.currency.as_deref(), Some("THB"));
+
+        // Only common codes are taken before an amount
+        let pharmacy = read("CVS 12.50");
+        assert_eq!(pharmacy.currency, None);
+        assert_eq!(pharmacy.payee, "CVS");
+    }
+
+    #[test]
+    fn counts_relative_dates_back_from_today() {
+        let date = |text: &str| read(&format!("5 at Cafe {}", text)).date.unwrap();
+        assert_eq!(date("3 days ago"), NaiveDate::from_ymd_opt(2024, 3, 3).unwrap());
+        assert_eq!(date("a week ago"), NaiveDate::from_ymd_opt(2024, 2, 28).unwrap());
+        assert_eq!(date("day before yesterday"), NaiveDate::from_ymd_opt(2024, 3, 4).unwrap());
+        assert_eq!(date("last monday"), NaiveDate::from_ymd_opt(2024, 3, 4).unwrap());
+        assert_eq!(date("wednesday"), today());
+        assert_eq!(date("last wednesday"), NaiveDate::from_ymd_opt(2024, 2, 28).unwrap());
+    }
+
+    #[test]
+    fn says_what_is_missing() {
+        let error = |text: &str| parse(text, today(), &TextEntryConfig::default()).unwrap_err();
+        assert_eq!(error("lunch at Cafe"), "No amount found in the text");
+        assert_eq!(error("12.50 yesterday"), "No payee found in the text; name one after \"at\"");
+    }
+}
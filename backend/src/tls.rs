@@ -0,0 +1,219 @@
+use crate::config::TlsConfig;
+use crate::storage::StorageError;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Datelike, TimeDelta, Utc};
+use futures_util::Stream;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair};
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::server::TlsStream;
+
+/// How long a client may take over the handshake before it is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshaken connections waiting for the server to take them
+const ACCEPT_BACKLOG: usize = 64;
+
+/// How long a generated certificate is valid for
+const SELF_SIGNED_VALIDITY_DAYS: i64 = 10 * 365;
+
+/// Load the configured certificate and key, generating a self-signed pair
+/// first when that is asked for and there is none yet
+pub async fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, StorageError> {
+    let (cert_path, key_path) = (Path::new(&config.cert_path), Path::new(&config.key_path));
+    if config.self_signed && !cert_path.exists() && !key_path.exists() {
+        let (cert, key) = self_signed(&config.hostnames)?;
+        // Only the server's user may read the key
+        for (path, pem, mode) in [
+            (key_path, pem("PRIVATE KEY", &key), 0o600),
+            (cert_path, pem("CERTIFICATE", &cert), 0o644),
+        ] {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).await?;
+            }
+            let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(mode).open(path).await?;
+            file.write_all(pem.as_bytes()).await?;
+        }
+        tracing::info!(
+            "Generated a self-signed certificate for {} in {}",
+            config.hostnames.join(", "),
+            config.cert_path
+        );
+    }
+
+    let certs = CertificateDer::pem_slice_iter(&fs::read(cert_path).await?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read {}: {}", config.cert_path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{} holds no certificate", config.cert_path).into());
+    }
+    let key = PrivateKeyDer::from_pem_slice(&fs::read(key_path).await?)
+        .map_err(|e| format!("Failed to read {}: {}", config.key_path, e))?;
+
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Connections to `listener` once their handshake is done. Handshakes go on
+/// alongside each other, so a slow client doesn't hold up the rest.
+pub fn incoming(listener: TcpListener, acceptor: TlsAcceptor) -> impl Stream<Item = io::Result<TlsStream<TcpStream>>> {
+    let (sender, receiver) = mpsc::channel(ACCEPT_BACKLOG);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("Failed to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                // Clients that give up on an untrusted certificate fail here, which isn't worth logging
+                if let Ok(Ok(stream)) = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    let _ = sender.send(Ok(stream)).await;
+                }
+            });
+        }
+    });
+    futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|stream| (stream, receiver))
+    })
+}
+
+/// A certificate for `hostnames`, signed by its own key, in DER, and the key as PKCS#8
+fn self_signed(hostnames: &[String]) -> Result<(Vec<u8>, Vec<u8>), StorageError> {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)?;
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)?;
+
+    let mut serial = [0u8; 16];
+    rng.fill(&mut serial)?;
+    // Positive, and without a leading zero byte DER would have to drop
+    serial[0] = serial[0] & 0x7f | 0x40;
+
+    let common_name = hostnames.first().map_or("wdmmg", String::as_str);
+    let name = der::sequence(&[der::set(&[der::sequence(&[
+        der::COMMON_NAME.to_vec(),
+        der::tlv(der::UTF8_STRING, common_name.as_bytes()),
+    ])])]);
+    let now = Utc::now();
+    let alt_names: Vec<_> = hostnames
+        .iter()
+        .map(|hostname| match hostname.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => der::tlv(der::IP_ADDRESS, &ip.octets()),
+            Ok(IpAddr::V6(ip)) => der::tlv(der::IP_ADDRESS, &ip.octets()),
+            Err(_) => der::tlv(der::DNS_NAME, hostname.as_bytes()),
+        })
+        .collect();
+
+    let tbs = der::sequence(&[
+        der::tlv(der::VERSION, &der::tlv(der::INTEGER, &[2])),
+        der::tlv(der::INTEGER, &serial),
+        der::sequence(&[der::ECDSA_WITH_SHA256.to_vec()]),
+        name.clone(),
+        der::sequence(&[der::time(now - TimeDelta::days(1)), der::time(now + TimeDelta::days(SELF_SIGNED_VALIDITY_DAYS))]),
+        name,
+        der::sequence(&[
+            der::sequence(&[der::EC_PUBLIC_KEY.to_vec(), der::PRIME256V1.to_vec()]),
+            der::bit_string(key_pair.public_key().as_ref()),
+        ]),
+        der::tlv(
+            der::EXTENSIONS,
+            &der::sequence(&[der::sequence(&[
+                der::SUBJECT_ALT_NAME.to_vec(),
+                der::tlv(der::OCTET_STRING, &der::sequence(&alt_names)),
+            ])]),
+        ),
+    ]);
+    let signature = key_pair.sign(&rng, &tbs)?;
+    let cert = der::sequence(&[
+        tbs,
+        der::sequence(&[der::ECDSA_WITH_SHA256.to_vec()]),
+        der::bit_string(signature.as_ref()),
+    ]);
+    Ok((cert, pkcs8.as_ref().to_vec()))
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let lines: Vec<_> = encoded.as_bytes().chunks(64).map(|line| String::from_utf8_lossy(line)).collect();
+    format!("-----BEGIN {label}-----\n{}\n-----END {label}-----\n", lines.join("\n"))
+}
+
+/// Just enough DER to write a self-signed certificate
+mod der {
+    use super::*;
+
+    pub const INTEGER: u8 = 0x02;
+    pub const OCTET_STRING: u8 = 0x04;
+    pub const UTF8_STRING: u8 = 0x0c;
+    /// Explicitly tagged fields of the certificate
+    pub const VERSION: u8 = 0xa0;
+    pub const EXTENSIONS: u8 = 0xa3;
+    /// Kinds of subject alternative name
+    pub const DNS_NAME: u8 = 0x82;
+    pub const IP_ADDRESS: u8 = 0x87;
+
+    /// Object identifiers, encoded
+    pub const ECDSA_WITH_SHA256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+    pub const EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+    pub const PRIME256V1: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+    pub const COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+    pub const SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x11];
+
+    pub fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        let len = content.len();
+        if len < 0x80 {
+            out.push(len as u8);
+        } else {
+            let bytes: Vec<u8> = len.to_be_bytes().into_iter().skip_while(|&byte| byte == 0).collect();
+            out.push(0x80 | bytes.len() as u8);
+            out.extend(bytes);
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    pub fn sequence(items: &[Vec<u8>]) -> Vec<u8> {
+        tlv(0x30, &items.concat())
+    }
+
+    pub fn set(items: &[Vec<u8>]) -> Vec<u8> {
+        tlv(0x31, &items.concat())
+    }
+
+    pub fn bit_string(bytes: &[u8]) -> Vec<u8> {
+        // No unused bits in the last byte
+        tlv(0x03, &[&[0], bytes].concat())
+    }
+
+    /// UTCTime up to 2049, as X.509 requires, and GeneralizedTime after
+    pub fn time(at: DateTime<Utc>) -> Vec<u8> {
+        if at.year() < 2050 {
+            tlv(0x17, at.format("%y%m%d%H%M%SZ").to_string().as_bytes())
+        } else {
+            tlv(0x18, at.format("%Y%m%d%H%M%SZ").to_string().as_bytes())
+        }
+    }
+}
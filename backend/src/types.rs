@@ -1,5 +1,9 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
+use crate::config::{AmountPrecision, Month};
+use crate::currency::round_half_even;
+use crate::import::ImportProfile;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct TransactionId {
@@ -7,33 +11,189 @@ pub struct TransactionId {
     pub amount_cents: i64, // Store amount in cents to avoid floating point comparison issues
     pub currency: String,
     pub payee: String,
+    /// Tells apart identical transactions created with `allow_duplicate`; 0 for the first
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub occurrence: u32,
+}
+
+fn is_zero(occurrence: &u32) -> bool {
+    *occurrence == 0
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CurrentTransaction {
     pub account_id: String,
+    /// Stable, opaque id the server gives each transaction. Unlike `id` it
+    /// survives edits, and it is shared by every record of the transaction.
+    /// Empty only in data persisted before ids were given, until the store
+    /// loads it.
+    #[serde(default)]
+    pub uuid: String,
     pub id: TransactionId,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HistoricalTransaction {
     pub account_id: String,
+    #[serde(default)]
+    pub uuid: String,
     pub id: TransactionId,
     pub memo: Option<String>,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
 }
 
-#[derive(Debug, Deserialize)]
+/// Data integrations attach to a transaction, such as an invoice number or order id
+pub type Metadata = BTreeMap<String, serde_json::Value>;
+
+/// Which part of a listing to return, from `limit` and `offset` query parameters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Page {
+    pub offset: usize,
+    /// Everything after `offset` when not set
+    pub limit: Option<usize>,
+}
+
+impl Page {
+    pub fn slice<'a, T>(&self, items: &'a [T]) -> &'a [T] {
+        let start = self.offset.min(items.len());
+        let end = match self.limit {
+            Some(limit) => start.saturating_add(limit).min(items.len()),
+            None => items.len(),
+        };
+        &items[start..end]
+    }
+}
+
+/// An earlier moment to show the store as it was at, from the `as_of_revision`
+/// or `as_of_date` query parameter
+#[derive(Debug, Clone, Copy)]
+pub enum AsOf {
+    /// Just after the event at this position in the log
+    Revision(u64),
+    /// Just after the last event recorded at or before this instant
+    Date(DateTime<Utc>),
+}
+
+/// Which transactions a listing includes; every condition that is set must hold
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    pub account_id: Option<String>,
+    /// Inclusive bounds on the timestamp
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Lowercase text the payee must contain, ignoring case
+    pub payee: Option<String>,
+    pub currency: Option<String>,
+    /// Inclusive bounds on the amount
+    pub min_amount_cents: Option<i64>,
+    pub max_amount_cents: Option<i64>,
+    /// Values metadata keys must have. A string value matches its text,
+    /// anything else its JSON.
+    pub metadata: Vec<(String, String)>,
+}
+
+impl TransactionFilter {
+    pub fn includes_account(&self, account_id: &str) -> bool {
+        self.account_id.as_deref().is_none_or(|wanted| wanted == account_id)
+    }
+
+    pub fn matches(&self, id: &TransactionId) -> bool {
+        self.from.is_none_or(|from| id.timestamp >= from)
+            && self.to.is_none_or(|to| id.timestamp <= to)
+            && self.payee.as_deref().is_none_or(|payee| id.payee.to_lowercase().contains(payee))
+            && self.currency.as_deref().is_none_or(|currency| id.currency == currency)
+            && self.min_amount_cents.is_none_or(|min| id.amount_cents >= min)
+            && self.max_amount_cents.is_none_or(|max| id.amount_cents <= max)
+    }
+
+    pub fn matches_metadata(&self, metadata: &Metadata) -> bool {
+        self.metadata.iter().all(|(key, wanted)| match metadata.get(key) {
+            Some(serde_json::Value::String(value)) => value == wanted,
+            Some(value) => serde_json::from_str::<serde_json::Value>(wanted).is_ok_and(|wanted| *value == wanted),
+            None => false,
+        })
+    }
+}
+
+/// Order of a transaction listing, from `sort=<field>` for ascending or
+/// `sort=-<field>` for descending
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionSort {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Timestamp,
+    Amount,
+    Payee,
+}
+
+impl Default for TransactionSort {
+    /// Newest first
+    fn default() -> Self {
+        Self {
+            field: SortField::Timestamp,
+            descending: true,
+        }
+    }
+}
+
+impl TransactionSort {
+    pub fn compare(&self, a: &TransactionId, b: &TransactionId) -> std::cmp::Ordering {
+        let ordering = match self.field {
+            SortField::Timestamp => a.timestamp.cmp(&b.timestamp),
+            SortField::Amount => a.amount_cents.cmp(&b.amount_cents),
+            SortField::Payee => a.payee.cmp(&b.payee),
+        };
+        if self.descending { ordering.reverse() } else { ordering }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CreateTransactionRequest {
     pub account_id: String,
     pub timestamp: DateTime<Utc>,
     pub payee: String,
     pub amount: f64,
     pub currency: String,
+    /// Record an identical transaction as a further occurrence instead of refusing it
+    #[serde(default)]
+    pub allow_duplicate: bool,
+    /// Record it even though its currency isn't the account's
+    #[serde(default)]
+    pub foreign_currency: bool,
+    #[serde(default)]
+    pub metadata: Metadata,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct CsvTransaction {
-    pub timestamp: String, // Will be parsed into DateTime<Utc>
+pub struct ParseTextRequest {
+    /// A line such as `lunch 14.20 at Pho King yesterday`
+    pub text: String,
+    /// The account the draft is for; the configured one when absent
+    pub account_id: Option<String>,
+    /// The currency of an amount written without one; the configured one when absent
+    pub currency: Option<String>,
+    /// The client's date, which words such as `yesterday` count back from;
+    /// the server's UTC date when absent
+    pub today: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParseTextResponse {
+    /// What the text says, to confirm and then send to `POST /transactions`
+    pub draft: CreateTransactionRequest,
+    /// Words nothing in the draft was read from
+    pub ignored: Vec<String>,
+}
+
+/// One transaction as read from an imported file, before it is validated
+#[derive(Debug)]
+pub struct RawTransaction {
+    pub timestamp: DateTime<Utc>,
     pub payee: String,
     pub amount: f64,
     pub currency: String,
@@ -44,9 +204,995 @@ pub struct UpdateMemoRequest {
     pub memo: Option<String>,
 }
 
+/// One entry of `PUT /transactions/memos`, naming the transaction by its id
+/// as listings return it
+#[derive(Debug, Deserialize)]
+pub struct MemoUpdate {
+    pub account_id: String,
+    pub id: TransactionId,
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateMemosRequest {
+    pub memos: Vec<MemoUpdate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpdateMemosResponse {
+    pub updated: usize,
+}
+
+/// Keys to set on a transaction's metadata; a null value removes the key.
+/// Keys left out keep their value.
+#[derive(Debug, Deserialize)]
+pub struct UpdateMetadataRequest {
+    pub metadata: Metadata,
+}
+
+/// Corrections to a transaction; fields left out keep their value
+#[derive(Debug, Deserialize)]
+pub struct EditTransactionRequest {
+    pub timestamp: Option<DateTime<Utc>>,
+    pub payee: Option<String>,
+    pub amount: Option<f64>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct BulkImportResponse {
+    /// What to attach the statement file to with `PUT /imports/:id/source`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub import_id: Option<String>,
     pub imported: usize,
     pub duplicates: usize,
     pub errors: Vec<String>,
-}
\ No newline at end of file
+}
+
+/// A row of an import that couldn't be read, kept until a corrected version
+/// of it is submitted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedRow {
+    /// Where the row is in the file, as in the import's errors
+    pub position: String,
+    pub error: String,
+    /// The row's values by column header, empty when the row itself couldn't be split
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportFailuresResponse {
+    pub import_id: String,
+    pub account_id: String,
+    pub rows: Vec<QuarantinedRow>,
+}
+
+/// A quarantined row as it should have been read, named by its position
+#[derive(Debug, Deserialize)]
+pub struct CorrectedRow {
+    pub position: String,
+    pub timestamp: DateTime<Utc>,
+    pub payee: String,
+    pub amount: f64,
+    pub currency: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReprocessImportRequest {
+    pub rows: Vec<CorrectedRow>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertBalanceRequest {
+    pub date: NaiveDate,
+    pub balance: f64,
+    pub currency: String,
+}
+
+/// Expected balance of one currency in an account at the start of `date`,
+/// i.e. the sum of all current transactions before that day
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceAssertion {
+    pub account_id: String,
+    pub date: NaiveDate,
+    pub balance_cents: i64,
+    pub currency: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceAssertionResult {
+    pub assertion: BalanceAssertion,
+    pub actual_cents: i64,
+    pub passed: bool,
+}
+
+/// An account whose balance in one currency is below its configured threshold
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LowBalance {
+    pub account_id: String,
+    pub currency: String,
+    pub balance_cents: i64,
+    pub threshold_cents: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountBalance {
+    pub currency: String,
+    pub balance_cents: i64,
+}
+
+/// An account and its balances. Accounts with transactions from before
+/// accounts were created with `POST /accounts` have no details.
+#[derive(Debug, Serialize)]
+pub struct AccountSummary {
+    pub account_id: String,
+    #[serde(flatten)]
+    pub details: Option<AccountDetails>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Liability for credit cards and loans, whose negative balances are
+    /// owed; asset for the rest, including accounts with no type
+    pub class: AccountClass,
+    pub current_transactions: usize,
+    pub balances: Vec<AccountBalance>,
+}
+
+/// What kind of account it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    Checking,
+    Savings,
+    CreditCard,
+    Loan,
+    Cash,
+    Asset,
+}
+
+impl AccountType {
+    pub fn class(self) -> AccountClass {
+        match self {
+            Self::CreditCard | Self::Loan => AccountClass::Liability,
+            Self::Checking | Self::Savings | Self::Cash | Self::Asset => AccountClass::Asset,
+        }
+    }
+}
+
+/// Which side of net worth an account's balance counts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountClass {
+    Asset,
+    Liability,
+}
+
+/// Net worth in one currency, as amounts in different currencies aren't added up
+#[derive(Debug, Serialize)]
+pub struct NetWorth {
+    pub currency: String,
+    /// The balances of asset accounts, an overdrawn one taking away from them
+    pub assets_cents: i64,
+    /// What is owed on liability accounts, as a positive amount
+    pub liabilities_cents: i64,
+    pub net_worth_cents: i64,
+}
+
+impl NetWorth {
+    /// Round assets and liabilities to `step_cents`, and net worth with them,
+    /// as what the rounded assets leave after the rounded liabilities, so a
+    /// rounded report still adds up
+    pub fn round(&mut self, step_cents: i64) {
+        self.assets_cents = round_half_even(self.assets_cents, step_cents);
+        self.liabilities_cents = round_half_even(self.liabilities_cents, step_cents);
+        self.net_worth_cents = self.assets_cents - self.liabilities_cents;
+    }
+}
+
+/// What an account is, as given to `POST /accounts` and `PUT /accounts/:account_id`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountDetails {
+    pub display_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub institution: Option<String>,
+    /// A grouping of the user's own, such as Household or Business
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// The last few digits of its number at the institution, to tell it
+    /// apart from others there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub number_suffix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub account_type: Option<AccountType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opening_balance: Option<OpeningBalance>,
+}
+
+/// What an account held when its recorded history starts, which its
+/// balances count on from. Transactions before `date` are taken to be in it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpeningBalance {
+    /// The balance is at the start of this date, before its transactions
+    pub date: NaiveDate,
+    pub balance_cents: i64,
+    pub currency: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAccountRequest {
+    pub account_id: String,
+    #[serde(flatten)]
+    pub details: AccountDetails,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeAccountsRequest {
+    /// The account to move everything from and remove
+    pub source_account_id: String,
+    /// The account everything moves into, which stays
+    pub target_account_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MergeAccountsResponse {
+    pub source_account_id: String,
+    /// The account merged into, as it is now
+    pub account: AccountSummary,
+    /// Current transactions moved from the source
+    pub moved: usize,
+    /// Current transactions the target had already, dropped with their history
+    pub duplicates: usize,
+}
+
+/// Which accounts a listing or report covers, by their details. Names are
+/// matched ignoring case; accounts with no details match only when nothing
+/// is asked of them.
+#[derive(Debug, Clone, Default)]
+pub struct AccountSelector {
+    pub institution: Option<String>,
+    pub group: Option<String>,
+    pub account_type: Option<AccountType>,
+}
+
+impl AccountSelector {
+    pub fn matches(&self, details: Option<&AccountDetails>) -> bool {
+        let named = |wanted: &Option<String>, name: Option<&String>| {
+            wanted
+                .as_ref()
+                .is_none_or(|wanted| name.is_some_and(|name| name.eq_ignore_ascii_case(wanted)))
+        };
+        named(&self.institution, details.and_then(|details| details.institution.as_ref()))
+            && named(&self.group, details.and_then(|details| details.group.as_ref()))
+            && self
+                .account_type
+                .is_none_or(|wanted| details.and_then(|details| details.account_type) == Some(wanted))
+    }
+}
+
+/// What accounts are put together by in `GET /account-groups`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountGrouping {
+    Institution,
+    Group,
+}
+
+/// The accounts sharing an institution or group, and what they add up to
+#[derive(Debug, Serialize)]
+pub struct AccountGroup {
+    /// None for the accounts that have no institution or group
+    pub name: Option<String>,
+    pub accounts: Vec<String>,
+    /// Ordered by currency
+    pub totals: Vec<NetWorth>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProjectedInterestResponse {
+    pub account_id: String,
+    pub from: NaiveDate,
+    /// The first day after the horizon
+    pub until: NaiveDate,
+    pub balances: Vec<ProjectedInterest>,
+}
+
+/// What one currency's balance would earn, or be charged when negative, if
+/// it stayed as it is. Interest accrues daily without compounding.
+#[derive(Debug, Serialize)]
+pub struct ProjectedInterest {
+    pub currency: String,
+    pub balance_cents: i64,
+    pub interest_cents: i64,
+    /// One for each rate in effect over the horizon
+    pub periods: Vec<InterestPeriod>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InterestPeriod {
+    pub from: NaiveDate,
+    /// The first day the next rate is in effect, or the end of the horizon
+    pub until: NaiveDate,
+    pub annual_percent: f64,
+    pub interest_cents: i64,
+}
+
+/// The hash chained over every event of the log up to and including `seq`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainCheckpoint {
+    pub seq: u64,
+    pub hash: String,
+}
+
+/// A checkpoint the event log no longer matches: some event after the
+/// checkpoint before it and up to `seq` has changed or gone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainMismatch {
+    pub seq: u64,
+    pub recorded: String,
+    /// Unset when the event is no longer in the log
+    pub found: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegrityRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Events hashed
+    pub events: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mismatch: Option<ChainMismatch>,
+    /// Why the log couldn't be checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What is kept of the hash chain between checks
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IntegrityChain {
+    /// Every thousandth event and the last one hashed, in order
+    pub checkpoints: Vec<ChainCheckpoint>,
+    pub last_run: Option<IntegrityRun>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntegrityResponse {
+    /// Whether the last check found the log as it was recorded
+    pub ok: bool,
+    /// The hash of the whole log as of the last check, to note down
+    /// somewhere the server can't write to
+    pub head: Option<ChainCheckpoint>,
+    pub last_run: Option<IntegrityRun>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PayeeTotal {
+    pub currency: String,
+    pub count: usize,
+    pub total_cents: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PayeeStats {
+    pub payee: String,
+    /// Current transactions with this payee, across every account
+    pub count: usize,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub totals: Vec<PayeeTotal>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CurrencyInfo {
+    pub code: String,
+    /// Digits after the decimal point in the currency's minor unit
+    pub exponent: u32,
+    /// Decimal places amounts may be entered with, which can be fewer than `exponent`
+    pub allowed_decimals: u32,
+}
+
+/// Configuration the frontend needs, without secrets
+#[derive(Debug, Serialize)]
+pub struct BootstrapSettings {
+    pub amount_precision: AmountPrecision,
+    pub import_profiles: Vec<String>,
+    pub low_balance_thresholds: HashMap<String, HashMap<String, f64>>,
+}
+
+/// Everything the frontend needs to start, in one response
+#[derive(Debug, Serialize)]
+pub struct BootstrapResponse {
+    /// Changes whenever the data does; see `GET /events`
+    pub revision: u64,
+    /// Every currency used by a transaction or a low balance threshold
+    pub currencies: Vec<CurrencyInfo>,
+    pub accounts: Vec<AccountSummary>,
+    pub settings: BootstrapSettings,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NetWorthResponse {
+    /// Ordered by currency
+    pub currencies: Vec<NetWorth>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MaintenanceCheckResponse {
+    pub ok: bool,
+    pub failed_assertions: Vec<BalanceAssertionResult>,
+    pub low_balances: Vec<LowBalance>,
+}
+
+/// One inconsistency between the current and historical transactions
+#[derive(Debug, Serialize)]
+pub struct Discrepancy {
+    pub kind: DiscrepancyKind,
+    pub account_id: String,
+    /// The transaction concerned, if the problem is with a single one
+    pub id: Option<TransactionId>,
+    /// What is wrong, including positions in the account's history where relevant
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscrepancyKind {
+    /// A current transaction that was never recorded in the history
+    OrphanedCurrent,
+    /// A current transaction whose own id or account doesn't match where it is kept
+    MisfiledCurrent,
+    /// A historical record kept under another account's history
+    MisfiledHistorical,
+    /// A historical record dated in the future or implausibly far in the past
+    ImpossibleTimestamp,
+    /// The same transaction recorded more than once in an account's history
+    DuplicateHistorical,
+    /// An account with history but no current transactions entry
+    MissingAccount,
+}
+
+/// One item of the month-end checklist
+#[derive(Debug, Serialize)]
+pub struct ClosingCheck {
+    pub kind: ClosingCheckKind,
+    pub account_id: String,
+    /// The currency concerned, for checks made per currency
+    pub currency: Option<String>,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClosingCheckKind {
+    /// Transactions have been imported up to the end of the month
+    ImportedThroughMonthEnd,
+    /// The balance at the end of the month is asserted, and the assertion holds
+    BalanceAsserted,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MonthClosingResponse {
+    pub month: Month,
+    /// Whether every check passed
+    pub complete: bool,
+    /// Whether the month is closed for every account, locking its transactions
+    pub closed: bool,
+    pub checks: Vec<ClosingCheck>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyResponse {
+    pub ok: bool,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Approximate memory one account's transactions take up in the store
+#[derive(Debug, Serialize)]
+pub struct AccountMemory {
+    pub account_id: String,
+    pub current_transactions: usize,
+    /// Entries the current transactions map has room for without growing
+    pub current_capacity: usize,
+    pub historical_transactions: usize,
+    pub historical_capacity: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemoryResponse {
+    /// Every account plus balance assertions and import records
+    pub total_bytes: usize,
+    /// Largest first
+    pub accounts: Vec<AccountMemory>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompactResponse {
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+fn default_preview_limit() -> usize {
+    5
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PreviewMappingRequest {
+    /// The first lines of the file, header row included
+    pub sample: String,
+    #[serde(default)]
+    pub profile: ImportProfile,
+    /// How many data rows to parse
+    #[serde(default = "default_preview_limit")]
+    pub limit: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColumnMapping {
+    pub index: usize,
+    pub header: String,
+    pub field: Option<&'static str>, // None when the column is ignored
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewMappingResponse {
+    pub columns: Vec<ColumnMapping>,
+    pub missing_columns: Vec<String>,
+    pub transactions: Vec<TransactionId>,
+    pub errors: Vec<String>,
+}
+
+/// Which account, and which profile, one file of a batch import goes into
+#[derive(Debug, Deserialize)]
+pub struct BatchImportEntry {
+    /// File name of the uploaded part
+    pub file: String,
+    pub account_id: String,
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Import rows whose currency isn't the account's
+    #[serde(default)]
+    pub foreign_currency: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchImportFileResult {
+    pub file: String,
+    pub account_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<BulkImportResponse>,
+    /// Why the file wasn't imported at all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchImportResponse {
+    pub files: Vec<BatchImportFileResult>,
+}
+
+/// Outcome of one import, kept so import quality can be tracked over time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRecord {
+    /// Unset for imports recorded before imports had ids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub account_id: String,
+    /// Where the transactions came from, e.g. `csv:<profile>` or `webhook:<source>`
+    pub source: String,
+    pub imported_at: DateTime<Utc>,
+    /// Rows or payload entries read, whether or not they were usable
+    pub rows: usize,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub errors: usize,
+    /// The original statement file, when one has been attached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement: Option<StatementFile>,
+}
+
+/// Describes a statement file attached to an import; the file itself is kept
+/// outside the store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatementFile {
+    pub file_name: String,
+    pub content_type: String,
+    pub size: usize,
+    pub attached_at: DateTime<Utc>,
+}
+
+/// Totals over every import from one source
+#[derive(Debug, Serialize)]
+pub struct ImportSourceMetrics {
+    pub source: String,
+    pub imports: usize,
+    pub rows: usize,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub errors: usize,
+    /// Fraction of rows that could not be imported
+    pub error_rate: f64,
+    /// Fraction of imported rows that were already known
+    pub duplicate_rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportMetricsResponse {
+    pub sources: Vec<ImportSourceMetrics>,
+    /// Every import, oldest first
+    pub imports: Vec<ImportRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupResponse {
+    pub file: String,
+    /// Older backups removed by the retention policy
+    pub pruned: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RemoteBackupStatus {
+    /// Where backups are pushed, without credentials
+    pub target: String,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// Why the last push failed; cleared by the next successful one
+    pub last_error: Option<String>,
+    /// Backups currently on the remote, oldest first
+    pub backups: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreRequest {
+    /// File name of the backup, as returned when it was taken
+    pub backup: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreResponse {
+    pub restored: String,
+    /// Backup of the state that was replaced, so the restore can be undone
+    pub safety_backup: String,
+}
+
+/// What the server found when it checked itself on startup
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusResponse {
+    pub started_at: DateTime<Utc>,
+    /// No check that guards against damaging the data failed, and changes are still saved
+    pub ok: bool,
+    /// Started with `--force` although such a check failed
+    pub forced: bool,
+    pub checks: Vec<StartupCheck>,
+    /// Why changes are no longer taken, once the storage backend refused them for good
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_failure: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupCheck {
+    pub kind: StartupCheckKind,
+    pub passed: bool,
+    /// Failing it keeps the server from starting without `--force`
+    pub fatal: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupCheckKind {
+    /// The storage backend's files can be written
+    DataDirWritable,
+    /// The database schema isn't newer than this build knows
+    SchemaVersion,
+    /// The clock isn't behind the newest change recorded
+    Clock,
+    /// No other server is writing the same files
+    Lock,
+    /// A backup was taken recently
+    BackupsRecent,
+}
+
+/// The kinds of change a subscriber can be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriptionEvent {
+    Created,
+    Imported,
+    /// A new memo, metadata, timestamp, payee or amount
+    Edited,
+    Deleted,
+}
+
+fn all_subscription_events() -> Vec<SubscriptionEvent> {
+    vec![
+        SubscriptionEvent::Created,
+        SubscriptionEvent::Imported,
+        SubscriptionEvent::Edited,
+        SubscriptionEvent::Deleted,
+    ]
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSubscriptionRequest {
+    /// Where each change is POSTed, as JSON
+    pub url: String,
+    /// Which changes to send; all of them when absent
+    #[serde(default = "all_subscription_events")]
+    pub events: Vec<SubscriptionEvent>,
+    /// Signs each delivery with an `X-Wdmmg-Signature: sha256=<hex HMAC of the body>` header
+    pub secret: Option<String>,
+}
+
+/// A registered URL, with how its deliveries are going
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<SubscriptionEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// The user who registered it, who is only sent changes to the accounts
+    /// they may see; None for an admin's, sent everything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Position in the event log everything up to has been sent
+    pub delivered_seq: u64,
+    /// Why the latest delivery failed, while it's being retried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Failed attempts in a row at the delivery being retried
+    #[serde(default)]
+    pub failures: u32,
+    /// When the delivery being retried is next tried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<DateTime<Utc>>,
+    /// The latest delivery attempts, oldest first
+    #[serde(default)]
+    pub deliveries: Vec<DeliveryAttempt>,
+}
+
+/// One try at sending a subscriber one change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryAttempt {
+    pub seq: u64,
+    pub event: SubscriptionEvent,
+    pub attempted_at: DateTime<Utc>,
+    /// 1 for the first try, counting up while it's retried
+    pub attempt: u32,
+    /// What the subscriber answered, unless it couldn't be reached
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// A subscription as the API shows it, without its secret
+#[derive(Debug, Serialize)]
+pub struct SubscriptionResponse {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<SubscriptionEvent>,
+    pub signed: bool,
+    pub created_at: DateTime<Utc>,
+    pub delivered_seq: u64,
+    pub last_error: Option<String>,
+    pub failures: u32,
+    pub retry_at: Option<DateTime<Utc>>,
+}
+
+impl From<&Subscription> for SubscriptionResponse {
+    fn from(subscription: &Subscription) -> Self {
+        Self {
+            id: subscription.id.clone(),
+            url: subscription.url.clone(),
+            events: subscription.events.clone(),
+            signed: subscription.secret.is_some(),
+            created_at: subscription.created_at,
+            delivered_seq: subscription.delivered_seq,
+            last_error: subscription.last_error.clone(),
+            failures: subscription.failures,
+            retry_at: subscription.retry_at,
+        }
+    }
+}
+
+/// The dashboard the frontend shows, the same on every device. The server
+/// keeps it as the frontend sends it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DashboardLayout {
+    /// Widgets and where they go
+    #[serde(default)]
+    pub widgets: Vec<serde_json::Value>,
+    /// Saved reports chosen to be shown
+    #[serde(default)]
+    pub saved_reports: Vec<serde_json::Value>,
+    /// When it was last saved, set by the server whatever is sent; never,
+    /// when absent
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// What changed between two backups, or a backup and the store as it is now
+#[derive(Debug, Serialize)]
+pub struct SnapshotDiffResponse {
+    pub a: String,
+    pub b: String,
+    /// Current in `b` only
+    pub added: Vec<HistoricalTransaction>,
+    /// Current in `a` only
+    pub removed: Vec<HistoricalTransaction>,
+    pub modified: Vec<ModifiedTransaction>,
+}
+
+/// One transaction current in both snapshots, as it is in each
+#[derive(Debug, Serialize)]
+pub struct ModifiedTransaction {
+    pub before: HistoricalTransaction,
+    pub after: HistoricalTransaction,
+}
+
+/// How `POST /import` combines an archive with what the store already holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveImportMode {
+    /// Discard everything and load the archive
+    Replace,
+    /// Keep everything and add what the archive has that the store doesn't
+    Merge,
+}
+
+/// What an archive import loaded, counted per kind of record
+#[derive(Debug, Serialize)]
+pub struct ImportArchiveResponse {
+    pub mode: ArchiveImportMode,
+    pub accounts: usize,
+    pub current_transactions: usize,
+    pub historical_transactions: usize,
+    pub balance_assertions: usize,
+    pub imports: usize,
+}
+
+/// A user of `auth.provider = "users"`, without their password
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What `POST /auth/register` and `POST /auth/login` are sent
+#[derive(Debug, Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    /// Sent back as `Authorization: Bearer <token>`; browsers are also given
+    /// it in a cookie
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub user: User,
+}
+
+/// What a request's credentials let it do
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Read only, as a dashboard does
+    Read,
+    /// Import statements and nothing else, not even read, as an automated
+    /// bank fetcher does, so a token leaked from it can't expose the ledger
+    Import,
+    /// Everything, as signing in with a password allows
+    #[default]
+    Full,
+}
+
+impl Scope {
+    /// Whether credentials of this scope may do what needs `needed`: full
+    /// credentials anything, the others only what they are named for
+    pub fn allows(self, needed: Scope) -> bool {
+        self == Scope::Full || self == needed
+    }
+}
+
+/// An API token of a user, without the token itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub scope: Scope,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What `POST /auth/tokens` is sent
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scope: Scope,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedTokenResponse {
+    /// Sent back as `Authorization: Bearer <token>`. Only its hash is kept,
+    /// so it can't be shown again.
+    pub token: String,
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+}
+
+/// What another user may do with an account shared with them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Read,
+    /// Read and change its transactions, as its owner can
+    ReadWrite,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::ReadWrite => "read_write",
+        }
+    }
+}
+
+impl std::str::FromStr for Permission {
+    type Err = String;
+
+    fn from_str(permission: &str) -> Result<Self, Self::Err> {
+        match permission {
+            "read" => Ok(Self::Read),
+            "read_write" => Ok(Self::ReadWrite),
+            _ => Err(format!("invalid permission '{}', expected read or read_write", permission)),
+        }
+    }
+}
+
+/// What `PUT /accounts/:account_id/grants/:user` is sent
+#[derive(Debug, Deserialize)]
+pub struct GrantRequest {
+    pub permission: Permission,
+}
+
+/// What `PUT /accounts/:account_id/owner` is sent
+#[derive(Debug, Deserialize)]
+pub struct OwnerRequest {
+    pub owner: String,
+}
+
+/// A user an account is shared with
+#[derive(Debug, Serialize)]
+pub struct AccountGrant {
+    pub user: String,
+    pub permission: Permission,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountGrantsResponse {
+    pub account_id: String,
+    pub owner: String,
+    pub grants: Vec<AccountGrant>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounded_net_worth_is_rounded_assets_less_rounded_liabilities() {
+        let mut total = NetWorth {
+            currency: "AUD".to_string(),
+            assets_cents: 1_060,
+            liabilities_cents: 440,
+            net_worth_cents: 620,
+        };
+        total.round(100);
+        assert_eq!((total.assets_cents, total.liabilities_cents), (1_100, 400));
+        assert_eq!(total.net_worth_cents, 700);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::config::UnixSocketConfig;
+use crate::storage::StorageError;
+use futures_util::Stream;
+use std::fs::Permissions;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::net::{UnixListener, UnixStream};
+
+/// The socket file's path, removed again when the server stops
+pub struct SocketFile {
+    path: PathBuf,
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Listen on the configured socket. A socket file nothing is listening on
+/// any more, left by a server that didn't stop cleanly, is replaced.
+pub async fn bind(config: &UnixSocketConfig) -> Result<(UnixListener, SocketFile), StorageError> {
+    let mode = u32::from_str_radix(&config.mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("Invalid socket mode {}, expected octal permissions such as 660", config.mode))?;
+    let path = PathBuf::from(&config.path);
+
+    match fs::symlink_metadata(&path).await {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(format!("{} exists and is not a socket", config.path).into());
+        }
+        Ok(_) => match UnixStream::connect(&path).await {
+            Ok(_) => return Err(format!("Another server is listening on {}", config.path).into()),
+            Err(_) => fs::remove_file(&path).await?,
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).await?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    let socket = SocketFile { path };
+    fs::set_permissions(&socket.path, Permissions::from_mode(mode)).await?;
+    Ok((listener, socket))
+}
+
+/// Connections to `listener`, as they are accepted
+pub fn incoming(listener: UnixListener) -> impl Stream<Item = io::Result<UnixStream>> {
+    futures_util::stream::unfold(listener, |listener| async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => return Some((Ok(stream), listener)),
+                Err(e) => tracing::warn!("Failed to accept a connection: {}", e),
+            }
+        }
+    })
+}
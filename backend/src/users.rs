@@ -0,0 +1,408 @@
+use crate::auth::{AuthProvider, BEARER_CHALLENGE, Identity, credentials};
+use crate::config::{AuthConfig, Config};
+use crate::error::{ApiError, AuthError};
+use crate::storage::StorageError;
+use crate::types::{ApiToken, CreateTokenRequest, CreatedTokenResponse, Credentials, Scope, User};
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, TimeDelta, Utc};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use warp::http::{HeaderMap, StatusCode};
+
+/// The cookie browsers are given the session token in
+const SESSION_COOKIE: &str = "wdmmg_session";
+
+const MAX_USERNAME_LEN: usize = 64;
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 1024;
+const MAX_TOKEN_NAME_LEN: usize = 100;
+
+/// Hashed in place of the password of a user who doesn't exist, so signing
+/// in as one takes as long as with a wrong password
+static UNKNOWN_USER_HASH: OnceLock<String> = OnceLock::new();
+
+/// Users registered with `POST /auth/register` and their sessions, kept in a
+/// file of their own, readable only by the server's user, as they aren't
+/// financial data
+#[derive(Clone)]
+pub struct Users {
+    path: PathBuf,
+    open_registration: bool,
+    session_days: u32,
+    // Cookies are only sent back over HTTPS when the server serves it itself
+    secure_cookies: bool,
+    file: Arc<Mutex<UsersFile>>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct UsersFile {
+    users: Vec<StoredUser>,
+    /// Keyed by the SHA-256 of their token, so the file holds no tokens that
+    /// could be used
+    sessions: HashMap<String, Session>,
+    /// API tokens, which last until revoked, keyed like `sessions`
+    #[serde(default)]
+    tokens: HashMap<String, StoredToken>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredUser {
+    #[serde(flatten)]
+    user: User,
+    /// The argon2id hash of their password, as a PHC string
+    password_hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Session {
+    user_id: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredToken {
+    user_id: String,
+    #[serde(flatten)]
+    api_token: ApiToken,
+}
+
+impl Users {
+    /// The users, when `auth.provider` is `users`
+    pub async fn from_config(config: &Config) -> Result<Option<Self>, StorageError> {
+        let Some(AuthConfig::Users {
+            file,
+            open_registration,
+            session_days,
+        }) = &config.auth
+        else {
+            return Ok(None);
+        };
+        let path = PathBuf::from(file);
+        let file = if path.exists() {
+            serde_json::from_slice(&fs::read(&path).await?)?
+        } else {
+            UsersFile::default()
+        };
+        Ok(Some(Self {
+            path,
+            open_registration: *open_registration,
+            session_days: *session_days,
+            secure_cookies: config.server.tls.is_some(),
+            file: Arc::new(Mutex::new(file)),
+        }))
+    }
+
+    /// Register a user, which only the first may do for themselves unless
+    /// registration is open; later ones are registered by someone `signed_in`
+    pub async fn register(&self, credentials: Credentials, signed_in: bool) -> Result<User, ApiError> {
+        let username = credentials.username.trim().to_string();
+        validate(&username, &credentials.password)?;
+        self.may_register(&*self.file.lock().await, &username, signed_in)?;
+
+        // Hashing takes a while, so the file isn't held meanwhile and has to
+        // be checked again after
+        let password_hash = hash_password(credentials.password).await?;
+        let mut file = self.file.lock().await;
+        self.may_register(&file, &username, signed_in)?;
+        let user = User {
+            id: uuid::Uuid::new_v4().to_string(),
+            username,
+            created_at: Utc::now(),
+        };
+        file.users.push(StoredUser {
+            user: user.clone(),
+            password_hash,
+        });
+        if let Err(e) = self.save(&file).await {
+            file.users.pop();
+            return Err(internal(e));
+        }
+        Ok(user)
+    }
+
+    fn may_register(&self, file: &UsersFile, username: &str, signed_in: bool) -> Result<(), ApiError> {
+        if !file.users.is_empty() && !self.open_registration && !signed_in {
+            return Err(ApiError {
+                message: "Registration is closed; a signed-in user has to register new users".to_string(),
+                status: StatusCode::FORBIDDEN,
+            });
+        }
+        if file.users.iter().any(|stored| stored.user.username.eq_ignore_ascii_case(username)) {
+            return Err(ApiError {
+                message: format!("The username {} is taken", username),
+                status: StatusCode::CONFLICT,
+            });
+        }
+        Ok(())
+    }
+
+    /// Sign a user in, for a new session's token and when it expires
+    pub async fn login(&self, credentials: Credentials) -> Result<(String, DateTime<Utc>, User), ApiError> {
+        let username = credentials.username.trim();
+        let stored = self
+            .file
+            .lock()
+            .await
+            .users
+            .iter()
+            .find(|stored| stored.user.username.eq_ignore_ascii_case(username))
+            .cloned();
+        let verified = verify_password(credentials.password, stored.as_ref().map(|stored| stored.password_hash.clone())).await;
+        let Some(stored) = stored.filter(|_| verified) else {
+            // Not an AuthError, which the authentication of the routes tried
+            // after this one would be found before
+            return Err(ApiError {
+                message: "Invalid username or password".to_string(),
+                status: StatusCode::UNAUTHORIZED,
+            });
+        };
+
+        let token = new_token().map_err(internal)?;
+        let now = Utc::now();
+        let expires_at = now + TimeDelta::days(i64::from(self.session_days));
+        let mut file = self.file.lock().await;
+        file.sessions.retain(|_, session| session.expires_at > now);
+        let session = Session {
+            user_id: stored.user.id.clone(),
+            created_at: now,
+            expires_at,
+        };
+        file.sessions.insert(token_hash(&token), session);
+        self.save(&file).await.map_err(internal)?;
+        Ok((token, expires_at, stored.user))
+    }
+
+    /// The user signed in with `token`, while their session lasts, or whose
+    /// API token it is, with what it lets them do
+    pub async fn token_user(&self, token: &str) -> Option<(User, Scope)> {
+        let file = self.file.lock().await;
+        let hash = token_hash(token);
+        let (user_id, scope) = match file.sessions.get(&hash).filter(|session| session.expires_at > Utc::now()) {
+            Some(session) => (&session.user_id, Scope::Full),
+            None => {
+                let stored = file.tokens.get(&hash)?;
+                (&stored.user_id, stored.api_token.scope)
+            }
+        };
+        file.users
+            .iter()
+            .find(|stored| stored.user.id == *user_id)
+            .map(|stored| (stored.user.clone(), scope))
+    }
+
+    /// Give `user` a token allowing `request.scope`, lasting until revoked
+    pub async fn create_token(&self, user: &User, request: CreateTokenRequest) -> Result<CreatedTokenResponse, ApiError> {
+        let name = request.name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_TOKEN_NAME_LEN {
+            return Err(ApiError {
+                message: format!("Token names are 1 to {} characters long", MAX_TOKEN_NAME_LEN),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+        let token = new_token().map_err(internal)?;
+        let api_token = ApiToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            scope: request.scope,
+            created_at: Utc::now(),
+        };
+        let mut file = self.file.lock().await;
+        let hash = token_hash(&token);
+        file.tokens.insert(
+            hash.clone(),
+            StoredToken {
+                user_id: user.id.clone(),
+                api_token: api_token.clone(),
+            },
+        );
+        if let Err(e) = self.save(&file).await {
+            file.tokens.remove(&hash);
+            return Err(internal(e));
+        }
+        Ok(CreatedTokenResponse { token, api_token })
+    }
+
+    /// The API tokens of `user`, oldest first
+    pub async fn tokens(&self, user: &User) -> Vec<ApiToken> {
+        let file = self.file.lock().await;
+        let mut tokens: Vec<ApiToken> = file
+            .tokens
+            .values()
+            .filter(|stored| stored.user_id == user.id)
+            .map(|stored| stored.api_token.clone())
+            .collect();
+        tokens.sort_by_key(|token| token.created_at);
+        tokens
+    }
+
+    /// Revoke the API token `id` of `user`
+    pub async fn revoke_token(&self, user: &User, id: &str) -> Result<(), ApiError> {
+        let mut file = self.file.lock().await;
+        let before = file.tokens.len();
+        file.tokens.retain(|_, stored| stored.user_id != user.id || stored.api_token.id != id);
+        if file.tokens.len() == before {
+            return Err(ApiError {
+                message: "Token not found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+        self.save(&file).await.map_err(internal)
+    }
+
+    /// End the session of `token`
+    pub async fn logout(&self, token: &str) -> Result<(), ApiError> {
+        let mut file = self.file.lock().await;
+        if file.sessions.remove(&token_hash(token)).is_some() {
+            self.save(&file).await.map_err(internal)?;
+        }
+        Ok(())
+    }
+
+    /// A `Set-Cookie` value giving browsers `token` until `expires_at`
+    pub fn cookie(&self, token: &str, expires_at: DateTime<Utc>) -> String {
+        let max_age = (expires_at - Utc::now()).num_seconds().max(0);
+        let secure = if self.secure_cookies { "; Secure" } else { "" };
+        format!("{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}", SESSION_COOKIE, token, max_age, secure)
+    }
+
+    /// A `Set-Cookie` value making browsers forget the session
+    pub fn cleared_cookie(&self) -> String {
+        self.cookie("", Utc::now())
+    }
+
+    async fn save(&self, file: &UsersFile) -> Result<(), StorageError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        // Only the server's user may read the password hashes
+        let mut tmp = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)
+            .await?;
+        tmp.write_all(&serde_json::to_vec_pretty(file)?).await?;
+        tmp.flush().await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuthProvider for Users {
+    async fn authenticate(&self, headers: &HeaderMap, _remote: Option<SocketAddr>) -> Result<Identity, AuthError> {
+        let token = session_token(headers)
+            .ok_or_else(|| AuthError::challenge("Sign in with POST /auth/login first", BEARER_CHALLENGE))?;
+        self.token_user(token)
+            .await
+            .map(|(user, scope)| Identity {
+                user: user.username,
+                scope,
+            })
+            .ok_or_else(|| AuthError::challenge("The session has expired or was signed out", BEARER_CHALLENGE))
+    }
+}
+
+/// The session token of a request, as a bearer token or in the cookie
+pub fn session_token(headers: &HeaderMap) -> Option<&str> {
+    credentials(headers, "bearer").or_else(|| {
+        headers
+            .get_all("cookie")
+            .iter()
+            .filter_map(|cookies| cookies.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .find_map(|cookie| cookie.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='))
+            .filter(|token| !token.is_empty())
+    })
+}
+
+fn validate(username: &str, password: &str) -> Result<(), ApiError> {
+    let bad_request = |message: String| ApiError {
+        message,
+        status: StatusCode::BAD_REQUEST,
+    };
+    if username.is_empty() || username.chars().count() > MAX_USERNAME_LEN {
+        return Err(bad_request(format!("Usernames are 1 to {} characters long", MAX_USERNAME_LEN)));
+    }
+    if !username.chars().all(|c| c.is_alphanumeric() || "._-@".contains(c)) {
+        return Err(bad_request("Usernames are letters, digits and . _ - @".to_string()));
+    }
+    let length = password.chars().count();
+    if !(MIN_PASSWORD_LEN..=MAX_PASSWORD_LEN).contains(&length) {
+        return Err(bad_request(format!(
+            "Passwords are {} to {} characters long",
+            MIN_PASSWORD_LEN, MAX_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+async fn hash_password(password: String) -> Result<String, ApiError> {
+    let mut salt = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| internal("Failed to generate a salt".into()))?;
+    // Deliberately slow, so kept off the threads serving requests
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::encode_b64(&salt)?;
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .map_err(|e| internal(e.into()))?
+    .map_err(|e| internal(e.to_string().into()))
+}
+
+async fn verify_password(password: String, hash: Option<String>) -> bool {
+    tokio::task::spawn_blocking(move || {
+        let hash = hash.unwrap_or_else(|| {
+            UNKNOWN_USER_HASH
+                .get_or_init(|| {
+                    let salt = SaltString::encode_b64(b"unknown user").expect("salt is long enough");
+                    Argon2::default()
+                        .hash_password(b"unknown user", &salt)
+                        .map(|hash| hash.to_string())
+                        .unwrap_or_default()
+                })
+                .clone()
+        });
+        PasswordHash::new(&hash)
+            .is_ok_and(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+    })
+    .await
+    .unwrap_or(false)
+}
+
+fn new_token() -> Result<String, StorageError> {
+    let mut token = [0u8; 32];
+    SystemRandom::new().fill(&mut token).map_err(|_| "Failed to generate a token")?;
+    Ok(URL_SAFE_NO_PAD.encode(token))
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn internal(e: StorageError) -> ApiError {
+    ApiError {
+        message: format!("Failed to save users: {}", e),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
@@ -1,8 +1,22 @@
-use crate::error::ApiError;
-use crate::store::TransactionStore;
+use crate::auth::Access;
+use crate::backup::Backups;
+use crate::config::{AmountPrecision, Config};
+use crate::currency::{STORED_DECIMALS, allowed_decimals, decimal_places};
+use crate::dashboard::Dashboard;
+use crate::error::{ApiError, FieldError};
+use crate::frontend::Frontend;
+use crate::hash_chain::HashChain;
+use crate::idempotency::{Claim, Idempotency};
+use crate::limits::{Limit, Slot};
+use crate::quarantine::Quarantine;
+use crate::statements::Statements;
+use crate::store::{Past, TransactionStore};
+use crate::subscriptions::Subscriptions;
 use crate::types::*;
-use chrono::{DateTime, Utc};
+use crate::users::Users;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
 use std::collections::HashMap;
+use std::sync::Arc;
 use warp::{self, Filter};
 
 pub fn get_required_param(params: &HashMap<String, String>, key: &str) -> Result<String, ApiError> {
@@ -26,43 +40,457 @@ pub fn parse_amount(amount_str: &str) -> Result<f64, ApiError> {
     })
 }
 
-pub fn parse_csv_string(csv_data: bytes::Bytes) -> Result<String, ApiError> {
-    String::from_utf8(csv_data.to_vec()).map_err(|_| ApiError {
-        message: "Invalid UTF-8 in CSV".to_string(),
+/// A non-negative integer query parameter, or `default` when it's absent
+pub fn get_usize_param(params: &HashMap<String, String>, key: &str, default: usize) -> Result<usize, ApiError> {
+    match params.get(key) {
+        Some(value) => value.parse().map_err(|_| ApiError {
+            message: format!("Invalid {} parameter", key),
+            status: warp::http::StatusCode::BAD_REQUEST,
+        }),
+        None => Ok(default),
+    }
+}
+
+/// Largest `round_to` a report accepts, in whole units of the currency
+const MAX_ROUND_TO: f64 = 1_000_000_000.0;
+
+/// The step, in cents, a report's amounts are rounded to: `round_to`, in whole
+/// units of the currency, or whole units with `hide_cents=true`. `None` leaves
+/// amounts exact.
+pub fn rounding_from_query(params: &HashMap<String, String>) -> Result<Option<i64>, ApiError> {
+    let invalid = |message: &str| ApiError {
+        message: message.to_string(),
         status: warp::http::StatusCode::BAD_REQUEST,
+    };
+
+    let step_cents = match params.get("round_to") {
+        Some(value) => {
+            let round_to: f64 = value.parse().map_err(|_| invalid("Invalid round_to parameter"))?;
+            let step_cents = (round_to * 100.0).round();
+            if !round_to.is_finite() || step_cents < 1.0 || (round_to * 100.0 - step_cents).abs() > 1e-6 {
+                return Err(invalid("round_to must be a positive multiple of 0.01"));
+            }
+            if round_to > MAX_ROUND_TO {
+                return Err(invalid(&format!("round_to must be at most {}", MAX_ROUND_TO)));
+            }
+            Some(step_cents as i64)
+        }
+        None => None,
+    };
+    if params.get("hide_cents").is_some_and(|value| value == "true") {
+        return match step_cents {
+            Some(step_cents) if step_cents % 100 != 0 => {
+                Err(invalid("round_to must be a whole number with hide_cents=true"))
+            }
+            Some(step_cents) => Ok(Some(step_cents)),
+            None => Ok(Some(100)),
+        };
+    }
+    Ok(step_cents)
+}
+
+/// The `limit` and `offset` query parameters of a listing
+pub fn page_from_query(params: &HashMap<String, String>) -> Result<Page, ApiError> {
+    Ok(Page {
+        offset: get_usize_param(params, "offset", 0)?,
+        limit: match params.get("limit") {
+            Some(_) => Some(get_usize_param(params, "limit", 0)?),
+            None => None,
+        },
     })
 }
 
-pub fn process_csv_transaction(
-    csv_transaction: CsvTransaction,
-    account_id: &str,
-) -> Result<(TransactionId, CurrentTransaction, HistoricalTransaction), String> {
-    let timestamp = csv_transaction.timestamp.parse::<DateTime<Utc>>()
-        .map_err(|e| format!("Invalid timestamp format - {}", e))?;
+/// The `as_of_revision` or `as_of_date` query parameter, the latter a
+/// timestamp or a date standing for the end of that day, in UTC
+pub fn as_of_from_query(params: &HashMap<String, String>) -> Result<Option<AsOf>, ApiError> {
+    let invalid = |message: &str| ApiError {
+        message: message.to_string(),
+        status: warp::http::StatusCode::BAD_REQUEST,
+    };
+
+    match (params.get("as_of_revision"), params.get("as_of_date")) {
+        (Some(_), Some(_)) => Err(invalid("Pass as_of_revision or as_of_date, not both")),
+        (Some(revision), None) => revision
+            .parse()
+            .map(|revision| Some(AsOf::Revision(revision)))
+            .map_err(|_| invalid("Invalid as_of_revision parameter")),
+        (None, Some(date)) => {
+            let end_of_day = || {
+                let day = date.parse::<NaiveDate>().ok()?;
+                Some(day.succ_opt()?.and_hms_opt(0, 0, 0)?.and_utc() - TimeDelta::microseconds(1))
+            };
+            date.parse()
+                .ok()
+                .or_else(end_of_day)
+                .map(|date| Some(AsOf::Date(date)))
+                .ok_or_else(|| invalid("Invalid as_of_date parameter"))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// The store as it was at the moment the query parameters name, if they name one
+pub async fn past_from_query(store: &TransactionStore, params: &HashMap<String, String>) -> Result<Option<Past>, ApiError> {
+    match as_of_from_query(params)? {
+        Some(as_of) => store.as_of(as_of).await.map(Some),
+        None => Ok(None),
+    }
+}
 
-    let transaction_id = TransactionId {
-        timestamp,
-        amount_cents: (csv_transaction.amount * 100.0).round() as i64,
-        currency: csv_transaction.currency,
-        payee: csv_transaction.payee,
+/// The `account_id`, `from`, `to`, `payee`, `currency`, `min_amount` and
+/// `max_amount` query parameters of a transaction listing
+pub fn filter_from_query(params: &HashMap<String, String>) -> Result<TransactionFilter, ApiError> {
+    let timestamp = |key: &str| params.get(key).map(|value| parse_timestamp(value)).transpose();
+    let amount_cents = |key: &str| {
+        params
+            .get(key)
+            .map(|value| parse_amount(value).map(|amount| (amount * 100.0).round() as i64))
+            .transpose()
     };
+    Ok(TransactionFilter {
+        account_id: params.get("account_id").cloned(),
+        from: timestamp("from")?,
+        to: timestamp("to")?,
+        payee: params.get("payee").map(|payee| payee.to_lowercase()),
+        currency: params.get("currency").cloned(),
+        min_amount_cents: amount_cents("min_amount")?,
+        max_amount_cents: amount_cents("max_amount")?,
+        metadata: params
+            .iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix("meta.")?.to_string(), value.clone())))
+            .collect(),
+    })
+}
+
+/// The `institution`, `group` and `type` query parameters of an account
+/// listing or report
+pub fn selector_from_query(params: &HashMap<String, String>) -> Result<AccountSelector, ApiError> {
+    let account_type = params
+        .get("type")
+        .map(|value| serde_json::from_value(serde_json::Value::String(value.clone())))
+        .transpose()
+        .map_err(|_| ApiError {
+            message: "Invalid type parameter, expected checking, savings, credit_card, loan, cash or asset".to_string(),
+            status: warp::http::StatusCode::BAD_REQUEST,
+        })?;
+    Ok(AccountSelector {
+        institution: params.get("institution").cloned(),
+        group: params.get("group").cloned(),
+        account_type,
+    })
+}
 
-    let current_transaction = CurrentTransaction {
-        account_id: account_id.to_string(),
-        id: transaction_id.clone(),
+/// The `sort` query parameter of a transaction listing: `timestamp`, `amount`
+/// or `payee`, prefixed with `-` for descending. Newest first when absent.
+pub fn sort_from_query(params: &HashMap<String, String>) -> Result<TransactionSort, ApiError> {
+    let Some(value) = params.get("sort") else {
+        return Ok(TransactionSort::default());
     };
+    let (descending, field) = match value.strip_prefix('-') {
+        Some(field) => (true, field),
+        None => (false, value.as_str()),
+    };
+    let field = match field {
+        "timestamp" => SortField::Timestamp,
+        "amount" => SortField::Amount,
+        "payee" => SortField::Payee,
+        _ => {
+            return Err(ApiError {
+                message: "Invalid sort parameter, expected timestamp, amount or payee, optionally prefixed with -"
+                    .to_string(),
+                status: warp::http::StatusCode::BAD_REQUEST,
+            });
+        }
+    };
+    Ok(TransactionSort { field, descending })
+}
 
-    let historical_transaction = HistoricalTransaction {
-        account_id: account_id.to_string(),
-        id: transaction_id.clone(),
-        memo: None,
+/// Identify a transaction by its `timestamp`, `amount`, `currency` and `payee`
+/// query parameters, and `occurrence` for a duplicate
+pub fn transaction_id_from_query(params: &HashMap<String, String>) -> Result<TransactionId, ApiError> {
+    let timestamp = parse_timestamp(&get_required_param(params, "timestamp")?)?;
+    let amount = parse_amount(&get_required_param(params, "amount")?)?;
+    let occurrence = match params.get("occurrence") {
+        Some(occurrence) => occurrence.parse().map_err(|_| ApiError {
+            message: "Invalid occurrence format".to_string(),
+            status: warp::http::StatusCode::BAD_REQUEST,
+        })?,
+        None => 0,
     };
+    Ok(TransactionId {
+        timestamp,
+        amount_cents: (amount * 100.0).round() as i64,
+        currency: get_required_param(params, "currency")?,
+        payee: get_required_param(params, "payee")?,
+        occurrence,
+    })
+}
+
+/// The account and id of the transaction named by a uuid in the path
+pub fn transaction_from_uuid(store: &TransactionStore, uuid: &str) -> Result<(String, TransactionId), ApiError> {
+    store.resolve_uuid(uuid).ok_or(ApiError {
+        message: "Transaction not found".to_string(),
+        status: warp::http::StatusCode::NOT_FOUND,
+    })
+}
+
+/// Whether an `If-None-Match` or `If-Match` header lists `etag`, so the client's copy is current
+pub fn etag_matches(tags: &str, etag: &str) -> bool {
+    tags
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Whether the request asks to change transactions in a locked period with `override_lock=true`
+pub fn override_lock(params: &HashMap<String, String>) -> bool {
+    params.get("override_lock").is_some_and(|value| value == "true")
+}
+
+/// Whether `name` matches `pattern`, ignoring case, where `*` matches any run
+/// of characters and `?` any one
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    // Greedy match that backtracks to the last `*`
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Check that `amount` can be stored in `currency`, returning it as it is to
+/// be stored: rounded to the decimal places the currency allows, and that
+/// hundredths can hold, when rounding is what `precision` asks for
+pub fn validate_amount(
+    amount: f64,
+    currency: &str,
+    precision: AmountPrecision,
+) -> Result<f64, FieldError> {
+    let allowed = allowed_decimals(currency);
+    let storable = allowed.min(STORED_DECIMALS);
+    let places = decimal_places(amount);
+    if places <= storable {
+        return Ok(amount);
+    }
+    match precision {
+        AmountPrecision::Reject => Err(FieldError {
+            field: "amount".to_string(),
+            value: amount.to_string(),
+            message: if places > allowed {
+                format!("Amount has {} decimal places but {} allows at most {}", places, currency, allowed)
+            } else {
+                // Rather than silently dropping the currency's last digits
+                format!(
+                    "Amount has {} decimal places but amounts are stored with at most {}",
+                    places, STORED_DECIMALS
+                )
+            },
+        }),
+        AmountPrecision::Round => {
+            let scale = 10f64.powi(storable as i32);
+            Ok((amount * scale).round() / scale)
+        }
+    }
+}
+
+/// Versions of the API this server answers, oldest first. A breaking change
+/// ships as a new version while the old ones keep answering as they did.
+pub const API_VERSIONS: &[u32] = &[1];
+pub const LATEST_API_VERSION: u32 = 1;
 
-    Ok((transaction_id, current_transaction, historical_transaction))
+/// Take the `v<n>` segment of a versioned path, refusing versions the server doesn't have
+pub fn with_path_api_version() -> impl warp::Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::path::param::<String>()
+        .and_then(|segment: String| async move {
+            api_version(segment.strip_prefix('v').unwrap_or_default(), warp::http::StatusCode::NOT_FOUND)
+        })
+        .untuple_one()
+}
+
+/// Check the `Api-Version` header of an unversioned path, if there is one
+pub fn with_header_api_version() -> impl warp::Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("api-version")
+        .and_then(|version: Option<String>| async move {
+            match version {
+                Some(version) => api_version(version.trim(), warp::http::StatusCode::BAD_REQUEST),
+                None => Ok(()),
+            }
+        })
+        .untuple_one()
+}
+
+fn api_version(version: &str, status: warp::http::StatusCode) -> Result<(), warp::Rejection> {
+    if version.parse().is_ok_and(|version| API_VERSIONS.contains(&version)) {
+        return Ok(());
+    }
+    let supported: Vec<_> = API_VERSIONS.iter().map(u32::to_string).collect();
+    Err(warp::reject::custom(ApiError {
+        message: format!(
+            "API version '{}' is not supported; supported versions are {}",
+            version,
+            supported.join(", ")
+        ),
+        status,
+    }))
 }
 
 pub fn with_store(
     store: TransactionStore,
 ) -> impl warp::Filter<Extract = (TransactionStore,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || store.clone())
-}
\ No newline at end of file
+}
+
+/// The store as the sender of the request may see and change it
+pub fn with_user_store(
+    store: TransactionStore,
+    access: Access,
+) -> impl warp::Filter<Extract = (TransactionStore,), Error = warp::Rejection> + Clone {
+    access.owner().map(move |owner| store.for_owner(owner))
+}
+
+pub fn with_config(
+    config: Arc<Config>,
+) -> impl warp::Filter<Extract = (Arc<Config>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || config.clone())
+}
+
+pub fn with_backups(
+    backups: Backups,
+) -> impl warp::Filter<Extract = (Backups,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || backups.clone())
+}
+
+/// The dashboard of the sender of the request, each user having their own
+pub fn with_user_dashboard(
+    dashboard: Dashboard,
+    access: Access,
+) -> impl warp::Filter<Extract = (Dashboard,), Error = warp::Rejection> + Clone {
+    access.user().map(move |user| dashboard.for_user(user))
+}
+
+pub fn with_frontend(
+    frontend: Option<Frontend>,
+) -> impl warp::Filter<Extract = (Option<Frontend>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || frontend.clone())
+}
+
+pub fn with_hash_chain(
+    hash_chain: HashChain,
+) -> impl warp::Filter<Extract = (HashChain,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || hash_chain.clone())
+}
+
+pub fn with_quarantine(
+    quarantine: Quarantine,
+) -> impl warp::Filter<Extract = (Quarantine,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || quarantine.clone())
+}
+
+/// The statements of the imports the sender of the request may see
+pub fn with_user_statements(
+    statements: Statements,
+    access: Access,
+) -> impl warp::Filter<Extract = (Statements,), Error = warp::Rejection> + Clone {
+    access.owner().map(move |owner| statements.for_owner(owner))
+}
+
+pub fn with_status(
+    status: Arc<StatusResponse>,
+) -> impl warp::Filter<Extract = (Arc<StatusResponse>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || status.clone())
+}
+
+pub fn with_subscriptions(
+    subscriptions: Subscriptions,
+) -> impl warp::Filter<Extract = (Subscriptions,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || subscriptions.clone())
+}
+
+/// The local users, for routes that are only there when `auth.provider` is `users`
+pub fn with_users(users: Option<Users>) -> impl warp::Filter<Extract = (Users,), Error = warp::Rejection> + Clone {
+    warp::any().and_then(move || {
+        let users = users.clone();
+        async move {
+            users.ok_or_else(|| {
+                warp::reject::custom(ApiError {
+                    message: "Local user accounts aren't enabled; set auth.provider to \"users\"".to_string(),
+                    status: warp::http::StatusCode::NOT_FOUND,
+                })
+            })
+        }
+    })
+}
+
+/// Claim the request's `Idempotency-Key` header, if it has one, for its
+/// sender, method and path
+pub fn with_idempotency(
+    idempotency: Idempotency,
+    access: Access,
+) -> impl warp::Filter<Extract = (Claim,), Error = warp::Rejection> + Clone {
+    access
+        .user()
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and_then(move |user: Option<String>, method, path: warp::path::FullPath, key| {
+            let claim = idempotency.claim(user.as_deref(), key, &method, path.as_str());
+            async move { claim.map_err(warp::reject::custom) }
+        })
+}
+
+/// Wait for a slot of `limit`, handed to the handler so it is held until the handler returns
+pub fn with_slot(
+    limit: Limit,
+) -> impl warp::Filter<Extract = (Slot,), Error = warp::Rejection> + Clone {
+    warp::any().and_then(move || {
+        let limit = limit.clone();
+        async move { limit.acquire().await.map_err(warp::reject::custom) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_amounts_to_the_decimals_their_currency_allows() {
+        assert_eq!(validate_amount(100.4, "JPY", AmountPrecision::Round).unwrap(), 100.0);
+        assert_eq!(validate_amount(100.6, "JPY", AmountPrecision::Round).unwrap(), 101.0);
+        assert_eq!(validate_amount(12.345, "USD", AmountPrecision::Round).unwrap(), 12.35);
+        // Stored in hundredths, so three-decimal currencies round to two
+        assert_eq!(validate_amount(1.234, "KWD", AmountPrecision::Round).unwrap(), 1.23);
+    }
+
+    #[test]
+    fn rejects_amounts_finer_than_their_currency_allows() {
+        assert!(validate_amount(100.4, "JPY", AmountPrecision::Reject).is_err());
+        assert!(validate_amount(12.345, "USD", AmountPrecision::Reject).is_err());
+        assert_eq!(validate_amount(100.0, "JPY", AmountPrecision::Reject).unwrap(), 100.0);
+        assert_eq!(validate_amount(12.34, "USD", AmountPrecision::Reject).unwrap(), 12.34);
+    }
+
+    #[test]
+    fn tells_what_kwd_allows_apart_from_what_can_be_stored() {
+        let finer = validate_amount(1.2345, "KWD", AmountPrecision::Reject).unwrap_err();
+        assert_eq!(finer.message, "Amount has 4 decimal places but KWD allows at most 3");
+        let unstorable = validate_amount(1.234, "KWD", AmountPrecision::Reject).unwrap_err();
+        assert_eq!(unstorable.message, "Amount has 3 decimal places but amounts are stored with at most 2");
+        assert_eq!(validate_amount(1.23, "KWD", AmountPrecision::Reject).unwrap(), 1.23);
+    }
+}